@@ -0,0 +1,101 @@
+use crate::dynamodb::{delete_item, list_items, put_item};
+use serde::Serialize;
+
+/// Visibility rules for S3 key prefixes, one item per prefix (relative to
+/// the stage's base path) with the visibility as its value.
+pub const PREFIX_ACL_PARTITION: &str = "prefix_acl";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    /// Listed and readable by anyone; downloads are served from `cdn_url`.
+    Public,
+    /// Readable by anyone who knows the key, listed only to admins.
+    Unlisted,
+    /// Every presign and listing needs the admin token.
+    Private,
+}
+
+impl Visibility {
+    pub fn parse(value: &str) -> Option<Visibility> {
+        match value {
+            "public" => Some(Visibility::Public),
+            "unlisted" => Some(Visibility::Unlisted),
+            "private" => Some(Visibility::Private),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Visibility::Public => "public",
+            Visibility::Unlisted => "unlisted",
+            Visibility::Private => "private",
+        }
+    }
+
+    /// Whether a request may presign keys (or, with `listing`, list keys)
+    /// under this visibility.
+    pub fn allows(self, admin: bool, listing: bool) -> bool {
+        match self {
+            Visibility::Public => true,
+            Visibility::Unlisted => admin || !listing,
+            Visibility::Private => admin,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct AclRule {
+    pub prefix: String,
+    pub visibility: Visibility,
+}
+
+/// Every rule, loaded once per request and matched in memory.
+pub struct Rules(Vec<AclRule>);
+
+impl Rules {
+    pub async fn load() -> Result<Rules, Box<dyn std::error::Error + Send + Sync>> {
+        let items = list_items(PREFIX_ACL_PARTITION.to_string()).await?;
+        Ok(Rules(
+            items
+                .into_iter()
+                .filter_map(|item| {
+                    let visibility = Visibility::parse(item.value.as_deref()?)?;
+                    Some(AclRule {
+                        prefix: item.idx,
+                        visibility,
+                    })
+                })
+                .collect(),
+        ))
+    }
+
+    /// Visibility of `key` (relative to the base path): the rule with the
+    /// longest matching prefix, else `default_visibility` (default `public`,
+    /// which is how the bucket behaved before rules existed).
+    pub fn visibility(&self, key: &str) -> Visibility {
+        self.0
+            .iter()
+            .filter(|rule| key.starts_with(&rule.prefix))
+            .max_by_key(|rule| rule.prefix.len())
+            .map(|rule| rule.visibility)
+            .or_else(|| Visibility::parse(&std::env::var("default_visibility").ok()?))
+            .unwrap_or(Visibility::Public)
+    }
+
+    pub fn into_rules(self) -> Vec<AclRule> {
+        self.0
+    }
+}
+
+pub async fn set_rule(
+    prefix: String,
+    visibility: Visibility,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    put_item(PREFIX_ACL_PARTITION.to_string(), prefix, visibility.as_str().to_string()).await
+}
+
+pub async fn delete_rule(prefix: String) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    delete_item(PREFIX_ACL_PARTITION.to_string(), prefix).await
+}
@@ -0,0 +1,454 @@
+use crate::dynamodb::{delete_item, generate_idx, list_items, put_record, query_records};
+use crate::outbound;
+use crate::posts::posts_part;
+use crate::view::View;
+use aws_sdk_dynamodb::types::AttributeValue;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use lambda_http::http::HeaderMap;
+use rsa::pkcs1v15::{Signature, SigningKey, VerifyingKey};
+use rsa::pkcs8::{DecodePrivateKey, DecodePublicKey};
+use rsa::signature::{SignatureEncoding, Signer, Verifier};
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime};
+use url::Url;
+
+/// Remote actors following the blog, keyed by actor id, with their inbox.
+pub const FOLLOWERS_PARTITION: &str = "followers";
+
+pub const ACTIVITY_JSON: &str = "application/activity+json";
+const ACTIVITY_STREAMS: &str = "https://www.w3.org/ns/activitystreams";
+
+/// Largest remote actor document read.
+const MAX_ACTOR_BYTES: usize = 1024 * 1024;
+
+/// Furthest an incoming signature's `Date` may be from now, either way.
+const MAX_SIGNATURE_SKEW: Duration = Duration::from_secs(60 * 60);
+
+/// Headers an incoming signature must cover, so it cannot be replayed
+/// against another inbox, at another time or with another body.
+const REQUIRED_SIGNED_HEADERS: [&str; 4] = ["(request-target)", "host", "date", "digest"];
+
+/// Settings read from `api_url`, `site_url`, `activitypub_username`,
+/// `activitypub_part`, `activitypub_public_key` and `activitypub_private_key`.
+pub struct ActivityPubConfig {
+    pub api_url: String,
+    pub site_url: String,
+    pub username: String,
+    pub part: String,
+    pub public_key_pem: String,
+    private_key: RsaPrivateKey,
+}
+
+impl ActivityPubConfig {
+    pub fn actor_id(&self) -> String {
+        format!("{}/activitypub/actor", self.api_url)
+    }
+
+    fn key_id(&self) -> String {
+        format!("{}#main-key", self.actor_id())
+    }
+
+    fn domain(&self) -> String {
+        Url::parse(&self.site_url)
+            .ok()
+            .and_then(|u| u.host_str().map(|h| h.to_string()))
+            .unwrap_or_default()
+    }
+
+    fn post_url(&self, idx: &str) -> String {
+        format!("{}/posts/{idx}", self.site_url)
+    }
+}
+
+/// `None` (and every ActivityPub route answering 404) unless fully configured.
+pub fn config() -> Option<&'static ActivityPubConfig> {
+    static CONFIG: OnceLock<Option<ActivityPubConfig>> = OnceLock::new();
+    CONFIG
+        .get_or_init(|| {
+            let var = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+            let site_url = var("site_url")?.trim_end_matches('/').to_string();
+            let private_key_pem = var("activitypub_private_key")?;
+            let private_key = match RsaPrivateKey::from_pkcs8_pem(&private_key_pem) {
+                Ok(key) => key,
+                Err(e) => {
+                    tracing::error!("activitypub_private_key is not a PKCS#8 PEM: {}", e);
+                    return None;
+                }
+            };
+
+            Some(ActivityPubConfig {
+                api_url: var("api_url")
+                    .map(|u| u.trim_end_matches('/').to_string())
+                    .unwrap_or_else(|| site_url.clone()),
+                username: var("activitypub_username").unwrap_or_else(|| "blog".to_string()),
+                part: var("activitypub_part").unwrap_or_else(posts_part),
+                public_key_pem: var("activitypub_public_key")?,
+                site_url,
+                private_key,
+            })
+        })
+        .as_ref()
+}
+
+/// Actor ids and inboxes come from remote servers, so they go through the
+/// outbound client like any other untrusted URL.
+fn client() -> Result<&'static reqwest::Client, Box<dyn std::error::Error + Send + Sync>> {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    outbound::cached(&CLIENT, Duration::from_secs(10), 5)
+}
+
+/// JRD answer for `acct:<username>@<site domain>`, `None` for anyone else.
+pub fn webfinger(config: &ActivityPubConfig, resource: &str) -> Option<Value> {
+    let expected = format!("acct:{}@{}", config.username, config.domain());
+    if resource != expected {
+        return None;
+    }
+
+    Some(json!({
+        "subject": expected,
+        "links": [{
+            "rel": "self",
+            "type": ACTIVITY_JSON,
+            "href": config.actor_id(),
+        }],
+    }))
+}
+
+pub fn actor(config: &ActivityPubConfig) -> Value {
+    let id = config.actor_id();
+    json!({
+        "@context": [ACTIVITY_STREAMS, "https://w3id.org/security/v1"],
+        "id": id,
+        "type": "Person",
+        "preferredUsername": config.username,
+        "url": config.site_url,
+        "inbox": format!("{}/activitypub/inbox", config.api_url),
+        "outbox": format!("{}/activitypub/outbox", config.api_url),
+        "publicKey": {
+            "id": config.key_id(),
+            "owner": id,
+            "publicKeyPem": config.public_key_pem,
+        },
+    })
+}
+
+fn create_note(config: &ActivityPubConfig, idx: &str, content: &str) -> Value {
+    let url = config.post_url(idx);
+    json!({
+        "@context": ACTIVITY_STREAMS,
+        "id": format!("{url}#create"),
+        "type": "Create",
+        "actor": config.actor_id(),
+        "to": [format!("{ACTIVITY_STREAMS}#Public")],
+        "object": {
+            "id": url,
+            "type": "Note",
+            "attributedTo": config.actor_id(),
+            "content": View::Public.post_value(content),
+            "url": url,
+            "to": [format!("{ACTIVITY_STREAMS}#Public")],
+        },
+    })
+}
+
+/// Published posts (the live items of `activitypub_part`) as `Create(Note)`s.
+pub async fn outbox(
+    config: &ActivityPubConfig,
+) -> Result<Value, Box<dyn std::error::Error + Send + Sync>> {
+    let items = list_items(config.part.clone()).await?;
+    let activities: Vec<Value> = items
+        .iter()
+        .rev()
+        .map(|item| create_note(config, &item.idx, item.value.as_deref().unwrap_or_default()))
+        .collect();
+
+    Ok(json!({
+        "@context": ACTIVITY_STREAMS,
+        "id": format!("{}/activitypub/outbox", config.api_url),
+        "type": "OrderedCollection",
+        "totalItems": activities.len(),
+        "orderedItems": activities,
+    }))
+}
+
+/// Signs a request per draft-cavage HTTP Signatures, as Mastodon expects, and
+/// returns the headers to send with it.
+fn signed_headers(
+    config: &ActivityPubConfig,
+    method: &str,
+    url: &Url,
+    body: Option<&[u8]>,
+) -> Vec<(&'static str, String)> {
+    let host = url.host_str().unwrap_or_default().to_string();
+    let date = httpdate::fmt_http_date(SystemTime::now());
+    let target = match url.query() {
+        Some(query) => format!("{} {}?{query}", method.to_lowercase(), url.path()),
+        None => format!("{} {}", method.to_lowercase(), url.path()),
+    };
+
+    let mut headers = vec![("host", host.clone()), ("date", date.clone())];
+    let mut signed = vec![
+        ("(request-target)", target),
+        ("host", host),
+        ("date", date),
+    ];
+    if let Some(body) = body {
+        let digest = format!("SHA-256={}", BASE64.encode(Sha256::digest(body)));
+        headers.push(("digest", digest.clone()));
+        signed.push(("digest", digest));
+    }
+
+    let signing_string = signed
+        .iter()
+        .map(|(name, value)| format!("{name}: {value}"))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let signature = SigningKey::<Sha256>::new(config.private_key.clone())
+        .sign(signing_string.as_bytes())
+        .to_bytes();
+    let header_names = signed.iter().map(|(n, _)| *n).collect::<Vec<_>>().join(" ");
+
+    headers.push((
+        "signature",
+        format!(
+            "keyId=\"{}\",algorithm=\"rsa-sha256\",headers=\"{header_names}\",signature=\"{}\"",
+            config.key_id(),
+            BASE64.encode(signature)
+        ),
+    ));
+    headers
+}
+
+/// Fetches a remote actor (or key) document with a signed GET, as servers
+/// running in secure mode require.
+async fn remote_document(
+    config: &ActivityPubConfig,
+    id: &str,
+) -> Result<Value, Box<dyn std::error::Error + Send + Sync>> {
+    let url = Url::parse(id)?;
+    let mut request = client()?.get(url.as_str()).header("accept", ACTIVITY_JSON);
+    for (name, value) in signed_headers(config, "GET", &url, None) {
+        request = request.header(name, value);
+    }
+
+    let response = outbound::send(request).await?.error_for_status()?;
+    let (body, truncated) = outbound::read_limited(response, MAX_ACTOR_BYTES).await?;
+    if truncated {
+        return Err("remote actor document is too large".into());
+    }
+    Ok(serde_json::from_slice(&body)?)
+}
+
+/// A remote actor's preferred inbox, from its actor document.
+async fn remote_inbox(
+    config: &ActivityPubConfig,
+    actor_id: &str,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let actor = remote_document(config, actor_id).await?;
+    actor["endpoints"]["sharedInbox"]
+        .as_str()
+        .or_else(|| actor["inbox"].as_str())
+        .map(|s| s.to_string())
+        .ok_or_else(|| "remote actor has no inbox".into())
+}
+
+/// POSTs a signed activity to a remote inbox.
+pub async fn deliver(
+    config: &ActivityPubConfig,
+    inbox: &str,
+    activity: &Value,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let url = Url::parse(inbox)?;
+    let body = serde_json::to_vec(activity)?;
+
+    let mut request = client()?
+        .post(url.as_str())
+        .header("content-type", ACTIVITY_JSON)
+        .body(body.clone());
+    for (name, value) in signed_headers(config, "POST", &url, Some(&body)) {
+        request = request.header(name, value);
+    }
+
+    outbound::send(request).await?.error_for_status()?;
+    Ok(())
+}
+
+/// `scheme://host[:port]` of a URL, which a key and its owner must share.
+fn origin(url: &str) -> Option<String> {
+    Url::parse(url).ok().map(|u| u.origin().ascii_serialization())
+}
+
+/// The RSA public key `key_id` names, provided its owner is `actor_id`. The
+/// key must be served from the actor's own origin, since any server could
+/// publish a key document naming someone else as its owner.
+async fn owned_key(
+    config: &ActivityPubConfig,
+    key_id: &str,
+    actor_id: &str,
+) -> Result<RsaPublicKey, &'static str> {
+    if origin(key_id).is_none() || origin(key_id) != origin(actor_id) {
+        return Err("signing key is not the actor's");
+    }
+    let document = remote_document(config, key_id).await.map_err(|e| {
+        tracing::warn!("activitypub key fetch from {} failed: {:?}", key_id, e);
+        "signing key unavailable"
+    })?;
+    // actors usually embed their key; a key may also be its own document
+    let key = match &document["publicKey"] {
+        Value::Object(_) => &document["publicKey"],
+        _ => &document,
+    };
+    if key["id"].as_str() != Some(key_id) || key["owner"].as_str() != Some(actor_id) {
+        return Err("signing key is not the actor's");
+    }
+    key["publicKeyPem"]
+        .as_str()
+        .and_then(|pem| RsaPublicKey::from_public_key_pem(pem).ok())
+        .ok_or("signing key unreadable")
+}
+
+/// Checks an incoming activity's draft-cavage HTTP Signature: it must cover
+/// the request target, host, date and body digest, the date must be within
+/// an hour, the digest must match `body`, and the key must belong to
+/// `actor_id`. Fails with the reason otherwise.
+pub async fn verify_signature(
+    config: &ActivityPubConfig,
+    headers: &HeaderMap,
+    body: &[u8],
+    actor_id: &str,
+) -> Result<(), &'static str> {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+
+    let params: HashMap<&str, &str> = header("signature")
+        .ok_or("missing signature")?
+        .split(',')
+        .filter_map(|param| param.trim().split_once('='))
+        .map(|(key, value)| (key, value.trim_matches('"')))
+        .collect();
+    let (Some(key_id), Some(signature)) = (params.get("keyId"), params.get("signature")) else {
+        return Err("malformed signature");
+    };
+    let signed: Vec<&str> = params.get("headers").unwrap_or(&"date").split(' ').collect();
+    if REQUIRED_SIGNED_HEADERS.iter().any(|h| !signed.contains(h)) {
+        return Err("signature does not cover (request-target), host, date and digest");
+    }
+
+    let date = header("date")
+        .and_then(|d| httpdate::parse_http_date(d).ok())
+        .ok_or("missing date")?;
+    let skew = date
+        .duration_since(SystemTime::now())
+        .or_else(|_| SystemTime::now().duration_since(date))
+        .unwrap_or_default();
+    if skew > MAX_SIGNATURE_SKEW {
+        return Err("signature date out of range");
+    }
+
+    let digest = format!("SHA-256={}", BASE64.encode(Sha256::digest(body)));
+    if header("digest") != Some(digest.as_str()) {
+        return Err("digest mismatch");
+    }
+
+    // senders sign the inbox URL the actor document advertises
+    let inbox = Url::parse(&format!("{}/activitypub/inbox", config.api_url))
+        .map_err(|_| "inbox url unavailable")?;
+    let mut lines = Vec::with_capacity(signed.len());
+    for name in &signed {
+        let value = match *name {
+            "(request-target)" => format!("post {}", inbox.path()),
+            "host" => header("host")
+                .or_else(|| inbox.host_str())
+                .ok_or("missing host")?
+                .to_string(),
+            other => header(other).ok_or("missing signed header")?.to_string(),
+        };
+        lines.push(format!("{name}: {value}"));
+    }
+
+    let signature = BASE64
+        .decode(signature)
+        .ok()
+        .and_then(|bytes| Signature::try_from(bytes.as_slice()).ok())
+        .ok_or("malformed signature")?;
+    let key = owned_key(config, key_id, actor_id).await?;
+    VerifyingKey::<Sha256>::new(key)
+        .verify(lines.join("\n").as_bytes(), &signature)
+        .map_err(|_| "invalid signature")
+}
+
+/// Handles `Follow` (store follower, send `Accept`) and `Undo(Follow)`.
+/// Other activity types are acknowledged and ignored.
+///
+/// Callers check the request with `verify_signature` first, so `actor` is
+/// the key owner; the follower's inbox is still resolved from its actor
+/// document rather than taken from the payload.
+pub async fn inbox(
+    config: &ActivityPubConfig,
+    activity: Value,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let actor_id = activity["actor"].as_str().unwrap_or_default().to_string();
+    if actor_id.is_empty() {
+        return Err("activity has no actor".into());
+    }
+
+    match activity["type"].as_str() {
+        Some("Follow") => {
+            let inbox = remote_inbox(config, &actor_id).await?;
+
+            let mut attributes = HashMap::new();
+            attributes.insert("inbox".to_string(), AttributeValue::S(inbox.clone()));
+            put_record(FOLLOWERS_PARTITION.to_string(), actor_id, attributes).await?;
+
+            let accept = json!({
+                "@context": ACTIVITY_STREAMS,
+                "id": format!("{}#accept-{}", config.actor_id(), generate_idx()),
+                "type": "Accept",
+                "actor": config.actor_id(),
+                "object": activity,
+            });
+            deliver(config, &inbox, &accept).await
+        }
+        Some("Undo") if activity["object"]["type"].as_str() == Some("Follow") => {
+            delete_item(FOLLOWERS_PARTITION.to_string(), actor_id).await
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Sends a post to every follower's inbox (once per shared inbox) and returns
+/// how many deliveries succeeded.
+pub async fn publish(
+    config: &ActivityPubConfig,
+    idx: &str,
+    content: &str,
+) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    let followers =
+        query_records(FOLLOWERS_PARTITION.to_string(), None, Vec::new(), usize::MAX, false)
+            .await?;
+    let mut inboxes: Vec<String> = followers
+        .iter()
+        .filter_map(|f| match f.get("inbox") {
+            Some(AttributeValue::S(inbox)) => Some(inbox.clone()),
+            _ => None,
+        })
+        .collect();
+    inboxes.sort();
+    inboxes.dedup();
+
+    let activity = create_note(config, idx, content);
+
+    let mut delivered = 0;
+    for inbox in inboxes {
+        match deliver(config, &inbox, &activity).await {
+            Ok(()) => delivered += 1,
+            Err(e) => tracing::warn!("activitypub delivery to {} failed: {:?}", inbox, e),
+        }
+    }
+
+    Ok(delivered)
+}
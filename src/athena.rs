@@ -0,0 +1,203 @@
+use crate::init;
+use aws_sdk_athena::types::{QueryExecutionContext, ResultConfiguration};
+use aws_sdk_athena::Client;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+const MAX_RESULTS: i32 = 1000;
+
+/// Where the access log written by `firehose` is catalogued.
+pub struct AthenaConfig {
+    pub database: String,
+    pub table: String,
+    pub workgroup: String,
+    pub output: Option<String>,
+}
+
+/// Reads `athena_database` (required), `athena_table` (default `access_log`),
+/// `athena_workgroup` (default `primary`) and `athena_output`. The analytics
+/// routes are disabled when no database is configured.
+pub fn config() -> Option<AthenaConfig> {
+    let var = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+    let table = var("athena_table").unwrap_or_else(|| "access_log".to_string());
+    if !table.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        tracing::error!("athena_table {:?} is not a plain table name", table);
+        return None;
+    }
+
+    Some(AthenaConfig {
+        database: var("athena_database")?,
+        table,
+        workgroup: var("athena_workgroup").unwrap_or_else(|| "primary".to_string()),
+        output: var("athena_output"),
+    })
+}
+
+/// The queries the dashboard may run; callers only choose the parameters.
+#[derive(Debug, Clone, Copy)]
+pub enum NamedQuery {
+    ViewsByDay,
+    TopReferrers,
+}
+
+impl NamedQuery {
+    pub fn parse(name: &str) -> Option<NamedQuery> {
+        match name {
+            "views_by_day" => Some(NamedQuery::ViewsByDay),
+            "top_referrers" => Some(NamedQuery::TopReferrers),
+            _ => None,
+        }
+    }
+
+    /// SQL with `?` placeholders for the first and last day of the range.
+    fn sql(&self, table: &str, limit: usize) -> String {
+        let in_range = "from_unixtime(ts / 1000) >= date_parse(?, '%Y-%m-%d') \
+             AND from_unixtime(ts / 1000) < date_parse(?, '%Y-%m-%d') + INTERVAL '1' DAY";
+        match self {
+            NamedQuery::ViewsByDay => format!(
+                "SELECT date_format(from_unixtime(ts / 1000), '%Y-%m-%d') AS day, \
+                 count(*) AS views FROM \"{table}\" \
+                 WHERE method = 'POST' AND path LIKE '/posts/%/view' AND {in_range} \
+                 GROUP BY 1 ORDER BY 1"
+            ),
+            NamedQuery::TopReferrers => format!(
+                "SELECT referrer, count(*) AS requests FROM \"{table}\" \
+                 WHERE referrer <> '' AND {in_range} \
+                 GROUP BY referrer ORDER BY requests DESC LIMIT {limit}"
+            ),
+        }
+    }
+}
+
+/// `YYYY-MM-DD`, the only shape accepted as a query parameter.
+pub fn is_date(value: &str) -> bool {
+    let bytes = value.as_bytes();
+    bytes.len() == 10
+        && bytes.iter().enumerate().all(|(i, b)| match i {
+            4 | 7 => *b == b'-',
+            _ => b.is_ascii_digit(),
+        })
+}
+
+#[derive(Debug, Serialize)]
+pub struct QueryStatus {
+    pub state: String,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct QueryResults {
+    pub columns: Vec<String>,
+    pub rows: Vec<Map<String, Value>>,
+    #[serde(rename = "nextToken")]
+    pub next_token: Option<String>,
+}
+
+async fn athena_client() -> Client {
+    let config = init::sdk_config().await;
+    Client::new(config)
+}
+
+/// Starts `query` over `[from, to]` (inclusive days) and returns the
+/// execution id to poll.
+pub async fn start(
+    config: &AthenaConfig,
+    query: NamedQuery,
+    from: &str,
+    to: &str,
+    limit: usize,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let client = athena_client().await;
+
+    let mut request = client
+        .start_query_execution()
+        .query_string(query.sql(&config.table, limit))
+        .query_execution_context(
+            QueryExecutionContext::builder()
+                .database(&config.database)
+                .build(),
+        )
+        .work_group(&config.workgroup)
+        .execution_parameters(format!("'{from}'"))
+        .execution_parameters(format!("'{to}'"));
+    if let Some(output) = &config.output {
+        request = request.result_configuration(
+            ResultConfiguration::builder().output_location(output).build(),
+        );
+    }
+
+    let output = request.send().await?;
+    Ok(output
+        .query_execution_id
+        .ok_or("athena returned no execution id")?)
+}
+
+pub async fn status(id: &str) -> Result<QueryStatus, Box<dyn std::error::Error + Send + Sync>> {
+    let client = athena_client().await;
+
+    let output = client
+        .get_query_execution()
+        .query_execution_id(id)
+        .send()
+        .await?;
+    let status = output.query_execution.and_then(|q| q.status);
+
+    Ok(QueryStatus {
+        state: status
+            .as_ref()
+            .and_then(|s| s.state.as_ref())
+            .map(|s| s.as_str().to_string())
+            .unwrap_or_default(),
+        reason: status.and_then(|s| s.state_change_reason),
+    })
+}
+
+/// One page of a finished query's rows, keyed by column name. Athena repeats
+/// the column names as the first row of the first page only.
+pub async fn results(
+    id: &str,
+    next_token: Option<String>,
+) -> Result<QueryResults, Box<dyn std::error::Error + Send + Sync>> {
+    let client = athena_client().await;
+
+    let first_page = next_token.is_none();
+    let output = client
+        .get_query_results()
+        .query_execution_id(id)
+        .set_next_token(next_token)
+        .max_results(MAX_RESULTS)
+        .send()
+        .await?;
+
+    let result_set = output.result_set.ok_or("athena returned no result set")?;
+    let columns: Vec<String> = result_set
+        .result_set_metadata
+        .and_then(|m| m.column_info)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|c| c.name)
+        .collect();
+
+    let rows = result_set
+        .rows
+        .unwrap_or_default()
+        .into_iter()
+        .skip(if first_page { 1 } else { 0 })
+        .map(|row| {
+            columns
+                .iter()
+                .zip(row.data.unwrap_or_default())
+                .map(|(column, datum)| {
+                    let value = datum.var_char_value.map(Value::String).unwrap_or(Value::Null);
+                    (column.clone(), value)
+                })
+                .collect()
+        })
+        .collect();
+
+    Ok(QueryResults {
+        columns,
+        rows,
+        next_token: output.next_token,
+    })
+}
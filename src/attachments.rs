@@ -0,0 +1,207 @@
+use crate::clock::now_millis;
+use crate::dynamodb::{
+    dynamodb_client, query_records, schema, BumpsVersion, TABLE_NAME, UPDATED_AT_ATTRIBUTE,
+    VERSION_BUMP,
+};
+use crate::files::FileType;
+use crate::s3::StoredObject;
+use aws_sdk_dynamodb::types::{AttributeValue, ReturnValue};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+
+/// Display settings of uploaded files, one item per key relative to the
+/// stage's base. Files without an item show in name order, uncaptioned.
+pub const ATTACHMENTS_PARTITION: &str = "attachments";
+
+/// Longest caption or alt text kept, in characters.
+pub const MAX_TEXT_CHARS: usize = 1000;
+
+const ORDER_ATTRIBUTE: &str = "display_order";
+const CAPTION_ATTRIBUTE: &str = "caption";
+const ALT_ATTRIBUTE: &str = "alt";
+
+/// Keeps `null` apart from a missing field: `Some(None)` clears the field.
+fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::deserialize(deserializer).map(Some)
+}
+
+/// Changes to a file's display settings. Fields left out are kept, and
+/// `null` removes one.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct AttachmentPatch {
+    #[serde(default, deserialize_with = "present")]
+    pub order: Option<Option<u32>>,
+    #[serde(default, deserialize_with = "present")]
+    pub caption: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    pub alt: Option<Option<String>>,
+}
+
+impl AttachmentPatch {
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, text) in [("caption", &self.caption), ("alt", &self.alt)] {
+            if let Some(Some(text)) = text {
+                if text.chars().count() > MAX_TEXT_CHARS {
+                    return Err(format!("{name} is longer than {MAX_TEXT_CHARS} characters"));
+                }
+            }
+        }
+        if self.order.is_none() && self.caption.is_none() && self.alt.is_none() {
+            return Err("nothing to change".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DisplaySettings {
+    pub order: Option<u32>,
+    pub caption: Option<String>,
+    pub alt: Option<String>,
+}
+
+impl DisplaySettings {
+    fn from_record(record: &HashMap<String, AttributeValue>) -> DisplaySettings {
+        let text = |name: &str| match record.get(name) {
+            Some(AttributeValue::S(s)) => Some(s.clone()),
+            _ => None,
+        };
+        DisplaySettings {
+            order: match record.get(ORDER_ATTRIBUTE) {
+                Some(AttributeValue::N(n)) => n.parse().ok(),
+                _ => None,
+            },
+            caption: text(CAPTION_ATTRIBUTE),
+            alt: text(ALT_ATTRIBUTE),
+        }
+    }
+}
+
+/// Applies `patch` to the settings of `key` and returns them as they are
+/// now.
+pub async fn update(
+    part: String,
+    key: String,
+    patch: AttachmentPatch,
+) -> Result<DisplaySettings, Box<dyn std::error::Error + Send + Sync>> {
+    let client = dynamodb_client().await;
+    let schema = schema();
+
+    let mut request = client
+        .update_item()
+        .table_name(TABLE_NAME)
+        .key(&schema.partition_key, AttributeValue::S(part))
+        .key(&schema.sort_key, AttributeValue::S(key))
+        .expression_attribute_names("#updated", UPDATED_AT_ATTRIBUTE)
+        .expression_attribute_values(":now", AttributeValue::N(now_millis().to_string()))
+        .return_values(ReturnValue::AllNew)
+        .bump_version();
+    let mut assignments = vec!["#updated = :now".to_string(), VERSION_BUMP.to_string()];
+    let mut removals = Vec::new();
+    let changes = [
+        (
+            "order",
+            ORDER_ATTRIBUTE,
+            patch
+                .order
+                .map(|o| o.map(|o| AttributeValue::N(o.to_string()))),
+        ),
+        (
+            "caption",
+            CAPTION_ATTRIBUTE,
+            patch.caption.map(|c| c.map(AttributeValue::S)),
+        ),
+        (
+            "alt",
+            ALT_ATTRIBUTE,
+            patch.alt.map(|a| a.map(AttributeValue::S)),
+        ),
+    ];
+    for (name, attribute, change) in changes {
+        match change {
+            None => continue,
+            Some(Some(value)) => {
+                assignments.push(format!("#{name} = :{name}"));
+                request = request.expression_attribute_values(format!(":{name}"), value);
+            }
+            Some(None) => removals.push(format!("#{name}")),
+        }
+        request = request.expression_attribute_names(format!("#{name}"), attribute);
+    }
+
+    let mut expression = format!("SET {}", assignments.join(", "));
+    if !removals.is_empty() {
+        expression.push_str(&format!(" REMOVE {}", removals.join(", ")));
+    }
+    let output = request.update_expression(expression).send().await?;
+    Ok(output
+        .attributes
+        .as_ref()
+        .map(DisplaySettings::from_record)
+        .unwrap_or_default())
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Attachment {
+    /// Relative to the stage's base, as `PATCH /api/files/{key}` takes it.
+    pub key: String,
+    pub name: String,
+    #[serde(rename = "type")]
+    pub file_type: FileType,
+    pub size: i64,
+    pub last_modified: i64,
+    #[serde(flatten)]
+    pub display: DisplaySettings,
+}
+
+/// The files in `objects` (keys relative to the stage's base, all under
+/// `prefix`) with their display settings from `part`: those given an order
+/// first, by order, then the rest by name.
+pub async fn in_order(
+    part: String,
+    prefix: &str,
+    objects: Vec<StoredObject>,
+) -> Result<Vec<Attachment>, Box<dyn std::error::Error + Send + Sync>> {
+    let range = (prefix.to_string(), format!("{prefix}\u{10FFFF}"));
+    let records = query_records(part, Some(range), Vec::new(), usize::MAX, false).await?;
+    let sort_key = &schema().sort_key;
+    let mut settings: HashMap<String, DisplaySettings> = records
+        .iter()
+        .filter_map(|record| match record.get(sort_key) {
+            Some(AttributeValue::S(key)) => {
+                Some((key.clone(), DisplaySettings::from_record(record)))
+            }
+            _ => None,
+        })
+        .collect();
+
+    let mut attachments: Vec<Attachment> = objects
+        .into_iter()
+        .filter_map(|object| {
+            let name = object.key.rsplit('/').next()?.to_string();
+            if name.is_empty() {
+                return None;
+            }
+            Some(Attachment {
+                display: settings.remove(&object.key).unwrap_or_default(),
+                file_type: FileType::of(&name),
+                key: object.key,
+                name,
+                size: object.size,
+                last_modified: object.last_modified,
+            })
+        })
+        .collect();
+    attachments.sort_by(|a, b| {
+        let rank = |a: &Attachment| (a.display.order.is_none(), a.display.order);
+        rank(a).cmp(&rank(b)).then_with(|| a.name.cmp(&b.name))
+    });
+    Ok(attachments)
+}
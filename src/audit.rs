@@ -0,0 +1,181 @@
+use crate::auth::is_admin;
+use crate::clock::now_millis;
+use crate::dynamodb::{
+    generate_idx, put_record, query_records, record_to_json, schema, update_record,
+};
+use aws_sdk_dynamodb::types::AttributeValue;
+use lambda_http::{Body, Request, RequestExt};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+/// Partition holding audit entries; the generic item routes refuse to write it.
+pub const AUDIT_PARTITION: &str = "audit";
+
+const DEFAULT_RETENTION_DAYS: u64 = 90;
+
+/// Handle to an audit entry written before a mutating request is executed.
+pub struct AuditEntry {
+    idx: String,
+}
+
+/// Filters accepted by `GET /admin/audit`.
+#[derive(Debug, Default)]
+pub struct AuditQuery {
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+    pub method: Option<String>,
+    pub route: Option<String>,
+    pub principal: Option<String>,
+    pub limit: usize,
+}
+
+/// Best-effort caller address: the first `X-Forwarded-For` hop set by API
+/// Gateway / CloudFront.
+pub fn client_ip(req: &Request) -> String {
+    req.headers()
+        .get("x-forwarded-for")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.split(',').next())
+        .map(|ip| ip.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+fn payload_hash(body: &Body) -> String {
+    let bytes: &[u8] = match body {
+        Body::Text(s) => s.as_bytes(),
+        Body::Binary(b) => b,
+        _ => &[],
+    };
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+fn retention_secs() -> u64 {
+    let days = std::env::var("audit_retention_days")
+        .ok()
+        .and_then(|d| d.parse().ok())
+        .unwrap_or(DEFAULT_RETENTION_DAYS);
+    days * 24 * 60 * 60
+}
+
+/// Records the intent of a mutating request before it runs.
+pub async fn begin(req: &Request) -> Result<AuditEntry, Box<dyn std::error::Error + Send + Sync>> {
+    let now = now_millis();
+    let request_id = req
+        .lambda_context_ref()
+        .map(|c| c.request_id.clone())
+        .unwrap_or_default();
+    // the ULID keeps entries apart when Lambda gives no request id
+    let idx = format!("{now:013}#{}", generate_idx());
+
+    let principal = if is_admin(req) { "admin" } else { "anonymous" };
+    let user_agent = req
+        .headers()
+        .get("user-agent")
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or_default();
+
+    let mut attributes = HashMap::new();
+    let mut set = |name: &str, value: AttributeValue| {
+        attributes.insert(name.to_string(), value);
+    };
+    set("principal", AttributeValue::S(principal.to_string()));
+    if !request_id.is_empty() {
+        set("request_id", AttributeValue::S(request_id));
+    }
+    set("ip", AttributeValue::S(client_ip(req)));
+    set("user_agent", AttributeValue::S(user_agent.to_string()));
+    set("method", AttributeValue::S(req.method().to_string()));
+    set("route", AttributeValue::S(req.uri().path().to_string()));
+    set("payload_sha256", AttributeValue::S(payload_hash(req.body())));
+    set("result", AttributeValue::S("pending".to_string()));
+    set("at", AttributeValue::N(now.to_string()));
+    set("ttl", AttributeValue::N((now / 1000 + retention_secs()).to_string()));
+
+    put_record(AUDIT_PARTITION.to_string(), idx.clone(), attributes).await?;
+    Ok(AuditEntry { idx })
+}
+
+/// Stamps the outcome of the request onto its audit entry.
+pub async fn finish(
+    entry: &AuditEntry,
+    status: u16,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let result = if status < 400 { "ok" } else { "error" };
+
+    let mut attributes = HashMap::new();
+    attributes.insert("result".to_string(), AttributeValue::S(result.to_string()));
+    attributes.insert("status".to_string(), AttributeValue::N(status.to_string()));
+
+    update_record(AUDIT_PARTITION.to_string(), entry.idx.clone(), attributes).await
+}
+
+impl AuditQuery {
+    /// The filters in a stable form, to tie a cursor to them.
+    pub fn fingerprint(&self) -> String {
+        format!(
+            "audit\n{:?}\n{:?}\n{:?}\n{:?}\n{:?}",
+            self.from, self.to, self.method, self.route, self.principal
+        )
+    }
+}
+
+/// Lists audit entries newest first, at most `query.limit` of them. With
+/// `after`, the `idx` of the last entry of the previous page, the listing
+/// continues below it; entries are keyed by time, so new ones never shift
+/// later pages. Also returns the `idx` to continue after when there are
+/// more entries.
+pub async fn search(
+    query: AuditQuery,
+    after: Option<String>,
+) -> Result<(Vec<serde_json::Value>, Option<String>), Box<dyn std::error::Error + Send + Sync>>
+{
+    let from = query.from.unwrap_or(0);
+    let to = query.to.unwrap_or_else(now_millis);
+    // `~` sorts after `#`, so the upper bound includes every entry at `to`;
+    // a cursor bounds the range at itself, inclusive, so one extra is read
+    let (to, extra) = match &after {
+        Some(after) => (after.clone(), 1),
+        None => (format!("{to:013}~"), 0),
+    };
+    let range = (format!("{from:013}"), to);
+
+    let mut filters = Vec::new();
+    if let Some(method) = query.method {
+        filters.push(("method".to_string(), AttributeValue::S(method.to_uppercase())));
+    }
+    if let Some(route) = query.route {
+        filters.push(("route".to_string(), AttributeValue::S(route)));
+    }
+    if let Some(principal) = query.principal {
+        filters.push(("principal".to_string(), AttributeValue::S(principal)));
+    }
+
+    // one more than a page tells whether there is another
+    let mut records = query_records(
+        AUDIT_PARTITION.to_string(),
+        Some(range),
+        filters,
+        query.limit + extra + 1,
+        true,
+    )
+    .await?;
+
+    let sort_key = &schema().sort_key;
+    let idx = |record: &HashMap<String, AttributeValue>| match record.get(sort_key) {
+        Some(AttributeValue::S(idx)) => Some(idx.clone()),
+        _ => None,
+    };
+    if let Some(after) = &after {
+        records.retain(|record| idx(record).as_ref() != Some(after));
+    }
+    let next = if query.limit > 0 && records.len() > query.limit {
+        idx(&records[query.limit - 1])
+    } else {
+        None
+    };
+    records.truncate(query.limit);
+    Ok((records.iter().map(record_to_json).collect(), next))
+}
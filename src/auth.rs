@@ -0,0 +1,27 @@
+use lambda_http::Request;
+
+/// True when the request carries `Authorization: Bearer <admin_token>`.
+///
+/// Admin routes are closed entirely when `admin_token` is not configured.
+pub fn is_admin(req: &Request) -> bool {
+    let expected = match std::env::var("admin_token") {
+        Ok(token) if !token.is_empty() => token,
+        _ => return false,
+    };
+
+    let supplied = req
+        .headers()
+        .get("authorization")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .unwrap_or_default();
+
+    constant_time_eq(supplied.as_bytes(), expected.as_bytes())
+}
+
+/// Whether `a` and `b` are equal, compared without short-circuiting on the
+/// first differing byte, so the time taken says nothing about a secret.
+/// Only the length can leak, which tokens and signatures do not hide.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
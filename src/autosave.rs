@@ -0,0 +1,157 @@
+use crate::clock::now_millis;
+use crate::dynamodb::{dynamodb_client, get_record, schema, TABLE_NAME, UPDATED_AT_ATTRIBUTE};
+use aws_sdk_dynamodb::operation::put_item::PutItemError;
+use aws_sdk_dynamodb::types::{AttributeValue, ReturnValuesOnConditionCheckFailure};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Autosaved editor contents, one item per post id. They are not revisions:
+/// each save replaces the last, and DynamoDB TTL drops them once stale.
+pub const AUTOSAVES_PARTITION: &str = "autosaves";
+
+/// Largest body kept, well under DynamoDB's 400 KB item limit.
+pub const MAX_AUTOSAVE_BYTES: usize = 350 * 1024;
+
+const DEFAULT_TTL_DAYS: u64 = 7;
+
+/// How long an autosave is kept after its last write, from
+/// `autosave_ttl_days`.
+fn ttl_millis() -> u64 {
+    std::env::var("autosave_ttl_days")
+        .ok()
+        .and_then(|d| d.parse().ok())
+        .filter(|d| *d > 0)
+        .unwrap_or(DEFAULT_TTL_DAYS)
+        * 24
+        * 60
+        * 60
+        * 1000
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AutosavePayload {
+    pub body: String,
+    #[serde(default)]
+    pub title: Option<String>,
+    /// Increases with every save from the editor (a timestamp will do), so a
+    /// save that arrives late cannot replace a newer one.
+    #[serde(default)]
+    pub seq: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Autosave {
+    pub body: String,
+    pub title: Option<String>,
+    pub seq: Option<u64>,
+    pub saved_at: u64,
+    pub expires_at: u64,
+    /// Whether the autosave was written after the post was last saved, i.e.
+    /// holds work the post doesn't.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub newer_than_post: Option<bool>,
+}
+
+impl Autosave {
+    fn from_record(record: &HashMap<String, AttributeValue>) -> Option<Autosave> {
+        let text = |name: &str| match record.get(name) {
+            Some(AttributeValue::S(s)) => Some(s.clone()),
+            _ => None,
+        };
+        let number = |name: &str| match record.get(name) {
+            Some(AttributeValue::N(n)) => n.parse().ok(),
+            _ => None,
+        };
+        Some(Autosave {
+            body: text("body")?,
+            title: text("title"),
+            seq: number("seq"),
+            saved_at: number("saved_at")?,
+            expires_at: number("expires_at")?,
+            newer_than_post: None,
+        })
+    }
+}
+
+/// Writes the autosave of post `idx` in one unconditional put, or one
+/// conditioned on `seq` when the editor sends it. `Err` carries the newer
+/// autosave that was kept instead.
+pub async fn save(
+    part: String,
+    idx: String,
+    payload: AutosavePayload,
+) -> Result<Result<Autosave, Autosave>, Box<dyn std::error::Error + Send + Sync>> {
+    let client = dynamodb_client().await;
+    let schema = schema();
+
+    let saved_at = now_millis();
+    let expires_at = saved_at + ttl_millis();
+    let mut request = client
+        .put_item()
+        .table_name(TABLE_NAME)
+        .item(&schema.partition_key, AttributeValue::S(part))
+        .item(&schema.sort_key, AttributeValue::S(idx))
+        .item("body", AttributeValue::S(payload.body.clone()))
+        .item("saved_at", AttributeValue::N(saved_at.to_string()))
+        .item("expires_at", AttributeValue::N(expires_at.to_string()))
+        .item(
+            "ttl",
+            AttributeValue::N((expires_at / 1000 + 1).to_string()),
+        );
+    if let Some(title) = &payload.title {
+        request = request.item("title", AttributeValue::S(title.clone()));
+    }
+    if let Some(seq) = payload.seq {
+        request = request
+            .item("seq", AttributeValue::N(seq.to_string()))
+            .condition_expression("attribute_not_exists(#seq) OR #seq < :seq")
+            .expression_attribute_names("#seq", "seq")
+            .expression_attribute_values(":seq", AttributeValue::N(seq.to_string()))
+            .return_values_on_condition_check_failure(ReturnValuesOnConditionCheckFailure::AllOld);
+    }
+
+    match request.send().await {
+        Ok(_) => Ok(Ok(Autosave {
+            body: payload.body,
+            title: payload.title,
+            seq: payload.seq,
+            saved_at,
+            expires_at,
+            newer_than_post: None,
+        })),
+        Err(e) => match e.as_service_error() {
+            Some(PutItemError::ConditionalCheckFailedException(failed)) => {
+                let kept = failed.item().and_then(Autosave::from_record);
+                Ok(Err(kept.ok_or("newer autosave missing")?))
+            }
+            _ => Err(e.into()),
+        },
+    }
+}
+
+/// The autosave of post `idx` in `posts_part`, if one is left, and whether
+/// it is newer than the post's last save. A post that was never saved counts
+/// as older.
+pub async fn load(
+    part: String,
+    posts_part: String,
+    idx: String,
+) -> Result<Option<Autosave>, Box<dyn std::error::Error + Send + Sync>> {
+    let (autosave, post) = tokio::join!(get_record(part, idx.clone()), get_record(posts_part, idx));
+    // expired autosaves linger until DynamoDB TTL removes them
+    let Some(mut autosave) = autosave?
+        .as_ref()
+        .and_then(Autosave::from_record)
+        .filter(|a| a.expires_at >= now_millis())
+    else {
+        return Ok(None);
+    };
+    let updated_at = post?.and_then(|post| match post.get(UPDATED_AT_ATTRIBUTE) {
+        Some(AttributeValue::N(n)) => n.parse::<u64>().ok(),
+        _ => None,
+    });
+    autosave.newer_than_post = Some(updated_at.is_none_or(|at| autosave.saved_at > at));
+    Ok(Some(autosave))
+}
@@ -0,0 +1,127 @@
+use crate::clock::now_millis;
+use crate::s3::{get_object, put_object, ObjectBody};
+use std::sync::OnceLock;
+use std::time::Duration;
+use url::Url;
+
+pub const DEFAULT_SIZE: u32 = 80;
+
+/// Sizes avatars are served in; a requested size is rounded up to the next
+/// one (or down to the largest), so each hash is cached a few times at most.
+const SIZES: [u32; 5] = [40, 80, 160, 320, 640];
+
+const DEFAULT_CACHE_DAYS: u64 = 30;
+
+/// How long a cached Gravatar image is served before it is fetched again,
+/// from `avatar_cache_days`.
+fn cache_millis() -> u64 {
+    let days = std::env::var("avatar_cache_days")
+        .ok()
+        .and_then(|d| d.parse().ok())
+        .unwrap_or(DEFAULT_CACHE_DAYS);
+    days * 24 * 60 * 60 * 1000
+}
+
+/// The served size for a requested one.
+pub fn snap_size(requested: u32) -> u32 {
+    SIZES
+        .iter()
+        .copied()
+        .find(|size| *size >= requested)
+        .unwrap_or(SIZES[SIZES.len() - 1])
+}
+
+/// Gravatar's fallback when a hash has no avatar, e.g. `identicon`, `retro`
+/// or `mp`. `404` makes the route answer 404 instead.
+fn default_image() -> String {
+    std::env::var("avatar_default")
+        .ok()
+        .filter(|d| !d.is_empty())
+        .unwrap_or_else(|| "identicon".to_string())
+}
+
+fn client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        reqwest::Client::builder()
+            .timeout(Duration::from_secs(5))
+            .build()
+            .unwrap_or_default()
+    })
+}
+
+/// Gravatar accepts MD5 or SHA-256 hashes of the trimmed, lowercased email.
+pub fn is_email_hash(value: &str) -> bool {
+    (value.len() == 32 || value.len() == 64)
+        && value.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+/// Gravatar's image for `hash` at `size`; `None` when it has none (only with
+/// `avatar_default=404`).
+async fn fetch(
+    hash: &str,
+    size: u32,
+) -> Result<Option<ObjectBody>, Box<dyn std::error::Error + Send + Sync>> {
+    let url = Url::parse_with_params(
+        &format!("https://gravatar.com/avatar/{hash}"),
+        [("s", size.to_string()), ("d", default_image())],
+    )?;
+    let response = client().get(url).send().await?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    let response = response.error_for_status()?;
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|c| c.to_str().ok())
+        .filter(|c| c.starts_with("image/"))
+        .unwrap_or("image/png")
+        .to_string();
+    let bytes = response.bytes().await?.to_vec();
+
+    Ok(Some(ObjectBody {
+        bytes,
+        content_type: Some(content_type),
+        last_modified: now_millis() as i64,
+    }))
+}
+
+/// The avatar for `hash`: an image uploaded to `{root}avatars/{hash}` if
+/// there is one, else Gravatar's, fetched per size (see `snap_size`) and kept
+/// under `{root}avatars/gravatar/` for `avatar_cache_days`. A stale copy is
+/// still served when Gravatar cannot be reached. `None` when Gravatar has
+/// none either (only with `avatar_default=404`).
+pub async fn avatar(
+    bucket: &str,
+    root_path: &str,
+    hash: &str,
+    size: u32,
+) -> Result<Option<ObjectBody>, Box<dyn std::error::Error + Send + Sync>> {
+    if let Some(uploaded) = get_object(bucket, format!("{root_path}avatars/{hash}")).await? {
+        return Ok(Some(uploaded));
+    }
+    let cache_key = format!("{root_path}avatars/gravatar/{hash}-{size}");
+    let cached = get_object(bucket, cache_key.clone()).await?;
+    let fresh = |image: &ObjectBody| {
+        let age = now_millis().saturating_sub(image.last_modified.max(0) as u64);
+        age < cache_millis()
+    };
+    if cached.as_ref().is_some_and(fresh) {
+        return Ok(cached);
+    }
+
+    match fetch(hash, size).await {
+        Ok(Some(image)) => {
+            let content_type = image.content_type.as_deref().unwrap_or("image/png");
+            put_object(bucket, cache_key, image.bytes.clone(), content_type).await?;
+            Ok(Some(image))
+        }
+        Ok(None) => Ok(None),
+        Err(e) if cached.is_some() => {
+            tracing::warn!("gravatar fetch failed, serving a stale avatar: {:?}", e);
+            Ok(cached)
+        }
+        Err(e) => Err(e),
+    }
+}
@@ -0,0 +1,144 @@
+use crate::clock::{now_millis, utc_date};
+use crate::dynamodb::{get_record, put_record, record_to_json, scan_table, schema};
+use crate::s3::{copy_object_to, list_all_objects, put_object_to};
+use aws_sdk_dynamodb::types::AttributeValue;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+/// Bookkeeping of the backups; `last` holds the last successful run.
+pub const BACKUPS_PARTITION: &str = "backups";
+const LAST_RUN_IDX: &str = "last";
+
+/// Where backups go: `backup_bucket` in `backup_region`.
+pub struct BackupTarget {
+    pub bucket: String,
+    pub region: String,
+}
+
+impl BackupTarget {
+    pub fn from_env() -> Option<BackupTarget> {
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+        Some(BackupTarget {
+            bucket: var("backup_bucket")?,
+            region: var("backup_region")?,
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupReport {
+    pub export_key: String,
+    pub items: usize,
+    pub objects_copied: usize,
+    /// Objects left out because they predate the previous run.
+    pub objects_skipped: usize,
+}
+
+/// An attribute in DynamoDB JSON (`{"S": "..."}`, `{"N": "1"}`, ...), the
+/// format `aws dynamodb put-item` and the console's import read back.
+fn typed_json(value: &AttributeValue) -> Value {
+    match value {
+        AttributeValue::S(s) => json!({ "S": s }),
+        AttributeValue::N(n) => json!({ "N": n }),
+        AttributeValue::Bool(b) => json!({ "BOOL": b }),
+        AttributeValue::Null(_) => json!({ "NULL": true }),
+        AttributeValue::B(b) => json!({ "B": BASE64.encode(b.as_ref()) }),
+        AttributeValue::Ss(items) => json!({ "SS": items }),
+        AttributeValue::Ns(items) => json!({ "NS": items }),
+        AttributeValue::Bs(items) => {
+            let items: Vec<String> = items.iter().map(|b| BASE64.encode(b.as_ref())).collect();
+            json!({ "BS": items })
+        }
+        AttributeValue::L(items) => {
+            let items: Vec<Value> = items.iter().map(typed_json).collect();
+            json!({ "L": items })
+        }
+        AttributeValue::M(map) => {
+            let map: serde_json::Map<String, Value> =
+                map.iter().map(|(k, v)| (k.clone(), typed_json(v))).collect();
+            json!({ "M": map })
+        }
+        _ => Value::Null,
+    }
+}
+
+/// Copies the blog's data to the backup bucket: the whole table as one
+/// NDJSON export under `dynamodb/`, and every object of `bucket` added or
+/// changed since the previous successful run under `s3/`. The run is
+/// recorded only when both parts succeed, so a failed run is caught up by
+/// the next one.
+pub async fn run(
+    bucket: &str,
+    target: &BackupTarget,
+) -> Result<BackupReport, Box<dyn std::error::Error + Send + Sync>> {
+    let started_at = now_millis();
+    let since = match get_record(BACKUPS_PARTITION.to_string(), LAST_RUN_IDX.to_string()).await? {
+        Some(last) => match last.get("started_at") {
+            Some(AttributeValue::N(n)) => n.parse::<i64>().unwrap_or_default(),
+            _ => 0,
+        },
+        None => 0,
+    };
+
+    let items = scan_table().await?;
+    let mut export = String::new();
+    for item in &items {
+        let line: serde_json::Map<String, Value> =
+            item.iter().map(|(k, v)| (k.clone(), typed_json(v))).collect();
+        export.push_str(&Value::Object(line).to_string());
+        export.push('\n');
+    }
+    let export_key = format!("dynamodb/{}/{started_at}.ndjson", utc_date(started_at));
+    put_object_to(
+        &target.bucket,
+        &target.region,
+        export_key.clone(),
+        export.into_bytes(),
+        "application/x-ndjson",
+    )
+    .await?;
+
+    let mut report = BackupReport {
+        export_key,
+        items: items.len(),
+        objects_copied: 0,
+        objects_skipped: 0,
+    };
+    for object in list_all_objects(bucket, "").await? {
+        if object.last_modified < since {
+            report.objects_skipped += 1;
+            continue;
+        }
+        copy_object_to(bucket, &object.key, &target.bucket, &target.region, "s3/").await?;
+        report.objects_copied += 1;
+    }
+
+    let attributes = HashMap::from([
+        ("started_at".to_string(), AttributeValue::N(started_at.to_string())),
+        ("finished_at".to_string(), AttributeValue::N(now_millis().to_string())),
+        ("export_key".to_string(), AttributeValue::S(report.export_key.clone())),
+        ("items".to_string(), AttributeValue::N(report.items.to_string())),
+        ("objects_copied".to_string(), AttributeValue::N(report.objects_copied.to_string())),
+    ]);
+    put_record(BACKUPS_PARTITION.to_string(), LAST_RUN_IDX.to_string(), attributes).await?;
+
+    Ok(report)
+}
+
+/// The last successful run, `None` before the first one.
+pub async fn last_run() -> Result<Option<Value>, Box<dyn std::error::Error + Send + Sync>> {
+    let record = get_record(BACKUPS_PARTITION.to_string(), LAST_RUN_IDX.to_string()).await?;
+    Ok(record.map(|record| {
+        let mut run = record_to_json(&record);
+        if let Some(fields) = run.as_object_mut() {
+            let schema = schema();
+            fields.remove(&schema.partition_key);
+            fields.remove(&schema.sort_key);
+        }
+        run
+    }))
+}
@@ -0,0 +1,236 @@
+use crate::backup::BackupTarget;
+use crate::ctx::Config;
+use crate::digest;
+use crate::gc;
+use crate::http_handler::function_handler;
+use crate::jobs::{self, JobKind};
+use crate::posts;
+use crate::publish;
+use crate::snapshots;
+use crate::stage::Stage;
+use crate::tags;
+use crate::transition;
+use lambda_http::request::LambdaRequest;
+use lambda_http::{service_fn, Adapter, Error, LambdaEvent, Service};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::Instrument;
+
+/// A direct invocation that is not an HTTP event: `{"cmd": "...", "args":
+/// {...}}`. API Gateway and function URL events never have a top-level
+/// `cmd`, and only principals allowed `lambda:InvokeFunction` can send one.
+#[derive(Debug, Deserialize)]
+struct Command {
+    cmd: String,
+    #[serde(default)]
+    args: Value,
+}
+
+impl Command {
+    fn from_payload(payload: &Value) -> Option<Command> {
+        payload.get("cmd")?.as_str()?;
+        serde_json::from_value(payload.clone()).ok()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NoArgs {}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct StageArgs {
+    #[serde(default)]
+    stage: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct GcArgs {
+    #[serde(default)]
+    stage: Option<String>,
+    #[serde(default)]
+    grace_days: Option<u64>,
+    #[serde(default)]
+    dry_run: Option<bool>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct DigestArgs {
+    #[serde(default)]
+    stage: Option<String>,
+    #[serde(default)]
+    force: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct PublishArgs {
+    #[serde(default)]
+    stage: Option<String>,
+    idx: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct TransitionArgs {
+    #[serde(default)]
+    stage: Option<String>,
+    prefix: String,
+    storage_class: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct RetagArgs {
+    #[serde(default)]
+    stage: Option<String>,
+    from: Vec<String>,
+    into: String,
+}
+
+fn args<T: DeserializeOwned>(command: &Command) -> Result<T, String> {
+    let args = match &command.args {
+        Value::Null => json!({}),
+        args => args.clone(),
+    };
+    serde_json::from_value(args).map_err(|e| format!("invalid args for {}: {e}", command.cmd))
+}
+
+async fn stage(name: Option<&str>) -> Result<Stage, Box<dyn std::error::Error + Send + Sync>> {
+    Stage::from_name(name.unwrap_or_default()).await
+}
+
+/// The bucket, for commands that need one.
+fn bucket(config: &Config) -> Result<String, String> {
+    match config.bucket.is_empty() {
+        true => Err("s3_bucket is not set".to_string()),
+        false => Ok(config.bucket.clone()),
+    }
+}
+
+/// Runs `command` with the same services the admin routes use and returns
+/// its outcome. Commands backed by a job run as one and wait for it, so
+/// they take the job's lock and can be looked up under `/jobs/{id}`.
+async fn dispatch(
+    command: &Command,
+    correlation_id: &str,
+) -> Result<Value, Box<dyn std::error::Error + Send + Sync>> {
+    let config = Config::from_env();
+    let kind = match command.cmd.as_str() {
+        "gc" => {
+            let args: GcArgs = args(command)?;
+            let stage = stage(args.stage.as_deref()).await?;
+            JobKind::Gc {
+                bucket: bucket(&config)?,
+                base_path: stage.s3_base(&config.root_path),
+                stage,
+                grace_days: args.grace_days.unwrap_or(gc::DEFAULT_GRACE_DAYS),
+                dry_run: args.dry_run.unwrap_or(true),
+            }
+        }
+        "backup" => {
+            args::<NoArgs>(command)?;
+            BackupTarget::from_env().ok_or("backup is not configured")?;
+            JobKind::Backup {
+                bucket: bucket(&config)?,
+            }
+        }
+        "links.check" => {
+            args::<NoArgs>(command)?;
+            JobKind::LinkCheck
+        }
+        "outbox.sweep" => {
+            args::<NoArgs>(command)?;
+            JobKind::OutboxSweep
+        }
+        "static.export" => {
+            let args: StageArgs = args(command)?;
+            let stage = stage(args.stage.as_deref()).await?;
+            JobKind::StaticExport {
+                bucket: bucket(&config)?,
+                base_path: stage.s3_base(&config.root_path),
+                stage,
+                site_url: config.site_url.clone(),
+                cdn_url: config.cdn_url.clone(),
+            }
+        }
+        "s3.transition" => {
+            let args: TransitionArgs = args(command)?;
+            let prefix = args.prefix.trim_start_matches('/');
+            if prefix.is_empty() || prefix.split('/').any(|segment| segment == "..") {
+                return Err("prefix must be a path under the stage".into());
+            }
+            let storage_class = transition::target_class(&args.storage_class)?;
+            let base_path = stage(args.stage.as_deref()).await?.s3_base(&config.root_path);
+            JobKind::Transition {
+                bucket: bucket(&config)?,
+                prefix: format!("{base_path}{prefix}"),
+                storage_class: storage_class.as_str().to_string(),
+            }
+        }
+        "tags.rename" | "tags.merge" => {
+            let args: RetagArgs = args(command)?;
+            let into = args.into.trim().to_string();
+            let from_empty = args.from.is_empty() || args.from.iter().any(|t| t.trim().is_empty());
+            if into.is_empty() || from_empty {
+                return Err("tags must not be empty".into());
+            }
+            JobKind::Retag {
+                part: stage(args.stage.as_deref()).await?.partition(&posts::posts_part()),
+                from: args.from,
+                into,
+                merge: command.cmd == "tags.merge",
+            }
+        }
+        "tags.recount" => {
+            let args: StageArgs = args(command)?;
+            let part = stage(args.stage.as_deref()).await?.partition(&posts::posts_part());
+            return Ok(json!({ "tags": tags::recount(part).await? }));
+        }
+        "digest.send" => {
+            let args: DigestArgs = args(command)?;
+            return digest::run(stage(args.stage.as_deref()).await?, args.force).await;
+        }
+        "snapshots.refresh" => {
+            let args: StageArgs = args(command)?;
+            let refreshed = snapshots::refresh(stage(args.stage.as_deref()).await?).await?;
+            return Ok(json!({ "refreshed": refreshed }));
+        }
+        cmd => {
+            let step = publish::Step::from_command(cmd).ok_or(format!("unknown command {cmd}"))?;
+            let args: PublishArgs = args(command)?;
+            return publish::run(step, stage(args.stage.as_deref()).await?, &args.idx).await;
+        }
+    };
+
+    let job = jobs::run_now(kind, correlation_id).await?;
+    if job["status"] == "failed" {
+        let error = job["error"].as_str().unwrap_or("job failed");
+        return Err(format!("{} failed: {error}", command.cmd).into());
+    }
+    Ok(job)
+}
+
+/// Entry point of every invocation: commands go to `dispatch`, anything
+/// else is taken for an HTTP event and served by `function_handler`.
+pub async fn invoke(event: LambdaEvent<Value>) -> Result<Value, Error> {
+    let (payload, context) = event.into_parts();
+    if let Some(command) = Command::from_payload(&payload) {
+        let span = tracing::info_span!("command", cmd = %command.cmd);
+        let dispatched = dispatch(&command, &context.request_id)
+            .instrument(span)
+            .await;
+        if let Err(e) = &dispatched {
+            tracing::error!("command {} failed: {:?}", command.cmd, e);
+        }
+        return dispatched;
+    }
+
+    let request: LambdaRequest = serde_json::from_value(payload)?;
+    let mut http = Adapter::from(service_fn(function_handler));
+    let response = http.call(LambdaEvent::new(request, context)).await?;
+    Ok(serde_json::to_value(response)?)
+}
@@ -0,0 +1,267 @@
+use crate::clock::now_millis;
+use crate::dynamodb::{
+    batch_put_items, delete_item, dynamodb_client, get_record, query_records, schema,
+    BumpsVersion, CREATED_AT_ATTRIBUTE, TABLE_NAME, VERSION_BUMP,
+};
+use crate::settings::SiteSettings;
+use aws_sdk_dynamodb::operation::update_item::UpdateItemError;
+use aws_sdk_dynamodb::types::AttributeValue;
+use serde::{Deserialize, Serialize};
+
+/// Comments kept by this API, which so far are only imported ones: item
+/// `idx = {post}#{comment id}`, so a post's comments are one range.
+pub const COMMENTS_PARTITION: &str = "comments";
+
+/// Per-post overrides of the site's comment settings, kept as attributes of
+/// the post item so saving the post leaves them alone.
+const ENABLED_ATTRIBUTE: &str = "comments_enabled";
+const CLOSE_AFTER_ATTRIBUTE: &str = "comments_close_after_days";
+
+/// Longest auto-close period, about ten years.
+pub const MAX_CLOSE_AFTER_DAYS: u32 = 3650;
+
+const DAY_MILLIS: u64 = 86_400_000;
+
+/// A post's own comment settings; `None` falls back to the site's.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct PostCommentSettings {
+    #[serde(default)]
+    pub enabled: Option<bool>,
+    #[serde(default)]
+    pub close_after_days: Option<u32>,
+}
+
+impl PostCommentSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        match self.close_after_days {
+            Some(days) if days == 0 || days > MAX_CLOSE_AFTER_DAYS => Err(format!(
+                "closeAfterDays must be 1 to {MAX_CLOSE_AFTER_DAYS}"
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Whether a post takes comments right now, and why.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommentStatus {
+    /// The post's own settings, as stored.
+    pub post: PostCommentSettings,
+    pub enabled: bool,
+    pub close_after_days: Option<u32>,
+    /// When comments close (epoch milliseconds), if they auto-close.
+    pub closes_at: Option<u64>,
+    pub open: bool,
+}
+
+impl CommentStatus {
+    /// Combines the post's settings with the site's: a post can turn comments
+    /// on or off and set its own auto-close period; whatever it leaves unset
+    /// comes from `site`. Auto-close counts from the post's `created_at`.
+    pub fn resolve(
+        post: PostCommentSettings,
+        site: &SiteSettings,
+        created_at: Option<u64>,
+        now: u64,
+    ) -> CommentStatus {
+        let enabled = post.enabled.unwrap_or(site.comments_enabled);
+        let close_after_days = post.close_after_days.or(site.comments_close_after_days);
+        let closes_at = match (close_after_days, created_at) {
+            (Some(days), Some(created_at)) => Some(created_at + u64::from(days) * DAY_MILLIS),
+            _ => None,
+        };
+        CommentStatus {
+            post,
+            enabled,
+            close_after_days,
+            closes_at,
+            open: enabled && closes_at.is_none_or(|closes_at| now < closes_at),
+        }
+    }
+}
+
+/// The comment status of post `idx` under `site`'s settings, `None` when
+/// there is no such post.
+pub async fn status(
+    part: String,
+    idx: String,
+    site: &SiteSettings,
+) -> Result<Option<CommentStatus>, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(record) = get_record(part, idx).await? else {
+        return Ok(None);
+    };
+    let number = |name: &str| match record.get(name) {
+        Some(AttributeValue::N(n)) => n.parse().ok(),
+        _ => None,
+    };
+    let post = PostCommentSettings {
+        enabled: match record.get(ENABLED_ATTRIBUTE) {
+            Some(AttributeValue::Bool(enabled)) => Some(*enabled),
+            _ => None,
+        },
+        close_after_days: number(CLOSE_AFTER_ATTRIBUTE).map(|days: u64| days as u32),
+    };
+    let (created_at, now) = (number(CREATED_AT_ATTRIBUTE), now_millis());
+    Ok(Some(CommentStatus::resolve(post, site, created_at, now)))
+}
+
+/// Stores the comment settings of post `idx`; unset fields are removed so
+/// the site's apply again. `false` when there is no such post.
+pub async fn save(
+    part: String,
+    idx: String,
+    settings: &PostCommentSettings,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let client = dynamodb_client().await;
+    let schema = schema();
+
+    let mut request = client
+        .update_item()
+        .table_name(TABLE_NAME)
+        .key(&schema.partition_key, AttributeValue::S(part))
+        .key(&schema.sort_key, AttributeValue::S(idx))
+        .condition_expression("attribute_exists(#pk)")
+        .expression_attribute_names("#pk", &schema.partition_key)
+        .expression_attribute_names("#enabled", ENABLED_ATTRIBUTE)
+        .expression_attribute_names("#close", CLOSE_AFTER_ATTRIBUTE)
+        .bump_version();
+
+    let mut set = vec![VERSION_BUMP];
+    let mut remove = Vec::new();
+    match settings.enabled {
+        Some(enabled) => {
+            set.push("#enabled = :enabled");
+            let enabled = AttributeValue::Bool(enabled);
+            request = request.expression_attribute_values(":enabled", enabled);
+        }
+        None => remove.push("#enabled"),
+    }
+    match settings.close_after_days {
+        Some(days) => {
+            set.push("#close = :close");
+            let days = AttributeValue::N(days.to_string());
+            request = request.expression_attribute_values(":close", days);
+        }
+        None => remove.push("#close"),
+    }
+    let mut update = vec![format!("SET {}", set.join(", "))];
+    if !remove.is_empty() {
+        update.push(format!("REMOVE {}", remove.join(", ")));
+    }
+
+    match request.update_expression(update.join(" ")).send().await {
+        Ok(_) => Ok(true),
+        Err(e) => match e.as_service_error() {
+            Some(UpdateItemError::ConditionalCheckFailedException(_)) => Ok(false),
+            _ => Err(e.into()),
+        },
+    }
+}
+
+/// Who wrote a comment, as the comment system that took it knew them.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommentAuthor {
+    pub name: String,
+    pub username: Option<String>,
+    pub email: Option<String>,
+    pub anonymous: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Comment {
+    pub id: String,
+    /// Idx of the post it was left on.
+    pub post: String,
+    /// Id of the comment it replies to, on the same post.
+    pub parent: Option<String>,
+    pub author: CommentAuthor,
+    /// HTML, as the comment system that took it kept it.
+    pub message: String,
+    /// Epoch milliseconds.
+    pub created_at: u64,
+    /// Where it was imported from, e.g. `disqus`.
+    pub source: Option<String>,
+}
+
+fn comment_idx(post: &str, id: &str) -> String {
+    format!("{post}#{id}")
+}
+
+/// Writes `comments` into `part`, the stage's `COMMENTS_PARTITION`. A
+/// comment already there under the same post and id is replaced.
+pub async fn store(
+    part: &str,
+    comments: &[Comment],
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    for chunk in comments.chunks(25) {
+        let mut items = Vec::with_capacity(chunk.len());
+        for comment in chunk {
+            let idx = comment_idx(&comment.post, &comment.id);
+            items.push((part.to_string(), idx, serde_json::to_string(comment)?));
+        }
+        batch_put_items(items).await?;
+    }
+    Ok(())
+}
+
+/// The comments of `part` on post `post`, or on every post, oldest first
+/// within each post.
+pub async fn list(
+    part: String,
+    post: Option<&str>,
+) -> Result<Vec<Comment>, Box<dyn std::error::Error + Send + Sync>> {
+    // the post's comments all sort between `{post}#` and `{post}#\u{10FFFF}`
+    let range = post.map(|post| (comment_idx(post, ""), comment_idx(post, "\u{10FFFF}")));
+    let records = query_records(part, range, Vec::new(), usize::MAX, false).await?;
+
+    let value_attribute = &schema().value_attribute;
+    let mut comments: Vec<Comment> = records
+        .iter()
+        .filter_map(|record| match record.get(value_attribute) {
+            Some(AttributeValue::S(value)) => serde_json::from_str(value).ok(),
+            _ => None,
+        })
+        .collect();
+    comments.sort_by(|a, b| {
+        a.post
+            .cmp(&b.post)
+            .then_with(|| a.created_at.cmp(&b.created_at))
+            .then_with(|| a.id.cmp(&b.id))
+    });
+    Ok(comments)
+}
+
+/// The comments of `part` whose author gave `email`, compared without case.
+pub async fn by_author_email(
+    part: String,
+    email: &str,
+) -> Result<Vec<Comment>, Box<dyn std::error::Error + Send + Sync>> {
+    let comments = list(part, None).await?;
+    Ok(comments
+        .into_iter()
+        .filter(|comment| {
+            comment
+                .author
+                .email
+                .as_deref()
+                .is_some_and(|e| e.trim().eq_ignore_ascii_case(email))
+        })
+        .collect())
+}
+
+/// Deletes `comments` from `part`. Replies to them keep their `parent`,
+/// which no longer resolves.
+pub async fn remove(
+    part: &str,
+    comments: &[Comment],
+) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    for comment in comments {
+        let idx = comment_idx(&comment.post, &comment.id);
+        delete_item(part.to_string(), idx).await?;
+    }
+    Ok(comments.len())
+}
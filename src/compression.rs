@@ -0,0 +1,72 @@
+use crate::dynamodb::schema;
+use crate::overflow;
+use aws_sdk_dynamodb::primitives::Blob;
+use aws_sdk_dynamodb::types::AttributeValue;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
+/// Set on items whose value attribute holds the gzip of the value, as binary.
+pub const COMPRESSED_ATTRIBUTE: &str = "compressed";
+
+/// Shorter values gain too little to be worth the CPU.
+const MIN_BYTES: usize = 1024;
+
+/// Whether post values are compressed on save, from `compress_posts`.
+fn enabled() -> bool {
+    std::env::var("compress_posts").is_ok_and(|v| v == "true")
+}
+
+/// Gzip of `value`. The header carries no timestamp, so the same value and
+/// library version always give the same bytes.
+fn gzip(value: &str) -> Result<Vec<u8>, std::io::Error> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(value.as_bytes())?;
+    encoder.finish()
+}
+
+/// The gzip of post `value` to store instead of it: with `compress_posts`
+/// on, for values of at least 1 KiB that it makes smaller.
+pub fn compress(value: &str) -> Result<Option<Vec<u8>>, std::io::Error> {
+    if !enabled() || value.len() < MIN_BYTES {
+        return Ok(None);
+    }
+    let compressed = gzip(value)?;
+    Ok(Some(compressed).filter(|c| c.len() < value.len()))
+}
+
+/// What to store for post `value` and, if it was spilled, its S3 key (see
+/// `overflow::spill`). A value still too large inline once compressed is
+/// spilled uncompressed.
+pub async fn store(
+    part: &str,
+    idx: &str,
+    value: String,
+) -> Result<(AttributeValue, Option<String>), Box<dyn std::error::Error + Send + Sync>> {
+    if let Some(compressed) = compress(&value)?.filter(|c| c.len() <= overflow::threshold()) {
+        return Ok((AttributeValue::B(Blob::new(compressed)), None));
+    }
+    let (value, value_ref) = overflow::spill(part, idx, value).await?;
+    Ok((AttributeValue::S(value), value_ref))
+}
+
+/// Puts a compressed value back on a record read from the table as text, so
+/// callers see the item as if it had been stored plain.
+pub fn decompress(
+    record: &mut HashMap<String, AttributeValue>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if !matches!(record.get(COMPRESSED_ATTRIBUTE), Some(AttributeValue::Bool(true))) {
+        return Ok(());
+    }
+    let value_attribute = &schema().value_attribute;
+    let Some(AttributeValue::B(compressed)) = record.get(value_attribute) else {
+        return Ok(());
+    };
+
+    let mut value = String::new();
+    GzDecoder::new(compressed.as_ref()).read_to_string(&mut value)?;
+    record.insert(value_attribute.clone(), AttributeValue::S(value));
+    Ok(())
+}
@@ -0,0 +1,65 @@
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+const DEFAULT_LIMIT: usize = 1;
+const DEFAULT_QUEUE_MS: u64 = 2000;
+
+/// Routes that hold a lot of memory or fan out widely while they run.
+const GUARDED_ROUTES: [(&str, &str); 4] = [
+    ("POST", "/dynamodb/import"),
+    ("POST", "/stage/promote"),
+    ("POST", "/api/s3/upload-urls"),
+    ("GET", "/admin/summary"),
+];
+
+/// One semaphore per guarded route, shared by every request this container
+/// serves. Sized by `route_concurrency`.
+fn semaphores() -> &'static Vec<Arc<Semaphore>> {
+    static SEMAPHORES: OnceLock<Vec<Arc<Semaphore>>> = OnceLock::new();
+    SEMAPHORES.get_or_init(|| {
+        let limit = std::env::var("route_concurrency")
+            .ok()
+            .and_then(|l| l.parse().ok())
+            .filter(|l| *l > 0)
+            .unwrap_or(DEFAULT_LIMIT);
+        GUARDED_ROUTES
+            .iter()
+            .map(|_| Arc::new(Semaphore::new(limit)))
+            .collect()
+    })
+}
+
+fn queue_timeout() -> Duration {
+    let ms = std::env::var("route_queue_ms")
+        .ok()
+        .and_then(|ms| ms.parse().ok())
+        .unwrap_or(DEFAULT_QUEUE_MS);
+    Duration::from_millis(ms)
+}
+
+/// Returned when a guarded route stayed saturated for the whole queue wait.
+pub struct Busy;
+
+/// Waits up to `route_queue_ms` for a slot on a guarded route, and never past
+/// `remaining` (the time left before the invocation's deadline). The permit
+/// is released when dropped; unguarded routes get `None` straight away.
+pub async fn acquire(
+    method: &str,
+    path: &str,
+    remaining: Option<Duration>,
+) -> Result<Option<OwnedSemaphorePermit>, Busy> {
+    let Some(slot) = GUARDED_ROUTES
+        .iter()
+        .position(|(m, p)| *m == method && *p == path)
+    else {
+        return Ok(None);
+    };
+
+    let semaphore = semaphores()[slot].clone();
+    let wait = remaining.map_or(queue_timeout(), |r| r.min(queue_timeout()));
+    match tokio::time::timeout(wait, semaphore.acquire_owned()).await {
+        Ok(Ok(permit)) => Ok(Some(permit)),
+        _ => Err(Busy),
+    }
+}
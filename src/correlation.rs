@@ -0,0 +1,85 @@
+use aws_sdk_dynamodb::config::interceptors::BeforeTransmitInterceptorContextMut;
+use aws_sdk_dynamodb::config::{ConfigBag, Intercept, RuntimeComponents};
+use lambda_http::{Request, RequestExt};
+use std::future::Future;
+
+pub const CORRELATION_HEADER: &str = "x-correlation-id";
+
+tokio::task_local! {
+    /// Correlation id of the request being served, read by `Propagate`,
+    /// which runs on the request's task.
+    static CURRENT: String;
+}
+
+/// Runs `future` with its SDK calls sending `id`.
+pub async fn scope<F: Future>(id: String, future: F) -> F::Output {
+    CURRENT.scope(id, future).await
+}
+
+/// Sends the request's correlation id as `X-Correlation-Id` on every call of
+/// the DynamoDB and S3 clients, so captured SDK traffic ties back to the
+/// request. Added after signing, so it never becomes part of a signature.
+#[derive(Debug)]
+pub struct Propagate;
+
+impl Intercept for Propagate {
+    fn name(&self) -> &'static str {
+        "Propagate"
+    }
+
+    fn modify_before_transmit(
+        &self,
+        context: &mut BeforeTransmitInterceptorContextMut<'_>,
+        _runtime_components: &RuntimeComponents,
+        _cfg: &mut ConfigBag,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let request = context.request_mut();
+        // presigned URLs are handed to the client, which sends its own headers
+        if request.uri().contains("X-Amz-Signature=") {
+            return Ok(());
+        }
+        // calls outside `scope`, such as cold-start work, send nothing
+        if let Ok(id) = CURRENT.try_with(String::clone) {
+            if !id.is_empty() {
+                request.headers_mut().insert(CORRELATION_HEADER, id);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_safe_id(value: &str) -> bool {
+    !value.is_empty()
+        && value.len() <= 128
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'))
+}
+
+/// Trace id of a W3C `traceparent` (`00-<32 hex>-<16 hex>-<2 hex>`).
+fn traceparent_trace_id(value: &str) -> Option<&str> {
+    let mut parts = value.split('-');
+    let (_version, trace_id, parent_id, _flags) =
+        (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+    let valid = trace_id.len() == 32
+        && parent_id.len() == 16
+        && trace_id.chars().all(|c| c.is_ascii_hexdigit())
+        && trace_id.chars().any(|c| c != '0');
+    valid.then_some(trace_id)
+}
+
+/// Id tying this request to the SPA's logs: the client's `X-Correlation-Id`,
+/// else the trace id of its `traceparent`, else the Lambda request id.
+pub fn correlation_id(req: &Request) -> String {
+    let header = |name: &str| req.headers().get(name).and_then(|h| h.to_str().ok());
+
+    if let Some(id) = header(CORRELATION_HEADER).filter(|id| is_safe_id(id)) {
+        return id.to_string();
+    }
+    if let Some(trace_id) = header("traceparent").and_then(traceparent_trace_id) {
+        return trace_id.to_string();
+    }
+    req.lambda_context_ref()
+        .map(|c| c.request_id.clone())
+        .unwrap_or_default()
+}
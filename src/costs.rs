@@ -0,0 +1,295 @@
+use crate::clock::{now_millis, utc_date};
+use crate::dynamodb::{dynamodb_client, query_records, schema, TABLE_NAME};
+use aws_sdk_dynamodb::config::interceptors::{
+    AfterDeserializationInterceptorContextRef, BeforeSerializationInterceptorContextMut,
+    BeforeTransmitInterceptorContextRef,
+};
+use aws_sdk_dynamodb::config::{ConfigBag, Intercept, RuntimeComponents};
+use aws_sdk_dynamodb::operation::{
+    batch_get_item, batch_write_item, delete_item, get_item, put_item, query, scan,
+    transact_get_items, transact_write_items, update_item,
+};
+use aws_sdk_dynamodb::types::{AttributeValue, ConsumedCapacity, ReturnConsumedCapacity};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+/// Daily cost aggregates live in one partition per UTC day, `costs#YYYY-MM-DD`,
+/// with one item per route.
+pub const COSTS_PARTITION_PREFIX: &str = "costs#";
+
+/// On-demand list prices in us-east-1, in USD: DynamoDB per million read and
+/// write request units, S3 Standard per thousand requests of each tier.
+const USD_PER_MILLION_READ_UNITS: f64 = 0.125;
+const USD_PER_MILLION_WRITE_UNITS: f64 = 0.625;
+const USD_PER_THOUSAND_S3_TIER1: f64 = 0.005;
+const USD_PER_THOUSAND_S3_TIER2: f64 = 0.0004;
+
+/// What one request, or one route over a day, used of the billed services.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Usage {
+    /// DynamoDB capacity units consumed by reads (gets, queries, scans).
+    pub read_units: f64,
+    /// DynamoDB capacity units consumed by writes, transactions included.
+    pub write_units: f64,
+    /// S3 requests billed as PUT, COPY, POST or LIST.
+    pub s3_tier1: u64,
+    /// S3 requests billed as GET and the like. Deletes are free and not
+    /// counted; neither are presigned URLs, which the client uses.
+    pub s3_tier2: u64,
+}
+
+impl Usage {
+    /// What the usage costs at on-demand list prices.
+    pub fn estimated_usd(&self) -> f64 {
+        self.read_units / 1e6 * USD_PER_MILLION_READ_UNITS
+            + self.write_units / 1e6 * USD_PER_MILLION_WRITE_UNITS
+            + self.s3_tier1 as f64 / 1e3 * USD_PER_THOUSAND_S3_TIER1
+            + self.s3_tier2 as f64 / 1e3 * USD_PER_THOUSAND_S3_TIER2
+    }
+}
+
+tokio::task_local! {
+    /// Usage of the request being served, shared with the SDK interceptors,
+    /// which run on the request's task.
+    static CURRENT: Arc<Mutex<Usage>>;
+}
+
+fn add(f: impl FnOnce(&mut Usage)) {
+    // calls outside `track`, such as cold-start work, are not attributed
+    let _ = CURRENT.try_with(|usage| f(&mut usage.lock().unwrap_or_else(|e| e.into_inner())));
+}
+
+/// Runs `future` with its AWS usage added up in `usage`.
+pub async fn track<F: Future>(usage: Arc<Mutex<Usage>>, future: F) -> F::Output {
+    CURRENT.scope(usage, future).await
+}
+
+fn units<'a>(capacity: impl IntoIterator<Item = &'a ConsumedCapacity>) -> f64 {
+    capacity.into_iter().filter_map(|c| c.capacity_units).sum()
+}
+
+/// Asks every DynamoDB call for its consumed capacity (`TOTAL`) and adds it
+/// to the request's usage.
+#[derive(Debug)]
+pub struct CapacityMeter;
+
+impl Intercept for CapacityMeter {
+    fn name(&self) -> &'static str {
+        "CapacityMeter"
+    }
+
+    fn modify_before_serialization(
+        &self,
+        context: &mut BeforeSerializationInterceptorContextMut<'_>,
+        _runtime_components: &RuntimeComponents,
+        _cfg: &mut ConfigBag,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let input = context.input_mut();
+        macro_rules! request {
+            ($($op:ident::$ty:ident),*) => {
+                $(if let Some(input) = input.downcast_mut::<$op::$ty>() {
+                    let capacity = &mut input.return_consumed_capacity;
+                    capacity.get_or_insert(ReturnConsumedCapacity::Total);
+                    return Ok(());
+                })*
+            };
+        }
+        request!(
+            get_item::GetItemInput,
+            put_item::PutItemInput,
+            update_item::UpdateItemInput,
+            delete_item::DeleteItemInput,
+            query::QueryInput,
+            scan::ScanInput,
+            batch_get_item::BatchGetItemInput,
+            batch_write_item::BatchWriteItemInput,
+            transact_get_items::TransactGetItemsInput,
+            transact_write_items::TransactWriteItemsInput
+        );
+        Ok(())
+    }
+
+    fn read_after_deserialization(
+        &self,
+        context: &AfterDeserializationInterceptorContextRef<'_>,
+        _runtime_components: &RuntimeComponents,
+        _cfg: &mut ConfigBag,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let Ok(output) = context.output_or_error() else {
+            return Ok(());
+        };
+        let (read, write) = if let Some(o) = output.downcast_ref::<get_item::GetItemOutput>() {
+            (units(&o.consumed_capacity), 0.0)
+        } else if let Some(o) = output.downcast_ref::<query::QueryOutput>() {
+            (units(&o.consumed_capacity), 0.0)
+        } else if let Some(o) = output.downcast_ref::<scan::ScanOutput>() {
+            (units(&o.consumed_capacity), 0.0)
+        } else if let Some(o) = output.downcast_ref::<batch_get_item::BatchGetItemOutput>() {
+            (units(o.consumed_capacity.iter().flatten()), 0.0)
+        } else if let Some(o) = output.downcast_ref::<transact_get_items::TransactGetItemsOutput>()
+        {
+            (units(o.consumed_capacity.iter().flatten()), 0.0)
+        } else if let Some(o) = output.downcast_ref::<put_item::PutItemOutput>() {
+            (0.0, units(&o.consumed_capacity))
+        } else if let Some(o) = output.downcast_ref::<update_item::UpdateItemOutput>() {
+            (0.0, units(&o.consumed_capacity))
+        } else if let Some(o) = output.downcast_ref::<delete_item::DeleteItemOutput>() {
+            (0.0, units(&o.consumed_capacity))
+        } else if let Some(o) = output.downcast_ref::<batch_write_item::BatchWriteItemOutput>() {
+            (0.0, units(o.consumed_capacity.iter().flatten()))
+        } else if let Some(o) =
+            output.downcast_ref::<transact_write_items::TransactWriteItemsOutput>()
+        {
+            (0.0, units(o.consumed_capacity.iter().flatten()))
+        } else {
+            return Ok(());
+        };
+        add(|usage| {
+            usage.read_units += read;
+            usage.write_units += write;
+        });
+        Ok(())
+    }
+}
+
+/// Counts the S3 requests sent, retries included since each is billed, by
+/// pricing tier.
+#[derive(Debug)]
+pub struct S3RequestMeter;
+
+impl Intercept for S3RequestMeter {
+    fn name(&self) -> &'static str {
+        "S3RequestMeter"
+    }
+
+    fn read_before_transmit(
+        &self,
+        context: &BeforeTransmitInterceptorContextRef<'_>,
+        _runtime_components: &RuntimeComponents,
+        _cfg: &mut ConfigBag,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let request = context.request();
+        let uri = request.uri();
+        // presigning runs up to here without sending anything
+        if uri.contains("X-Amz-Signature=") {
+            return Ok(());
+        }
+        match request.method() {
+            "DELETE" => {}
+            // `DeleteObjects` is a POST, but free like other deletes
+            "POST" if uri.contains("?delete") => {}
+            "PUT" | "POST" => add(|usage| usage.s3_tier1 += 1),
+            "GET" if uri.contains("list-type=") => add(|usage| usage.s3_tier1 += 1),
+            _ => add(|usage| usage.s3_tier2 += 1),
+        }
+        Ok(())
+    }
+}
+
+/// Adds one request's usage to the daily aggregate of `route`.
+pub async fn record(
+    route: &str,
+    usage: &Usage,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let client = dynamodb_client().await;
+    let schema = schema();
+    let part = format!("{COSTS_PARTITION_PREFIX}{}", utc_date(now_millis()));
+    let number = |n: String| AttributeValue::N(n);
+
+    client
+        .update_item()
+        .table_name(TABLE_NAME)
+        .key(&schema.partition_key, AttributeValue::S(part))
+        .key(&schema.sort_key, AttributeValue::S(route.to_string()))
+        .update_expression(
+            "ADD requests :one, read_units :read, write_units :write, \
+             s3_tier1 :tier1, s3_tier2 :tier2",
+        )
+        .expression_attribute_values(":one", number("1".to_string()))
+        .expression_attribute_values(":read", number(usage.read_units.to_string()))
+        .expression_attribute_values(":write", number(usage.write_units.to_string()))
+        .expression_attribute_values(":tier1", number(usage.s3_tier1.to_string()))
+        .expression_attribute_values(":tier2", number(usage.s3_tier2.to_string()))
+        .send()
+        .await?;
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RouteCosts {
+    pub route: String,
+    pub requests: u64,
+    #[serde(flatten)]
+    pub usage: Usage,
+    pub estimated_usd: f64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CostReport {
+    pub day: String,
+    pub requests: u64,
+    #[serde(flatten)]
+    pub usage: Usage,
+    pub estimated_usd: f64,
+    /// Costliest first.
+    pub routes: Vec<RouteCosts>,
+}
+
+/// Usage per route for one day and the day's totals.
+pub async fn report(day: String) -> Result<CostReport, Box<dyn std::error::Error + Send + Sync>> {
+    let part = format!("{COSTS_PARTITION_PREFIX}{day}");
+    let records = query_records(part, None, Vec::new(), usize::MAX, false).await?;
+    let sort_key = &schema().sort_key;
+
+    let number = |record: &HashMap<String, AttributeValue>, name: &str| -> f64 {
+        match record.get(name) {
+            Some(AttributeValue::N(n)) => n.parse().unwrap_or(0.0),
+            _ => 0.0,
+        }
+    };
+    let mut routes: Vec<RouteCosts> = records
+        .iter()
+        .filter_map(|record| {
+            let Some(AttributeValue::S(route)) = record.get(sort_key) else {
+                return None;
+            };
+            let usage = Usage {
+                read_units: number(record, "read_units"),
+                write_units: number(record, "write_units"),
+                s3_tier1: number(record, "s3_tier1") as u64,
+                s3_tier2: number(record, "s3_tier2") as u64,
+            };
+            Some(RouteCosts {
+                route: route.clone(),
+                requests: number(record, "requests") as u64,
+                estimated_usd: usage.estimated_usd(),
+                usage,
+            })
+        })
+        .collect();
+    routes.sort_by(|a, b| {
+        b.estimated_usd
+            .total_cmp(&a.estimated_usd)
+            .then_with(|| a.route.cmp(&b.route))
+    });
+
+    let mut total = Usage::default();
+    for route in &routes {
+        total.read_units += route.usage.read_units;
+        total.write_units += route.usage.write_units;
+        total.s3_tier1 += route.usage.s3_tier1;
+        total.s3_tier2 += route.usage.s3_tier2;
+    }
+    Ok(CostReport {
+        day,
+        requests: routes.iter().map(|r| r.requests).sum(),
+        estimated_usd: total.estimated_usd(),
+        usage: total,
+        routes,
+    })
+}
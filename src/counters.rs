@@ -0,0 +1,155 @@
+use crate::dynamodb::{dynamodb_client, schema, TABLE_NAME};
+use aws_sdk_dynamodb::types::{
+    AttributeValue, KeysAndAttributes, ReturnValue, TransactWriteItem, Update,
+};
+use std::collections::HashMap;
+use ulid::Ulid;
+
+/// Shards of hot counters: shard `n` of counter `name` is the item
+/// `idx = name` in partition `counter_shards#{n}`, so the writes of one
+/// counter spread over as many partitions as it has shards.
+pub const SHARDS_PARTITION_PREFIX: &str = "counter_shards#";
+
+const COUNT_ATTRIBUTE: &str = "count";
+
+const DEFAULT_SHARDS: usize = 1;
+const MAX_SHARDS: usize = 16;
+
+/// Most keys one `BatchGetItem` takes.
+const BATCH_GET_LIMIT: usize = 100;
+
+/// Shards per counter, from `counter_shards` (default 1, at most 16). It can
+/// be raised at any time; lowering it hides the counts of the dropped shards
+/// until it is raised again.
+pub fn shard_count() -> usize {
+    std::env::var("counter_shards")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(DEFAULT_SHARDS)
+        .clamp(1, MAX_SHARDS)
+}
+
+fn shard_part(shard: usize) -> String {
+    format!("{SHARDS_PARTITION_PREFIX}{shard}")
+}
+
+/// Adds one to a random shard of counter `name`. Returns the shard and its
+/// new count, not the counter's total (see `totals`).
+pub async fn add(name: String) -> Result<(usize, i64), Box<dyn std::error::Error + Send + Sync>> {
+    let client = dynamodb_client().await;
+    let schema = schema();
+
+    // a ULID's random bits pick the shard
+    let shard = (Ulid::generate().random() % shard_count() as u128) as usize;
+    let output = client
+        .update_item()
+        .table_name(TABLE_NAME)
+        .key(&schema.partition_key, AttributeValue::S(shard_part(shard)))
+        .key(&schema.sort_key, AttributeValue::S(name))
+        .update_expression("ADD #count :one")
+        .expression_attribute_names("#count", COUNT_ATTRIBUTE)
+        .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
+        .return_values(ReturnValue::UpdatedNew)
+        .send()
+        .await?;
+
+    let attributes = output.attributes.unwrap_or_default();
+    let count = match attributes.get(COUNT_ATTRIBUTE) {
+        Some(AttributeValue::N(n)) => n.parse()?,
+        _ => 0,
+    };
+    Ok((shard, count))
+}
+
+/// Shard items at `keys` (at most 100) that exist, resubmitting whatever
+/// DynamoDB reports as unprocessed.
+async fn batch_get(
+    keys: Vec<HashMap<String, AttributeValue>>,
+) -> Result<Vec<HashMap<String, AttributeValue>>, Box<dyn std::error::Error + Send + Sync>> {
+    let client = dynamodb_client().await;
+    let schema = schema();
+
+    let request = KeysAndAttributes::builder()
+        .set_keys(Some(keys))
+        .projection_expression("#idx, #count")
+        .expression_attribute_names("#idx", &schema.sort_key)
+        .expression_attribute_names("#count", COUNT_ATTRIBUTE)
+        .build()?;
+
+    let mut items = Vec::new();
+    let mut pending = HashMap::from([(TABLE_NAME.to_string(), request)]);
+    for attempt in 0..5 {
+        let output = client
+            .batch_get_item()
+            .set_request_items(Some(pending))
+            .send()
+            .await?;
+
+        items.extend(output.responses.unwrap_or_default().into_values().flatten());
+        pending = output.unprocessed_keys.unwrap_or_default();
+        if pending.values().all(|k| k.keys.is_empty()) {
+            return Ok(items);
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50 << attempt)).await;
+    }
+
+    Err("batch get left unprocessed keys".into())
+}
+
+/// Totals of the counters `names`, each the sum of its shards. Counters that
+/// were never added to are `0`.
+pub async fn totals(
+    names: &[String],
+) -> Result<HashMap<String, i64>, Box<dyn std::error::Error + Send + Sync>> {
+    let schema = schema();
+
+    let keys: Vec<HashMap<String, AttributeValue>> = names
+        .iter()
+        .flat_map(|name| {
+            (0..shard_count()).map(move |shard| {
+                let part = AttributeValue::S(shard_part(shard));
+                HashMap::from([
+                    (schema.partition_key.clone(), part),
+                    (schema.sort_key.clone(), AttributeValue::S(name.clone())),
+                ])
+            })
+        })
+        .collect();
+
+    let mut totals: HashMap<String, i64> = names.iter().map(|n| (n.clone(), 0)).collect();
+    for chunk in keys.chunks(BATCH_GET_LIMIT) {
+        for item in batch_get(chunk.to_vec()).await? {
+            let (Some(AttributeValue::S(name)), Some(AttributeValue::N(count))) =
+                (item.get(&schema.sort_key), item.get(COUNT_ATTRIBUTE))
+            else {
+                continue;
+            };
+            if let Some(total) = totals.get_mut(name) {
+                *total += count.parse::<i64>()?;
+            }
+        }
+    }
+    Ok(totals)
+}
+
+/// Transaction entry taking `count` out of `shard` of counter `name`, to be
+/// moved elsewhere in the same transaction. Fails if the shard no longer
+/// holds that much, i.e. someone else moved it first.
+pub fn drain(
+    name: &str,
+    shard: usize,
+    count: i64,
+) -> Result<TransactWriteItem, Box<dyn std::error::Error + Send + Sync>> {
+    let schema = schema();
+    let update = Update::builder()
+        .table_name(TABLE_NAME)
+        .key(&schema.partition_key, AttributeValue::S(shard_part(shard)))
+        .key(&schema.sort_key, AttributeValue::S(name.to_string()))
+        .update_expression("ADD #count :taken")
+        .condition_expression("#count >= :count")
+        .expression_attribute_names("#count", COUNT_ATTRIBUTE)
+        .expression_attribute_values(":taken", AttributeValue::N((-count).to_string()))
+        .expression_attribute_values(":count", AttributeValue::N(count.to_string()))
+        .build()?;
+    Ok(TransactWriteItem::builder().update(update).build())
+}
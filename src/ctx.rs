@@ -0,0 +1,112 @@
+use crate::auth::is_admin;
+use crate::clock::now_millis;
+use crate::correlation::correlation_id;
+use crate::stage::Stage;
+use crate::urls::PublicUrls;
+use crate::view::View;
+use crate::{failover, images, s3};
+use lambda_http::{Request, RequestExt};
+use std::time::Duration;
+use tokio::sync::OnceCell;
+
+/// Deployment settings the routes read, looked up once per request.
+pub struct Config {
+    /// Empty when `s3_bucket` is not set; `route` refuses the S3 routes then.
+    pub bucket: String,
+    /// `s3_path`, the root every stage's keys live under.
+    pub root_path: String,
+    pub site_url: Option<String>,
+    pub api_url: Option<String>,
+    pub cdn_url: Option<String>,
+}
+
+impl Config {
+    pub fn from_env() -> Config {
+        let var = |name: &str| {
+            std::env::var(name)
+                .ok()
+                .map(|v| v.trim_end_matches('/').to_string())
+                .filter(|v| !v.is_empty())
+        };
+        Config {
+            bucket: failover::s3_bucket().unwrap_or_default(),
+            root_path: std::env::var("s3_path").unwrap_or_default(),
+            site_url: var("site_url"),
+            api_url: var("api_url"),
+            cdn_url: images::cdn_url(),
+        }
+    }
+}
+
+/// Who is making the request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Principal {
+    Anonymous,
+    Admin,
+}
+
+/// Everything a route needs besides the request itself, resolved once in
+/// `route` and passed down instead of each route reading the environment,
+/// checking the bearer token or building SDK clients on its own.
+pub struct Ctx {
+    pub config: Config,
+    pub principal: Principal,
+    /// Correlation id of the request, see `correlation::correlation_id`.
+    pub request_id: String,
+    /// When Lambda will stop the invocation, in epoch milliseconds.
+    pub deadline: Option<u64>,
+    pub stage: Stage,
+    /// S3 key prefix of `stage`, under `config.root_path`.
+    pub base_path: String,
+    /// Public base URLs, from the config or the request's own origin.
+    pub urls: Option<PublicUrls>,
+    s3: OnceCell<aws_sdk_s3::Client>,
+}
+
+impl Ctx {
+    /// Fails only when the live generation cannot be read.
+    pub async fn new(req: &Request) -> Result<Ctx, Box<dyn std::error::Error + Send + Sync>> {
+        let config = Config::from_env();
+        let stage = Stage::from_request(req).await?;
+        let base_path = stage.s3_base(&config.root_path);
+        let urls =
+            PublicUrls::from_request(req, config.site_url.as_ref(), config.api_url.as_ref());
+        Ok(Ctx {
+            principal: if is_admin(req) {
+                Principal::Admin
+            } else {
+                Principal::Anonymous
+            },
+            request_id: correlation_id(req),
+            deadline: req.lambda_context_ref().map(|c| c.deadline),
+            stage,
+            base_path,
+            urls,
+            config,
+            s3: OnceCell::new(),
+        })
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.principal == Principal::Admin
+    }
+
+    pub fn view(&self) -> View {
+        if self.is_admin() {
+            View::Admin
+        } else {
+            View::Public
+        }
+    }
+
+    /// Time left before the deadline; `None` outside Lambda.
+    pub fn remaining(&self) -> Option<Duration> {
+        let deadline = self.deadline?;
+        Some(Duration::from_millis(deadline.saturating_sub(now_millis())))
+    }
+
+    /// S3 client shared by every call the request makes, built on first use.
+    pub async fn s3(&self) -> &aws_sdk_s3::Client {
+        self.s3.get_or_init(s3::s3_client).await
+    }
+}
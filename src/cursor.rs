@@ -0,0 +1,44 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64URL;
+use base64::Engine;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Query param carrying the cursor of the next page.
+pub const CURSOR_PARAM: &str = "cursor";
+
+/// What a cursor holds: where the previous page ended, in whatever form the
+/// search behind it resumes from, and a fingerprint of the search it
+/// belongs to.
+#[derive(Serialize, Deserialize)]
+struct Envelope<T> {
+    #[serde(rename = "q")]
+    query: String,
+    #[serde(rename = "s")]
+    state: T,
+}
+
+fn fingerprint(query: &str) -> String {
+    Sha256::digest(query.as_bytes())[..8]
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// An opaque cursor resuming the search described by `query` (its filters,
+/// in any stable form) from `state`.
+pub fn encode<T: Serialize>(query: &str, state: T) -> String {
+    let envelope = Envelope {
+        query: fingerprint(query),
+        state,
+    };
+    BASE64URL.encode(serde_json::to_vec(&envelope).unwrap_or_default())
+}
+
+/// The state in `cursor`, if it was made by `encode` for the same `query`;
+/// a cursor from another search is as invalid as a mangled one.
+pub fn decode<T: DeserializeOwned>(query: &str, cursor: &str) -> Option<T> {
+    let bytes = BASE64URL.decode(cursor).ok()?;
+    let envelope: Envelope<T> = serde_json::from_slice(&bytes).ok()?;
+    (envelope.query == fingerprint(query)).then_some(envelope.state)
+}
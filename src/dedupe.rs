@@ -0,0 +1,40 @@
+use crate::dynamodb::{get_item_value, put_item};
+use crate::s3::head_object;
+
+/// Content-hash index of presigned uploads: `idx` is the base64 SHA-256 the
+/// client declared, `value` the key it was uploaded to.
+pub const UPLOAD_HASHES_PARTITION: &str = "upload_hashes";
+
+/// Returns the key of an existing object the upload would duplicate: the
+/// target key itself, or another object uploaded with the same checksum.
+/// Index entries whose object no longer exists are ignored.
+pub async fn find_duplicate(
+    bucket: &str,
+    key: &str,
+    checksum_sha256: Option<&str>,
+) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+    if head_object(bucket, key.to_string()).await?.is_some() {
+        return Ok(Some(key.to_string()));
+    }
+
+    let Some(checksum) = checksum_sha256 else {
+        return Ok(None);
+    };
+    let indexed =
+        get_item_value(UPLOAD_HASHES_PARTITION.to_string(), checksum.to_string()).await?;
+    match indexed {
+        Some(existing) if head_object(bucket, existing.clone()).await?.is_some() => {
+            Ok(Some(existing))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Remembers which key an upload with this checksum was presigned for. The
+/// checksum is signed into the URL, so only matching content can land there.
+pub async fn record_upload(
+    checksum_sha256: String,
+    key: String,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    put_item(UPLOAD_HASHES_PARTITION.to_string(), checksum_sha256, key).await
+}
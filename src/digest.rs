@@ -0,0 +1,253 @@
+use crate::clock::{now_millis, rfc3339, utc_date};
+use crate::comments::{self, COMMENTS_PARTITION};
+use crate::dynamodb::{dynamodb_client, schema, TABLE_NAME};
+use crate::posts::{self, daily_views, list_posts, PostSort};
+use crate::ses;
+use crate::settings::{self, SiteSettings, SETTINGS_PARTITION};
+use crate::stage::Stage;
+use crate::usage;
+use aws_sdk_dynamodb::operation::delete_item::DeleteItemError;
+use aws_sdk_dynamodb::operation::update_item::UpdateItemError;
+use aws_sdk_dynamodb::types::AttributeValue;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+const DAY_MILLIS: u64 = 24 * 60 * 60 * 1000;
+
+/// Posts listed under the most viewed.
+const TOP_POSTS: usize = 5;
+
+/// Comments listed in the email; the rest are only counted.
+const MAX_LISTED_COMMENTS: usize = 50;
+
+/// Item of the settings partition holding the last day a digest went out.
+const SENT_IDX: &str = "digest_sent";
+const DAY_ATTRIBUTE: &str = "day";
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DigestComment {
+    pub post: String,
+    pub post_title: Option<String>,
+    pub author: String,
+    pub created_at: u64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TopPost {
+    pub idx: String,
+    pub title: Option<String>,
+    pub views: i64,
+}
+
+/// What happened on the site on one UTC day.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Digest {
+    pub day: String,
+    /// Comments left that day, by their timestamp.
+    pub new_comments: usize,
+    /// The first of them, oldest first.
+    pub comments: Vec<DigestComment>,
+    /// Views of all posts that day.
+    pub views: i64,
+    /// The most viewed posts of all time.
+    pub top_posts: Vec<TopPost>,
+    /// Requests answered with a server error that day.
+    pub errors: i64,
+}
+
+/// The digest of `day` (`YYYY-MM-DD`) for `stage`. Views and errors are
+/// counted for the whole site.
+pub async fn compile(
+    stage: Stage,
+    day: String,
+) -> Result<Digest, Box<dyn std::error::Error + Send + Sync>> {
+    let part = stage.partition(&posts::posts_part());
+    let titled = vec!["title".to_string()];
+    let viewed = vec!["title".to_string(), "views".to_string()];
+    let (comments, posts, top, views, errors) = tokio::join!(
+        comments::list(stage.partition(COMMENTS_PARTITION), None),
+        list_posts(
+            part.clone(),
+            PostSort::CreatedAt,
+            true,
+            usize::MAX,
+            Some(titled)
+        ),
+        list_posts(part, PostSort::Views, true, TOP_POSTS, Some(viewed)),
+        daily_views(std::slice::from_ref(&day)),
+        usage::errors(day.clone()),
+    );
+
+    let title_of = |post: &Value| post["title"].as_str().map(String::from);
+    let titles: HashMap<String, Option<String>> = posts?
+        .iter()
+        .filter_map(|post| Some((post["idx"].as_str()?.to_string(), title_of(post))))
+        .collect();
+    let mut comments: Vec<_> = comments?
+        .into_iter()
+        .filter(|comment| utc_date(comment.created_at) == day)
+        .collect();
+    comments.sort_by_key(|comment| comment.created_at);
+
+    Ok(Digest {
+        new_comments: comments.len(),
+        comments: comments
+            .into_iter()
+            .take(MAX_LISTED_COMMENTS)
+            .map(|comment| DigestComment {
+                post_title: titles.get(&comment.post).cloned().flatten(),
+                post: comment.post,
+                author: comment.author.name,
+                created_at: comment.created_at,
+            })
+            .collect(),
+        views: views?.first().copied().unwrap_or_default(),
+        top_posts: top?
+            .iter()
+            .filter_map(|post| {
+                Some(TopPost {
+                    idx: post["idx"].as_str()?.to_string(),
+                    title: title_of(post),
+                    views: post["views"].as_i64().unwrap_or_default(),
+                })
+            })
+            .collect(),
+        errors: errors?,
+        day,
+    })
+}
+
+/// Subject and plain-text body of the digest email.
+fn render(digest: &Digest, site_title: &str) -> (String, String) {
+    let subject = format!("{site_title}: digest for {}", digest.day);
+    let mut text = format!(
+        "{site_title}, {}\n\nViews: {}\nServer errors: {}\nNew comments: {}\n",
+        digest.day, digest.views, digest.errors, digest.new_comments
+    );
+    if !digest.comments.is_empty() {
+        text.push('\n');
+        for comment in &digest.comments {
+            let post = comment.post_title.as_deref().unwrap_or(&comment.post);
+            let at = rfc3339(comment.created_at);
+            text.push_str(&format!("- {} on \"{post}\" at {at}\n", comment.author));
+        }
+        if digest.new_comments > digest.comments.len() {
+            let more = digest.new_comments - digest.comments.len();
+            text.push_str(&format!("- and {more} more\n"));
+        }
+    }
+    if !digest.top_posts.is_empty() {
+        text.push_str("\nMost viewed posts:\n");
+        for (rank, post) in digest.top_posts.iter().enumerate() {
+            let title = post.title.as_deref().unwrap_or(&post.idx);
+            text.push_str(&format!("{}. {title} ({} views)\n", rank + 1, post.views));
+        }
+    }
+    (subject, text)
+}
+
+/// Marks `day` as sent in `settings_part`, unless it or a later day
+/// already is. `false` when it was.
+async fn claim(
+    settings_part: String,
+    day: &str,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let schema = schema();
+    let claimed = dynamodb_client()
+        .await
+        .update_item()
+        .table_name(TABLE_NAME)
+        .key(&schema.partition_key, AttributeValue::S(settings_part))
+        .key(&schema.sort_key, AttributeValue::S(SENT_IDX.to_string()))
+        .update_expression("SET #day = :day")
+        .condition_expression("attribute_not_exists(#day) OR #day < :day")
+        .expression_attribute_names("#day", DAY_ATTRIBUTE)
+        .expression_attribute_values(":day", AttributeValue::S(day.to_string()))
+        .send()
+        .await;
+    match claimed {
+        Ok(_) => Ok(true),
+        Err(e) => match e.as_service_error() {
+            Some(UpdateItemError::ConditionalCheckFailedException(_)) => Ok(false),
+            _ => Err(e.into()),
+        },
+    }
+}
+
+/// Gives back a claim whose digest could not be sent, so the next run
+/// tries again.
+async fn release(
+    settings_part: String,
+    day: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let schema = schema();
+    let released = dynamodb_client()
+        .await
+        .delete_item()
+        .table_name(TABLE_NAME)
+        .key(&schema.partition_key, AttributeValue::S(settings_part))
+        .key(&schema.sort_key, AttributeValue::S(SENT_IDX.to_string()))
+        .condition_expression("#day = :day")
+        .expression_attribute_names("#day", DAY_ATTRIBUTE)
+        .expression_attribute_values(":day", AttributeValue::S(day.to_string()))
+        .send()
+        .await;
+    match released {
+        Ok(_) => Ok(()),
+        Err(e) => match e.as_service_error() {
+            Some(DeleteItemError::ConditionalCheckFailedException(_)) => Ok(()),
+            _ => Err(e.into()),
+        },
+    }
+}
+
+/// Sends the digest of the previous UTC day to the recipients in the
+/// stage's settings, once it is the settings' hour or later. Meant to run
+/// every hour from an EventBridge schedule: each day's digest goes out
+/// once, and a failed send is retried by the next run. `force` sends
+/// whatever the hour, still only once per day. The outcome says what was
+/// done, with `sent` and a `reason` when nothing was.
+pub async fn run(
+    stage: Stage,
+    force: bool,
+) -> Result<Value, Box<dyn std::error::Error + Send + Sync>> {
+    let from = ses::sender().ok_or("ses_from is not set")?;
+    let settings_part = stage.partition(SETTINGS_PARTITION);
+    let SiteSettings { title, digest, .. } = settings::load(settings_part.clone()).await?;
+    if !digest.enabled || digest.recipients.is_empty() {
+        return Ok(json!({ "sent": false, "reason": "disabled" }));
+    }
+
+    let now = now_millis();
+    let hour = (now % DAY_MILLIS / 3_600_000) as u8;
+    if !force && hour < digest.hour {
+        return Ok(json!({ "sent": false, "reason": "not_due" }));
+    }
+    let day = utc_date(now - DAY_MILLIS);
+    if !claim(settings_part.clone(), &day).await? {
+        return Ok(json!({ "sent": false, "reason": "already_sent", "day": day }));
+    }
+
+    let sent = async {
+        let compiled = compile(stage, day.clone()).await?;
+        let (subject, text) = render(&compiled, &title);
+        ses::send(&from, &digest.recipients, &subject, &text).await
+    }
+    .await;
+    match sent {
+        Ok(message_id) => Ok(json!({
+            "sent": true,
+            "day": day,
+            "recipients": digest.recipients.len(),
+            "messageId": message_id,
+        })),
+        Err(e) => {
+            release(settings_part, &day).await?;
+            Err(e)
+        }
+    }
+}
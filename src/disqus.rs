@@ -0,0 +1,335 @@
+use crate::clock::parse_rfc3339;
+use crate::comments::{self, Comment, CommentAuthor};
+use crate::dynamodb::list_items;
+use crate::slugs;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use url::Url;
+use xmlparser::{ElementEnd, Token, Tokenizer};
+
+/// Unmatched threads listed in the report; the rest are only counted.
+const MAX_REPORTED_THREADS: usize = 100;
+
+/// Deepest nesting of elements accepted; Disqus exports need four.
+const MAX_DEPTH: usize = 16;
+
+/// An element of the export with only what the import reads: its local
+/// name, its `dsq:id`, its text and its child elements.
+#[derive(Debug, Default)]
+struct Element {
+    name: String,
+    id: Option<String>,
+    text: String,
+    children: Vec<Element>,
+}
+
+impl Element {
+    fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    fn text_of(&self, name: &str) -> &str {
+        self.child(name).map_or("", |child| child.text.trim())
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.text_of(name) == "true"
+    }
+
+    /// The `dsq:id` of child `name`, which is how posts point at their
+    /// thread and parent.
+    fn ref_of(&self, name: &str) -> Option<&str> {
+        self.child(name)?.id.as_deref()
+    }
+}
+
+/// Text with the predefined and numeric character references resolved.
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(at) = rest.find('&') {
+        out.push_str(&rest[..at]);
+        rest = &rest[at..];
+        let Some(end) = rest.find(';') else { break };
+        let resolved = match &rest[1..end] {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            reference => match reference.strip_prefix('#') {
+                Some(hex) if hex.starts_with(['x', 'X']) => u32::from_str_radix(&hex[1..], 16)
+                    .ok()
+                    .and_then(char::from_u32),
+                Some(decimal) => decimal.parse().ok().and_then(char::from_u32),
+                None => None,
+            },
+        };
+        match resolved {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// The root element of `xml`.
+fn parse_tree(xml: &str) -> Result<Element, String> {
+    let mut open: Vec<Element> = Vec::new();
+    let mut root = None;
+    for token in Tokenizer::from(xml) {
+        let token = token.map_err(|e| format!("invalid xml: {e}"))?;
+        match token {
+            Token::ElementStart { local, .. } => {
+                if root.is_some() {
+                    return Err("invalid xml: content after the root element".to_string());
+                }
+                if open.len() >= MAX_DEPTH {
+                    return Err("invalid xml: elements nested too deep".to_string());
+                }
+                open.push(Element {
+                    name: local.as_str().to_string(),
+                    ..Element::default()
+                });
+            }
+            Token::Attribute { local, value, .. } if local.as_str() == "id" => {
+                if let Some(element) = open.last_mut() {
+                    element.id = Some(unescape(value.as_str()));
+                }
+            }
+            Token::ElementEnd {
+                end: ElementEnd::Close(..) | ElementEnd::Empty,
+                ..
+            } => {
+                let element = open.pop().ok_or("invalid xml: unbalanced end tag")?;
+                match open.last_mut() {
+                    Some(parent) => parent.children.push(element),
+                    None => root = Some(element),
+                }
+            }
+            Token::Text { text } => {
+                if let Some(element) = open.last_mut() {
+                    element.text.push_str(&unescape(text.as_str()));
+                }
+            }
+            Token::Cdata { text, .. } => {
+                if let Some(element) = open.last_mut() {
+                    element.text.push_str(text.as_str());
+                }
+            }
+            _ => {}
+        }
+    }
+    root.ok_or_else(|| "invalid xml: no root element".to_string())
+}
+
+/// A discussion of the export, one per page it was embedded in.
+struct Thread<'a> {
+    identifier: &'a str,
+    link: &'a str,
+    title: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnmatchedThread {
+    pub identifier: String,
+    pub link: String,
+    pub title: String,
+    pub comments: usize,
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DisqusImport {
+    pub threads: usize,
+    /// Threads whose post was found.
+    pub matched_threads: usize,
+    pub imported: usize,
+    /// Comments deleted or marked as spam in Disqus.
+    pub skipped: usize,
+    /// Comments of threads whose post was not found.
+    pub unmatched: usize,
+    /// The first threads with comments whose post was not found.
+    pub unmatched_threads: Vec<UnmatchedThread>,
+}
+
+/// The post a thread was embedded in: the one whose idx is the thread's
+/// identifier, or else the one whose idx or slug ends its link.
+async fn post_of(
+    thread: &Thread<'_>,
+    posts: &HashSet<String>,
+    posts_part: &str,
+    slugs_part: &str,
+) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+    if posts.contains(thread.identifier) {
+        return Ok(Some(thread.identifier.to_string()));
+    }
+    let Ok(link) = Url::parse(thread.link) else {
+        return Ok(None);
+    };
+    let Some(last) = link
+        .path_segments()
+        .and_then(|mut s| s.rfind(|s| !s.is_empty()))
+    else {
+        return Ok(None);
+    };
+    let last = percent_encoding::percent_decode_str(last).decode_utf8_lossy();
+    if posts.contains(last.as_ref()) {
+        return Ok(Some(last.into_owned()));
+    }
+    let resolved = slugs::resolve(posts_part.to_string(), slugs_part.to_string(), &last).await?;
+    Ok(resolved.and_then(|post| post["idx"].as_str().map(String::from)))
+}
+
+/// Imports the comments of a Disqus XML export into `comments_part`. Each
+/// thread is matched to a post of `posts_part` (see `post_of`), and its
+/// comments keep their Disqus timestamps, authors and HTML. Replies keep
+/// their parent, or the nearest ancestor that was imported when the parent
+/// was deleted or spam. Comments are stored under `disqus-{id}`, so
+/// importing the same export again replaces rather than duplicates them.
+pub async fn import(
+    xml: &str,
+    posts_part: String,
+    slugs_part: String,
+    comments_part: String,
+) -> Result<Result<DisqusImport, String>, Box<dyn std::error::Error + Send + Sync>> {
+    let root = match parse_tree(xml) {
+        Ok(root) if root.name == "disqus" => root,
+        Ok(_) => return Ok(Err("not a Disqus export".to_string())),
+        Err(e) => return Ok(Err(e)),
+    };
+
+    let threads: HashMap<&str, Thread> = root
+        .children
+        .iter()
+        .filter(|element| element.name == "thread")
+        .filter_map(|element| {
+            let thread = Thread {
+                identifier: element.text_of("id"),
+                link: element.text_of("link"),
+                title: element.text_of("title"),
+            };
+            Some((element.id.as_deref()?, thread))
+        })
+        .collect();
+    let disqus_posts: Vec<&Element> = root
+        .children
+        .iter()
+        .filter(|element| element.name == "post" && element.id.is_some())
+        .collect();
+    let mut report = DisqusImport {
+        threads: threads.len(),
+        ..Default::default()
+    };
+
+    let posts: HashSet<String> = list_items(posts_part.clone())
+        .await?
+        .into_iter()
+        .map(|item| item.idx)
+        .collect();
+    let mut matched: HashMap<&str, String> = HashMap::new();
+    let mut unmatched: HashMap<&str, usize> = HashMap::new();
+    for (id, thread) in &threads {
+        match post_of(thread, &posts, &posts_part, &slugs_part).await? {
+            Some(post) => {
+                matched.insert(id, post);
+            }
+            None => {
+                unmatched.insert(id, 0);
+            }
+        }
+    }
+    report.matched_threads = matched.len();
+
+    let kept: HashMap<&str, &Element> = disqus_posts
+        .iter()
+        .filter(|post| !post.flag("isDeleted") && !post.flag("isSpam"))
+        .filter_map(|post| Some((post.id.as_deref()?, *post)))
+        .collect();
+    let by_id: HashMap<&str, &Element> = disqus_posts
+        .iter()
+        .filter_map(|post| Some((post.id.as_deref()?, *post)))
+        .collect();
+
+    let mut imported = Vec::new();
+    for post in &disqus_posts {
+        let id = post.id.as_deref().unwrap_or_default();
+        if !kept.contains_key(id) {
+            report.skipped += 1;
+            continue;
+        }
+        let thread = post.ref_of("thread").unwrap_or_default();
+        let Some(post_idx) = matched.get(thread) else {
+            report.unmatched += 1;
+            if let Some(count) = unmatched.get_mut(thread) {
+                *count += 1;
+            }
+            continue;
+        };
+
+        // climb past parents that were not kept; the walk is bounded in
+        // case the export has a cycle
+        let mut parent = post.ref_of("parent");
+        for _ in 0..by_id.len() {
+            match parent {
+                Some(p) if !kept.contains_key(p) => {
+                    parent = by_id.get(p).and_then(|p| p.ref_of("parent"));
+                }
+                _ => break,
+            }
+        }
+        let parent = parent
+            .and_then(|p| kept.get(p))
+            .filter(|p| p.ref_of("thread") == Some(thread))
+            .and_then(|p| p.id.as_deref());
+
+        let author = post.child("author");
+        let author_text = |name: &str| author.map_or("", |author| author.text_of(name));
+        let optional = |text: &str| Some(text.to_string()).filter(|t| !t.is_empty());
+        imported.push(Comment {
+            id: format!("disqus-{id}"),
+            post: post_idx.clone(),
+            parent: parent.map(|p| format!("disqus-{p}")),
+            author: CommentAuthor {
+                name: author_text("name").to_string(),
+                username: optional(author_text("username")),
+                email: optional(author_text("email")),
+                anonymous: author.is_some_and(|author| author.flag("isAnonymous")),
+            },
+            message: post.text_of("message").to_string(),
+            created_at: parse_rfc3339(post.text_of("createdAt")).unwrap_or_default(),
+            source: Some("disqus".to_string()),
+        });
+    }
+
+    comments::store(&comments_part, &imported).await?;
+    report.imported = imported.len();
+
+    let mut unmatched: Vec<(&str, usize)> = unmatched
+        .into_iter()
+        .filter(|(_, count)| *count > 0)
+        .collect();
+    unmatched.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+    report.unmatched_threads = unmatched
+        .into_iter()
+        .take(MAX_REPORTED_THREADS)
+        .map(|(id, comments)| {
+            let thread = &threads[id];
+            UnmatchedThread {
+                identifier: thread.identifier.to_string(),
+                link: thread.link.to_string(),
+                title: thread.title.to_string(),
+                comments,
+            }
+        })
+        .collect();
+    Ok(Ok(report))
+}
@@ -0,0 +1,104 @@
+use crate::clock::{now_millis, utc_date};
+use crate::dynamodb::{increment_counter, query_records, schema};
+use crate::stage::Stage;
+use aws_sdk_dynamodb::types::AttributeValue;
+use serde::Serialize;
+
+/// Download counters of one file live in `downloads#{key}`, `key` relative to
+/// the stage's base path: one item per UTC day plus an all-time `total`.
+pub const DOWNLOADS_PARTITION_PREFIX: &str = "downloads#";
+
+const COUNT_ATTRIBUTE: &str = "count";
+
+/// Sorts after every `YYYY-MM-DD`, so day ranges never include it.
+const TOTAL_IDX: &str = "total";
+
+/// Days `GET /api/files/{key}/stats` covers unless asked for more or fewer.
+pub const DEFAULT_DAYS: usize = 30;
+pub const MAX_DAYS: usize = 365;
+
+/// Unstaged partition of the counters of `key`.
+pub fn partition(key: &str) -> String {
+    format!("{DOWNLOADS_PARTITION_PREFIX}{key}")
+}
+
+/// Partition of the counters of the object at S3 `key`, in the stage the key
+/// belongs to.
+pub fn partition_of(key: &str, root_path: &str) -> String {
+    let relative = key.strip_prefix(root_path).unwrap_or(key);
+    match relative.strip_prefix(Stage::Draft.s3_base("").as_str()) {
+        Some(relative) => Stage::Draft.partition(&partition(relative)),
+        // counters are not content, so every live generation shares them
+        None => partition(relative),
+    }
+}
+
+/// Counts one download in `part` (see `partition`), for today and overall.
+pub async fn record(part: String) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let day = utc_date(now_millis());
+    let (daily, total) = tokio::join!(
+        increment_counter(part.clone(), day, COUNT_ATTRIBUTE, 1),
+        increment_counter(part, TOTAL_IDX.to_string(), COUNT_ATTRIBUTE, 1),
+    );
+    daily?;
+    total?;
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+pub struct DayCount {
+    pub day: String,
+    pub count: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DownloadStats {
+    /// Every download recorded, not only those in `days`.
+    pub total: i64,
+    /// Oldest first, one entry per day including days without downloads.
+    pub days: Vec<DayCount>,
+}
+
+/// Downloads counted in `part` over the last `days` UTC days, today included.
+pub async fn stats(
+    part: String,
+    days: usize,
+) -> Result<DownloadStats, Box<dyn std::error::Error + Send + Sync>> {
+    let now = now_millis();
+    let days: Vec<String> = (0..days as u64)
+        .rev()
+        .map(|ago| utc_date(now - ago * 86_400_000))
+        .collect();
+    let Some(first) = days.first() else {
+        return Ok(DownloadStats {
+            total: 0,
+            days: Vec::new(),
+        });
+    };
+
+    // the day range plus `total`, which sorts right after it
+    let range = Some((first.clone(), TOTAL_IDX.to_string()));
+    let records = query_records(part, range, Vec::new(), usize::MAX, false).await?;
+
+    let sort_key = &schema().sort_key;
+    let count = |idx: &str| {
+        records
+            .iter()
+            .find(|r| matches!(r.get(sort_key), Some(AttributeValue::S(i)) if i == idx))
+            .and_then(|r| match r.get(COUNT_ATTRIBUTE) {
+                Some(AttributeValue::N(n)) => n.parse().ok(),
+                _ => None,
+            })
+            .unwrap_or(0)
+    };
+    Ok(DownloadStats {
+        total: count(TOTAL_IDX),
+        days: days
+            .iter()
+            .map(|day| DayCount {
+                day: day.clone(),
+                count: count(day),
+            })
+            .collect(),
+    })
+}
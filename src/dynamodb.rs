@@ -1,12 +1,362 @@
-use aws_config::BehaviorVersion;
-use aws_sdk_dynamodb::{types::AttributeValue, Client};
+use aws_sdk_dynamodb::{
+    operation::update_item::builders::UpdateItemFluentBuilder,
+    types::builders::UpdateBuilder,
+    types::{
+        AttributeDefinition, AttributeValue, BillingMode, CreateGlobalSecondaryIndexAction, Delete,
+        GlobalSecondaryIndexUpdate, KeySchemaElement, KeyType, Projection, ProjectionType,
+        ProvisionedThroughput, Put, PutRequest, ReturnValue, ScalarAttributeType, Select,
+        TransactWriteItem, Update, WriteRequest,
+    },
+    Client,
+};
+use crate::clock::now_millis;
+use crate::compression;
+use crate::costs::CapacityMeter;
+use crate::correlation::Propagate;
+use crate::failover::{self, FailureDetector};
+use crate::overflow::{self, VALUE_REF_ATTRIBUTE};
+use crate::series::SeriesLinks;
+use serde::Serialize;
 use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use ulid::{Generator, Ulid};
 
-const TABLE_NAME: &str = "blog_deepria_master";
+pub const TABLE_NAME: &str = "blog_deepria_master";
+pub const PINNED_ATTRIBUTE: &str = "pinned";
+pub const SORT_WEIGHT_ATTRIBUTE: &str = "sort_weight";
+pub const SERIES_ATTRIBUTE: &str = "series";
+pub const CREATED_AT_ATTRIBUTE: &str = "created_at";
+pub const UPDATED_AT_ATTRIBUTE: &str = "updated_at";
+/// Set on items whose value is a KMS envelope (see `secrets`).
+pub const ENCRYPTED_ATTRIBUTE: &str = "encrypted";
+/// Counts the writes of an item's value; its ETag is built from it.
+pub const VERSION_ATTRIBUTE: &str = "version";
+
+/// What an `If-Match` header asks of the item a write replaces.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Precondition {
+    /// `*`: the item must exist.
+    Exists,
+    /// The item must be at one of these versions. Version 0 stands for
+    /// an item last written before versions were kept.
+    Versions(Vec<u64>),
+}
+
+impl Precondition {
+    /// Parses an `If-Match` header. ETags this API did not issue, weak ones
+    /// included, can never match and are dropped, which may leave no
+    /// version at all.
+    pub fn from_if_match(header: &str) -> Precondition {
+        let tags: Vec<&str> = header.split(',').map(|tag| tag.trim()).collect();
+        if tags.contains(&"*") {
+            return Precondition::Exists;
+        }
+        Precondition::Versions(
+            tags.iter()
+                .filter_map(|tag| tag.strip_prefix('"')?.strip_suffix('"')?.parse().ok())
+                .collect(),
+        )
+    }
+
+    /// Whether no item could satisfy it.
+    pub fn is_unsatisfiable(&self) -> bool {
+        matches!(self, Precondition::Versions(versions) if versions.is_empty())
+    }
+
+    /// The condition expression the write carries.
+    pub fn condition(&self) -> Condition {
+        let pk = ("#pk".to_string(), schema().partition_key.clone());
+        let Precondition::Versions(versions) = self else {
+            return Condition {
+                expression: "attribute_exists(#pk)".to_string(),
+                names: vec![pk],
+                values: Vec::new(),
+            };
+        };
+        let mut clauses = Vec::new();
+        let mut values = Vec::new();
+        for (i, version) in versions.iter().enumerate() {
+            if *version == 0 {
+                clauses.push("(attribute_exists(#pk) AND attribute_not_exists(#version))".into());
+            } else {
+                clauses.push(format!("#version = :if_match{i}"));
+                values.push((format!(":if_match{i}"), AttributeValue::N(version.to_string())));
+            }
+        }
+        let mut names = vec![("#version".to_string(), VERSION_ATTRIBUTE.to_string())];
+        if versions.contains(&0) {
+            names.push(pk);
+        }
+        Condition {
+            expression: clauses.join(" OR "),
+            names,
+            values,
+        }
+    }
+}
+
+/// A condition expression with the attribute names and values it uses.
+pub struct Condition {
+    pub expression: String,
+    pub names: Vec<(String, String)>,
+    pub values: Vec<(String, AttributeValue)>,
+}
+
+/// The assignment that bumps an item's `version`. Every write that changes
+/// an item carries it, so an `If-Match` or a post save that read an older
+/// version fails. `BumpsVersion::bump_version` binds what it uses.
+pub const VERSION_BUMP: &str = "#version = if_not_exists(#version, :zero) + :one";
+
+/// An update that can carry `VERSION_BUMP`.
+pub trait BumpsVersion: Sized {
+    fn bump_version(self) -> Self;
+}
+
+impl BumpsVersion for UpdateItemFluentBuilder {
+    fn bump_version(self) -> Self {
+        self.expression_attribute_names("#version", VERSION_ATTRIBUTE)
+            .expression_attribute_values(":zero", AttributeValue::N("0".to_string()))
+            .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
+    }
+}
+
+impl BumpsVersion for UpdateBuilder {
+    fn bump_version(self) -> Self {
+        self.expression_attribute_names("#version", VERSION_ATTRIBUTE)
+            .expression_attribute_values(":zero", AttributeValue::N("0".to_string()))
+            .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
+    }
+}
+
+/// The ETag of an item at `version`.
+pub fn etag(version: u64) -> String {
+    format!("\"{version}\"")
+}
+
+fn version_of(record: &HashMap<String, AttributeValue>) -> u64 {
+    match record.get(VERSION_ATTRIBUTE) {
+        Some(AttributeValue::N(n)) => n.parse().unwrap_or_default(),
+        _ => 0,
+    }
+}
+
+/// Attribute names of the table's partition key, sort key and value column.
+///
+/// Read from `dynamodb_partition_key`, `dynamodb_sort_key` and
+/// `dynamodb_value_attribute`, defaulting to `part` / `idx` / `value`.
+#[derive(Debug)]
+pub struct TableSchema {
+    pub partition_key: String,
+    pub sort_key: String,
+    pub value_attribute: String,
+}
+
+static SCHEMA: OnceLock<TableSchema> = OnceLock::new();
+
+impl TableSchema {
+    fn from_env() -> TableSchema {
+        let var = |name: &str, default: &str| {
+            std::env::var(name).unwrap_or_else(|_| default.to_string())
+        };
+        TableSchema {
+            partition_key: var("dynamodb_partition_key", "part"),
+            sort_key: var("dynamodb_sort_key", "idx"),
+            value_attribute: var("dynamodb_value_attribute", "value"),
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let names = [
+            ("dynamodb_partition_key", &self.partition_key),
+            ("dynamodb_sort_key", &self.sort_key),
+            ("dynamodb_value_attribute", &self.value_attribute),
+        ];
+        for (var, name) in names {
+            let valid_chars = name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+            if name.is_empty() || name.len() > 255 || !valid_chars {
+                return Err(format!("{var} is not a valid attribute name: {name:?}"));
+            }
+        }
+        if self.partition_key == self.sort_key
+            || self.partition_key == self.value_attribute
+            || self.sort_key == self.value_attribute
+        {
+            return Err("dynamodb attribute names must be distinct".to_string());
+        }
+        Ok(())
+    }
+}
+
+pub fn schema() -> &'static TableSchema {
+    SCHEMA.get_or_init(TableSchema::from_env)
+}
+
+/// Loads the attribute names and checks them against the table's key schema,
+/// so a misconfigured deployment fails at cold start instead of per request.
+pub async fn init_schema() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let schema = TableSchema::from_env();
+    schema.validate()?;
+
+    let client = dynamodb_client().await;
+    let output = client.describe_table().table_name(TABLE_NAME).send().await?;
+    let key_schema = output
+        .table
+        .and_then(|t| t.key_schema)
+        .unwrap_or_default();
+
+    for (key_type, expected) in [
+        (KeyType::Hash, &schema.partition_key),
+        (KeyType::Range, &schema.sort_key),
+    ] {
+        let actual = key_schema
+            .iter()
+            .find(|k| k.key_type == key_type)
+            .map(|k| k.attribute_name.as_str());
+        if actual != Some(expected.as_str()) {
+            return Err(format!(
+                "table {TABLE_NAME} {key_type} key is {actual:?}, configured {expected:?}"
+            )
+            .into());
+        }
+    }
+
+    let _ = SCHEMA.set(schema);
+    Ok(())
+}
 
 pub async fn dynamodb_client() -> Client {
-    let config = aws_config::load_defaults(BehaviorVersion::latest()).await;
-    Client::new(&config)
+    let config = failover::sdk_config().await;
+    let config = aws_sdk_dynamodb::config::Builder::from(&config)
+        .interceptor(FailureDetector)
+        .interceptor(CapacityMeter)
+        .interceptor(Propagate)
+        .build();
+    Client::from_conf(config)
+}
+
+/// What a failed DynamoDB call means for the client, from the error code the
+/// service returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// A condition did not hold, or a transaction collided with another.
+    Conflict,
+    /// The table or index does not exist.
+    NotFound,
+    /// Throughput or request limits were hit; retrying later can succeed.
+    Throttled,
+    /// DynamoDB rejected the request, e.g. an item over the size limit.
+    Invalid,
+    Other,
+}
+
+impl ErrorKind {
+    pub fn status(self) -> u16 {
+        match self {
+            ErrorKind::Conflict => 409,
+            ErrorKind::NotFound => 404,
+            ErrorKind::Throttled => 429,
+            ErrorKind::Invalid => 400,
+            ErrorKind::Other => 500,
+        }
+    }
+
+    /// Machine-readable code for the `error` field of error responses.
+    pub fn code(self) -> &'static str {
+        match self {
+            ErrorKind::Conflict => "conditional_check_failed",
+            ErrorKind::NotFound => "resource_not_found",
+            ErrorKind::Throttled => "throttled",
+            ErrorKind::Invalid => "validation_error",
+            ErrorKind::Other => "dynamodb_error",
+        }
+    }
+
+    fn from_code(code: &str) -> ErrorKind {
+        match code {
+            "ConditionalCheckFailedException"
+            | "ConditionalCheckFailed"
+            | "TransactionConflictException"
+            | "TransactionConflict" => ErrorKind::Conflict,
+            "ResourceNotFoundException" => ErrorKind::NotFound,
+            "ThrottlingException"
+            | "ThrottlingError"
+            | "ProvisionedThroughputExceededException"
+            | "ProvisionedThroughputExceeded"
+            | "RequestLimitExceeded" => ErrorKind::Throttled,
+            "ValidationException" | "ValidationError" | "ItemCollectionSizeLimitExceeded" => {
+                ErrorKind::Invalid
+            }
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+/// Classifies an error returned by the helpers in this module. Errors that
+/// did not come from DynamoDB, or carry no code, are `ErrorKind::Other`.
+pub fn error_kind(e: &(dyn std::error::Error + 'static)) -> ErrorKind {
+    use aws_sdk_dynamodb::error::{ProvideErrorMetadata, SdkError};
+    use aws_sdk_dynamodb::operation::{
+        batch_write_item::BatchWriteItemError, delete_item::DeleteItemError,
+        describe_table::DescribeTableError, get_item::GetItemError, put_item::PutItemError,
+        query::QueryError, scan::ScanError, transact_write_items::TransactWriteItemsError,
+        update_item::UpdateItemError, update_table::UpdateTableError,
+    };
+
+    macro_rules! by_code {
+        ($e:expr, $($op:ty),*) => {
+            $(
+                if let Some(e) = $e.downcast_ref::<SdkError<$op>>() {
+                    return e.code().map_or(ErrorKind::Other, ErrorKind::from_code);
+                }
+            )*
+        };
+    }
+
+    let mut current = Some(e);
+    while let Some(e) = current {
+        // a cancelled transaction only says why in its per-item reasons
+        if let Some(e) = e.downcast_ref::<SdkError<TransactWriteItemsError>>() {
+            if let Some(TransactWriteItemsError::TransactionCanceledException(cancelled)) =
+                e.as_service_error()
+            {
+                return cancelled
+                    .cancellation_reasons()
+                    .iter()
+                    .filter_map(|r| r.code())
+                    .map(ErrorKind::from_code)
+                    .find(|kind| *kind != ErrorKind::Other)
+                    .unwrap_or(ErrorKind::Conflict);
+            }
+        }
+        by_code!(
+            e,
+            GetItemError,
+            PutItemError,
+            UpdateItemError,
+            DeleteItemError,
+            QueryError,
+            ScanError,
+            BatchWriteItemError,
+            TransactWriteItemsError,
+            DescribeTableError,
+            UpdateTableError
+        );
+        current = e.source();
+    }
+    ErrorKind::Other
+}
+
+/// Generates a ULID for use as a sort key. Ids from the same container are
+/// strictly increasing, so server-assigned keys sort chronologically.
+pub fn generate_idx() -> String {
+    static GENERATOR: Mutex<Generator> = Mutex::new(Generator::new());
+    let mut generator = GENERATOR.lock().unwrap_or_else(|e| e.into_inner());
+    generator
+        .generate()
+        .unwrap_or_else(|_| Ulid::generate())
+        .to_string()
 }
 
 pub async fn get_item_value(
@@ -14,39 +364,454 @@ pub async fn get_item_value(
     idx: String,
 ) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
     let client = dynamodb_client().await;
+    let schema = schema();
 
     let output = client
         .query()
         .table_name(TABLE_NAME)
-        .key_condition_expression("part = :part AND idx = :idx")
+        .key_condition_expression("#part = :part AND #idx = :idx")
+        .expression_attribute_names("#part", &schema.partition_key)
+        .expression_attribute_names("#idx", &schema.sort_key)
         .expression_attribute_values(":part", AttributeValue::S(part))
         .expression_attribute_values(":idx", AttributeValue::S(idx))
         .send()
         .await?;
 
     let items_opt = output.items;
-    let first_item = match items_opt.and_then(|mut items| items.pop()) {
+    let mut first_item = match items_opt.and_then(|mut items| items.pop()) {
         Some(item) => item,
         None => return Ok(None),
     };
-    let value = match first_item.get("value") {
+    overflow::restore(&mut first_item).await?;
+    compression::decompress(&mut first_item)?;
+    let value = match first_item.get(&schema.value_attribute) {
         Some(AttributeValue::S(s)) => Some(s.clone()),
         _ => None,
     };
     Ok(value)
 }
 
+/// An item's value as `get_stored_value` reads it.
+pub struct StoredValue {
+    pub value: String,
+    /// Whether the value is a KMS envelope.
+    pub encrypted: bool,
+    pub version: u64,
+}
+
+/// `get_item_value` that also says whether the value is a KMS envelope, and
+/// at which version the item is.
+pub async fn get_stored_value(
+    part: String,
+    idx: String,
+) -> Result<Option<StoredValue>, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(record) = get_record(part, idx).await? else {
+        return Ok(None);
+    };
+    let Some(AttributeValue::S(value)) = record.get(&schema().value_attribute) else {
+        return Ok(None);
+    };
+    Ok(Some(StoredValue {
+        value: value.clone(),
+        encrypted: matches!(record.get(ENCRYPTED_ATTRIBUTE), Some(AttributeValue::Bool(true))),
+        version: version_of(&record),
+    }))
+}
+
+/// Sets an item's value, keeping its other attributes. `created_at` is
+/// stamped on first write, `updated_at` on every write (epoch millis) and
+/// `version` goes up by one. Oversized values are spilled to S3 (see
+/// `overflow::spill`).
 pub async fn put_item(
     part: String,
     idx: String,
     value: String,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    write_value(part, idx, value, false, None).await
+}
+
+/// `put_item` that only writes when the item meets `precondition`; a
+/// failed one is an `ErrorKind::Conflict`.
+pub async fn put_item_if(
+    part: String,
+    idx: String,
+    value: String,
+    precondition: Option<&Precondition>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    write_value(part, idx, value, false, precondition).await
+}
+
+/// `put_item_if` for a value sealed by `secrets::encrypt`; the item is
+/// flagged `encrypted` until a plain value overwrites it.
+pub async fn put_encrypted_item(
+    part: String,
+    idx: String,
+    envelope: String,
+    precondition: Option<&Precondition>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    write_value(part, idx, envelope, true, precondition).await
+}
+
+async fn write_value(
+    part: String,
+    idx: String,
+    value: String,
+    encrypted: bool,
+    precondition: Option<&Precondition>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let client = dynamodb_client().await;
+    let schema = schema();
+    let now = AttributeValue::N(now_millis().to_string());
+    let (value, value_ref) = overflow::spill(&part, &idx, value).await?;
+
+    let mut request = client
+        .update_item()
+        .table_name(TABLE_NAME)
+        .key(&schema.partition_key, AttributeValue::S(part))
+        .key(&schema.sort_key, AttributeValue::S(idx))
+        .expression_attribute_names("#value", &schema.value_attribute)
+        .expression_attribute_names("#created", CREATED_AT_ATTRIBUTE)
+        .expression_attribute_names("#updated", UPDATED_AT_ATTRIBUTE)
+        .expression_attribute_names("#ref", VALUE_REF_ATTRIBUTE)
+        .expression_attribute_names("#encrypted", ENCRYPTED_ATTRIBUTE)
+        .expression_attribute_names("#compressed", compression::COMPRESSED_ATTRIBUTE)
+        .expression_attribute_values(":value", AttributeValue::S(value))
+        .expression_attribute_values(":now", now)
+        .return_values(ReturnValue::UpdatedOld)
+        .bump_version();
+    let mut assignments = vec![
+        "#value = :value",
+        "#created = if_not_exists(#created, :now)",
+        "#updated = :now",
+        VERSION_BUMP,
+    ];
+    let mut removals = vec!["#compressed"];
+    match &value_ref {
+        Some(key) => {
+            assignments.push("#ref = :ref");
+            request = request.expression_attribute_values(":ref", AttributeValue::S(key.clone()));
+        }
+        None => removals.push("#ref"),
+    }
+    if encrypted {
+        assignments.push("#encrypted = :encrypted");
+        request = request.expression_attribute_values(":encrypted", AttributeValue::Bool(true));
+    } else {
+        removals.push("#encrypted");
+    }
+    if let Some(precondition) = precondition {
+        let condition = precondition.condition();
+        request = request.condition_expression(condition.expression);
+        for (name, attribute) in condition.names {
+            request = request.expression_attribute_names(name, attribute);
+        }
+        for (name, value) in condition.values {
+            request = request.expression_attribute_values(name, value);
+        }
+    }
+    let expression = format!("SET {} REMOVE {}", assignments.join(", "), removals.join(", "));
+    let result = request.update_expression(expression).send().await;
+
+    let old = match &result {
+        Ok(output) => output.attributes().and_then(overflow::value_ref),
+        Err(_) => None,
+    };
+    overflow::settle(result.is_ok(), value_ref.as_deref(), old).await;
+    result?;
+    Ok(())
+}
+
+pub async fn delete_item(
+    part: String,
+    idx: String,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    delete_item_if(part, idx, None).await
+}
+
+/// `delete_item` that only deletes when the item meets `precondition`; a
+/// failed one is an `ErrorKind::Conflict`.
+pub async fn delete_item_if(
+    part: String,
+    idx: String,
+    precondition: Option<&Precondition>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let client = dynamodb_client().await;
+    let schema = schema();
 
-    let mut item = HashMap::new();
-    item.insert("part".to_string(), AttributeValue::S(part));
-    item.insert("idx".to_string(), AttributeValue::S(idx));
-    item.insert("value".to_string(), AttributeValue::S(value));
+    let mut key = HashMap::new();
+    key.insert(schema.partition_key.clone(), AttributeValue::S(part));
+    key.insert(schema.sort_key.clone(), AttributeValue::S(idx));
+
+    let mut request = client
+        .delete_item()
+        .table_name(TABLE_NAME)
+        .set_key(Some(key))
+        .return_values(ReturnValue::AllOld);
+    if let Some(precondition) = precondition {
+        let condition = precondition.condition();
+        request = request.condition_expression(condition.expression);
+        for (name, attribute) in condition.names {
+            request = request.expression_attribute_names(name, attribute);
+        }
+        for (name, value) in condition.values {
+            request = request.expression_attribute_values(name, value);
+        }
+    }
+    let output = request.send().await?;
+    if let Some(key) = output.attributes().and_then(overflow::value_ref) {
+        overflow::discard(vec![key.to_string()]).await;
+    }
+
+    Ok(())
+}
+
+/// Every item of partition `part`, as stored: spilled and compressed values
+/// are not restored.
+pub async fn stored_records(
+    part: &str,
+) -> Result<Vec<HashMap<String, AttributeValue>>, Box<dyn std::error::Error + Send + Sync>> {
+    let client = dynamodb_client().await;
+    let schema = schema();
+
+    let mut items = Vec::new();
+    let mut start_key = None;
+    loop {
+        let output = client
+            .query()
+            .table_name(TABLE_NAME)
+            .key_condition_expression("#part = :part")
+            .expression_attribute_names("#part", &schema.partition_key)
+            .expression_attribute_values(":part", AttributeValue::S(part.to_string()))
+            .set_exclusive_start_key(start_key)
+            .send()
+            .await?;
+
+        items.extend(output.items.unwrap_or_default());
+        start_key = output.last_evaluated_key;
+        if start_key.is_none() {
+            break;
+        }
+    }
+    Ok(items)
+}
+
+/// The `version` of each of `records`, by idx.
+pub fn versions(records: &[HashMap<String, AttributeValue>]) -> HashMap<String, u64> {
+    let schema = schema();
+    records
+        .iter()
+        .filter_map(|record| match record.get(&schema.sort_key) {
+            Some(AttributeValue::S(idx)) => Some((idx.clone(), version_of(record))),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Runs `writes` as `TransactWriteItems` batches of up to 100, each landing
+/// all-or-nothing.
+async fn transact_in_batches(
+    writes: Vec<TransactWriteItem>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let client = dynamodb_client().await;
+    for chunk in writes.chunks(100) {
+        client
+            .transact_write_items()
+            .set_transact_items(Some(chunk.to_vec()))
+            .send()
+            .await?;
+    }
+    Ok(())
+}
+
+/// Writes every item of partition `draft` into partition `target`, which
+/// nothing reads yet. Each copy takes its content from the draft item, but
+/// the attributes named in `kept`, such as view counts, from its twin among
+/// `live` (the items of the partition `target` replaces), and goes one
+/// `version` past that twin, so a client holding the live version cannot
+/// write over it. Spilled bodies are copied, since a later draft save
+/// deletes the draft one. Returns how many items were written.
+pub async fn copy_partition(
+    draft: &str,
+    target: &str,
+    live: &[HashMap<String, AttributeValue>],
+    kept: &[&str],
+) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    let schema = schema();
+    let twins: HashMap<&str, &HashMap<String, AttributeValue>> = live
+        .iter()
+        .filter_map(|record| match record.get(&schema.sort_key) {
+            Some(AttributeValue::S(idx)) => Some((idx.as_str(), record)),
+            _ => None,
+        })
+        .collect();
+
+    let mut writes = Vec::new();
+    for mut item in stored_records(draft).await? {
+        let Some(AttributeValue::S(idx)) = item.get(&schema.sort_key).cloned() else {
+            continue;
+        };
+        let twin = twins.get(idx.as_str());
+        for name in kept.iter().chain([&VERSION_ATTRIBUTE]) {
+            item.remove(*name);
+            if let Some(value) = twin.and_then(|twin| twin.get(*name)) {
+                item.insert(name.to_string(), value.clone());
+            }
+        }
+        if let Some(twin) = twin {
+            let version = AttributeValue::N((version_of(twin) + 1).to_string());
+            item.insert(VERSION_ATTRIBUTE.to_string(), version);
+        }
+        if let Some(key) = overflow::value_ref(&item) {
+            let copy = overflow::copy(key, target, &idx).await?;
+            item.insert(VALUE_REF_ATTRIBUTE.to_string(), AttributeValue::S(copy));
+        }
+        item.insert(schema.partition_key.clone(), AttributeValue::S(target.to_string()));
+        let put = Put::builder()
+            .table_name(TABLE_NAME)
+            .set_item(Some(item))
+            .build()?;
+        writes.push(TransactWriteItem::builder().put(put).build());
+    }
+
+    let count = writes.len();
+    transact_in_batches(writes).await?;
+    Ok(count)
+}
+
+/// Deletes every item of partition `part`, and the bodies they spilled to
+/// S3. Returns how many items were deleted.
+pub async fn clear_partition(
+    part: &str,
+) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    let schema = schema();
+
+    let mut writes = Vec::new();
+    let mut bodies = Vec::new();
+    for record in stored_records(part).await? {
+        let Some(AttributeValue::S(idx)) = record.get(&schema.sort_key) else {
+            continue;
+        };
+        if let Some(key) = overflow::value_ref(&record) {
+            bodies.push(key.to_string());
+        }
+        let delete = Delete::builder()
+            .table_name(TABLE_NAME)
+            .key(&schema.partition_key, AttributeValue::S(part.to_string()))
+            .key(&schema.sort_key, AttributeValue::S(idx.clone()))
+            .build()?;
+        writes.push(TransactWriteItem::builder().delete(delete).build());
+    }
+    let removed = writes.len();
+    transact_in_batches(writes).await?;
+    overflow::discard(bodies).await;
+    Ok(removed)
+}
+
+/// Every item of the table, as stored: spilled values are not restored.
+pub async fn scan_table(
+) -> Result<Vec<HashMap<String, AttributeValue>>, Box<dyn std::error::Error + Send + Sync>> {
+    let client = dynamodb_client().await;
+
+    let mut items = Vec::new();
+    let mut start_key = None;
+    loop {
+        let output = client
+            .scan()
+            .table_name(TABLE_NAME)
+            .set_exclusive_start_key(start_key)
+            .send()
+            .await?;
+
+        items.extend(output.items.unwrap_or_default());
+        start_key = output.last_evaluated_key;
+        if start_key.is_none() {
+            break;
+        }
+    }
+
+    Ok(items)
+}
+
+/// Reads a whole item, `None` when it does not exist.
+pub async fn get_record(
+    part: String,
+    idx: String,
+) -> Result<Option<HashMap<String, AttributeValue>>, Box<dyn std::error::Error + Send + Sync>> {
+    let client = dynamodb_client().await;
+    let schema = schema();
+
+    let output = client
+        .get_item()
+        .table_name(TABLE_NAME)
+        .key(&schema.partition_key, AttributeValue::S(part))
+        .key(&schema.sort_key, AttributeValue::S(idx))
+        .send()
+        .await?;
+
+    let mut item = output.item;
+    if let Some(record) = item.as_mut() {
+        overflow::restore(record).await?;
+        compression::decompress(record)?;
+    }
+    Ok(item)
+}
+
+/// Writes up to 25 `(part, idx, value)` items with `BatchWriteItem`,
+/// resubmitting whatever DynamoDB reports as unprocessed.
+pub async fn batch_put_items(
+    items: Vec<(String, String, String)>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let client = dynamodb_client().await;
+    let schema = schema();
+
+    let now = AttributeValue::N(now_millis().to_string());
+
+    let mut requests = Vec::with_capacity(items.len());
+    for (part, idx, value) in items {
+        let (value, value_ref) = overflow::spill(&part, &idx, value).await?;
+        let mut item = HashMap::new();
+        item.insert(schema.partition_key.clone(), AttributeValue::S(part));
+        item.insert(schema.sort_key.clone(), AttributeValue::S(idx));
+        item.insert(schema.value_attribute.clone(), AttributeValue::S(value));
+        if let Some(key) = value_ref {
+            item.insert(VALUE_REF_ATTRIBUTE.to_string(), AttributeValue::S(key));
+        }
+        item.insert(CREATED_AT_ATTRIBUTE.to_string(), now.clone());
+        item.insert(UPDATED_AT_ATTRIBUTE.to_string(), now.clone());
+        let put = PutRequest::builder().set_item(Some(item)).build()?;
+        requests.push(WriteRequest::builder().put_request(put).build());
+    }
+
+    let mut pending = HashMap::from([(TABLE_NAME.to_string(), requests)]);
+    for attempt in 0..5 {
+        let output = client
+            .batch_write_item()
+            .set_request_items(Some(pending))
+            .send()
+            .await?;
+
+        pending = output.unprocessed_items.unwrap_or_default();
+        if pending.values().all(|r| r.is_empty()) {
+            return Ok(());
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50 << attempt)).await;
+    }
+
+    Err("batch write left unprocessed items".into())
+}
+
+/// Writes an item with the given key and arbitrary extra attributes.
+pub async fn put_record(
+    part: String,
+    idx: String,
+    attributes: HashMap<String, AttributeValue>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let client = dynamodb_client().await;
+    let schema = schema();
+
+    let mut item = attributes;
+    item.insert(schema.partition_key.clone(), AttributeValue::S(part));
+    item.insert(schema.sort_key.clone(), AttributeValue::S(idx));
 
     client
         .put_item()
@@ -58,20 +823,493 @@ pub async fn put_item(
     Ok(())
 }
 
-pub async fn delete_item(
+/// Sets the given attributes on an existing item, leaving the others as-is.
+/// Fails with a conditional check error when the item does not exist.
+pub async fn update_record(
     part: String,
     idx: String,
+    attributes: HashMap<String, AttributeValue>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let client = dynamodb_client().await;
+    let schema = schema();
 
-    let mut key = HashMap::new();
-    key.insert("part".to_string(), AttributeValue::S(part));
-    key.insert("idx".to_string(), AttributeValue::S(idx));
+    let mut request = client
+        .update_item()
+        .table_name(TABLE_NAME)
+        .key(&schema.partition_key, AttributeValue::S(part))
+        .key(&schema.sort_key, AttributeValue::S(idx));
+
+    let mut assignments = Vec::new();
+    for (i, (name, value)) in attributes.into_iter().enumerate() {
+        assignments.push(format!("#a{i} = :a{i}"));
+        request = request
+            .expression_attribute_names(format!("#a{i}"), name)
+            .expression_attribute_values(format!(":a{i}"), value);
+    }
+
+    assignments.push(VERSION_BUMP.to_string());
+    request
+        .bump_version()
+        .update_expression(format!("SET {}", assignments.join(", ")))
+        .condition_expression("attribute_exists(#pk)")
+        .expression_attribute_names("#pk", &schema.partition_key)
+        .send()
+        .await?;
+
+    Ok(())
+}
+
+/// Queries a global secondary index keyed by the partition attribute, in
+/// index sort order (`forward`) or reverse, returning at most `limit` items.
+pub async fn query_index(
+    index: &str,
+    part: String,
+    forward: bool,
+    limit: usize,
+) -> Result<Vec<HashMap<String, AttributeValue>>, Box<dyn std::error::Error + Send + Sync>> {
+    let client = dynamodb_client().await;
+    let schema = schema();
+
+    let mut records = Vec::new();
+    let mut start_key = None;
+    loop {
+        let output = client
+            .query()
+            .table_name(TABLE_NAME)
+            .index_name(index)
+            .key_condition_expression("#part = :part")
+            .expression_attribute_names("#part", &schema.partition_key)
+            .expression_attribute_values(":part", AttributeValue::S(part.clone()))
+            .scan_index_forward(forward)
+            .limit(limit.min(1000) as i32)
+            .set_exclusive_start_key(start_key)
+            .send()
+            .await?;
+
+        records.extend(output.items.unwrap_or_default());
+        start_key = output.last_evaluated_key;
+        if records.len() >= limit || start_key.is_none() {
+            break;
+        }
+    }
+
+    records.truncate(limit);
+    for record in records.iter_mut() {
+        overflow::restore(record).await?;
+        compression::decompress(record)?;
+    }
+    Ok(records)
+}
+
+/// Number of items in a partition, counted server-side.
+pub async fn count_items(part: String) -> Result<i64, Box<dyn std::error::Error + Send + Sync>> {
+    let client = dynamodb_client().await;
+    let schema = schema();
+
+    let mut count = 0;
+    let mut start_key = None;
+    loop {
+        let output = client
+            .query()
+            .table_name(TABLE_NAME)
+            .key_condition_expression("#part = :part")
+            .expression_attribute_names("#part", &schema.partition_key)
+            .expression_attribute_values(":part", AttributeValue::S(part.clone()))
+            .select(Select::Count)
+            .set_exclusive_start_key(start_key)
+            .send()
+            .await?;
+
+        count += output.count as i64;
+        start_key = output.last_evaluated_key;
+        if start_key.is_none() {
+            break;
+        }
+    }
+
+    Ok(count)
+}
+
+/// Atomically adds `by` to a numeric attribute, creating the item if needed,
+/// and returns the new total.
+pub async fn increment_counter(
+    part: String,
+    idx: String,
+    attribute: &str,
+    by: i64,
+) -> Result<i64, Box<dyn std::error::Error + Send + Sync>> {
+    let client = dynamodb_client().await;
+    let schema = schema();
+
+    let output = client
+        .update_item()
+        .table_name(TABLE_NAME)
+        .key(&schema.partition_key, AttributeValue::S(part))
+        .key(&schema.sort_key, AttributeValue::S(idx))
+        .update_expression("ADD #count :by")
+        .expression_attribute_names("#count", attribute)
+        .expression_attribute_values(":by", AttributeValue::N(by.to_string()))
+        .return_values(ReturnValue::UpdatedNew)
+        .send()
+        .await?;
+
+    let total = match output.attributes.as_ref().and_then(|a| a.get(attribute)) {
+        Some(AttributeValue::N(n)) => n.parse()?,
+        _ => 0,
+    };
+    Ok(total)
+}
+
+/// Queries a partition, optionally bounded to `idx BETWEEN from AND to`, and
+/// keeps only items whose attributes equal every `filters` entry. Pages
+/// through the partition until `limit` matching items are collected.
+pub async fn query_records(
+    part: String,
+    range: Option<(String, String)>,
+    filters: Vec<(String, AttributeValue)>,
+    limit: usize,
+    newest_first: bool,
+) -> Result<Vec<HashMap<String, AttributeValue>>, Box<dyn std::error::Error + Send + Sync>> {
+    let client = dynamodb_client().await;
+    let schema = schema();
+
+    let mut key_condition = "#part = :part".to_string();
+    if range.is_some() {
+        key_condition.push_str(" AND #idx BETWEEN :from AND :to");
+    }
+    let filter_expression = (0..filters.len())
+        .map(|i| format!("#f{i} = :f{i}"))
+        .collect::<Vec<_>>()
+        .join(" AND ");
+
+    let mut records = Vec::new();
+    let mut start_key = None;
+    loop {
+        let mut request = client
+            .query()
+            .table_name(TABLE_NAME)
+            .key_condition_expression(&key_condition)
+            .expression_attribute_names("#part", &schema.partition_key)
+            .expression_attribute_values(":part", AttributeValue::S(part.clone()))
+            .scan_index_forward(!newest_first)
+            .set_exclusive_start_key(start_key);
+        if let Some((from, to)) = &range {
+            request = request
+                .expression_attribute_names("#idx", &schema.sort_key)
+                .expression_attribute_values(":from", AttributeValue::S(from.clone()))
+                .expression_attribute_values(":to", AttributeValue::S(to.clone()));
+        }
+        if !filters.is_empty() {
+            request = request.filter_expression(&filter_expression);
+            for (i, (name, value)) in filters.iter().enumerate() {
+                request = request
+                    .expression_attribute_names(format!("#f{i}"), name)
+                    .expression_attribute_values(format!(":f{i}"), value.clone());
+            }
+        }
+
+        let output = request.send().await?;
+        records.extend(output.items.unwrap_or_default());
+        start_key = output.last_evaluated_key;
+        if records.len() >= limit || start_key.is_none() {
+            break;
+        }
+    }
+
+    records.truncate(limit);
+    for record in records.iter_mut() {
+        overflow::restore(record).await?;
+        compression::decompress(record)?;
+    }
+    Ok(records)
+}
+
+/// Renders an attribute map as plain JSON for API responses.
+pub fn record_to_json(record: &HashMap<String, AttributeValue>) -> serde_json::Value {
+    fn convert(value: &AttributeValue) -> serde_json::Value {
+        match value {
+            AttributeValue::S(s) => serde_json::Value::String(s.clone()),
+            AttributeValue::N(n) => n
+                .parse::<i64>()
+                .map(serde_json::Value::from)
+                .or_else(|_| n.parse::<f64>().map(serde_json::Value::from))
+                .unwrap_or(serde_json::Value::Null),
+            AttributeValue::Bool(b) => serde_json::Value::Bool(*b),
+            AttributeValue::L(items) => items.iter().map(convert).collect(),
+            AttributeValue::M(map) => map
+                .iter()
+                .map(|(k, v)| (k.clone(), convert(v)))
+                .collect::<serde_json::Map<_, _>>()
+                .into(),
+            AttributeValue::Ss(items) => items.clone().into(),
+            _ => serde_json::Value::Null,
+        }
+    }
+
+    record
+        .iter()
+        .map(|(k, v)| (k.clone(), convert(v)))
+        .collect::<serde_json::Map<_, _>>()
+        .into()
+}
+
+/// An item as returned by the partition listing.
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+pub struct ItemSummary {
+    pub idx: String,
+    pub value: Option<String>,
+    pub pinned: bool,
+    /// The value of an encrypted item is left out of listings.
+    pub encrypted: bool,
+    #[cfg_attr(feature = "ts", ts(type = "number | null"))]
+    pub sort_weight: Option<i64>,
+    /// What the item's ETag is built from, see `etag`.
+    #[cfg_attr(feature = "ts", ts(type = "number"))]
+    pub version: u64,
+    #[serde(skip)]
+    pub series_id: Option<String>,
+    /// Where `value` was read from when it was spilled to S3.
+    #[serde(skip)]
+    pub value_ref: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "ts", ts(optional))]
+    pub series: Option<SeriesLinks>,
+}
+
+/// Lists every item of a partition: pinned items first, then by ascending
+/// `sort_weight` (unweighted items last), then by `idx`.
+pub async fn list_items(
+    part: String,
+) -> Result<Vec<ItemSummary>, Box<dyn std::error::Error + Send + Sync>> {
+    let schema = schema();
+    let records = query_records(part, None, Vec::new(), usize::MAX, false).await?;
+
+    let mut items: Vec<ItemSummary> = records
+        .iter()
+        .map(|record| {
+            let string = |name: &str| match record.get(name) {
+                Some(AttributeValue::S(s)) => Some(s.clone()),
+                _ => None,
+            };
+            let flag = |name: &str| matches!(record.get(name), Some(AttributeValue::Bool(true)));
+            let encrypted = flag(ENCRYPTED_ATTRIBUTE);
+            ItemSummary {
+                idx: string(&schema.sort_key).unwrap_or_default(),
+                value: string(&schema.value_attribute).filter(|_| !encrypted),
+                pinned: flag(PINNED_ATTRIBUTE),
+                encrypted,
+                sort_weight: match record.get(SORT_WEIGHT_ATTRIBUTE) {
+                    Some(AttributeValue::N(n)) => n.parse().ok(),
+                    _ => None,
+                },
+                version: version_of(record),
+                series_id: string(SERIES_ATTRIBUTE),
+                value_ref: string(VALUE_REF_ATTRIBUTE),
+                series: None,
+            }
+        })
+        .collect();
+
+    items.sort_by(|a, b| {
+        b.pinned
+            .cmp(&a.pinned)
+            .then_with(|| match (a.sort_weight, b.sort_weight) {
+                (Some(x), Some(y)) => x.cmp(&y),
+                (Some(_), None) => std::cmp::Ordering::Less,
+                (None, Some(_)) => std::cmp::Ordering::Greater,
+                (None, None) => std::cmp::Ordering::Equal,
+            })
+            .then_with(|| a.idx.cmp(&b.idx))
+    });
+
+    Ok(items)
+}
+
+/// Pins or unpins an item. `false` when the item does not exist.
+pub async fn set_pinned(
+    part: String,
+    idx: String,
+    pinned: bool,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let mut attributes = HashMap::new();
+    attributes.insert(PINNED_ATTRIBUTE.to_string(), AttributeValue::Bool(pinned));
+    match update_record(part, idx, attributes).await {
+        Ok(()) => Ok(true),
+        Err(e) if error_kind(e.as_ref()) == ErrorKind::Conflict => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Gives each listed item a `sort_weight` equal to its position in `order`,
+/// which must not repeat an idx. All updates succeed or none do; every item
+/// must already exist, and the ones that do not are returned instead.
+pub async fn set_sort_weights(
+    part: String,
+    order: Vec<String>,
+) -> Result<Result<(), Vec<String>>, Box<dyn std::error::Error + Send + Sync>> {
+    use aws_sdk_dynamodb::operation::transact_write_items::TransactWriteItemsError;
+
+    let client = dynamodb_client().await;
+    let schema = schema();
+
+    let mut writes = Vec::with_capacity(order.len());
+    for (weight, idx) in order.iter().enumerate() {
+        let update = Update::builder()
+            .table_name(TABLE_NAME)
+            .key(&schema.partition_key, AttributeValue::S(part.clone()))
+            .key(&schema.sort_key, AttributeValue::S(idx.clone()))
+            .update_expression(format!("SET #w = :w, {VERSION_BUMP}"))
+            .condition_expression("attribute_exists(#pk)")
+            .expression_attribute_names("#w", SORT_WEIGHT_ATTRIBUTE)
+            .expression_attribute_names("#pk", &schema.partition_key)
+            .expression_attribute_values(":w", AttributeValue::N(weight.to_string()))
+            .bump_version()
+            .build()?;
+        writes.push(TransactWriteItem::builder().update(update).build());
+    }
+
+    let result = client
+        .transact_write_items()
+        .set_transact_items(Some(writes))
+        .send()
+        .await;
+    let Err(e) = result else {
+        return Ok(Ok(()));
+    };
+
+    // reasons line up with the writes; a failed condition is a missing item
+    if let Some(TransactWriteItemsError::TransactionCanceledException(cancelled)) =
+        e.as_service_error()
+    {
+        let missing: Vec<String> = cancelled
+            .cancellation_reasons()
+            .iter()
+            .zip(&order)
+            .filter(|(reason, _)| reason.code() == Some("ConditionalCheckFailed"))
+            .map(|(_, idx)| idx.clone())
+            .collect();
+        if !missing.is_empty() {
+            return Ok(Err(missing));
+        }
+    }
+    Err(e.into())
+}
+
+/// State of a global secondary index the code relies on.
+#[derive(Debug, Serialize)]
+pub struct IndexStatus {
+    pub name: String,
+    pub sort_key: String,
+    pub status: Option<String>,
+}
+
+/// Compares the table's GSIs with `required` (`(index name, numeric sort key
+/// attribute)`, partitioned by the partition key). `status` is `None` for
+/// indexes that do not exist.
+pub async fn describe_indexes(
+    required: &[(&str, &str)],
+) -> Result<Vec<IndexStatus>, Box<dyn std::error::Error + Send + Sync>> {
+    let client = dynamodb_client().await;
+
+    let table = client
+        .describe_table()
+        .table_name(TABLE_NAME)
+        .send()
+        .await?
+        .table
+        .ok_or("table description missing")?;
+    let existing = table.global_secondary_indexes.unwrap_or_default();
+
+    Ok(required
+        .iter()
+        .map(|(name, sort_key)| IndexStatus {
+            name: name.to_string(),
+            sort_key: sort_key.to_string(),
+            status: existing
+                .iter()
+                .find(|index| index.index_name.as_deref() == Some(*name))
+                .map(|index| {
+                    index
+                        .index_status
+                        .as_ref()
+                        .map(|s| s.as_str().to_string())
+                        .unwrap_or_default()
+                }),
+        })
+        .collect())
+}
+
+/// Starts creating a missing GSI. DynamoDB accepts one index creation per
+/// `UpdateTable` call, so callers create missing indexes one at a time.
+/// Provisioned tables give the index the table's own throughput.
+pub async fn create_index(
+    name: &str,
+    sort_key: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let client = dynamodb_client().await;
+    let schema = schema();
+
+    let table = client
+        .describe_table()
+        .table_name(TABLE_NAME)
+        .send()
+        .await?
+        .table
+        .ok_or("table description missing")?;
+    let throughput = match table.billing_mode_summary.and_then(|b| b.billing_mode) {
+        Some(BillingMode::PayPerRequest) => None,
+        _ => table.provisioned_throughput.map(|t| {
+            ProvisionedThroughput::builder()
+                .read_capacity_units(t.read_capacity_units.unwrap_or(1))
+                .write_capacity_units(t.write_capacity_units.unwrap_or(1))
+                .build()
+        }),
+    };
+
+    let mut index = CreateGlobalSecondaryIndexAction::builder()
+        .index_name(name)
+        .key_schema(
+            KeySchemaElement::builder()
+                .attribute_name(&schema.partition_key)
+                .key_type(KeyType::Hash)
+                .build()?,
+        )
+        .key_schema(
+            KeySchemaElement::builder()
+                .attribute_name(sort_key)
+                .key_type(KeyType::Range)
+                .build()?,
+        )
+        .projection(
+            Projection::builder()
+                .projection_type(ProjectionType::All)
+                .build(),
+        );
+    if let Some(throughput) = throughput {
+        index = index.provisioned_throughput(throughput?);
+    }
 
     client
-        .delete_item()
+        .update_table()
         .table_name(TABLE_NAME)
-        .set_key(Some(key))
+        .attribute_definitions(
+            AttributeDefinition::builder()
+                .attribute_name(&schema.partition_key)
+                .attribute_type(ScalarAttributeType::S)
+                .build()?,
+        )
+        .attribute_definitions(
+            AttributeDefinition::builder()
+                .attribute_name(sort_key)
+                .attribute_type(ScalarAttributeType::N)
+                .build()?,
+        )
+        .global_secondary_index_updates(
+            GlobalSecondaryIndexUpdate::builder()
+                .create(index.build()?)
+                .build(),
+        )
         .send()
         .await?;
 
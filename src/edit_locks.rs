@@ -0,0 +1,188 @@
+use crate::clock::now_millis;
+use crate::dynamodb::{dynamodb_client, generate_idx, get_record, schema, TABLE_NAME};
+use aws_sdk_dynamodb::operation::delete_item::DeleteItemError;
+use aws_sdk_dynamodb::operation::put_item::PutItemError;
+use aws_sdk_dynamodb::operation::update_item::UpdateItemError;
+use aws_sdk_dynamodb::types::{AttributeValue, ReturnValuesOnConditionCheckFailure};
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Soft edit locks on posts, one item per post id that is open in an editor.
+pub const EDIT_LOCKS_PARTITION: &str = "edit_locks";
+
+const DEFAULT_TTL_SECS: u64 = 120;
+
+/// How long a lock lasts without a heartbeat, from `edit_lock_ttl_secs`.
+fn ttl_millis() -> u64 {
+    std::env::var("edit_lock_ttl_secs")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .filter(|s| *s > 0)
+        .unwrap_or(DEFAULT_TTL_SECS)
+        * 1000
+}
+
+/// Who has a post open. `token` is only ever shown to the holder, who needs
+/// it for heartbeats and the release.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EditLock {
+    pub editor: String,
+    pub acquired_at: u64,
+    pub expires_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+impl EditLock {
+    /// The lock in `record`, without its token.
+    fn from_record(record: &HashMap<String, AttributeValue>) -> Option<EditLock> {
+        let number = |name: &str| match record.get(name) {
+            Some(AttributeValue::N(n)) => n.parse().ok(),
+            _ => None,
+        };
+        Some(EditLock {
+            editor: match record.get("editor") {
+                Some(AttributeValue::S(s)) => s.clone(),
+                _ => String::new(),
+            },
+            acquired_at: number("acquired_at")?,
+            expires_at: number("expires_at")?,
+            token: None,
+        })
+    }
+}
+
+/// The current lock on post `idx`, if any. Expired locks linger until
+/// DynamoDB TTL removes them, so they are filtered here.
+pub async fn status(
+    part: String,
+    idx: String,
+) -> Result<Option<EditLock>, Box<dyn std::error::Error + Send + Sync>> {
+    let record = get_record(part, idx).await?;
+    Ok(record
+        .as_ref()
+        .and_then(EditLock::from_record)
+        .filter(|lock| lock.expires_at >= now_millis()))
+}
+
+/// Takes the lock on post `idx` for `editor`, unless someone else holds it
+/// and `force` is not set. `Err` carries the other holder, so the editor can
+/// warn before both versions of the post get saved.
+pub async fn acquire(
+    part: String,
+    idx: String,
+    editor: String,
+    force: bool,
+) -> Result<Result<EditLock, EditLock>, Box<dyn std::error::Error + Send + Sync>> {
+    let client = dynamodb_client().await;
+    let schema = schema();
+
+    let token = generate_idx();
+    let now = now_millis();
+    let expires_at = now + ttl_millis();
+
+    let mut request = client
+        .put_item()
+        .table_name(TABLE_NAME)
+        .item(&schema.partition_key, AttributeValue::S(part))
+        .item(&schema.sort_key, AttributeValue::S(idx))
+        .item("editor", AttributeValue::S(editor.clone()))
+        .item("token", AttributeValue::S(token.clone()))
+        .item("acquired_at", AttributeValue::N(now.to_string()))
+        .item("expires_at", AttributeValue::N(expires_at.to_string()))
+        // lets DynamoDB TTL clean up locks of editors that were closed
+        .item("ttl", AttributeValue::N((expires_at / 1000 + 1).to_string()));
+    if !force {
+        request = request
+            .condition_expression("attribute_not_exists(#pk) OR #expires < :now")
+            .expression_attribute_names("#pk", &schema.partition_key)
+            .expression_attribute_names("#expires", "expires_at")
+            .expression_attribute_values(":now", AttributeValue::N(now.to_string()))
+            .return_values_on_condition_check_failure(
+                ReturnValuesOnConditionCheckFailure::AllOld,
+            );
+    }
+
+    match request.send().await {
+        Ok(_) => Ok(Ok(EditLock {
+            editor,
+            acquired_at: now,
+            expires_at,
+            token: Some(token),
+        })),
+        Err(e) => match e.as_service_error() {
+            Some(PutItemError::ConditionalCheckFailedException(failed)) => {
+                let holder = failed.item().and_then(EditLock::from_record);
+                Ok(Err(holder.ok_or("edit lock holder missing")?))
+            }
+            _ => Err(e.into()),
+        },
+    }
+}
+
+/// Extends the lock held with `token`. `None` when it was lost: it expired
+/// and someone else took it, or it was taken over with `force`.
+pub async fn heartbeat(
+    part: String,
+    idx: String,
+    token: String,
+) -> Result<Option<u64>, Box<dyn std::error::Error + Send + Sync>> {
+    let client = dynamodb_client().await;
+    let schema = schema();
+
+    let expires_at = now_millis() + ttl_millis();
+    let ttl = AttributeValue::N((expires_at / 1000 + 1).to_string());
+    let result = client
+        .update_item()
+        .table_name(TABLE_NAME)
+        .key(&schema.partition_key, AttributeValue::S(part))
+        .key(&schema.sort_key, AttributeValue::S(idx))
+        .update_expression("SET #expires = :expires, #ttl = :ttl")
+        .condition_expression("#token = :token")
+        .expression_attribute_names("#expires", "expires_at")
+        .expression_attribute_names("#ttl", "ttl")
+        .expression_attribute_names("#token", "token")
+        .expression_attribute_values(":expires", AttributeValue::N(expires_at.to_string()))
+        .expression_attribute_values(":ttl", ttl)
+        .expression_attribute_values(":token", AttributeValue::S(token))
+        .send()
+        .await;
+
+    match result {
+        Ok(_) => Ok(Some(expires_at)),
+        Err(e) => match e.as_service_error() {
+            Some(UpdateItemError::ConditionalCheckFailedException(_)) => Ok(None),
+            _ => Err(e.into()),
+        },
+    }
+}
+
+/// Frees the lock held with `token`; a lock that was lost is left alone.
+pub async fn release(
+    part: String,
+    idx: String,
+    token: String,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let client = dynamodb_client().await;
+    let schema = schema();
+
+    let result = client
+        .delete_item()
+        .table_name(TABLE_NAME)
+        .key(&schema.partition_key, AttributeValue::S(part))
+        .key(&schema.sort_key, AttributeValue::S(idx))
+        .condition_expression("#token = :token")
+        .expression_attribute_names("#token", "token")
+        .expression_attribute_values(":token", AttributeValue::S(token))
+        .send()
+        .await;
+
+    match result {
+        Ok(_) => Ok(()),
+        Err(e) => match e.as_service_error() {
+            Some(DeleteItemError::ConditionalCheckFailedException(_)) => Ok(()),
+            _ => Err(e.into()),
+        },
+    }
+}
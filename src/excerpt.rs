@@ -0,0 +1,96 @@
+const MAX_EXCERPT_CHARS: usize = 300;
+
+/// Drops markdown syntax, keeping the readable text: code blocks and images
+/// are removed, links keep their label, and heading, quote, list and
+/// emphasis markers as well as inline HTML tags are stripped.
+fn strip_markdown(markdown: &str) -> String {
+    let mut text = String::new();
+    let mut in_fence = false;
+
+    for line in markdown.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+            continue;
+        }
+        if in_fence {
+            continue;
+        }
+
+        let content = trimmed
+            .trim_start_matches('#')
+            .trim_start_matches('>')
+            .trim_start();
+        let content = content
+            .strip_prefix("- ")
+            .or_else(|| content.strip_prefix("* "))
+            .or_else(|| content.strip_prefix("+ "))
+            .unwrap_or(content);
+        text.push_str(&strip_inline(content));
+        text.push(' ');
+    }
+
+    text
+}
+
+fn strip_inline(line: &str) -> String {
+    let mut out = String::new();
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            // image: drop entirely
+            '!' if chars.peek() == Some(&'[') => {
+                chars.by_ref().find(|&c| c == ']');
+                if chars.peek() == Some(&'(') {
+                    chars.by_ref().find(|&c| c == ')');
+                }
+            }
+            // link: keep the label
+            '[' => {
+                let label: String = chars.by_ref().take_while(|&c| c != ']').collect();
+                out.push_str(&label);
+                if chars.peek() == Some(&'(') {
+                    chars.by_ref().find(|&c| c == ')');
+                }
+            }
+            '<' => {
+                chars.by_ref().find(|&c| c == '>');
+            }
+            '*' | '_' | '`' | '~' => {}
+            c => out.push(c),
+        }
+    }
+
+    out
+}
+
+/// The first `sentences` sentences of a markdown body as plain text, capped
+/// at `MAX_EXCERPT_CHARS` characters.
+pub fn excerpt(markdown: &str, sentences: usize) -> String {
+    let text = strip_markdown(markdown)
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let mut end = text.len();
+    let mut found = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let at_boundary = matches!(chars.peek(), None | Some((_, ' ')));
+        if matches!(c, '.' | '!' | '?' | '。' | '！' | '？') && at_boundary {
+            found += 1;
+            if found == sentences {
+                end = i + c.len_utf8();
+                break;
+            }
+        }
+    }
+
+    let excerpt = &text[..end];
+    if excerpt.chars().count() <= MAX_EXCERPT_CHARS {
+        return excerpt.to_string();
+    }
+    let cut: String = excerpt.chars().take(MAX_EXCERPT_CHARS).collect();
+    format!("{}…", cut.trim_end())
+}
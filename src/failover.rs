@@ -0,0 +1,114 @@
+use crate::clock::now_millis;
+use aws_config::{BehaviorVersion, Region, SdkConfig};
+use aws_sdk_dynamodb::config::interceptors::FinalizerInterceptorContextRef;
+use aws_sdk_dynamodb::config::{ConfigBag, Intercept, RuntimeComponents};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use tokio::sync::OnceCell;
+
+const DEFAULT_THRESHOLD: u32 = 5;
+const DEFAULT_COOLDOWN_SECS: u64 = 300;
+
+/// Consecutive primary-region failures seen by this container.
+static FAILURES: AtomicU32 = AtomicU32::new(0);
+/// Epoch millis until which this container uses the failover region.
+static FAILED_OVER_UNTIL: AtomicU64 = AtomicU64::new(0);
+
+fn var(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|v| !v.is_empty())
+}
+
+fn number<T: std::str::FromStr>(name: &str, default: T) -> T {
+    var(name).and_then(|v| v.parse().ok()).unwrap_or(default)
+}
+
+/// Whether this container currently sends DynamoDB and S3 calls to
+/// `failover_region`. Failover lasts `failover_cooldown_secs`; the next
+/// call after that tries the primary region again.
+pub fn is_failed_over() -> bool {
+    var("failover_region").is_some() && now_millis() < FAILED_OVER_UNTIL.load(Ordering::Relaxed)
+}
+
+fn record(failed: bool) {
+    if !failed {
+        FAILURES.store(0, Ordering::Relaxed);
+        return;
+    }
+    let failures = FAILURES.fetch_add(1, Ordering::Relaxed) + 1;
+    let Some(region) = var("failover_region") else {
+        return;
+    };
+    if failures >= number("failover_threshold", DEFAULT_THRESHOLD) && !is_failed_over() {
+        let cooldown = number("failover_cooldown_secs", DEFAULT_COOLDOWN_SECS);
+        FAILED_OVER_UNTIL.store(now_millis() + cooldown * 1000, Ordering::Relaxed);
+        FAILURES.store(0, Ordering::Relaxed);
+        tracing::warn!(
+            "failing over to {} for {}s after {} consecutive failures",
+            region,
+            cooldown,
+            failures
+        );
+    }
+}
+
+/// SDK configuration for the active region: `data_region` (default: the
+/// function's own region), or `failover_region` while failed over. Each is
+/// loaded once per container; clients built from it share its credentials.
+pub async fn sdk_config() -> SdkConfig {
+    static PRIMARY: OnceCell<SdkConfig> = OnceCell::const_new();
+    static FAILOVER: OnceCell<SdkConfig> = OnceCell::const_new();
+
+    let (cell, region) = if is_failed_over() {
+        (&FAILOVER, var("failover_region"))
+    } else {
+        (&PRIMARY, var("data_region"))
+    };
+    cell.get_or_init(|| async {
+        let mut loader = aws_config::defaults(BehaviorVersion::latest());
+        if let Some(region) = region {
+            loader = loader.region(Region::new(region));
+        }
+        loader.load().await
+    })
+    .await
+    .clone()
+}
+
+/// The bucket of the active region: `s3_bucket`, or its replica
+/// `failover_s3_bucket` while failed over.
+pub fn s3_bucket() -> Option<String> {
+    if is_failed_over() {
+        if let Some(replica) = var("failover_s3_bucket") {
+            return Some(replica);
+        }
+    }
+    var("s3_bucket")
+}
+
+/// Counts calls to the primary region that fail at the service (5xx) or get
+/// no response at all (timeouts, connection errors). Client errors, such as
+/// failed conditions, say nothing about the region's health.
+#[derive(Debug)]
+pub struct FailureDetector;
+
+impl Intercept for FailureDetector {
+    fn name(&self) -> &'static str {
+        "FailureDetector"
+    }
+
+    fn read_after_execution(
+        &self,
+        context: &FinalizerInterceptorContextRef<'_>,
+        _runtime_components: &RuntimeComponents,
+        _cfg: &mut ConfigBag,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if is_failed_over() {
+            return Ok(());
+        }
+        let failed = match context.response() {
+            Some(response) => response.status().is_server_error(),
+            None => context.output_or_error().is_some_and(|r| r.is_err()),
+        };
+        record(failed);
+        Ok(())
+    }
+}
@@ -0,0 +1,114 @@
+use crate::clock::rfc3339;
+use crate::posts::{list_posts, PostSort};
+use crate::urls::PublicUrls;
+use crate::view::View;
+use serde_json::{json, Value};
+
+/// Newest posts included in a feed.
+const FEED_SIZE: usize = 20;
+
+/// Format-neutral feed, built once and rendered by each feed route so the
+/// formats always list the same posts.
+pub struct Feed {
+    pub title: String,
+    pub home_page_url: String,
+    pub items: Vec<FeedItem>,
+}
+
+pub struct FeedItem {
+    pub id: String,
+    pub url: String,
+    pub title: String,
+    pub summary: Option<String>,
+    /// Markdown source of the post.
+    pub content: String,
+    pub published: Option<String>,
+    pub modified: Option<String>,
+    /// Where the post was first published, when that isn't this site.
+    pub canonical_url: Option<String>,
+    /// Copies of the post on other sites (`syndication[].url`).
+    pub syndication: Vec<String>,
+}
+
+fn timestamp(post: &Value, field: &str) -> Option<String> {
+    post.get(field).and_then(|t| t.as_u64()).map(rfc3339)
+}
+
+/// The `FEED_SIZE` most recently created posts of `part`, linked under
+/// the site of `urls`.
+pub async fn build(
+    part: String,
+    urls: &PublicUrls,
+    title: String,
+) -> Result<Feed, Box<dyn std::error::Error + Send + Sync>> {
+    let mut posts = list_posts(part, PostSort::CreatedAt, true, FEED_SIZE, None).await?;
+    posts.iter_mut().for_each(|post| View::Public.post(post));
+    let text = |post: &Value, field: &str| {
+        post.get(field).and_then(|v| v.as_str()).map(String::from)
+    };
+
+    let items = posts
+        .iter()
+        .filter_map(|post| {
+            let idx = text(post, "idx")?;
+            let url = urls.post(&idx);
+            let canonical_url = text(post, "canonicalUrl").filter(|c| c != &url);
+            Some(FeedItem {
+                id: url.clone(),
+                url,
+                title: text(post, "title").unwrap_or_else(|| idx.clone()),
+                summary: text(post, "excerpt").filter(|e| !e.is_empty()),
+                content: text(post, "body")
+                    .or_else(|| text(post, "content"))
+                    .or_else(|| text(post, "value"))
+                    .unwrap_or_default(),
+                published: timestamp(post, "created_at"),
+                modified: timestamp(post, "updated_at"),
+                canonical_url,
+                syndication: post
+                    .get("syndication")
+                    .and_then(|s| s.as_array())
+                    .map(|links| links.iter().filter_map(|l| text(l, "url")).collect())
+                    .unwrap_or_default(),
+            })
+        })
+        .collect();
+
+    Ok(Feed {
+        title,
+        home_page_url: urls.site().to_string(),
+        items,
+    })
+}
+
+/// JSON Feed 1.1 (https://www.jsonfeed.org/version/1.1/).
+pub fn to_json_feed(feed: &Feed, feed_url: &str) -> Value {
+    let items: Vec<Value> = feed
+        .items
+        .iter()
+        .map(|item| {
+            let mut entry = json!({
+                "id": item.id,
+                "url": item.url,
+                "external_url": item.canonical_url,
+                "title": item.title,
+                "summary": item.summary,
+                "content_text": item.content,
+                "date_published": item.published,
+                "date_modified": item.modified,
+            });
+            if !item.syndication.is_empty() {
+                entry["_syndication"] = json!({ "links": item.syndication });
+            }
+            entry
+        })
+        .collect();
+
+    json!({
+        "version": "https://jsonfeed.org/version/1.1",
+        "title": feed.title,
+        "home_page_url": feed.home_page_url,
+        "feed_url": feed_url,
+        "items": items,
+    })
+}
@@ -0,0 +1,94 @@
+use lambda_http::{Body, Response};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+/// Paths one `fields` parameter may name; the rest are ignored.
+const MAX_PATHS: usize = 100;
+
+/// Set in the extensions of a response whose route already applied
+/// `fields` in its own way, so it is not projected again.
+#[derive(Debug, Clone, Copy)]
+pub struct Projected;
+
+/// The attributes to keep, by name. `None` keeps an attribute whole,
+/// `Some` keeps only the named attributes inside it.
+#[derive(Debug, Default, PartialEq)]
+pub struct Projection(BTreeMap<String, Option<Projection>>);
+
+impl Projection {
+    /// Parses `fields`: comma-separated attribute names, with dots for
+    /// nested ones (`title,author.name`). `None` when it names nothing.
+    pub fn parse(fields: &str) -> Option<Projection> {
+        let mut projection = Projection::default();
+        let paths = fields
+            .split(',')
+            .map(str::trim)
+            .filter(|path| !path.is_empty())
+            .take(MAX_PATHS);
+        for path in paths {
+            let segments: Vec<&str> = path.split('.').map(str::trim).collect();
+            projection.add(&segments);
+        }
+        Some(projection).filter(|p| !p.0.is_empty())
+    }
+
+    fn add(&mut self, path: &[&str]) {
+        let Some((name, rest)) = path.split_first().filter(|(name, _)| !name.is_empty()) else {
+            return;
+        };
+        if rest.iter().all(|segment| segment.is_empty()) {
+            // the whole attribute wins over any part of it
+            self.0.insert(name.to_string(), None);
+            return;
+        }
+        let entry = self.0.entry(name.to_string());
+        if let Some(nested) = entry.or_insert_with(|| Some(Projection::default())) {
+            nested.add(rest);
+        }
+    }
+
+    /// `value` with only the projected attributes. Arrays are projected
+    /// element by element, and other values are kept as they are.
+    pub fn apply(&self, value: Value) -> Value {
+        match value {
+            Value::Object(map) => {
+                let kept: Map<String, Value> = map
+                    .into_iter()
+                    .filter_map(|(name, value)| match self.0.get(&name)? {
+                        Some(nested) => Some((name, nested.apply(value))),
+                        None => Some((name, value)),
+                    })
+                    .collect();
+                Value::Object(kept)
+            }
+            Value::Array(items) => Value::Array(items.into_iter().map(|v| self.apply(v)).collect()),
+            other => other,
+        }
+    }
+}
+
+/// Applies `projection` to a successful JSON response. Error responses,
+/// other content types, encoded bodies and responses their route already
+/// projected are left alone.
+pub fn project(response: &mut Response<Body>, projection: &Projection) {
+    let is_json = response
+        .headers()
+        .get("content-type")
+        .and_then(|h| h.to_str().ok())
+        .is_some_and(|t| t.starts_with("application/json"));
+    if !response.status().is_success()
+        || !is_json
+        || response.headers().contains_key("content-encoding")
+        || response.extensions().get::<Projected>().is_some()
+    {
+        return;
+    }
+    let Body::Text(text) = response.body() else {
+        return;
+    };
+    let Ok(value) = serde_json::from_str::<Value>(text) else {
+        return;
+    };
+    *response.body_mut() = Body::Text(projection.apply(value).to_string());
+    response.headers_mut().remove("content-length");
+}
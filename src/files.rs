@@ -0,0 +1,128 @@
+use crate::s3::StoredObject;
+use serde::{Deserialize, Serialize};
+
+/// Results `GET /api/files/search` returns unless asked for fewer.
+pub const DEFAULT_LIMIT: usize = 50;
+/// Most results one search returns.
+pub const MAX_LIMIT: usize = 200;
+
+/// Broad content classes the media search filters by, told apart by file
+/// extension since listings carry no content type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileType {
+    Image,
+    Video,
+    Audio,
+    Document,
+    Other,
+}
+
+impl FileType {
+    pub fn parse(value: &str) -> Option<FileType> {
+        match value {
+            "image" => Some(FileType::Image),
+            "video" => Some(FileType::Video),
+            "audio" => Some(FileType::Audio),
+            "document" => Some(FileType::Document),
+            "other" => Some(FileType::Other),
+            _ => None,
+        }
+    }
+
+    pub fn of(name: &str) -> FileType {
+        let extension = name
+            .rsplit_once('.')
+            .map(|(_, ext)| ext.to_ascii_lowercase())
+            .unwrap_or_default();
+        match extension.as_str() {
+            "jpg" | "jpeg" | "png" | "gif" | "webp" | "avif" | "svg" | "bmp" | "ico" | "heic" => {
+                FileType::Image
+            }
+            "mp4" | "webm" | "mov" | "m4v" | "mkv" | "avi" => FileType::Video,
+            "mp3" | "wav" | "ogg" | "oga" | "m4a" | "flac" | "aac" | "opus" => FileType::Audio,
+            "pdf" | "doc" | "docx" | "odt" | "rtf" | "txt" | "md" | "csv" | "xls" | "xlsx"
+            | "ods" | "ppt" | "pptx" | "odp" => FileType::Document,
+            _ => FileType::Other,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileMatch {
+    pub key: String,
+    pub name: String,
+    #[serde(rename = "type")]
+    pub file_type: FileType,
+    pub size: i64,
+    /// Epoch milliseconds.
+    pub last_modified: i64,
+}
+
+/// Where a file sorts among the results, in sort order: whether its name
+/// does not start with the query, its age as negated `last_modified`, and
+/// its key. A page's cursor is the position of its last file, so the next
+/// page starts after it however many uploads came or went in between.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Position(bool, i64, String);
+
+impl Position {
+    fn of(found: &FileMatch, starts_with_query: bool) -> Position {
+        Position(!starts_with_query, -found.last_modified, found.key.clone())
+    }
+}
+
+/// One page of a search: the files, how many matched in all, and the
+/// position to continue after when there are more.
+pub struct SearchPage {
+    pub files: Vec<FileMatch>,
+    pub total: usize,
+    pub next: Option<Position>,
+}
+
+/// Objects whose file name contains `query`, ignoring case, and that are of
+/// `file_type` if given. Names starting with the query come first, then the
+/// newest uploads. An empty query matches every name. At most `limit` files
+/// are returned, those sorting after `after` when it is given.
+pub fn search(
+    objects: Vec<StoredObject>,
+    query: &str,
+    file_type: Option<FileType>,
+    after: Option<&Position>,
+    limit: usize,
+) -> SearchPage {
+    let query = query.to_lowercase();
+    let mut matches: Vec<(Position, FileMatch)> = objects
+        .into_iter()
+        .filter_map(|object| {
+            let name = object.key.rsplit('/').next().unwrap_or_default().to_string();
+            let lowercase = name.to_lowercase();
+            if name.is_empty() || !lowercase.contains(&query) {
+                return None;
+            }
+            let found = FileMatch {
+                file_type: FileType::of(&name),
+                key: object.key,
+                name,
+                size: object.size,
+                last_modified: object.last_modified,
+            };
+            if file_type.is_some_and(|t| t != found.file_type) {
+                return None;
+            }
+            Some((Position::of(&found, lowercase.starts_with(&query)), found))
+        })
+        .collect();
+    let total = matches.len();
+
+    matches.retain(|(position, _)| after.is_none_or(|after| position > after));
+    matches.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let next = (matches.len() > limit).then(|| matches[limit - 1].0.clone());
+    matches.truncate(limit);
+    SearchPage {
+        files: matches.into_iter().map(|(_, found)| found).collect(),
+        total,
+        next,
+    }
+}
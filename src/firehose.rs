@@ -0,0 +1,89 @@
+use crate::init;
+use aws_sdk_firehose::primitives::Blob;
+use aws_sdk_firehose::types::Record;
+use aws_sdk_firehose::Client;
+use serde::Serialize;
+use std::sync::Mutex;
+
+/// PutRecordBatch accepts at most 500 records per call.
+const MAX_BATCH: usize = 500;
+
+/// Access records collected during the current invocation.
+static BUFFER: Mutex<Vec<AccessRecord>> = Mutex::new(Vec::new());
+
+/// One line of the access log. Field names are kept short and stable since
+/// they become Athena columns.
+#[derive(Debug, Serialize)]
+pub struct AccessRecord {
+    pub ts: u64,
+    pub method: String,
+    pub path: String,
+    pub referrer: String,
+    pub status: u16,
+    pub duration_ms: u64,
+    pub client: String,
+    pub stage: String,
+    pub correlation_id: String,
+    /// Viewer country and region, when CloudFront passed them on.
+    pub country: Option<String>,
+    pub region: Option<String>,
+    /// DynamoDB capacity units and S3 requests the request used.
+    pub ddb_read_units: f64,
+    pub ddb_write_units: f64,
+    pub s3_tier1: u64,
+    pub s3_tier2: u64,
+}
+
+/// Delivery stream from `firehose_stream`; access logging is off when unset.
+pub fn stream_name() -> Option<String> {
+    std::env::var("firehose_stream").ok().filter(|s| !s.is_empty())
+}
+
+async fn firehose_client() -> Client {
+    let config = init::sdk_config().await;
+    Client::new(config)
+}
+
+pub fn buffer(record: AccessRecord) {
+    BUFFER.lock().unwrap_or_else(|e| e.into_inner()).push(record);
+}
+
+/// Sends everything buffered so far as newline-delimited JSON, so the objects
+/// Firehose writes to S3 can be queried by Athena as-is. Returns the number of
+/// records delivered.
+pub async fn flush() -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(stream) = stream_name() else {
+        return Ok(0);
+    };
+    let records: Vec<AccessRecord> =
+        std::mem::take(&mut *BUFFER.lock().unwrap_or_else(|e| e.into_inner()));
+    if records.is_empty() {
+        return Ok(0);
+    }
+
+    let client = firehose_client().await;
+    let mut delivered = 0;
+
+    for chunk in records.chunks(MAX_BATCH) {
+        let mut batch = Vec::with_capacity(chunk.len());
+        for record in chunk {
+            let mut line = serde_json::to_vec(record)?;
+            line.push(b'\n');
+            batch.push(Record::builder().data(Blob::new(line)).build()?);
+        }
+
+        let output = client
+            .put_record_batch()
+            .delivery_stream_name(&stream)
+            .set_records(Some(batch))
+            .send()
+            .await?;
+        let failed = output.failed_put_count() as usize;
+        if failed > 0 {
+            tracing::warn!("firehose dropped {} access records", failed);
+        }
+        delivered += chunk.len() - failed;
+    }
+
+    Ok(delivered)
+}
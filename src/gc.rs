@@ -0,0 +1,76 @@
+use crate::clock::now_millis;
+use crate::dynamodb::get_record;
+use crate::s3::{delete_objects, list_all_objects, StoredObject};
+use crate::stage::Stage;
+use serde::Serialize;
+use std::collections::HashMap;
+
+pub const DEFAULT_GRACE_DAYS: u64 = 7;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GcReport {
+    pub dry_run: bool,
+    pub scanned: usize,
+    pub orphaned: Vec<StoredObject>,
+    pub reclaimable_bytes: i64,
+    pub deleted: usize,
+}
+
+/// Finds attachments (`upload/{part}/{idx}/...` under the stage's S3 base)
+/// whose item no longer exists, skipping objects younger than `grace_days`
+/// so uploads racing an item's first save survive. Orphans are only
+/// deleted when `dry_run` is off.
+pub async fn collect(
+    bucket: &str,
+    base_path: &str,
+    stage: Stage,
+    grace_days: u64,
+    dry_run: bool,
+) -> Result<GcReport, Box<dyn std::error::Error + Send + Sync>> {
+    let uploads = format!("{base_path}upload/");
+    let objects = list_all_objects(bucket, &uploads).await?;
+    let scanned = objects.len();
+    let cutoff = now_millis().saturating_sub(grace_days * 24 * 60 * 60 * 1000) as i64;
+
+    // loose uploads (no part/idx) are not attachments and are never collected
+    let mut by_owner: HashMap<(String, String), Vec<StoredObject>> = HashMap::new();
+    for object in objects {
+        let mut segments = object.key[uploads.len()..].splitn(3, '/');
+        let (Some(part), Some(idx), Some(_file)) =
+            (segments.next(), segments.next(), segments.next())
+        else {
+            continue;
+        };
+        if object.last_modified > cutoff {
+            continue;
+        }
+        by_owner
+            .entry((part.to_string(), idx.to_string()))
+            .or_default()
+            .push(object);
+    }
+
+    let mut orphaned = Vec::new();
+    for ((part, idx), objects) in by_owner {
+        if get_record(stage.partition(&part), idx).await?.is_none() {
+            orphaned.extend(objects);
+        }
+    }
+    orphaned.sort_by(|a, b| a.key.cmp(&b.key));
+
+    let reclaimable_bytes = orphaned.iter().map(|o| o.size).sum();
+    let deleted = if dry_run || orphaned.is_empty() {
+        0
+    } else {
+        delete_objects(bucket, orphaned.iter().map(|o| o.key.clone()).collect()).await?
+    };
+
+    Ok(GcReport {
+        dry_run,
+        scanned,
+        orphaned,
+        reclaimable_bytes,
+        deleted,
+    })
+}
@@ -0,0 +1,123 @@
+use crate::clock::{now_millis, utc_date};
+use crate::dynamodb::{increment_counter, query_records, schema};
+use aws_sdk_dynamodb::types::AttributeValue;
+use lambda_http::Request;
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Views per UTC day and country; `idx` is `{day}#{country}`.
+pub const COUNTRY_VIEWS_PARTITION: &str = "country_views";
+const COUNT_ATTRIBUTE: &str = "count";
+
+/// Country of views whose country is not known.
+pub const UNKNOWN_COUNTRY: &str = "unknown";
+
+/// Days the country breakdown covers unless asked for another number.
+pub const DEFAULT_DAYS: u64 = 7;
+pub const MAX_DAYS: u64 = 90;
+
+const DAY_MILLIS: u64 = 24 * 60 * 60 * 1000;
+
+/// Where a request came from, as coarse as CloudFront tells it: the
+/// viewer's country (ISO 3166-1 alpha-2) and region within it (the
+/// subdivision part of ISO 3166-2).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Geo {
+    pub country: Option<String>,
+    pub region: Option<String>,
+}
+
+fn header<'a>(req: &'a Request, name: &str) -> Option<&'a str> {
+    req.headers().get(name)?.to_str().ok().map(str::trim)
+}
+
+/// Uppercase `code` when it has `min..=max` ASCII letters and digits.
+fn code(value: Option<&str>, min: usize, max: usize) -> Option<String> {
+    value
+        .filter(|v| (min..=max).contains(&v.len()))
+        .filter(|v| v.bytes().all(|b| b.is_ascii_alphanumeric()))
+        .map(str::to_ascii_uppercase)
+}
+
+impl Geo {
+    /// The geo headers CloudFront adds when the distribution's origin
+    /// request policy forwards them. API Gateway edge-optimized endpoints
+    /// sit behind CloudFront and pass the country on too. Anything that is
+    /// not a well-formed code is ignored, as is CloudFront's `XX` for
+    /// viewers it could not place.
+    pub fn from_request(req: &Request) -> Geo {
+        let country = code(header(req, "cloudfront-viewer-country"), 2, 2)
+            .filter(|c| c.bytes().all(|b| b.is_ascii_alphabetic()) && c != "XX");
+        let region = country
+            .as_ref()
+            .and_then(|_| code(header(req, "cloudfront-viewer-country-region"), 1, 3));
+        Geo { country, region }
+    }
+
+    /// The country views are counted under.
+    pub fn country_or_unknown(&self) -> &str {
+        self.country.as_deref().unwrap_or(UNKNOWN_COUNTRY)
+    }
+}
+
+/// Counts a view from `geo` for today.
+pub async fn record_view(geo: &Geo) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let idx = format!("{}#{}", utc_date(now_millis()), geo.country_or_unknown());
+    increment_counter(
+        COUNTRY_VIEWS_PARTITION.to_string(),
+        idx,
+        COUNT_ATTRIBUTE,
+        1,
+    )
+    .await?;
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+pub struct CountryViews {
+    pub country: String,
+    pub views: i64,
+}
+
+/// Site-wide views per country over the last `days` UTC days, today
+/// included, most viewed first.
+pub async fn country_views(
+    days: u64,
+) -> Result<Vec<CountryViews>, Box<dyn std::error::Error + Send + Sync>> {
+    let now = now_millis();
+    let first = utc_date(now - days.saturating_sub(1) * DAY_MILLIS);
+    let last = utc_date(now);
+    // `$` sorts right after `#`, so `{last}$` bounds every country of `last`
+    let range = Some((format!("{first}#"), format!("{last}$")));
+    let records = query_records(
+        COUNTRY_VIEWS_PARTITION.to_string(),
+        range,
+        Vec::new(),
+        usize::MAX,
+        false,
+    )
+    .await?;
+
+    let sort_key = &schema().sort_key;
+    let mut totals: HashMap<String, i64> = HashMap::new();
+    for record in &records {
+        let Some(AttributeValue::S(idx)) = record.get(sort_key) else {
+            continue;
+        };
+        let Some((_, country)) = idx.split_once('#') else {
+            continue;
+        };
+        let count = match record.get(COUNT_ATTRIBUTE) {
+            Some(AttributeValue::N(n)) => n.parse().unwrap_or(0),
+            _ => 0,
+        };
+        *totals.entry(country.to_string()).or_default() += count;
+    }
+
+    let mut countries: Vec<CountryViews> = totals
+        .into_iter()
+        .map(|(country, views)| CountryViews { country, views })
+        .collect();
+    countries.sort_by(|a, b| b.views.cmp(&a.views).then_with(|| a.country.cmp(&b.country)));
+    Ok(countries)
+}
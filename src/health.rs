@@ -0,0 +1,127 @@
+use crate::clock::now_millis;
+use crate::dynamodb::{self, dynamodb_client, schema, TABLE_NAME};
+use crate::s3::{self, s3_client};
+use aws_sdk_dynamodb::types::AttributeValue;
+use serde::Serialize;
+use std::future::Future;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+const DEFAULT_CACHE_SECS: u64 = 5;
+
+/// Longest a dependency may take to answer before it counts as down.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Partition of the key readiness reads; nothing is ever stored there.
+const HEALTH_PARTITION: &str = "health";
+
+/// How long a readiness result is reused, from `health_cache_secs`.
+fn cache_millis() -> u64 {
+    std::env::var("health_cache_secs")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(DEFAULT_CACHE_SECS)
+        * 1000
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Check {
+    pub ok: bool,
+    pub latency_ms: u64,
+    /// Error code, as in error responses, such as `throttled`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Readiness {
+    pub ready: bool,
+    pub dynamodb: Check,
+    /// `None` when no bucket is configured.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub s3: Option<Check>,
+    /// Epoch milliseconds.
+    pub checked_at: u64,
+    /// Whether this is a result reused from an earlier check.
+    pub cached: bool,
+}
+
+async fn check(call: impl Future<Output = Result<(), &'static str>>) -> Check {
+    let started = now_millis();
+    let result = tokio::time::timeout(CHECK_TIMEOUT, call).await;
+    let latency_ms = now_millis().saturating_sub(started);
+    let error = match result {
+        Ok(Ok(())) => None,
+        Ok(Err(code)) => Some(code.to_string()),
+        Err(_) => Some("timeout".to_string()),
+    };
+    Check {
+        ok: error.is_none(),
+        latency_ms,
+        error,
+    }
+}
+
+/// Whether the table and, if one is set, the bucket answer. Results are
+/// reused for `health_cache_secs` (default 5), and requests arriving while
+/// a check runs wait for it rather than start their own, so synthetic
+/// monitors polling often cost one check per period per container.
+pub async fn readiness(bucket: &str) -> Readiness {
+    static LAST: Mutex<Option<Readiness>> = Mutex::const_new(None);
+
+    let mut last = LAST.lock().await;
+    if let Some(cached) = last.as_ref() {
+        if now_millis().saturating_sub(cached.checked_at) < cache_millis() {
+            return Readiness {
+                cached: true,
+                ..cached.clone()
+            };
+        }
+    }
+
+    let dynamodb = async {
+        let schema = schema();
+        let request = dynamodb_client()
+            .await
+            .get_item()
+            .table_name(TABLE_NAME)
+            .key(
+                &schema.partition_key,
+                AttributeValue::S(HEALTH_PARTITION.to_string()),
+            )
+            .key(&schema.sort_key, AttributeValue::S("ready".to_string()))
+            .projection_expression("#part")
+            .expression_attribute_names("#part", &schema.partition_key);
+        check(async {
+            let result = request.send().await;
+            result
+                .map(|_| ())
+                .map_err(|e| dynamodb::error_kind(&e).code())
+        })
+        .await
+    };
+    let s3 = async {
+        if bucket.is_empty() {
+            return None;
+        }
+        let request = s3_client().await.head_bucket().bucket(bucket);
+        let checked = check(async {
+            let result = request.send().await;
+            result.map(|_| ()).map_err(|e| s3::error_kind(&e).code())
+        });
+        Some(checked.await)
+    };
+    let (dynamodb, s3) = tokio::join!(dynamodb, s3);
+
+    let readiness = Readiness {
+        ready: dynamodb.ok && s3.as_ref().is_none_or(|s3| s3.ok),
+        dynamodb,
+        s3,
+        checked_at: now_millis(),
+        cached: false,
+    };
+    *last = Some(readiness.clone());
+    readiness
+}
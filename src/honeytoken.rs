@@ -0,0 +1,171 @@
+use crate::audit::client_ip;
+use crate::clock::now_millis;
+use crate::correlation::correlation_id;
+use crate::dynamodb::put_item;
+use crate::import::{self, ImportFormat};
+use crate::init;
+use lambda_http::{Body, Request};
+use serde_json::json;
+
+/// Value written to decoy items: something an intruder would want to open.
+const DECOY_VALUE: &str = r#"{"title":"Credentials backup","slug":"credentials-backup"}"#;
+
+/// Decoy items from `honeytoken_items`, as comma-separated `part/idx` pairs.
+fn decoy_items() -> Vec<(String, String)> {
+    std::env::var("honeytoken_items")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|entry| entry.trim().split_once('/'))
+        .filter(|(part, idx)| !part.is_empty() && !idx.is_empty())
+        .map(|(part, idx)| (part.to_string(), idx.to_string()))
+        .collect()
+}
+
+/// Decoy S3 filenames from `honeytoken_files`, comma-separated. They never
+/// need to exist: asking for a URL to one is the signal.
+fn decoy_files() -> Vec<String> {
+    std::env::var("honeytoken_files")
+        .unwrap_or_default()
+        .split(',')
+        .map(|f| f.trim().to_string())
+        .filter(|f| !f.is_empty())
+        .collect()
+}
+
+fn query_value(req: &Request, key: &str) -> Option<String> {
+    req.uri()
+        .query()
+        .and_then(|q| url::form_urlencoded::parse(q.as_bytes()).find(|(k, _)| k == key))
+        .map(|(_, v)| v.to_string())
+}
+
+fn body_json(req: &Request) -> Option<serde_json::Value> {
+    match req.body() {
+        Body::Text(s) => serde_json::from_str(s).ok(),
+        Body::Binary(b) => serde_json::from_slice(b).ok(),
+        _ => None,
+    }
+}
+
+/// The first decoy item among `items`.
+fn decoy_item(items: impl IntoIterator<Item = (String, String)>) -> Option<String> {
+    let decoys = decoy_items();
+    items
+        .into_iter()
+        .find(|item| decoys.contains(item))
+        .map(|(part, idx)| format!("item {part}/{idx}"))
+}
+
+/// The first decoy file among `filenames`.
+fn decoy_file<'a>(filenames: impl IntoIterator<Item = &'a str>) -> Option<String> {
+    let decoys = decoy_files();
+    filenames
+        .into_iter()
+        .find(|filename| decoys.iter().any(|decoy| decoy == filename))
+        .map(|filename| format!("file {filename}"))
+}
+
+/// Names the decoy this request touches, if any: an item read or written
+/// through `/dynamodb/item`, written by `/dynamodb/import` or listed by
+/// `/dynamodb/items`, or a file presigned through `/api/s3/*`, batches
+/// included.
+pub fn tripped(req: &Request) -> Option<String> {
+    let path = req.uri().path();
+
+    if path == "/dynamodb/item" {
+        let (part, idx) = if req.method() == "POST" {
+            let body = body_json(req)?;
+            let field = |name: &str| body.get(name)?.as_str().map(str::to_string);
+            (field("part")?, field("idx")?)
+        } else {
+            (query_value(req, "part")?, query_value(req, "idx")?)
+        };
+        return decoy_item([(part, idx)]);
+    }
+
+    // a listing returns every item of the partition, decoys included
+    if path == "/dynamodb/items" && req.method() == "GET" {
+        let part = query_value(req, "part")?;
+        return decoy_item(decoy_items().into_iter().filter(|(p, _)| *p == part));
+    }
+
+    if path == "/dynamodb/import" && req.method() == "POST" {
+        let content_type = req
+            .headers()
+            .get("content-type")
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or_default();
+        let body: &[u8] = match req.body() {
+            Body::Text(s) => s.as_bytes(),
+            Body::Binary(b) => b,
+            _ => return None,
+        };
+        let format = ImportFormat::from_content_type(content_type);
+        return decoy_item(import::record_keys(body, format));
+    }
+
+    if path == "/api/s3/upload-urls" && req.method() == "POST" {
+        let body = body_json(req)?;
+        let files = body.get("files")?.as_array()?;
+        return decoy_file(files.iter().filter_map(|f| f.get("filename")?.as_str()));
+    }
+
+    if path.starts_with("/api/s3/") {
+        let filename = query_value(req, "filename")?;
+        return decoy_file([filename.as_str()]);
+    }
+
+    None
+}
+
+/// Publishes a tripped decoy to `honeytoken_topic_arn`. Without a topic the
+/// alert is only logged.
+pub async fn alert(
+    decoy: &str,
+    req: &Request,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let header = |name: &str| {
+        req.headers()
+            .get(name)
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or_default()
+            .to_string()
+    };
+    let message = json!({
+        "decoy": decoy,
+        "method": req.method().as_str(),
+        "path": req.uri().path(),
+        "ip": client_ip(req),
+        "userAgent": header("user-agent"),
+        "correlationId": correlation_id(req),
+        "at": now_millis(),
+    });
+    tracing::warn!("honeytoken tripped: {}", message);
+
+    let Some(topic) = std::env::var("honeytoken_topic_arn")
+        .ok()
+        .filter(|t| !t.is_empty())
+    else {
+        return Ok(());
+    };
+
+    let config = init::sdk_config().await;
+    aws_sdk_sns::Client::new(config)
+        .publish()
+        .topic_arn(topic)
+        .subject("Blog honeytoken tripped")
+        .message(message.to_string())
+        .send()
+        .await?;
+
+    Ok(())
+}
+
+/// Writes every configured decoy item. Returns how many were seeded.
+pub async fn seed() -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    let items = decoy_items();
+    for (part, idx) in &items {
+        put_item(part.clone(), idx.clone(), DECOY_VALUE.to_string()).await?;
+    }
+    Ok(items.len())
+}
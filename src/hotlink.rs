@@ -0,0 +1,121 @@
+use crate::auth::constant_time_eq;
+use crate::clock::now_millis;
+use crate::files::FileType;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64URL;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use url::Url;
+
+/// Route prefix of protected images: `/img/{key}`, the key relative to the
+/// stage's base path.
+pub const IMAGE_ROUTE: &str = "/img/";
+
+/// Lifetime of a signed image URL unless the request asks otherwise.
+pub const DEFAULT_SIGNED_TTL_SECS: u64 = 24 * 3600;
+
+/// Longest a signed image URL may work.
+pub const MAX_SIGNED_TTL_SECS: u64 = 7 * 24 * 3600;
+
+/// Who may load images through `IMAGE_ROUTE`: pages on the site's own host
+/// or on a host listed in `img_referers`, and holders of a URL signed with
+/// `img_secret`. Requests without a `Referer` are turned away unless
+/// `img_allow_blank_referer` is `true`, since a hotlinking page can drop
+/// the header with a referrer policy.
+pub struct HotlinkConfig {
+    hosts: Vec<String>,
+    secret: Option<String>,
+    allow_blank: bool,
+}
+
+/// Why an image request was turned away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Refusal {
+    /// A signature was given but is wrong or expired.
+    InvalidSignature,
+    /// No signature, and the `Referer` is missing or from another site.
+    Referer,
+}
+
+impl HotlinkConfig {
+    /// `site` is the blog's own base URL, whose host is always allowed.
+    pub fn from_env(site: Option<&str>) -> HotlinkConfig {
+        let mut hosts: Vec<String> = std::env::var("img_referers")
+            .unwrap_or_default()
+            .split(',')
+            .map(|host| host.trim().to_ascii_lowercase())
+            .filter(|host| !host.is_empty())
+            .collect();
+        if let Some(host) = site.and_then(host_of) {
+            hosts.push(host);
+        }
+        HotlinkConfig {
+            hosts,
+            secret: std::env::var("img_secret").ok().filter(|s| !s.is_empty()),
+            allow_blank: std::env::var("img_allow_blank_referer").as_deref() == Ok("true"),
+        }
+    }
+
+    fn signature(secret: &str, key: &str, expires: u64) -> String {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac accepts any key length");
+        mac.update(format!("{key}\n{expires}").as_bytes());
+        BASE64URL.encode(mac.finalize().into_bytes())
+    }
+
+    /// The query (`expires` and `sig`) of a URL for `key` that works from
+    /// anywhere for `ttl_secs`, and when it expires (epoch seconds). `None`
+    /// without `img_secret`.
+    pub fn sign(&self, key: &str, ttl_secs: u64) -> Option<(String, u64)> {
+        let secret = self.secret.as_deref()?;
+        let expires = now_millis() / 1000 + ttl_secs;
+        let query = url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("expires", &expires.to_string())
+            .append_pair("sig", &Self::signature(secret, key, expires))
+            .finish();
+        Some((query, expires))
+    }
+
+    /// Whether a request for `key` with this `Referer` and, if given, this
+    /// signature may be served. A signature, when present, decides alone.
+    pub fn check(
+        &self,
+        key: &str,
+        referer: Option<&str>,
+        signed: Option<(u64, &str)>,
+    ) -> Result<(), Refusal> {
+        if let Some((expires, signature)) = signed {
+            let Some(secret) = self.secret.as_deref() else {
+                return Err(Refusal::InvalidSignature);
+            };
+            let expected = Self::signature(secret, key, expires);
+            let valid = constant_time_eq(expected.as_bytes(), signature.as_bytes());
+            return match valid && expires >= now_millis() / 1000 {
+                true => Ok(()),
+                false => Err(Refusal::InvalidSignature),
+            };
+        }
+
+        match referer.filter(|r| !r.is_empty()) {
+            None if self.allow_blank => Ok(()),
+            None => Err(Refusal::Referer),
+            Some(referer) => match host_of(referer) {
+                Some(host) if self.hosts.contains(&host) => Ok(()),
+                _ => Err(Refusal::Referer),
+            },
+        }
+    }
+}
+
+fn host_of(url: &str) -> Option<String> {
+    Some(Url::parse(url).ok()?.host_str()?.to_ascii_lowercase())
+}
+
+/// Whether `key` may be requested through `IMAGE_ROUTE`: an image, by its
+/// extension, with no way out of the stage's base path.
+pub fn is_image_key(key: &str) -> bool {
+    !key.is_empty()
+        && !key.starts_with('/')
+        && !key.split('/').any(|segment| segment == "..")
+        && FileType::of(key) == FileType::Image
+}
@@ -1,9 +1,99 @@
-use crate::dynamodb::{delete_item, get_item_value, put_item};
-use crate::s3::{list_objects, presign_delete, presign_download, presign_upload};
+use crate::acl::{self, Rules, Visibility, PREFIX_ACL_PARTITION};
+use crate::activitypub::{self, ACTIVITY_JSON, FOLLOWERS_PARTITION};
+use crate::athena::{self, NamedQuery};
+use crate::attachments::{self, AttachmentPatch, ATTACHMENTS_PARTITION};
+use crate::audit::{self, AuditQuery, AUDIT_PARTITION};
+use crate::autosave::{self, AutosavePayload, AUTOSAVES_PARTITION, MAX_AUTOSAVE_BYTES};
+use crate::avatar;
+use crate::backup::{self, BackupTarget, BACKUPS_PARTITION};
+use crate::clock::{now_millis, utc_date};
+use crate::comments::{self, PostCommentSettings, COMMENTS_PARTITION};
+use crate::concurrency::{self, Busy};
+use crate::correlation::{self, correlation_id, CORRELATION_HEADER};
+use crate::costs::{self, Usage, COSTS_PARTITION_PREFIX};
+use crate::counters::SHARDS_PARTITION_PREFIX;
+use crate::ctx::Ctx;
+use crate::cursor::{self, CURSOR_PARAM};
+use crate::dedupe::{self, UPLOAD_HASHES_PARTITION};
+use crate::digest;
+use crate::disqus;
+use crate::downloads::{self, DOWNLOADS_PARTITION_PREFIX};
+use crate::dynamodb::{
+    self, create_index, delete_item_if, describe_indexes, generate_idx, get_item_value,
+    get_stored_value, list_items, put_encrypted_item, put_item_if, set_pinned, set_sort_weights,
+    ErrorKind, Precondition, StoredValue,
+};
+use crate::edit_locks::{self, EDIT_LOCKS_PARTITION};
+use crate::firehose::{self, AccessRecord};
+use crate::failover;
+use crate::fields::{self, Projected, Projection};
+use crate::files::{self, FileType};
+use crate::gc;
+use crate::geo::{self, Geo, COUNTRY_VIEWS_PARTITION};
+use crate::health;
+use crate::honeytoken;
+use crate::hotlink::{self, HotlinkConfig, Refusal, IMAGE_ROUTE};
+use crate::images;
+use crate::import::{self, ImportFormat};
+use crate::jobs::{self, JobKind, JOBS_PARTITION, JOB_TOKEN_HEADER};
+use crate::latency;
+use crate::linkcheck::{self, LINK_STATUS_PARTITION};
+use crate::links::{self, Generations, LinkConfig, LINK_GENERATIONS_PARTITION, LINK_ROUTE};
+use crate::lint;
+use crate::locale;
+use crate::lock::LOCKS_PARTITION;
+use crate::masking;
+use crate::media_import;
+use crate::object_lock::{self, LegalHoldRequest, RetentionRequest};
+use crate::outbox::{self, OUTBOX_PARTITION};
+use crate::posts::{self, PostSort, DAILY_VIEWS_PARTITION};
+use crate::preview::{self, PreviewConfig, PREVIEW_ROUTE};
+use crate::promote::{self, PROMOTIONS_PARTITION};
+use crate::quota;
+use crate::reactions;
+use crate::replay::{self, DELIVERIES_PARTITION};
+use crate::s3::{
+    self, head_object, list_all_objects, list_objects, presign_delete, presign_download,
+    presign_upload, put_upload, upload_storage_class, ObjectInfo,
+};
+use crate::secrets;
+use crate::security_headers;
+use crate::series::{self, SeriesMember, SERIES_PARTITION};
+use crate::settings::{self, SiteSettings, SETTINGS_PARTITION};
+use crate::ses;
+use crate::shadow;
+use crate::shortlinks::{self, SHORTLINKS_PARTITION, SHORTLINK_ROUTE};
+use crate::subscribers::{self, SUBSCRIBERS_PARTITION};
+use crate::suggest;
+use crate::sync::{self, DEFAULT_SYNC_LIMIT, MAX_SYNC_LIMIT, TOMBSTONES_PARTITION_PREFIX};
+use crate::syndicate::{self, Target};
+use crate::tags::{self, TAG_COUNTS_PARTITION_PREFIX};
+use crate::themes::{self, Asset, Theme, THEMES_PARTITION};
+use crate::transition;
+use crate::summary;
+use crate::slugs::{self, SLUGS_PARTITION};
+use crate::snapshots::{self, Document};
+use crate::stage::{self, Stage};
+use crate::urls;
+use crate::usage::{self, DAILY_ERRORS_PARTITION, USAGE_PARTITION_PREFIX};
+use crate::user_data;
+use crate::webmention::{self, MENTIONS_PARTITION_PREFIX};
 use lambda_http::{Body, Error, Request, Response};
 use lambda_http::http::StatusCode;
+use percent_encoding::{percent_decode_str, utf8_percent_encode};
 use serde::Deserialize;
 use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::Instrument;
+
+// suggested chunking for resumable downloads
+const DEFAULT_CHUNK_SIZE: i64 = 8 * 1024 * 1024;
+const MIN_CHUNK_SIZE: i64 = 1024 * 1024;
+
+/// Largest drop `POST /api/s3/upload-urls` presigns in one request.
+const MAX_BATCH_UPLOADS: usize = 100;
 
 fn add_cors_headers(response: &mut Response<Body>) {
     response.headers_mut().insert(
@@ -12,19 +102,55 @@ fn add_cors_headers(response: &mut Response<Body>) {
     );
     response.headers_mut().insert(
         "Access-Control-Allow-Methods",
-        "GET,POST,DELETE,OPTIONS".parse().unwrap(),
+        "GET,POST,PUT,PATCH,DELETE,OPTIONS".parse().unwrap(),
     );
     response.headers_mut().insert(
         "Access-Control-Allow-Headers",
-        "Content-Type,Authorization".parse().unwrap(),
+        "Content-Type,Authorization,X-Stage,X-Correlation-Id,traceparent,If-Match".parse().unwrap(),
+    );
+    response.headers_mut().insert(
+        "Access-Control-Expose-Headers",
+        "X-Correlation-Id,ETag".parse().unwrap(),
     );
 }
 
 #[derive(Debug, Deserialize)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
 struct DynamodbPutItemPayload {
     part: String,
-    idx: String,
+    #[serde(default)]
+    #[cfg_attr(feature = "ts", ts(optional))]
+    idx: Option<String>,
     value: String,
+    /// Envelope-encrypt the value under `kms_key_id`; only admins can read it.
+    #[serde(default)]
+    #[cfg_attr(feature = "ts", ts(optional, as = "Option<bool>"))]
+    encrypted: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+struct DynamodbKeyPayload {
+    part: String,
+    idx: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+struct DynamodbOrderPayload {
+    part: String,
+    order: Vec<String>,
+}
+
+fn parse_json_body<T: serde::de::DeserializeOwned>(
+    body: &Body,
+) -> Result<Result<T, Response<Body>>, Error> {
+    match body {
+        Body::Text(s) => Ok(Ok(serde_json::from_str(s)?)),
+        Body::Binary(b) => Ok(Ok(serde_json::from_slice(b)?)),
+        Body::Empty => Ok(Err(text_response(400, "empty body".to_string())?)),
+        _ => Ok(Err(text_response(400, "unsupported body type".to_string())?)),
+    }
 }
 
 fn text_response(status: u16, body: String) -> Result<Response<Body>, Error> {
@@ -45,6 +171,214 @@ fn json_response(status: u16, value: serde_json::Value) -> Result<Response<Body>
     Ok(response)
 }
 
+/// Answers a failed DynamoDB call with the status and `error` code of its
+/// `dynamodb::ErrorKind`; throttled callers are told when to retry.
+fn dynamodb_error(e: &(dyn std::error::Error + 'static)) -> Result<Response<Body>, Error> {
+    let kind = dynamodb::error_kind(e);
+    let message = match kind {
+        ErrorKind::Conflict => "the item was changed concurrently",
+        ErrorKind::NotFound => "table or index not found",
+        ErrorKind::Throttled => "too many requests, retry later",
+        ErrorKind::Invalid => "request rejected by dynamodb",
+        ErrorKind::Other => "dynamodb error",
+    };
+    let mut response = json_response(
+        kind.status(),
+        json!({ "error": kind.code(), "message": message }),
+    )?;
+    if kind == ErrorKind::Throttled {
+        response.headers_mut().insert("retry-after", "1".parse()?);
+    }
+    Ok(response)
+}
+
+/// The `If-Match` precondition of a request, if it sent one.
+fn if_match(req: &Request) -> Option<Precondition> {
+    let header = req.headers().get("if-match")?.to_str().unwrap_or_default();
+    Some(Precondition::from_if_match(header))
+}
+
+fn precondition_failed() -> Result<Response<Body>, Error> {
+    json_response(
+        412,
+        json!({
+            "error": "precondition_failed",
+            "message": "the item does not match If-Match; read it again",
+        }),
+    )
+}
+
+/// Answers a failed item write: `412` when the write carried an `If-Match`
+/// that the item no longer meets, `dynamodb_error` otherwise.
+fn write_error(
+    e: &(dyn std::error::Error + 'static),
+    precondition: Option<&Precondition>,
+) -> Result<Response<Body>, Error> {
+    if precondition.is_some() && dynamodb::error_kind(e) == ErrorKind::Conflict {
+        return precondition_failed();
+    }
+    tracing::error!("dynamodb write error: {:?}", e);
+    dynamodb_error(e)
+}
+
+/// Answers a failed S3 call with the status and `error` code of its
+/// `s3::ErrorKind`.
+fn s3_error(e: &(dyn std::error::Error + 'static)) -> Result<Response<Body>, Error> {
+    let kind = s3::error_kind(e);
+    let message = match kind {
+        s3::ErrorKind::NotFound => "object not found",
+        s3::ErrorKind::AccessDenied => "access to the object was denied",
+        s3::ErrorKind::Archived => "file archived, request restore",
+        s3::ErrorKind::SlowDown => "too many requests, retry later",
+        s3::ErrorKind::Other => "s3 error",
+    };
+    let mut response = json_response(
+        kind.status(),
+        json!({ "error": kind.code(), "message": message }),
+    )?;
+    if kind == s3::ErrorKind::SlowDown {
+        response.headers_mut().insert("retry-after", "1".parse()?);
+    }
+    Ok(response)
+}
+
+/// Answers a request for an object that has to be restored from an archive
+/// storage class before it can be downloaded.
+fn archived_response(info: &ObjectInfo) -> Result<Response<Body>, Error> {
+    let message = if info.restoring {
+        "file archived, restore in progress"
+    } else {
+        "file archived, request restore"
+    };
+    json_response(
+        s3::ErrorKind::Archived.status(),
+        json!({
+            "error": s3::ErrorKind::Archived.code(),
+            "message": message,
+            "storageClass": info.storage_class,
+            "restoring": info.restoring,
+        }),
+    )
+}
+
+/// Checks that `key` exists and is not archived before a download is handed
+/// out for it, since S3 would only refuse it once the URL is followed. `Err`
+/// is the response to send instead.
+async fn check_downloadable(bucket: &str, key: &str) -> Result<Result<(), Response<Body>>, Error> {
+    match head_object(bucket, key.to_string()).await {
+        Ok(Some(info)) if info.archived => Ok(Err(archived_response(&info)?)),
+        Ok(Some(_)) => Ok(Ok(())),
+        Ok(None) => Ok(Err(json_response(
+            s3::ErrorKind::NotFound.status(),
+            json!({ "error": s3::ErrorKind::NotFound.code(), "message": "object not found" }),
+        )?)),
+        Err(e) => {
+            tracing::error!("s3 head error: {:?}", e);
+            Ok(Err(s3_error(e.as_ref())?))
+        }
+    }
+}
+
+/// `theme` with each asset key replaced by a URL: on the CDN when there is
+/// one, and otherwise presigned. Keys carry a hash of the content, so either
+/// URL changes whenever the asset does.
+async fn theme_urls(ctx: &Ctx, theme: &Theme) -> Result<serde_json::Value, Error> {
+    let mut urls = json!({ "name": theme.name, "updatedAt": theme.updated_at });
+    for (asset, key) in theme.assets() {
+        let object = format!("{}{key}", ctx.base_path);
+        let url = match &ctx.config.cdn_url {
+            Some(cdn) => format!("{cdn}/{object}"),
+            None => {
+                let ttl = Duration::from_secs(900);
+                presign_download(ctx.s3().await, &ctx.config.bucket, object, None, ttl).await?
+            }
+        };
+        urls[asset.name()] = json!(url);
+    }
+    Ok(urls)
+}
+
+/// Counts a download of the object at `key` for its stats. A failure is only
+/// logged; the download goes ahead.
+async fn record_download(key: &str, root_path: &str) {
+    if let Err(e) = downloads::record(downloads::partition_of(key, root_path)).await {
+        tracing::error!("dynamodb download count error: {:?}", e);
+    }
+}
+
+/// A single `bytes=start-end` range; the end may be omitted.
+fn is_byte_range(value: &str) -> bool {
+    let Some((start, end)) = value.strip_prefix("bytes=").and_then(|r| r.split_once('-')) else {
+        return false;
+    };
+    match (start.parse::<u64>(), end) {
+        (Ok(_), "") => true,
+        (Ok(start), end) => end.parse::<u64>().is_ok_and(|end| end >= start),
+        _ => false,
+    }
+}
+
+/// A SHA-256 digest is 32 bytes, i.e. 44 base64 characters ending in one `=`.
+fn is_base64_sha256(value: &str) -> bool {
+    value.len() == 44
+        && value.ends_with('=')
+        && value[..43]
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '/')
+}
+
+fn activity_response(status: u16, value: serde_json::Value) -> Result<Response<Body>, Error> {
+    let mut response = json_response(status, value)?;
+    response
+        .headers_mut()
+        .insert("content-type", ACTIVITY_JSON.parse()?);
+    Ok(response)
+}
+
+/// Starts `kind` as a job and answers 202 with the id to poll at `/jobs/{id}`.
+async fn submit_job(ctx: &Ctx, kind: JobKind) -> Result<Response<Body>, Error> {
+    match jobs::submit(kind, &ctx.request_id).await {
+        Ok(id) => {
+            let mut response = json_response(202, json!({ "jobId": id }))?;
+            response
+                .headers_mut()
+                .insert("location", format!("/jobs/{id}").parse()?);
+            Ok(response)
+        }
+        Err(e) => {
+            tracing::error!("job submit error: {:?}", e);
+            text_response(500, "job error".to_string())
+        }
+    }
+}
+
+/// Checks an object key (or, with `listing`, a prefix to list) against the
+/// prefix ACL. `Err` is the response to send instead.
+async fn check_prefix_acl(
+    ctx: &Ctx,
+    key: &str,
+    listing: bool,
+) -> Result<Result<Visibility, Response<Body>>, Error> {
+    let rules = match Rules::load().await {
+        Ok(rules) => rules,
+        Err(e) => {
+            tracing::error!("dynamodb prefix acl error: {:?}", e);
+            return Ok(Err(dynamodb_error(e.as_ref())?));
+        }
+    };
+    let visibility = rules.visibility(key.strip_prefix(&ctx.base_path).unwrap_or(key));
+    if !visibility.allows(ctx.is_admin(), listing) {
+        return Ok(Err(text_response(403, "forbidden".to_string())?));
+    }
+    Ok(Ok(visibility))
+}
+
+fn accept_language(req: &Request) -> Option<&str> {
+    req.headers()
+        .get("accept-language")
+        .and_then(|h| h.to_str().ok())
+}
+
 fn query_param(req: &Request, key: &str) -> Option<String> {
     req.uri()
         .query()
@@ -52,7 +386,237 @@ fn query_param(req: &Request, key: &str) -> Option<String> {
         .map(|(_, v)| v.to_string())
 }
 
+/// Whether `part` spells out a stage prefix, as `draft#audit` or `live#3#post`
+/// do. The live stage leaves such a part as it is, which would reach the
+/// draft copy of a reserved partition or another live generation, so the
+/// generic item routes refuse it.
+fn is_staged_part(part: &str) -> bool {
+    stage::is_staged(part.trim())
+}
+
+/// Partitions managed by dedicated routes that the generic item routes must not write.
+fn is_reserved_part(part: &str) -> bool {
+    is_staged_part(part)
+        || part == AUDIT_PARTITION
+        || part == SERIES_PARTITION
+        || part == FOLLOWERS_PARTITION
+        || part == UPLOAD_HASHES_PARTITION
+        || part == DAILY_VIEWS_PARTITION
+        || part == COUNTRY_VIEWS_PARTITION
+        || part == PROMOTIONS_PARTITION
+        || part == DAILY_ERRORS_PARTITION
+        || part == SLUGS_PARTITION
+        || part == LINK_STATUS_PARTITION
+        || part == DELIVERIES_PARTITION
+        || part == SUBSCRIBERS_PARTITION
+        || part == JOBS_PARTITION
+        || part == SETTINGS_PARTITION
+        || part == BACKUPS_PARTITION
+        || part == LOCKS_PARTITION
+        || part == PREFIX_ACL_PARTITION
+        || part == LINK_GENERATIONS_PARTITION
+        || part == EDIT_LOCKS_PARTITION
+        || part == AUTOSAVES_PARTITION
+        || part == ATTACHMENTS_PARTITION
+        || part == OUTBOX_PARTITION
+        || part == SHORTLINKS_PARTITION
+        || part == THEMES_PARTITION
+        || part == COMMENTS_PARTITION
+        || part.starts_with(USAGE_PARTITION_PREFIX)
+        || part.starts_with(COSTS_PARTITION_PREFIX)
+        || part.starts_with(DOWNLOADS_PARTITION_PREFIX)
+        || part.starts_with(SHARDS_PARTITION_PREFIX)
+        || part.starts_with(MENTIONS_PARTITION_PREFIX)
+        || part.starts_with(TOMBSTONES_PARTITION_PREFIX)
+        || part.starts_with(TAG_COUNTS_PARTITION_PREFIX)
+}
+
+fn is_mutating(method: &str) -> bool {
+    matches!(method, "POST" | "PUT" | "PATCH" | "DELETE")
+}
+
+fn is_autosave(path: &str) -> bool {
+    path.strip_prefix("/posts/")
+        .and_then(|rest| rest.strip_suffix("/autosave"))
+        .is_some_and(|id| !id.is_empty() && !id.contains('/'))
+}
+
 pub async fn function_handler(req: Request) -> Result<Response<Body>, Error> {
+    if req.method() == "OPTIONS" {
+        let mut response = route(req).await?;
+        security_headers::apply(&mut response);
+        return Ok(response);
+    }
+
+    // cold-start work is done in `init::init`; reaching here is the warmup
+    if req.method() == "POST" && req.uri().path() == "/warmup" {
+        let mut response = text_response(200, "warm".to_string())?;
+        security_headers::apply(&mut response);
+        return Ok(response);
+    }
+
+    // answered before usage and cost tracking, which write to DynamoDB
+    if req.method() == "GET" && req.uri().path() == "/health/live" {
+        let mut response = text_response(200, "OK".to_string())?;
+        security_headers::apply(&mut response);
+        return Ok(response);
+    }
+    if req.method() == "GET" && req.uri().path() == "/health/ready" {
+        let bucket = failover::s3_bucket().unwrap_or_default();
+        let readiness = health::readiness(&bucket).await;
+        let status = if readiness.ready { 200 } else { 503 };
+        let mut response = json_response(status, json!(readiness))?;
+        response.headers_mut().insert("cache-control", "no-store".parse()?);
+        security_headers::apply(&mut response);
+        return Ok(response);
+    }
+
+    // every log line of the request carries the id, and DynamoDB and S3
+    // calls send it (see `correlation::Propagate`)
+    let correlation_id = correlation_id(&req);
+    let span = tracing::info_span!(
+        "request",
+        correlation_id = %correlation_id,
+        method = %req.method(),
+        path = %req.uri().path(),
+    );
+
+    let started = now_millis();
+    let method = req.method().to_string();
+    let path = req.uri().path().to_string();
+    let geo = Geo::from_request(&req);
+    let access = firehose::stream_name().map(|_| AccessRecord {
+        ts: started,
+        method: method.clone(),
+        path: path.clone(),
+        referrer: req
+            .headers()
+            .get("referer")
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or_default()
+            .to_string(),
+        status: 0,
+        duration_ms: 0,
+        client: String::new(),
+        stage: Stage::name_of(&req).to_string(),
+        correlation_id: correlation_id.clone(),
+        country: geo.country,
+        region: geo.region,
+        ddb_read_units: 0.0,
+        ddb_write_units: 0.0,
+        s3_tier1: 0,
+        s3_tier2: 0,
+    });
+
+    let projection = query_param(&req, "fields").and_then(|f| Projection::parse(&f));
+
+    // counted alongside the request so usage tracking adds no latency
+    let key = usage::usage_key(&req);
+    let client = key.client().to_string();
+    let used = Arc::new(Mutex::new(Usage::default()));
+    let tracked = costs::track(used.clone(), handle(req));
+    let tracked = correlation::scope(correlation_id.clone(), tracked);
+    let (result, recorded) = async { tokio::join!(tracked, usage::record(key)) }
+        .instrument(span.clone())
+        .await;
+    if let Err(e) = recorded {
+        tracing::error!("usage record error: {:?}", e);
+    }
+
+    let duration_ms = now_millis().saturating_sub(started);
+    latency::observe(&method, &path, duration_ms);
+
+    // counted for the daily digest
+    if result.as_ref().map_or(true, |r| r.status().is_server_error()) {
+        if let Err(e) = usage::record_error().instrument(span.clone()).await {
+            tracing::error!("error count record error: {:?}", e);
+        }
+    }
+
+    let used = used.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    if let Err(e) = costs::record(&path, &used).instrument(span).await {
+        tracing::error!("cost record error: {:?}", e);
+    }
+
+    // delivered before returning: nothing runs once the invocation is frozen
+    if let Some(mut access) = access {
+        access.status = match &result {
+            Ok(response) => response.status().as_u16(),
+            Err(_) => 500,
+        };
+        access.duration_ms = duration_ms;
+        access.client = client;
+        access.ddb_read_units = used.read_units;
+        access.ddb_write_units = used.write_units;
+        access.s3_tier1 = used.s3_tier1;
+        access.s3_tier2 = used.s3_tier2;
+        firehose::buffer(access);
+        if let Err(e) = firehose::flush().await {
+            tracing::error!("firehose flush error: {:?}", e);
+        }
+    }
+
+    // answered here rather than by the runtime so errors carry the same headers
+    let mut response = match result {
+        Ok(response) => response,
+        Err(e) => {
+            tracing::error!("unhandled error: {:?}", e);
+            text_response(500, "internal error".to_string())?
+        }
+    };
+    if let Some(projection) = &projection {
+        fields::project(&mut response, projection);
+    }
+    if let Ok(value) = correlation_id.parse() {
+        response.headers_mut().insert(CORRELATION_HEADER, value);
+    }
+    security_headers::apply(&mut response);
+    Ok(response)
+}
+
+async fn handle(req: Request) -> Result<Response<Body>, Error> {
+    // the request is still served normally so the intruder sees nothing unusual
+    if let Some(decoy) = honeytoken::tripped(&req) {
+        if let Err(e) = honeytoken::alert(&decoy, &req).await {
+            tracing::error!("honeytoken alert error: {:?}", e);
+        }
+    }
+
+    // autosaves come every few seconds and publish nothing; auditing each
+    // one would triple their writes
+    if !is_mutating(req.method().as_str()) || is_autosave(req.uri().path()) {
+        if let Some(url) = shadow::target(&req) {
+            let path = req.uri().path().to_string();
+            let headers = req.headers().clone();
+            let (result, shadowed) = shadow::alongside(route(req), url, headers).await;
+            if let Ok(response) = &result {
+                shadow::compare(&path, response, shadowed);
+            }
+            return result;
+        }
+        return route(req).await;
+    }
+
+    // write-ahead: a mutation that cannot be audited is not executed
+    let entry = match audit::begin(&req).await {
+        Ok(entry) => entry,
+        Err(e) => {
+            tracing::error!("audit write error: {:?}", e);
+            return text_response(500, "audit error".to_string());
+        }
+    };
+
+    let result = route(req).await;
+
+    let status = result.as_ref().map(|r| r.status().as_u16()).unwrap_or(500);
+    if let Err(e) = audit::finish(&entry, status).await {
+        tracing::error!("audit finish error: {:?}", e);
+    }
+
+    result
+}
+
+async fn route(req: Request) -> Result<Response<Body>, Error> {
     if req.method() == "OPTIONS" {
         let mut response = Response::new(Body::Empty);
         *response.status_mut() = StatusCode::OK;
@@ -62,9 +626,59 @@ pub async fn function_handler(req: Request) -> Result<Response<Body>, Error> {
 
     let path = req.uri().path().to_string();
     let method = req.method().as_str();
+    let ctx = match Ctx::new(&req).await {
+        Ok(ctx) => ctx,
+        Err(e) => {
+            tracing::error!("dynamodb stage error: {:?}", e);
+            return dynamodb_error(e.as_ref());
+        }
+    };
+
+    // held until the route returns
+    let _permit = match concurrency::acquire(method, &path, ctx.remaining()).await {
+        Ok(permit) => permit,
+        Err(Busy) => {
+            let mut response = text_response(429, "too many concurrent requests".to_string())?;
+            response.headers_mut().insert("retry-after", "5".parse()?);
+            return Ok(response);
+        }
+    };
+
+    // active-passive: while failed over, the replica region only serves reads
+    if failover::is_failed_over() && is_mutating(method) {
+        let mut response = text_response(503, "read-only during failover".to_string())?;
+        response.headers_mut().insert("retry-after", "60".parse()?);
+        return Ok(response);
+    }
 
-    let bucket = std::env::var("s3_bucket").expect("s3_bucket env missing");
-    let base_path = std::env::var("s3_path").unwrap_or_default();
+    // S3 configuration is only required by the routes that touch S3; every
+    // other route works (and `ctx.config.bucket` stays empty) without it
+    let needs_s3 = path.starts_with("/api/s3/")
+        || path.starts_with("/api/files/")
+        || path == "/stage/promote"
+        || path == "/admin/gc"
+        || path == "/admin/backup"
+        || path == "/admin/export-static"
+        || path == "/admin/s3/transition"
+        || path.starts_with("/admin/users/")
+        || path.starts_with(IMAGE_ROUTE)
+        || path == "/theme"
+        || path.starts_with("/admin/themes")
+        || path == "/avatar"
+        || (path.starts_with("/posts/") && path.ends_with("/attachments"));
+    if needs_s3 && ctx.config.bucket.is_empty() {
+        tracing::error!("s3_bucket env missing");
+        return json_response(
+            503,
+            json!({ "error": "s3_not_configured", "message": "s3_bucket is not set" }),
+        );
+    }
+    let bucket = &ctx.config.bucket;
+    let root_path = &ctx.config.root_path;
+
+    // draft-site vs live-site content; every key below is scoped to it
+    let stage = ctx.stage;
+    let base_path = &ctx.base_path;
 
     // 1) health
     if method == "GET" && path == "/helloWorld" {
@@ -82,15 +696,49 @@ pub async fn function_handler(req: Request) -> Result<Response<Body>, Error> {
         if idx.is_empty() {
             return text_response(400, "idx is required".to_string());
         }
+        // reserved partitions hold subscriber data, logs and internal state
+        if is_staged_part(&part) || (is_reserved_part(&part) && !ctx.is_admin()) {
+            return text_response(403, "part is reserved".to_string());
+        }
 
-        return match get_item_value(part, idx).await {
-            Ok(Some(value)) => text_response(200, value),
-            Ok(None) => text_response(200, "".to_string()),
+        let stored = match get_stored_value(stage.partition(&part), idx).await {
+            Ok(Some(stored)) => stored,
+            Ok(None) => return text_response(200, "".to_string()),
             Err(e) => {
                 tracing::error!("dynamodb get error: {:?}", e);
-                text_response(500, "dynamodb error".to_string())
+                return dynamodb_error(e.as_ref());
             }
-        }
+        };
+        let etag = dynamodb::etag(stored.version);
+        let mut response = match stored {
+            StoredValue { encrypted: true, .. } if !ctx.is_admin() => {
+                return text_response(403, "forbidden".to_string())
+            }
+            StoredValue {
+                value: envelope,
+                encrypted: true,
+                ..
+            } => match secrets::decrypt(&envelope).await {
+                Ok(value) => text_response(200, value)?,
+                Err(e) => {
+                    tracing::error!("kms decrypt error: {:?}", e);
+                    return text_response(500, "kms error".to_string());
+                }
+            },
+            StoredValue { value, .. }
+                if part == posts::posts_part()
+                    && subscribers::is_subscribers_only(&value)
+                    && !ctx.is_admin() =>
+            {
+                return text_response(403, "subscribers only".to_string())
+            }
+            StoredValue { value, .. } if part == posts::posts_part() => {
+                text_response(200, ctx.view().post_value(&value))?
+            }
+            StoredValue { value, .. } => text_response(200, value)?,
+        };
+        response.headers_mut().insert("etag", etag.parse()?);
+        return Ok(response);
     }
 
     if path == "/dynamodb/item" && method == "POST" {
@@ -109,15 +757,59 @@ pub async fn function_handler(req: Request) -> Result<Response<Body>, Error> {
         if payload.part.is_empty() {
             return text_response(400, "part is required".to_string());
         }
-        if payload.idx.is_empty() {
-            return text_response(400, "idx is required".to_string());
+        if is_reserved_part(&payload.part) {
+            return text_response(403, "part is reserved".to_string());
+        }
+
+        // no idx: assign a time-sortable one and tell the client what it is
+        let (idx, generated) = match payload.idx.filter(|idx| !idx.is_empty()) {
+            Some(idx) => (idx, false),
+            None => (generate_idx(), true),
+        };
+        let precondition = if_match(&req);
+        if precondition.as_ref().is_some_and(Precondition::is_unsatisfiable) {
+            return precondition_failed();
         }
+        let precondition = precondition.as_ref();
 
-        if let Err(e) = put_item(payload.part, payload.idx, payload.value).await {
-            tracing::error!("dynamodb put error: {:?}", e);
-            return text_response(500, "dynamodb error".to_string());
+        let part = stage.partition(&payload.part);
+        if payload.encrypted {
+            let Some(key_id) = secrets::key_id() else {
+                return text_response(400, "encryption is not configured".to_string());
+            };
+            if payload.part == posts::posts_part() {
+                return text_response(400, "posts cannot be encrypted".to_string());
+            }
+            if payload.value.len() > secrets::MAX_SECRET_BYTES {
+                return text_response(413, "value too large to encrypt".to_string());
+            }
+            let envelope = match secrets::encrypt(&key_id, &payload.value).await {
+                Ok(envelope) => envelope,
+                Err(e) => {
+                    tracing::error!("kms encrypt error: {:?}", e);
+                    return text_response(500, "kms error".to_string());
+                }
+            };
+            if let Err(e) = put_encrypted_item(part, idx.clone(), envelope, precondition).await {
+                return write_error(e.as_ref(), precondition);
+            }
+        } else if payload.part == posts::posts_part() {
+            // posts also maintain their excerpt and the slug index
+            let slugs_part = stage.partition(SLUGS_PARTITION);
+            let saved =
+                posts::save_post(part, slugs_part, idx.clone(), payload.value, precondition).await;
+            match saved {
+                Ok(Ok(())) => {}
+                Ok(Err(_)) => return text_response(409, "slug already in use".to_string()),
+                Err(e) => return write_error(e.as_ref(), precondition),
+            }
+        } else if let Err(e) = put_item_if(part, idx.clone(), payload.value, precondition).await {
+            return write_error(e.as_ref(), precondition);
         }
 
+        if generated {
+            return json_response(200, json!({ "idx": idx }));
+        }
         return text_response(200, "Success".to_string());
     }
 
@@ -131,124 +823,2866 @@ pub async fn function_handler(req: Request) -> Result<Response<Body>, Error> {
         if idx.is_empty() {
             return text_response(400, "idx is required".to_string());
         }
+        if is_reserved_part(&part) {
+            return text_response(403, "part is reserved".to_string());
+        }
 
-        if let Err(e) = delete_item(part, idx).await {
-            tracing::error!("dynamodb delete error: {:?}", e);
-            return text_response(500, "dynamodb error".to_string());
+        let precondition = if_match(&req);
+        if precondition.as_ref().is_some_and(Precondition::is_unsatisfiable) {
+            return precondition_failed();
+        }
+        let precondition = precondition.as_ref();
+
+        let result = if part == posts::posts_part() {
+            posts::delete_post(stage.partition(&part), idx, precondition).await
+        } else {
+            delete_item_if(stage.partition(&part), idx, precondition).await
+        };
+        if let Err(e) = result {
+            return write_error(e.as_ref(), precondition);
         }
 
         return text_response(200, "Success".to_string());
     }
 
-    // 4) s3
-    if path == "/api/s3/list" && method == "GET" {
-        let part = query_param(&req, "part");
-        let idx = query_param(&req, "idx");
-
-        let prefix = if let (Some(part_val), Some(idx_val)) = (&part, &idx) {
-            if !part_val.is_empty() && !idx_val.is_empty() {
-                format!("{base_path}upload/{}/{}/", part_val, idx_val)
-            } else {
-                format!("{base_path}upload/")
-            }
-        } else {
-            format!("{base_path}upload/")
+    if path == "/dynamodb/import" && method == "POST" {
+        if !ctx.is_admin() {
+            return text_response(403, "forbidden".to_string());
+        }
+        let content_type = req
+            .headers()
+            .get("content-type")
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or_default();
+        let format = ImportFormat::from_content_type(content_type);
+        let body: &[u8] = match req.body() {
+            Body::Text(s) => s.as_bytes(),
+            Body::Binary(b) => b,
+            _ => return text_response(400, "empty body".to_string()),
         };
 
-        return match list_objects(&bucket, prefix).await {
-            Ok((folders, files)) => {
-                json_response(200, json!({ "folders": folders, "files": files }))
-            }
+        return match import::import(body, format, stage, is_reserved_part).await {
+            Ok(report) => json_response(200, json!(report)),
             Err(e) => {
-                tracing::error!("s3 list error: {:?}", e);
-                text_response(500, "s3 error".to_string())
+                tracing::error!("dynamodb import error: {:?}", e);
+                dynamodb_error(e.as_ref())
             }
         };
     }
 
-    if path == "/api/s3/upload-url" && method == "GET" {
-        let part = query_param(&req, "part");
-        let idx = query_param(&req, "idx");
-        let filename = query_param(&req, "filename").unwrap_or_default();
-
-        if filename.is_empty() {
-            return text_response(400, "filename is required".to_string());
+    if path == "/dynamodb/items" && method == "GET" {
+        let part = query_param(&req, "part").unwrap_or_default();
+        if part.is_empty() {
+            return text_response(400, "part is required".to_string());
+        }
+        if is_staged_part(&part) || (is_reserved_part(&part) && !ctx.is_admin()) {
+            return text_response(403, "part is reserved".to_string());
         }
 
-        let content_type =
-            query_param(&req, "contentType").unwrap_or("application/octet-stream".to_string());
+        let mut items = match list_items(stage.partition(&part)).await {
+            Ok(items) => items,
+            Err(e) => {
+                tracing::error!("dynamodb list error: {:?}", e);
+                return dynamodb_error(e.as_ref());
+            }
+        };
+        let series_part = stage.partition(SERIES_PARTITION);
+        if let Err(e) = series::attach_links(series_part, &part, &mut items).await {
+            tracing::error!("dynamodb series links error: {:?}", e);
+            return dynamodb_error(e.as_ref());
+        }
 
-        let key = if let (Some(part_val), Some(idx_val)) = (&part, &idx) {
-            if !part_val.is_empty() && !idx_val.is_empty() {
-                format!("{base_path}upload/{}/{}/{}", part_val, idx_val, filename)
-            } else {
-                format!("{base_path}upload/{}", filename)
+        if part == posts::posts_part() {
+            let view = ctx.view();
+            for item in &mut items {
+                item.value = item.value.as_deref().map(|v| view.post_value(v));
             }
+        }
+
+        let meta = quota::items_meta(items.len());
+        return json_response(200, json!({ "items": items, "meta": meta }));
+    }
+
+    if path == "/dynamodb/item/pin" && (method == "POST" || method == "DELETE") {
+        let (part, idx) = if method == "POST" {
+            let payload: DynamodbKeyPayload = match parse_json_body(req.body())? {
+                Ok(payload) => payload,
+                Err(response) => return Ok(response),
+            };
+            (payload.part, payload.idx)
         } else {
-            format!("{base_path}upload/{}", filename)
+            (
+                query_param(&req, "part").unwrap_or_default(),
+                query_param(&req, "idx").unwrap_or_default(),
+            )
         };
 
-        return match presign_upload(&bucket, key, content_type).await {
-            Ok(url) => text_response(200, url),
+        if part.is_empty() {
+            return text_response(400, "part is required".to_string());
+        }
+        if idx.is_empty() {
+            return text_response(400, "idx is required".to_string());
+        }
+        if is_reserved_part(&part) {
+            return text_response(403, "part is reserved".to_string());
+        }
+
+        return match set_pinned(stage.partition(&part), idx, method == "POST").await {
+            Ok(true) => text_response(200, "Success".to_string()),
+            Ok(false) => text_response(404, "item not found".to_string()),
             Err(e) => {
-                tracing::error!("s3 upload presign error: {:?}", e);
-                text_response(500, "s3 error".to_string())
+                tracing::error!("dynamodb pin error: {:?}", e);
+                dynamodb_error(e.as_ref())
             }
         };
     }
 
-    if path == "/api/s3/download-url" && method == "GET" {
-        let part = query_param(&req, "part");
-        let idx = query_param(&req, "idx");
-        let filename = query_param(&req, "filename").unwrap_or_default();
+    if path == "/dynamodb/items/order" && method == "POST" {
+        let payload: DynamodbOrderPayload = match parse_json_body(req.body())? {
+            Ok(payload) => payload,
+            Err(response) => return Ok(response),
+        };
 
-        if filename.is_empty() {
-            return text_response(400, "filename is required".to_string());
+        if payload.part.is_empty() {
+            return text_response(400, "part is required".to_string());
+        }
+        if payload.order.is_empty() || payload.order.len() > 100 {
+            return text_response(400, "order must list 1 to 100 idx values".to_string());
+        }
+        if payload.order.iter().any(|idx| idx.is_empty()) {
+            return text_response(400, "order must not contain an empty idx".to_string());
+        }
+        // one transaction cannot touch an item twice
+        let mut seen = HashSet::new();
+        if let Some(repeated) = payload.order.iter().find(|idx| !seen.insert(*idx)) {
+            return text_response(400, format!("order lists {repeated} more than once"));
+        }
+        if is_reserved_part(&payload.part) {
+            return text_response(403, "part is reserved".to_string());
         }
 
-        let key = if let (Some(part_val), Some(idx_val)) = (&part, &idx) {
-            if !part_val.is_empty() && !idx_val.is_empty() {
-                format!("{base_path}{}/{}/{}", part_val, idx_val, filename)
-            } else {
-                format!("{base_path}{}", filename)
+        return match set_sort_weights(stage.partition(&payload.part), payload.order).await {
+            Ok(Ok(())) => text_response(200, "Success".to_string()),
+            Ok(Err(missing)) => json_response(
+                404,
+                json!({ "error": "item not found", "missing": missing }),
+            ),
+            Err(e) => {
+                tracing::error!("dynamodb reorder error: {:?}", e);
+                dynamodb_error(e.as_ref())
             }
-        } else {
-            format!("{base_path}{}", filename)
         };
+    }
 
-        return match presign_download(&bucket, key).await {
-            Ok(url) => text_response(200, url),
+    // series
+    if path == "/series" && method == "GET" {
+        return match series::list_series(stage.partition(SERIES_PARTITION)).await {
+            Ok(list) => json_response(200, json!({ "series": list })),
             Err(e) => {
-                tracing::error!("s3 download presign error: {:?}", e);
-                text_response(500, "s3 error".to_string())
+                tracing::error!("dynamodb series list error: {:?}", e);
+                dynamodb_error(e.as_ref())
             }
         };
     }
 
-    if path == "/api/s3/delete-url" && method == "GET" {
-        let part = query_param(&req, "part");
-        let idx = query_param(&req, "idx");
-        let filename = query_param(&req, "filename").unwrap_or_default();
+    if let Some(rest) = path.strip_prefix("/series/") {
+        let series_part = stage.partition(SERIES_PARTITION);
+        let (id, sub) = match rest.split_once('/') {
+            Some((id, sub)) => (id.to_string(), Some(sub)),
+            None => (rest.to_string(), None),
+        };
+        if id.is_empty() {
+            return text_response(400, "series id is required".to_string());
+        }
 
-        if filename.is_empty() {
-            return text_response(400, "filename is required".to_string());
+        match (method, sub) {
+            ("GET", None) => {
+                return match series::get_series(series_part, id).await {
+                    Ok(Some(found)) => json_response(200, json!(found)),
+                    Ok(None) => text_response(404, "series not found".to_string()),
+                    Err(e) => {
+                        tracing::error!("dynamodb series get error: {:?}", e);
+                        dynamodb_error(e.as_ref())
+                    }
+                };
+            }
+            ("POST", None) => {
+                #[derive(Deserialize)]
+                struct SeriesPayload {
+                    title: String,
+                }
+                let payload: SeriesPayload = match parse_json_body(req.body())? {
+                    Ok(payload) => payload,
+                    Err(response) => return Ok(response),
+                };
+
+                if let Err(e) = series::put_series(series_part, id, payload.title).await {
+                    tracing::error!("dynamodb series put error: {:?}", e);
+                    return dynamodb_error(e.as_ref());
+                }
+                return text_response(200, "Success".to_string());
+            }
+            ("POST", Some("posts")) => {
+                let payload: DynamodbKeyPayload = match parse_json_body(req.body())? {
+                    Ok(payload) => payload,
+                    Err(response) => return Ok(response),
+                };
+                if payload.part.is_empty() || payload.idx.is_empty() {
+                    return text_response(400, "part and idx are required".to_string());
+                }
+
+                let post_part = stage.partition(&payload.part);
+                let member = SeriesMember {
+                    part: payload.part,
+                    idx: payload.idx,
+                };
+                if let Err(e) = series::add_post(series_part, id, post_part, member).await {
+                    tracing::error!("dynamodb series add error: {:?}", e);
+                    return dynamodb_error(e.as_ref());
+                }
+                return text_response(200, "Success".to_string());
+            }
+            ("DELETE", Some("posts")) => {
+                let part = query_param(&req, "part").unwrap_or_default();
+                let idx = query_param(&req, "idx").unwrap_or_default();
+                if part.is_empty() || idx.is_empty() {
+                    return text_response(400, "part and idx are required".to_string());
+                }
+
+                let post_part = stage.partition(&part);
+                let member = SeriesMember { part, idx };
+                return match series::remove_post(series_part, id, post_part, member).await {
+                    Ok(true) => text_response(200, "Success".to_string()),
+                    Ok(false) => text_response(404, "post is not in series".to_string()),
+                    Err(e) => {
+                        tracing::error!("dynamodb series remove error: {:?}", e);
+                        dynamodb_error(e.as_ref())
+                    }
+                };
+            }
+            _ => {}
         }
+    }
 
-        let key = if let (Some(part_val), Some(idx_val)) = (&part, &idx) {
-            if !part_val.is_empty() && !idx_val.is_empty() {
-                format!("{base_path}{}/{}/{}", part_val, idx_val, filename)
-            } else {
-                format!("{base_path}{}", filename)
+    // posts
+    if path == "/posts" && method == "GET" {
+        let sort = query_param(&req, "sort").unwrap_or_else(|| "created_at".to_string());
+        let Some(sort) = PostSort::parse(&sort) else {
+            return text_response(400, "sort must be created_at or views".to_string());
+        };
+        let descending = query_param(&req, "order").as_deref() != Some("asc");
+        let limit = query_param(&req, "limit")
+            .and_then(|l| l.parse::<usize>().ok())
+            .unwrap_or(20)
+            .clamp(1, 100);
+        let fields = query_param(&req, "fields").map(|f| {
+            f.split(',')
+                .map(|f| f.trim().to_string())
+                .filter(|f| !f.is_empty())
+                .collect()
+        });
+
+        let part = stage.partition(&posts::posts_part());
+        return match posts::list_posts_pinned_first(part, sort, descending, limit, fields).await {
+            Ok(mut list) => {
+                let view = ctx.view();
+                for post in list.iter_mut() {
+                    locale::select_variant(post, accept_language(&req));
+                    view.post(post);
+                }
+                if let Some(cdn) = &ctx.config.cdn_url {
+                    let base_url = format!("{cdn}/{base_path}");
+                    list.iter_mut().for_each(|post| images::rewrite_post(post, &base_url));
+                }
+                let mut response = json_response(200, json!({ "posts": list }))?;
+                response
+                    .headers_mut()
+                    .insert("vary", "Accept-Language".parse()?);
+                // `fields` names attributes of the posts, not of the envelope
+                response.extensions_mut().insert(Projected);
+                Ok(response)
+            }
+            Err(e) => {
+                tracing::error!("dynamodb posts list error: {:?}", e);
+                dynamodb_error(e.as_ref())
             }
-        } else {
-            format!("{base_path}{}", filename)
         };
+    }
+
+    // incremental sync for offline clients; the token is opaque to them
+    if path == "/sync" && method == "GET" {
+        let limit = query_param(&req, "limit")
+            .and_then(|l| l.parse::<usize>().ok())
+            .unwrap_or(DEFAULT_SYNC_LIMIT)
+            .clamp(1, MAX_SYNC_LIMIT);
+        let part = stage.partition(&posts::posts_part());
+        // a promotion since replaced every live post, so the client starts over
+        let promoted_since = |token: &str| match stage {
+            Stage::Live(generation) => (0..generation).any(|earlier| {
+                let earlier_part = Stage::Live(earlier).partition(&posts::posts_part());
+                cursor::decode::<serde_json::Value>(&earlier_part, token).is_some()
+            }),
+            Stage::Draft => false,
+        };
+        let from = match query_param(&req, "since").filter(|s| !s.is_empty()) {
+            None => None,
+            Some(token) => match cursor::decode(&part, &token) {
+                Some(position) => Some(position),
+                None if promoted_since(&token) => {
+                    return json_response(
+                        410,
+                        json!({
+                            "error": "sync token expired",
+                            "message": "sync again without since",
+                        }),
+                    )
+                }
+                None => return text_response(400, "invalid sync token".to_string()),
+            },
+        };
+
+        return match sync::changes(part.clone(), from, limit, ctx.view()).await {
+            Ok(Some(mut page)) => {
+                if let Some(cdn) = &ctx.config.cdn_url {
+                    let base_url = format!("{cdn}/{base_path}");
+                    for post in page.upserts.iter_mut() {
+                        images::rewrite_post(post, &base_url);
+                    }
+                }
+                json_response(
+                    200,
+                    json!({
+                        "upserts": page.upserts,
+                        "removals": page.removals,
+                        "token": cursor::encode(&part, page.next),
+                        "hasMore": page.has_more,
+                    }),
+                )
+            }
+            Ok(None) => json_response(
+                410,
+                json!({ "error": "sync token expired", "message": "sync again without since" }),
+            ),
+            Err(e) => {
+                tracing::error!("dynamodb sync error: {:?}", e);
+                dynamodb_error(e.as_ref())
+            }
+        };
+    }
+
+    // a 301-style payload rather than a redirect, like retired slugs
+    if let Some(code) = path.strip_prefix(SHORTLINK_ROUTE) {
+        if method == "GET" && !code.is_empty() {
+            let code = shortlinks::normalize_code(&percent_decode_str(code).decode_utf8_lossy());
+            let part = stage.partition(SHORTLINKS_PARTITION);
+            return match shortlinks::follow(part, code.clone()).await {
+                Ok(Some(target)) => json_response(
+                    200,
+                    json!({ "code": code, "status": 301, "location": target }),
+                ),
+                Ok(None) => text_response(404, "short link not found".to_string()),
+                Err(e) => {
+                    tracing::error!("dynamodb short link error: {:?}", e);
+                    dynamodb_error(e.as_ref())
+                }
+            };
+        }
+    }
+
+    if let Some(slug) = path.strip_prefix("/posts/by-slug/") {
+        if method == "GET" && !slug.is_empty() {
+            let slug = percent_decode_str(slug).decode_utf8_lossy();
+            let posts_part = stage.partition(&posts::posts_part());
+            let slugs_part = stage.partition(SLUGS_PARTITION);
+            return match slugs::resolve(posts_part.clone(), slugs_part, &slug).await {
+                Ok(Some(mut post)) => {
+                    let lang = locale::select_variant(&mut post, accept_language(&req));
+                    ctx.view().post(&mut post);
+                    if let Some(cdn) = &ctx.config.cdn_url {
+                        images::rewrite_post(&mut post, &format!("{cdn}/{base_path}"));
+                    }
+                    let mut response = json_response(200, post)?;
+                    let headers = response.headers_mut();
+                    headers.insert("vary", "Accept-Language".parse()?);
+                    if let Some(Ok(lang)) = lang.map(|l| l.parse()) {
+                        headers.insert("content-language", lang);
+                    }
+                    Ok(response)
+                }
+                Ok(None) => {
+                    let suggestions = match slugs::suggestion_candidates(posts_part).await {
+                        Ok(known) => suggest::closest(
+                            &slugs::normalize_slug(&slug),
+                            known.iter().map(String::as_str),
+                        ),
+                        Err(e) => {
+                            tracing::error!("dynamodb slug list error: {:?}", e);
+                            Vec::new()
+                        }
+                    };
+                    json_response(
+                        404,
+                        json!({ "error": "post not found", "suggestions": suggestions }),
+                    )
+                }
+                Err(e) => {
+                    tracing::error!("dynamodb slug error: {:?}", e);
+                    dynamodb_error(e.as_ref())
+                }
+            };
+        }
+    }
+
+    if let Some(id) = path
+        .strip_prefix("/posts/")
+        .and_then(|rest| rest.strip_suffix("/view"))
+    {
+        if method == "POST" && !id.is_empty() && !id.contains('/') {
+            let part = stage.partition(&posts::posts_part());
+            return match posts::record_view(part, id.to_string()).await {
+                Ok(Some(views)) => {
+                    // the post's count stands even when the country's is lost
+                    if let Err(e) = geo::record_view(&Geo::from_request(&req)).await {
+                        tracing::error!("dynamodb country view error: {:?}", e);
+                    }
+                    json_response(200, json!({ "views": views }))
+                }
+                Ok(None) => text_response(404, "post not found".to_string()),
+                Err(e) => {
+                    tracing::error!("dynamodb view error: {:?}", e);
+                    dynamodb_error(e.as_ref())
+                }
+            };
+        }
+    }
+
+    if let Some(id) = path
+        .strip_prefix("/posts/")
+        .and_then(|rest| rest.strip_suffix("/reactions"))
+    {
+        if !id.is_empty() && !id.contains('/') {
+            let part = stage.partition(&posts::posts_part());
+
+            if method == "GET" {
+                return match reactions::counts(&part, id).await {
+                    Ok(counts) => json_response(200, json!({ "reactions": counts })),
+                    Err(e) => {
+                        tracing::error!("dynamodb reactions error: {:?}", e);
+                        dynamodb_error(e.as_ref())
+                    }
+                };
+            }
+
+            if method == "POST" {
+                #[derive(Deserialize)]
+                struct ReactionPayload {
+                    emoji: String,
+                }
+                let payload: ReactionPayload = match parse_json_body(req.body())? {
+                    Ok(payload) => payload,
+                    Err(response) => return Ok(response),
+                };
+                let Some(emoji) = reactions::parse(&payload.emoji) else {
+                    return text_response(
+                        400,
+                        format!("emoji must be one of {}", reactions::REACTIONS.join(" ")),
+                    );
+                };
+
+                match posts::stored_views(part.clone(), id.to_string()).await {
+                    Ok(Some(_)) => {}
+                    Ok(None) => return text_response(404, "post not found".to_string()),
+                    Err(e) => {
+                        tracing::error!("dynamodb reactions error: {:?}", e);
+                        return dynamodb_error(e.as_ref());
+                    }
+                }
+                if let Err(e) = reactions::react(&part, id, emoji).await {
+                    tracing::error!("dynamodb reactions error: {:?}", e);
+                    return dynamodb_error(e.as_ref());
+                }
+                return match reactions::counts(&part, id).await {
+                    Ok(counts) => json_response(200, json!({ "reactions": counts })),
+                    Err(e) => {
+                        tracing::error!("dynamodb reactions error: {:?}", e);
+                        dynamodb_error(e.as_ref())
+                    }
+                };
+            }
+        }
+    }
+
+    // new comments live elsewhere; this says whether a post takes them
+    if let Some(id) = path
+        .strip_prefix("/posts/")
+        .and_then(|rest| rest.strip_suffix("/comments/settings"))
+    {
+        if !id.is_empty() && !id.contains('/') {
+            let part = stage.partition(&posts::posts_part());
+
+            if method == "PUT" {
+                if !ctx.is_admin() {
+                    return text_response(403, "forbidden".to_string());
+                }
+                let payload: PostCommentSettings = match parse_json_body(req.body())? {
+                    Ok(payload) => payload,
+                    Err(response) => return Ok(response),
+                };
+                if let Err(message) = payload.validate() {
+                    return text_response(400, message);
+                }
+                match comments::save(part.clone(), id.to_string(), &payload).await {
+                    Ok(true) => {}
+                    Ok(false) => return text_response(404, "post not found".to_string()),
+                    Err(e) => {
+                        tracing::error!("dynamodb comment settings error: {:?}", e);
+                        return dynamodb_error(e.as_ref());
+                    }
+                }
+            }
+
+            if method == "GET" || method == "PUT" {
+                let site = match settings::load(stage.partition(SETTINGS_PARTITION)).await {
+                    Ok(site) => site,
+                    Err(e) => {
+                        tracing::error!("dynamodb settings error: {:?}", e);
+                        return dynamodb_error(e.as_ref());
+                    }
+                };
+                return match comments::status(part, id.to_string(), &site).await {
+                    Ok(Some(status)) => json_response(200, json!(status)),
+                    Ok(None) => text_response(404, "post not found".to_string()),
+                    Err(e) => {
+                        tracing::error!("dynamodb comment settings error: {:?}", e);
+                        dynamodb_error(e.as_ref())
+                    }
+                };
+            }
+        }
+    }
+
+    // accessibility lint of the post body as it renders; drafts included
+    if let Some(id) = path
+        .strip_prefix("/posts/")
+        .and_then(|rest| rest.strip_suffix("/lint"))
+    {
+        if method == "GET" && !id.is_empty() && !id.contains('/') {
+            if !ctx.is_admin() {
+                return text_response(403, "forbidden".to_string());
+            }
+            let part = stage.partition(&posts::posts_part());
+            let value = match get_item_value(part, id.to_string()).await {
+                Ok(Some(value)) => value,
+                Ok(None) => return text_response(404, "post not found".to_string()),
+                Err(e) => {
+                    tracing::error!("dynamodb lint post error: {:?}", e);
+                    return dynamodb_error(e.as_ref());
+                }
+            };
+            let html = preview::render_body(&posts::post_body(&value), &HashMap::new());
+            let warnings = lint::audit(&html);
+            return json_response(200, json!({ "idx": id, "warnings": warnings }));
+        }
+    }
+
+    // a post's uploads in display order, with captions and alt text
+    if let Some(id) = path
+        .strip_prefix("/posts/")
+        .and_then(|rest| rest.strip_suffix("/attachments"))
+    {
+        if method == "GET" && !id.is_empty() && !id.contains('/') {
+            let posts_part = posts::posts_part();
+            let post = match get_item_value(stage.partition(&posts_part), id.to_string()).await {
+                Ok(Some(value)) => value,
+                Ok(None) => return text_response(404, "post not found".to_string()),
+                Err(e) => {
+                    tracing::error!("dynamodb attachments post error: {:?}", e);
+                    return dynamodb_error(e.as_ref());
+                }
+            };
+            let draft = serde_json::from_str::<serde_json::Value>(&post)
+                .is_ok_and(|post| post["status"] == "draft");
+            if draft && !ctx.is_admin() {
+                return text_response(404, "post not found".to_string());
+            }
+            // the keys would let anyone presign the files of a locked post
+            if subscribers::is_subscribers_only(&post) && !ctx.is_admin() {
+                return text_response(403, "subscribers only".to_string());
+            }
+
+            let prefix = format!("upload/{posts_part}/{id}/");
+            let listing = format!("{base_path}{prefix}");
+            let (objects, rules) = tokio::join!(list_all_objects(bucket, &listing), Rules::load());
+            let objects = match objects {
+                Ok(objects) => objects,
+                Err(e) => {
+                    tracing::error!("s3 list error: {:?}", e);
+                    return s3_error(e.as_ref());
+                }
+            };
+            let rules = match rules {
+                Ok(rules) => rules,
+                Err(e) => {
+                    tracing::error!("dynamodb prefix acl error: {:?}", e);
+                    return dynamodb_error(e.as_ref());
+                }
+            };
+            // keys relative to the stage's base, as the ACL and PATCH take them
+            let admin = ctx.is_admin();
+            let objects = objects
+                .into_iter()
+                .filter_map(|mut object| {
+                    object.key = object.key.strip_prefix(base_path.as_str())?.to_string();
+                    rules.visibility(&object.key).allows(admin, true).then_some(object)
+                })
+                .collect();
+
+            let part = stage.partition(ATTACHMENTS_PARTITION);
+            return match attachments::in_order(part, &prefix, objects).await {
+                Ok(attachments) => {
+                    json_response(200, json!({ "idx": id, "attachments": attachments }))
+                }
+                Err(e) => {
+                    tracing::error!("dynamodb attachments error: {:?}", e);
+                    dynamodb_error(e.as_ref())
+                }
+            };
+        }
+    }
+
+    // what a page or share button needs to link to a post
+    if let Some(id) = path
+        .strip_prefix("/posts/")
+        .and_then(|rest| rest.strip_suffix("/share"))
+    {
+        if method == "GET" && !id.is_empty() && !id.contains('/') {
+            let Some(public_urls) = &ctx.urls else {
+                return text_response(404, "site_url is not configured".to_string());
+            };
+            let part = stage.partition(&posts::posts_part());
+            let mut post = match get_item_value(part, id.to_string()).await {
+                Ok(Some(value)) => match serde_json::from_str(&value) {
+                    Ok(post @ serde_json::Value::Object(_)) => post,
+                    _ => json!({ "body": value }),
+                },
+                Ok(None) => return text_response(404, "post not found".to_string()),
+                Err(e) => {
+                    tracing::error!("dynamodb share post error: {:?}", e);
+                    return dynamodb_error(e.as_ref());
+                }
+            };
+            if post["status"] == "draft" && !ctx.is_admin() {
+                return text_response(404, "post not found".to_string());
+            }
+            ctx.view().post(&mut post);
+            if let Some(cdn) = &ctx.config.cdn_url {
+                images::rewrite_post(&mut post, &format!("{cdn}/{base_path}"));
+            }
+            return json_response(200, json!(urls::share(public_urls, id, &post)));
+        }
+    }
+
+    // soft edit locks: they warn a second editor, they don't block saves
+    if let Some(id) = path
+        .strip_prefix("/posts/")
+        .and_then(|rest| rest.strip_suffix("/lock"))
+    {
+        if !id.is_empty() && !id.contains('/') {
+            if !ctx.is_admin() {
+                return text_response(403, "forbidden".to_string());
+            }
+            let part = stage.partition(EDIT_LOCKS_PARTITION);
+            let id = id.to_string();
+
+            if method == "GET" {
+                return match edit_locks::status(part, id).await {
+                    Ok(lock) => json_response(200, json!({ "lock": lock })),
+                    Err(e) => {
+                        tracing::error!("dynamodb edit lock error: {:?}", e);
+                        dynamodb_error(e.as_ref())
+                    }
+                };
+            }
+
+            if method == "POST" {
+                #[derive(Deserialize)]
+                struct LockPayload {
+                    editor: String,
+                    #[serde(default)]
+                    force: bool,
+                }
+                let payload: LockPayload = match parse_json_body(req.body())? {
+                    Ok(payload) => payload,
+                    Err(response) => return Ok(response),
+                };
+                if payload.editor.is_empty() {
+                    return text_response(400, "editor is required".to_string());
+                }
+
+                return match edit_locks::acquire(part, id, payload.editor, payload.force).await {
+                    Ok(Ok(lock)) => json_response(200, json!({ "lock": lock })),
+                    Ok(Err(holder)) => json_response(409, json!({ "lock": holder })),
+                    Err(e) => {
+                        tracing::error!("dynamodb edit lock error: {:?}", e);
+                        dynamodb_error(e.as_ref())
+                    }
+                };
+            }
+
+            if method == "DELETE" {
+                let token = query_param(&req, "token").unwrap_or_default();
+                if token.is_empty() {
+                    return text_response(400, "token is required".to_string());
+                }
+
+                return match edit_locks::release(part, id, token).await {
+                    Ok(()) => text_response(204, String::new()),
+                    Err(e) => {
+                        tracing::error!("dynamodb edit lock error: {:?}", e);
+                        dynamodb_error(e.as_ref())
+                    }
+                };
+            }
+        }
+    }
+
+    // crash recovery for the editor; one put per save, no revision kept
+    if let Some(id) = path
+        .strip_prefix("/posts/")
+        .and_then(|rest| rest.strip_suffix("/autosave"))
+    {
+        if !id.is_empty() && !id.contains('/') {
+            if !ctx.is_admin() {
+                return text_response(403, "forbidden".to_string());
+            }
+            let part = stage.partition(AUTOSAVES_PARTITION);
+
+            if method == "GET" {
+                let posts_part = stage.partition(&posts::posts_part());
+                return match autosave::load(part, posts_part, id.to_string()).await {
+                    Ok(autosave) => json_response(200, json!({ "autosave": autosave })),
+                    Err(e) => {
+                        tracing::error!("dynamodb autosave error: {:?}", e);
+                        dynamodb_error(e.as_ref())
+                    }
+                };
+            }
+
+            if method == "PUT" {
+                let payload: AutosavePayload = match parse_json_body(req.body())? {
+                    Ok(payload) => payload,
+                    Err(response) => return Ok(response),
+                };
+                if payload.body.len() > MAX_AUTOSAVE_BYTES {
+                    let message = format!("body is larger than {MAX_AUTOSAVE_BYTES} bytes");
+                    return text_response(413, message);
+                }
+
+                return match autosave::save(part, id.to_string(), payload).await {
+                    Ok(Ok(saved)) => json_response(
+                        200,
+                        json!({ "savedAt": saved.saved_at, "expiresAt": saved.expires_at }),
+                    ),
+                    Ok(Err(newer)) => json_response(409, json!({ "autosave": newer })),
+                    Err(e) => {
+                        tracing::error!("dynamodb autosave error: {:?}", e);
+                        dynamodb_error(e.as_ref())
+                    }
+                };
+            }
+        }
+    }
+
+    if let Some(id) = path
+        .strip_prefix("/posts/")
+        .and_then(|rest| rest.strip_suffix("/lock/heartbeat"))
+    {
+        if method == "POST" && !id.is_empty() && !id.contains('/') {
+            if !ctx.is_admin() {
+                return text_response(403, "forbidden".to_string());
+            }
+            #[derive(Deserialize)]
+            struct HeartbeatPayload {
+                token: String,
+            }
+            let payload: HeartbeatPayload = match parse_json_body(req.body())? {
+                Ok(payload) => payload,
+                Err(response) => return Ok(response),
+            };
+
+            let part = stage.partition(EDIT_LOCKS_PARTITION);
+            return match edit_locks::heartbeat(part, id.to_string(), payload.token).await {
+                Ok(Some(expires_at)) => json_response(200, json!({ "expiresAt": expires_at })),
+                Ok(None) => text_response(409, "lock lost".to_string()),
+                Err(e) => {
+                    tracing::error!("dynamodb edit lock error: {:?}", e);
+                    dynamodb_error(e.as_ref())
+                }
+            };
+        }
+    }
+
+    if path == "/settings" && method == "GET" {
+        return match settings::load(stage.partition(SETTINGS_PARTITION)).await {
+            Ok(settings) => json_response(200, json!(settings)),
+            Err(e) => {
+                tracing::error!("dynamodb settings error: {:?}", e);
+                dynamodb_error(e.as_ref())
+            }
+        };
+    }
+
+    if path == "/theme" && method == "GET" {
+        let theme = match themes::active(stage.partition(SETTINGS_PARTITION)).await {
+            Ok(Some(theme)) => theme,
+            Ok(None) => return text_response(404, "no theme is active".to_string()),
+            Err(e) => {
+                tracing::error!("dynamodb theme error: {:?}", e);
+                return dynamodb_error(e.as_ref());
+            }
+        };
+        return match theme_urls(&ctx, &theme).await {
+            Ok(urls) => json_response(200, urls),
+            Err(e) => {
+                tracing::error!("s3 theme presign error: {:?}", e);
+                s3_error(e.as_ref())
+            }
+        };
+    }
+
+    if path == "/tags/cloud" && method == "GET" {
+        let by_count = match query_param(&req, "sort").as_deref() {
+            None | Some("tag") => false,
+            Some("count") => true,
+            Some(_) => return text_response(400, "sort must be tag or count".to_string()),
+        };
+        let mut cloud = match tags::cloud(&stage.partition(&posts::posts_part())).await {
+            Ok(cloud) => cloud,
+            Err(e) => {
+                tracing::error!("dynamodb tag cloud error: {:?}", e);
+                return dynamodb_error(e.as_ref());
+            }
+        };
+        if by_count {
+            cloud.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.tag.cmp(&b.tag)));
+        }
+        return json_response(200, json!({ "tags": cloud }));
+    }
+
+    // the comment system keeps the raw text and shows what this returns
+    if path == "/comments/mask" && method == "POST" {
+        #[derive(Deserialize)]
+        struct MaskPayload {
+            text: String,
+        }
+        let payload: MaskPayload = match parse_json_body(req.body())? {
+            Ok(payload) => payload,
+            Err(response) => return Ok(response),
+        };
+        if payload.text.len() > masking::MAX_TEXT_BYTES {
+            let message = format!("text is larger than {} bytes", masking::MAX_TEXT_BYTES);
+            return text_response(413, message);
+        }
+
+        return match settings::load(stage.partition(SETTINGS_PARTITION)).await {
+            Ok(site) => json_response(
+                200,
+                json!(masking::mask(&payload.text, &site.comment_masking)),
+            ),
+            Err(e) => {
+                tracing::error!("dynamodb settings error: {:?}", e);
+                dynamodb_error(e.as_ref())
+            }
+        };
+    }
+
+    // readers' browsers never contact Gravatar themselves
+    if path == "/avatar" && method == "GET" {
+        let hash = query_param(&req, "email_hash").unwrap_or_default().to_lowercase();
+        if !avatar::is_email_hash(&hash) {
+            return text_response(400, "email_hash must be an MD5 or SHA-256 digest".to_string());
+        }
+        let size = query_param(&req, "size")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(avatar::DEFAULT_SIZE);
+        let size = avatar::snap_size(size);
+
+        return match avatar::avatar(bucket, root_path, &hash, size).await {
+            Ok(Some(image)) => {
+                let content_type = image.content_type.as_deref().unwrap_or("image/png");
+                Ok(Response::builder()
+                    .status(200)
+                    .header("content-type", content_type)
+                    .header("cache-control", "public, max-age=604800, immutable")
+                    .body(Body::Binary(image.bytes))?)
+            }
+            Ok(None) => text_response(404, "avatar not found".to_string()),
+            Err(e) => {
+                tracing::error!("avatar error: {:?}", e);
+                text_response(502, "avatar unavailable".to_string())
+            }
+        };
+    }
+
+    if let Some(id) = path
+        .strip_prefix("/posts/")
+        .and_then(|rest| rest.strip_suffix("/syndicate"))
+    {
+        if method == "POST" && !id.is_empty() && !id.contains('/') {
+            if !ctx.is_admin() {
+                return text_response(403, "forbidden".to_string());
+            }
+            let Some(public_urls) = &ctx.urls else {
+                return text_response(404, "site_url is not configured".to_string());
+            };
+
+            // `{"targets": ["devto"]}` narrows the push; default is every configured target
+            #[derive(Deserialize)]
+            struct SyndicatePayload {
+                targets: Option<Vec<String>>,
+            }
+            let requested = match req.body() {
+                Body::Empty => None,
+                body => match parse_json_body::<SyndicatePayload>(body)? {
+                    Ok(payload) => payload.targets,
+                    Err(response) => return Ok(response),
+                },
+            };
+            let configured = Target::configured();
+            let targets = match requested {
+                None => configured,
+                Some(names) => {
+                    let mut targets = Vec::new();
+                    for name in names {
+                        match Target::parse(&name).filter(|t| configured.contains(t)) {
+                            Some(target) => targets.push(target),
+                            None => {
+                                return text_response(
+                                    400,
+                                    format!("{name} is not a configured syndication target"),
+                                )
+                            }
+                        }
+                    }
+                    targets
+                }
+            };
+            if targets.is_empty() {
+                return text_response(400, "no syndication targets configured".to_string());
+            }
+
+            let posts_part = stage.partition(&posts::posts_part());
+            let slugs_part = stage.partition(SLUGS_PARTITION);
+            let result =
+                syndicate::syndicate(posts_part, slugs_part, id.to_string(), public_urls, targets)
+                    .await;
+            return match result {
+                Ok(Ok(syndication)) => json_response(200, json!(syndication)),
+                Ok(Err(syndicate::Refusal::NotFound)) => {
+                    text_response(404, "post not found".to_string())
+                }
+                Ok(Err(syndicate::Refusal::NotPublic)) => {
+                    text_response(400, "only published public posts are syndicated".to_string())
+                }
+                Err(e) => {
+                    tracing::error!("syndication error: {:?}", e);
+                    text_response(500, "syndication error".to_string())
+                }
+            };
+        }
+    }
+
+    if let (Some(document), "GET") = (Document::at(&path), method) {
+        let Some(public_urls) = &ctx.urls else {
+            return text_response(404, format!("{} is not configured", document.name()));
+        };
+
+        let snapshot =
+            match snapshots::load(document, &ctx.config, stage, base_path, public_urls).await {
+                Ok(snapshot) => snapshot,
+                Err(e) => {
+                    tracing::error!("{} snapshot error: {:?}", document.name(), e);
+                    return text_response(500, format!("{} error", document.name()));
+                }
+            };
+        let fresh = req
+            .headers()
+            .get("if-none-match")
+            .and_then(|h| h.to_str().ok())
+            .is_some_and(|tags| snapshots::matches(tags, &snapshot.etag));
+        let response = Response::builder()
+            .header("etag", &snapshot.etag)
+            .header("cache-control", "public, max-age=0, must-revalidate");
+        return Ok(if fresh {
+            response.status(304).body(Body::Empty)?
+        } else {
+            response
+                .status(200)
+                .header("content-type", document.content_type())
+                .body(Body::Binary(snapshot.bytes))?
+        });
+    }
+
+    if path == "/webhooks/stripe" && method == "POST" {
+        let body: &[u8] = match req.body() {
+            Body::Text(s) => s.as_bytes(),
+            Body::Binary(b) => b,
+            _ => return text_response(400, "empty body".to_string()),
+        };
+        let signature = req
+            .headers()
+            .get("stripe-signature")
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or_default();
+        if !subscribers::verify_signature(signature, body) {
+            return text_response(400, "invalid signature".to_string());
+        }
+        let event: serde_json::Value = serde_json::from_slice(body)?;
+
+        // Stripe redelivers until it sees a 2xx, so a replay is acknowledged
+        let delivery = event["id"].as_str().unwrap_or_default().to_string();
+        match replay::claim("stripe", &delivery).await {
+            Ok(true) => {}
+            Ok(false) => return text_response(200, "already processed".to_string()),
+            Err(e) => {
+                tracing::error!("dynamodb delivery claim error: {:?}", e);
+                return dynamodb_error(e.as_ref());
+            }
+        }
+
+        return match subscribers::handle_event(&event).await {
+            Ok(()) => text_response(200, "OK".to_string()),
+            Err(e) => {
+                tracing::error!("stripe event error: {:?}", e);
+                if let Err(e) = replay::release("stripe", &delivery).await {
+                    tracing::error!("dynamodb delivery release error: {:?}", e);
+                }
+                dynamodb_error(e.as_ref())
+            }
+        };
+    }
+
+    // webmention (https://www.w3.org/TR/webmention/)
+    if path == "/webmention" && method == "POST" {
+        let form: Vec<(String, String)> = match req.body() {
+            Body::Text(s) => url::form_urlencoded::parse(s.as_bytes()).into_owned().collect(),
+            Body::Binary(b) => url::form_urlencoded::parse(b).into_owned().collect(),
+            _ => Vec::new(),
+        };
+        let field = |key: &str| {
+            form.iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.clone())
+                .unwrap_or_default()
+        };
+
+        let (source, target) = (field("source"), field("target"));
+
+        let posts_part = stage.partition(&posts::posts_part());
+        return match webmention::receive(posts_part, &source, &target).await {
+            Ok(Ok(())) => text_response(200, "OK".to_string()),
+            Ok(Err(rejection @ webmention::Rejection::Replayed)) => {
+                text_response(409, rejection.to_string())
+            }
+            Ok(Err(rejection)) => text_response(400, rejection.to_string()),
+            Err(e) => {
+                tracing::error!("dynamodb webmention error: {:?}", e);
+                dynamodb_error(e.as_ref())
+            }
+        };
+    }
+
+    if let Some(post_id) = path
+        .strip_prefix("/posts/")
+        .and_then(|rest| rest.strip_suffix("/mentions"))
+    {
+        if method == "GET" && !post_id.is_empty() && !post_id.contains('/') {
+            return match webmention::list_mentions(post_id.to_string()).await {
+                Ok(mentions) => json_response(200, json!({ "mentions": mentions })),
+                Err(e) => {
+                    tracing::error!("dynamodb mentions error: {:?}", e);
+                    dynamodb_error(e.as_ref())
+                }
+            };
+        }
+    }
+
+    // activitypub
+    if path == "/.well-known/webfinger" || path.starts_with("/activitypub/") {
+        let Some(config) = activitypub::config() else {
+            return text_response(404, format!("not found: {method} {path}"));
+        };
+
+        if path == "/.well-known/webfinger" && method == "GET" {
+            let resource = query_param(&req, "resource").unwrap_or_default();
+            return match activitypub::webfinger(config, &resource) {
+                Some(jrd) => {
+                    let mut response = json_response(200, jrd)?;
+                    response
+                        .headers_mut()
+                        .insert("content-type", "application/jrd+json".parse()?);
+                    Ok(response)
+                }
+                None => text_response(404, "unknown resource".to_string()),
+            };
+        }
+
+        if path == "/activitypub/actor" && method == "GET" {
+            return activity_response(200, activitypub::actor(config));
+        }
+
+        if path == "/activitypub/outbox" && method == "GET" {
+            return match activitypub::outbox(config).await {
+                Ok(outbox) => activity_response(200, outbox),
+                Err(e) => {
+                    tracing::error!("activitypub outbox error: {:?}", e);
+                    dynamodb_error(e.as_ref())
+                }
+            };
+        }
+
+        if path == "/activitypub/inbox" && method == "POST" {
+            let body: &[u8] = match req.body() {
+                Body::Text(s) => s.as_bytes(),
+                Body::Binary(b) => b,
+                _ => return text_response(400, "empty body".to_string()),
+            };
+            let activity: serde_json::Value = match serde_json::from_slice(body) {
+                Ok(activity) => activity,
+                Err(_) => return text_response(400, "invalid activity".to_string()),
+            };
+            let actor_id = activity["actor"].as_str().unwrap_or_default();
+            if let Err(reason) =
+                activitypub::verify_signature(config, req.headers(), body, actor_id).await
+            {
+                return text_response(401, reason.to_string());
+            }
+
+            // activities without an id cannot be told apart and are processed as-is
+            let delivery = activity["id"].as_str().map(str::to_string);
+            if let Some(delivery) = &delivery {
+                match replay::claim("activitypub", delivery).await {
+                    Ok(true) => {}
+                    Ok(false) => return text_response(409, "replayed delivery".to_string()),
+                    Err(e) => {
+                        tracing::error!("dynamodb delivery claim error: {:?}", e);
+                        return dynamodb_error(e.as_ref());
+                    }
+                }
+            }
+
+            let result = activitypub::inbox(config, activity).await;
+            if let (Err(_), Some(delivery)) = (&result, &delivery) {
+                if let Err(e) = replay::release("activitypub", delivery).await {
+                    tracing::error!("dynamodb delivery release error: {:?}", e);
+                }
+            }
+
+            return match result {
+                Ok(()) => text_response(202, "Accepted".to_string()),
+                Err(e) => {
+                    tracing::error!("activitypub inbox error: {:?}", e);
+                    text_response(500, "activitypub error".to_string())
+                }
+            };
+        }
+    }
+
+    // 3) stage promotion: make the draft site's content the live one
+    if path == "/stage/promote" && (method == "POST" || method == "GET") {
+        if !ctx.is_admin() {
+            return text_response(403, "forbidden".to_string());
+        }
+        if method == "GET" {
+            return match promote::current().await {
+                Ok(Some(promotion)) => json_response(200, json!(promotion)),
+                Ok(None) => text_response(404, "no promotion yet".to_string()),
+                Err(e) => {
+                    tracing::error!("dynamodb promotion error: {:?}", e);
+                    dynamodb_error(e.as_ref())
+                }
+            };
+        }
+        return match promote::run(bucket, root_path).await {
+            Ok(Some(promotion)) if promotion.status == promote::Status::Succeeded => {
+                json_response(200, json!(promotion))
+            }
+            // the live content is as it was; `error` says why
+            Ok(Some(promotion)) => json_response(500, json!(promotion)),
+            Ok(None) => text_response(409, "another promotion is running".to_string()),
+            Err(e) => {
+                tracing::error!("promote error: {:?}", e);
+                dynamodb_error(e.as_ref())
+            }
+        };
+    }
+
+    // jobs: status is admin-only, as every job is started by an admin route;
+    // the worker's run call carries the job's token instead
+    if let Some(rest) = path.strip_prefix("/jobs/") {
+        let (id, action) = rest.split_once('/').unwrap_or((rest, ""));
+        if id.is_empty() {
+            return text_response(404, "job not found".to_string());
+        }
+
+        if action.is_empty() && method == "GET" {
+            // params and results name buckets, paths and orphaned keys
+            if !ctx.is_admin() {
+                return text_response(403, "forbidden".to_string());
+            }
+            return match jobs::get(id).await {
+                Ok(Some(job)) => json_response(200, job),
+                Ok(None) => text_response(404, "job not found".to_string()),
+                Err(e) => {
+                    tracing::error!("dynamodb job error: {:?}", e);
+                    dynamodb_error(e.as_ref())
+                }
+            };
+        }
+
+        // invoked asynchronously by `jobs::submit`
+        if action == "run" && method == "POST" {
+            let token = req
+                .headers()
+                .get(JOB_TOKEN_HEADER)
+                .and_then(|h| h.to_str().ok())
+                .unwrap_or_default();
+            if token.is_empty() {
+                return text_response(403, "forbidden".to_string());
+            }
+            return match jobs::run(id, token).await {
+                Ok(true) => text_response(204, String::new()),
+                Ok(false) => text_response(409, "job is not pending".to_string()),
+                Err(e) => {
+                    tracing::error!("job {} error: {:?}", id, e);
+                    text_response(500, "job error".to_string())
+                }
+            };
+        }
+    }
+
+    // admin
+    if path.starts_with("/admin/") && !ctx.is_admin() {
+        return text_response(403, "forbidden".to_string());
+    }
+
+    if path == "/admin/acl" && method == "GET" {
+        return match Rules::load().await {
+            Ok(rules) => json_response(200, json!({ "rules": rules.into_rules() })),
+            Err(e) => {
+                tracing::error!("dynamodb prefix acl error: {:?}", e);
+                dynamodb_error(e.as_ref())
+            }
+        };
+    }
+
+    if path == "/admin/acl" && method == "PUT" {
+        #[derive(Deserialize)]
+        struct AclPayload {
+            prefix: String,
+            visibility: String,
+        }
+        let payload: AclPayload = match parse_json_body(req.body())? {
+            Ok(payload) => payload,
+            Err(response) => return Ok(response),
+        };
+        if payload.prefix.is_empty() {
+            return text_response(400, "prefix is required".to_string());
+        }
+        let Some(visibility) = Visibility::parse(&payload.visibility) else {
+            return text_response(400, "visibility must be public, unlisted or private".to_string());
+        };
+
+        return match acl::set_rule(payload.prefix, visibility).await {
+            Ok(()) => text_response(204, String::new()),
+            Err(e) => {
+                tracing::error!("dynamodb prefix acl error: {:?}", e);
+                dynamodb_error(e.as_ref())
+            }
+        };
+    }
+
+    if path == "/admin/acl" && method == "DELETE" {
+        let prefix = query_param(&req, "prefix").unwrap_or_default();
+        if prefix.is_empty() {
+            return text_response(400, "prefix is required".to_string());
+        }
+
+        return match acl::delete_rule(prefix).await {
+            Ok(()) => text_response(204, String::new()),
+            Err(e) => {
+                tracing::error!("dynamodb prefix acl error: {:?}", e);
+                dynamodb_error(e.as_ref())
+            }
+        };
+    }
+
+    if path == "/admin/links" && method == "GET" {
+        return match Generations::load().await {
+            Ok(generations) => {
+                json_response(200, json!({ "generations": generations.into_generations() }))
+            }
+            Err(e) => {
+                tracing::error!("dynamodb link generations error: {:?}", e);
+                dynamodb_error(e.as_ref())
+            }
+        };
+    }
+
+    // every download link issued so far for a key under `prefix` stops working
+    if path == "/admin/links/revoke" && method == "POST" {
+        #[derive(Deserialize)]
+        struct RevokePayload {
+            prefix: String,
+        }
+        let payload: RevokePayload = match parse_json_body(req.body())? {
+            Ok(payload) => payload,
+            Err(response) => return Ok(response),
+        };
+
+        return match links::bump(payload.prefix.clone()).await {
+            Ok(generation) => json_response(
+                200,
+                json!({ "prefix": payload.prefix, "generation": generation }),
+            ),
+            Err(e) => {
+                tracing::error!("dynamodb link generations error: {:?}", e);
+                dynamodb_error(e.as_ref())
+            }
+        };
+    }
+
+    if path == "/admin/shortlinks" && method == "GET" {
+        return match shortlinks::list(stage.partition(SHORTLINKS_PARTITION)).await {
+            Ok(links) => json_response(200, json!({ "shortlinks": links })),
+            Err(e) => {
+                tracing::error!("dynamodb short link error: {:?}", e);
+                dynamodb_error(e.as_ref())
+            }
+        };
+    }
+
+    if path == "/admin/shortlinks" && method == "POST" {
+        #[derive(Deserialize)]
+        struct ShortLinkPayload {
+            target: String,
+            code: Option<String>,
+        }
+        let payload: ShortLinkPayload = match parse_json_body(req.body())? {
+            Ok(payload) => payload,
+            Err(response) => return Ok(response),
+        };
+        if !shortlinks::is_valid_target(&payload.target) {
+            return text_response(400, "target must be an http(s) URL".to_string());
+        }
+        let code = payload.code.map(|c| shortlinks::normalize_code(&c));
+        if code.as_deref().is_some_and(|c| !shortlinks::is_valid_code(c)) {
+            return text_response(
+                400,
+                "code must be up to 32 letters, digits, - or _".to_string(),
+            );
+        }
+
+        let part = stage.partition(SHORTLINKS_PARTITION);
+        return match shortlinks::create(part, payload.target.clone(), code).await {
+            Ok(Some(code)) => {
+                let url = ctx
+                    .config
+                    .api_url
+                    .as_ref()
+                    .map(|api| format!("{api}{SHORTLINK_ROUTE}{code}"));
+                json_response(
+                    201,
+                    json!({ "code": code, "target": payload.target, "url": url }),
+                )
+            }
+            Ok(None) => text_response(409, "code is taken".to_string()),
+            Err(e) => {
+                tracing::error!("dynamodb short link error: {:?}", e);
+                dynamodb_error(e.as_ref())
+            }
+        };
+    }
+
+    if path == "/admin/preview" && method == "POST" {
+        #[derive(Deserialize)]
+        struct PreviewPayload {
+            idx: String,
+        }
+        let Some(previews) = PreviewConfig::from_env() else {
+            return text_response(404, "previews are not configured".to_string());
+        };
+        let payload: PreviewPayload = match parse_json_body(req.body())? {
+            Ok(payload) => payload,
+            Err(response) => return Ok(response),
+        };
+
+        let part = stage.partition(&posts::posts_part());
+        match get_item_value(part, payload.idx.clone()).await {
+            Ok(Some(_)) => {}
+            Ok(None) => return text_response(404, "post not found".to_string()),
+            Err(e) => {
+                tracing::error!("dynamodb preview post error: {:?}", e);
+                return dynamodb_error(e.as_ref());
+            }
+        }
+        let (token, expires) = previews.mint(stage, &payload.idx);
+        let url = ctx
+            .config
+            .api_url
+            .as_ref()
+            .map(|api| format!("{api}{PREVIEW_ROUTE}{token}"));
+        return json_response(
+            200,
+            json!({ "token": token, "url": url, "expiresAt": expires * 1000 }),
+        );
+    }
+
+    if path == "/admin/img/sign" && method == "POST" {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct SignPayload {
+            key: String,
+            expires_in: Option<u64>,
+        }
+        let payload: SignPayload = match parse_json_body(req.body())? {
+            Ok(payload) => payload,
+            Err(response) => return Ok(response),
+        };
+        if !hotlink::is_image_key(&payload.key) {
+            return text_response(400, "key must be an image under the stage".to_string());
+        }
+        let ttl = payload.expires_in.unwrap_or(hotlink::DEFAULT_SIGNED_TTL_SECS);
+        if ttl == 0 || ttl > hotlink::MAX_SIGNED_TTL_SECS {
+            return text_response(
+                400,
+                format!("expiresIn must be 1 to {}", hotlink::MAX_SIGNED_TTL_SECS),
+            );
+        }
+
+        let site = ctx.urls.as_ref().map(|urls| urls.site());
+        let object = format!("{base_path}{}", payload.key);
+        let Some((query, expires)) = HotlinkConfig::from_env(site).sign(&object, ttl) else {
+            return text_response(404, "signed images are not configured".to_string());
+        };
+        let url = ctx.urls.as_ref().map(|urls| {
+            let key = utf8_percent_encode(&payload.key, links::KEY_SEGMENT);
+            format!("{}{IMAGE_ROUTE}{key}?{query}", urls.api())
+        });
+        return json_response(200, json!({ "url": url, "expiresAt": expires * 1000 }));
+    }
+
+    if path == "/admin/settings" && method == "PUT" {
+        let payload: serde_json::Value = match parse_json_body(req.body())? {
+            Ok(payload) => payload,
+            Err(response) => return Ok(response),
+        };
+        let settings: SiteSettings = match serde_json::from_value(payload) {
+            Ok(settings) => settings,
+            Err(e) => return text_response(400, format!("invalid settings: {e}")),
+        };
+        if let Err(message) = settings.validate() {
+            return text_response(400, message);
+        }
+
+        return match settings::save(stage.partition(SETTINGS_PARTITION), &settings).await {
+            Ok(()) => {
+                // the feed carries the site title
+                if let Err(e) = snapshots::refresh(stage).await {
+                    tracing::error!("snapshots of {} failed: {:?}", stage.as_str(), e);
+                }
+                json_response(200, json!(settings))
+            }
+            Err(e) => {
+                tracing::error!("dynamodb settings error: {:?}", e);
+                dynamodb_error(e.as_ref())
+            }
+        };
+    }
+
+    if path == "/admin/audit" && method == "GET" {
+        let number = |key: &str| query_param(&req, key).and_then(|v| v.parse().ok());
+        let query = AuditQuery {
+            from: number("from"),
+            to: number("to"),
+            method: query_param(&req, "method"),
+            route: query_param(&req, "route"),
+            principal: query_param(&req, "principal"),
+            limit: number("limit").map(|l| l as usize).unwrap_or(50).min(500),
+        };
+
+        let search = query.fingerprint();
+        let after = match query_param(&req, CURSOR_PARAM).filter(|c| !c.is_empty()) {
+            Some(c) => match cursor::decode::<String>(&search, &c) {
+                Some(idx) => Some(idx),
+                None => return text_response(400, "invalid cursor".to_string()),
+            },
+            None => None,
+        };
+
+        return match audit::search(query, after).await {
+            Ok((entries, next)) => {
+                let next = next.map(|idx| cursor::encode(&search, idx));
+                json_response(200, json!({ "entries": entries, "nextCursor": next }))
+            }
+            Err(e) => {
+                tracing::error!("audit search error: {:?}", e);
+                dynamodb_error(e.as_ref())
+            }
+        };
+    }
+
+    if path == "/admin/activitypub/publish" && method == "POST" {
+        let Some(config) = activitypub::config() else {
+            return text_response(404, "activitypub is not configured".to_string());
+        };
+        #[derive(Deserialize)]
+        struct PublishPayload {
+            idx: String,
+        }
+        let payload: PublishPayload = match parse_json_body(req.body())? {
+            Ok(payload) => payload,
+            Err(response) => return Ok(response),
+        };
+
+        let content = match get_item_value(config.part.clone(), payload.idx.clone()).await {
+            Ok(Some(value)) => value,
+            Ok(None) => return text_response(404, "post not found".to_string()),
+            Err(e) => {
+                tracing::error!("dynamodb get error: {:?}", e);
+                return dynamodb_error(e.as_ref());
+            }
+        };
+
+        return match activitypub::publish(config, &payload.idx, &content).await {
+            Ok(delivered) => json_response(200, json!({ "delivered": delivered })),
+            Err(e) => {
+                tracing::error!("activitypub publish error: {:?}", e);
+                text_response(500, "activitypub error".to_string())
+            }
+        };
+    }
+
+    if path == "/admin/summary" && method == "GET" {
+        let bucket = Some(bucket.clone()).filter(|b| !b.is_empty());
+        return json_response(200, summary::summary(bucket, root_path.clone()).await);
+    }
+
+    if path == "/admin/digest" && method == "GET" {
+        let day = query_param(&req, "day")
+            .unwrap_or_else(|| utc_date(now_millis().saturating_sub(24 * 3600 * 1000)));
+        return match digest::compile(stage, day).await {
+            Ok(digest) => json_response(200, json!(digest)),
+            Err(e) => {
+                tracing::error!("dynamodb digest error: {:?}", e);
+                dynamodb_error(e.as_ref())
+            }
+        };
+    }
+
+    if path == "/admin/digest/send" && method == "POST" {
+        if ses::sender().is_none() {
+            return text_response(404, "digest email is not configured".to_string());
+        }
+        return match digest::run(stage, true).await {
+            Ok(outcome) => json_response(200, outcome),
+            Err(e) => {
+                tracing::error!("digest send error: {:?}", e);
+                text_response(502, "digest could not be sent".to_string())
+            }
+        };
+    }
+
+    if path == "/admin/indexes" && (method == "GET" || method == "POST") {
+        let indexes = match describe_indexes(&posts::REQUIRED_INDEXES).await {
+            Ok(indexes) => indexes,
+            Err(e) => {
+                tracing::error!("dynamodb describe error: {:?}", e);
+                return dynamodb_error(e.as_ref());
+            }
+        };
+
+        // POST provisions the first missing index; repeat until none are missing
+        let mut created = None;
+        if method == "POST" {
+            if let Some(missing) = indexes.iter().find(|index| index.status.is_none()) {
+                if let Err(e) = create_index(&missing.name, &missing.sort_key).await {
+                    tracing::error!("dynamodb create index error: {:?}", e);
+                    return dynamodb_error(e.as_ref());
+                }
+                created = Some(missing.name.clone());
+            }
+        }
+
+        let missing: Vec<&str> = indexes
+            .iter()
+            .filter(|index| index.status.is_none())
+            .map(|index| index.name.as_str())
+            .collect();
+        return json_response(
+            200,
+            json!({ "indexes": indexes, "missing": missing, "created": created }),
+        );
+    }
+
+    if path == "/admin/analytics/countries" && method == "GET" {
+        let days = query_param(&req, "days")
+            .and_then(|d| d.parse::<u64>().ok())
+            .unwrap_or(geo::DEFAULT_DAYS)
+            .clamp(1, geo::MAX_DAYS);
+        return match geo::country_views(days).await {
+            Ok(countries) => json_response(200, json!({ "days": days, "countries": countries })),
+            Err(e) => {
+                tracing::error!("dynamodb country views error: {:?}", e);
+                dynamodb_error(e.as_ref())
+            }
+        };
+    }
+
+    if path == "/admin/analytics/queries" && method == "POST" {
+        let Some(config) = athena::config() else {
+            return text_response(404, "analytics is not configured".to_string());
+        };
+        #[derive(Deserialize)]
+        struct QueryPayload {
+            query: String,
+            from: String,
+            to: String,
+            limit: Option<usize>,
+        }
+        let payload: QueryPayload = match parse_json_body(req.body())? {
+            Ok(payload) => payload,
+            Err(response) => return Ok(response),
+        };
+        let Some(query) = NamedQuery::parse(&payload.query) else {
+            return text_response(400, "unknown query".to_string());
+        };
+        if !athena::is_date(&payload.from) || !athena::is_date(&payload.to) {
+            return text_response(400, "from and to must be YYYY-MM-DD".to_string());
+        }
+        let limit = payload.limit.unwrap_or(20).clamp(1, 1000);
+
+        return match athena::start(&config, query, &payload.from, &payload.to, limit).await {
+            Ok(id) => json_response(202, json!({ "executionId": id })),
+            Err(e) => {
+                tracing::error!("athena start error: {:?}", e);
+                text_response(500, "athena error".to_string())
+            }
+        };
+    }
+
+    if let Some(rest) = path.strip_prefix("/admin/analytics/queries/") {
+        if method == "GET" {
+            if athena::config().is_none() {
+                return text_response(404, "analytics is not configured".to_string());
+            }
+            let (id, results) = match rest.strip_suffix("/results") {
+                Some(id) => (id, true),
+                None => (rest, false),
+            };
+            if id.is_empty() || id.contains('/') {
+                return text_response(404, "not found".to_string());
+            }
+
+            if results {
+                return match athena::results(id, query_param(&req, "nextToken")).await {
+                    Ok(page) => json_response(200, json!(page)),
+                    Err(e) => {
+                        tracing::error!("athena results error: {:?}", e);
+                        text_response(500, "athena error".to_string())
+                    }
+                };
+            }
+            return match athena::status(id).await {
+                Ok(status) => json_response(200, json!(status)),
+                Err(e) => {
+                    tracing::error!("athena status error: {:?}", e);
+                    text_response(500, "athena error".to_string())
+                }
+            };
+        }
+    }
+
+    // data protection requests from subscribers
+    if let Some(rest) = path.strip_prefix("/admin/users/") {
+        let (id, export) = match rest.strip_suffix("/export") {
+            Some(id) => (id, true),
+            None => (rest, false),
+        };
+        if id.is_empty() || id.contains('/') {
+            return text_response(404, "not found".to_string());
+        }
+
+        if export && method == "GET" {
+            return match user_data::export(bucket, root_path, id).await {
+                Ok(Some(export)) => {
+                    let expires_in = Duration::from_secs(900);
+                    let key = export.key.clone();
+                    let url = presign_download(ctx.s3().await, bucket, key, None, expires_in);
+                    match url.await {
+                        Ok(url) => json_response(
+                            200,
+                            json!({
+                                "url": url,
+                                "expiresAt": now_millis() + expires_in.as_millis() as u64,
+                                "export": export,
+                            }),
+                        ),
+                        Err(e) => {
+                            tracing::error!("s3 export presign error: {:?}", e);
+                            s3_error(e.as_ref())
+                        }
+                    }
+                }
+                Ok(None) => text_response(404, "user not found".to_string()),
+                Err(e) => {
+                    tracing::error!("user export error: {:?}", e);
+                    text_response(500, "user export error".to_string())
+                }
+            };
+        }
+        if !export && method == "DELETE" {
+            return match user_data::erase(bucket, root_path, id).await {
+                Ok(Some(erased)) => json_response(200, json!(erased)),
+                Ok(None) => text_response(404, "user not found".to_string()),
+                Err(e) => {
+                    tracing::error!("user erase error: {:?}", e);
+                    text_response(500, "user erase error".to_string())
+                }
+            };
+        }
+    }
+
+    if path == "/admin/honeytokens/seed" && method == "POST" {
+        return match honeytoken::seed().await {
+            Ok(seeded) => json_response(200, json!({ "seeded": seeded })),
+            Err(e) => {
+                tracing::error!("honeytoken seed error: {:?}", e);
+                dynamodb_error(e.as_ref())
+            }
+        };
+    }
+
+    if path == "/admin/broken-links" && method == "GET" {
+        let all = query_param(&req, "all").as_deref() == Some("true");
+        return match linkcheck::broken_links(all).await {
+            Ok(links) => json_response(200, json!({ "links": links })),
+            Err(e) => {
+                tracing::error!("dynamodb link status error: {:?}", e);
+                dynamodb_error(e.as_ref())
+            }
+        };
+    }
+
+    // run on a schedule by an EventBridge API destination
+    if path == "/admin/broken-links/check" && method == "POST" {
+        return submit_job(&ctx, JobKind::LinkCheck).await;
+    }
+
+    // publishes what the outbox holds; scheduled like the link check
+    if path == "/admin/outbox/sweep" && method == "POST" {
+        if outbox::topic_arn().is_none() {
+            return text_response(404, "outbox publishing is not configured".to_string());
+        }
+        return submit_job(&ctx, JobKind::OutboxSweep).await;
+    }
+
+    if path == "/admin/gc" && method == "POST" {
+        let dry_run = query_param(&req, "dryRun").as_deref() != Some("false");
+        let grace_days = query_param(&req, "graceDays")
+            .and_then(|d| d.parse().ok())
+            .unwrap_or(gc::DEFAULT_GRACE_DAYS);
+
+        let kind = JobKind::Gc {
+            bucket: bucket.clone(),
+            base_path: base_path.clone(),
+            stage,
+            grace_days,
+            dry_run,
+        };
+        return submit_job(&ctx, kind).await;
+    }
+
+    // run on a schedule by an EventBridge API destination, like the link check
+    if path == "/admin/backup" && method == "POST" {
+        if BackupTarget::from_env().is_none() {
+            return text_response(404, "backup is not configured".to_string());
+        }
+        let kind = JobKind::Backup {
+            bucket: bucket.clone(),
+        };
+        return submit_job(&ctx, kind).await;
+    }
+
+    // a static copy of the published blog, to serve if the API is down
+    if path == "/admin/export-static" && method == "POST" {
+        let kind = JobKind::StaticExport {
+            bucket: bucket.clone(),
+            base_path: base_path.clone(),
+            stage,
+            site_url: ctx.urls.as_ref().map(|urls| urls.site().to_string()),
+            cdn_url: ctx.config.cdn_url.clone(),
+        };
+        return submit_job(&ctx, kind).await;
+    }
+
+    if path == "/admin/themes" && method == "GET" {
+        return match themes::list(stage.partition(THEMES_PARTITION)).await {
+            Ok(themes) => json_response(200, json!({ "themes": themes })),
+            Err(e) => {
+                tracing::error!("dynamodb themes error: {:?}", e);
+                dynamodb_error(e.as_ref())
+            }
+        };
+    }
+
+    if let Some(rest) = path.strip_prefix("/admin/themes/") {
+        let (name, action) = rest.split_once('/').unwrap_or((rest, ""));
+        if !themes::is_theme_name(name) {
+            let message = "theme names are lowercase letters, digits and dashes".to_string();
+            return text_response(400, message);
+        }
+        let themes_part = stage.partition(THEMES_PARTITION);
+
+        if action == "activate" && method == "POST" {
+            let settings_part = stage.partition(SETTINGS_PARTITION);
+            let theme = match themes::activate(themes_part, settings_part, name).await {
+                Ok(Some(theme)) => theme,
+                Ok(None) => return text_response(404, "theme not found".to_string()),
+                Err(e) => {
+                    tracing::error!("dynamodb theme activate error: {:?}", e);
+                    return dynamodb_error(e.as_ref());
+                }
+            };
+            return match theme_urls(&ctx, &theme).await {
+                Ok(urls) => json_response(200, urls),
+                Err(e) => {
+                    tracing::error!("s3 theme presign error: {:?}", e);
+                    s3_error(e.as_ref())
+                }
+            };
+        }
+
+        if let (Some(asset), "PUT") = (Asset::from_name(action), method) {
+            let content_type = req
+                .headers()
+                .get("content-type")
+                .and_then(|h| h.to_str().ok())
+                .unwrap_or_default();
+            if asset.extension(content_type).is_none() {
+                let message = format!("unsupported content type for the {} asset", asset.name());
+                return text_response(415, message);
+            }
+            let bytes = match req.body() {
+                Body::Text(s) => s.as_bytes().to_vec(),
+                Body::Binary(b) => b.clone(),
+                _ => return text_response(400, "empty body".to_string()),
+            };
+            if bytes.len() > themes::MAX_ASSET_BYTES {
+                let message = format!("assets are at most {} bytes", themes::MAX_ASSET_BYTES);
+                return text_response(413, message);
+            }
+
+            let uploaded =
+                themes::upload(bucket, base_path, themes_part, name, asset, bytes, content_type);
+            return match uploaded.await {
+                Ok(theme) => json_response(200, json!(theme)),
+                Err(e) => {
+                    tracing::error!("theme upload error: {:?}", e);
+                    s3_error(e.as_ref())
+                }
+            };
+        }
+    }
+
+    // moves cold files to a cheaper class without a lifecycle rule
+    if path == "/admin/s3/transition" && method == "POST" {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct TransitionPayload {
+            prefix: String,
+            storage_class: String,
+        }
+        let payload: TransitionPayload = match parse_json_body(req.body())? {
+            Ok(payload) => payload,
+            Err(response) => return Ok(response),
+        };
+        let prefix = payload.prefix.trim_start_matches('/');
+        if prefix.is_empty() || prefix.split('/').any(|segment| segment == "..") {
+            return text_response(400, "prefix must be a path under the stage".to_string());
+        }
+        let storage_class = match transition::target_class(&payload.storage_class) {
+            Ok(class) => class,
+            Err(message) => return text_response(400, message),
+        };
+
+        let kind = JobKind::Transition {
+            bucket: bucket.clone(),
+            prefix: format!("{base_path}{prefix}"),
+            storage_class: storage_class.as_str().to_string(),
+        };
+        return submit_job(&ctx, kind).await;
+    }
+
+    if path == "/admin/backup" && method == "GET" {
+        return match backup::last_run().await {
+            Ok(last) => json_response(200, json!({ "lastRun": last })),
+            Err(e) => {
+                tracing::error!("dynamodb backup error: {:?}", e);
+                dynamodb_error(e.as_ref())
+            }
+        };
+    }
+
+    if path == "/admin/comments/export" && method == "GET" {
+        let post = query_param(&req, "post").filter(|post| !post.is_empty());
+        let part = stage.partition(COMMENTS_PARTITION);
+        return match comments::list(part, post.as_deref()).await {
+            Ok(comments) => json_response(
+                200,
+                json!({ "exportedAt": now_millis(), "post": post, "comments": comments }),
+            ),
+            Err(e) => {
+                tracing::error!("dynamodb comment export error: {:?}", e);
+                dynamodb_error(e.as_ref())
+            }
+        };
+    }
+
+    if path == "/admin/comments/import" && method == "POST" {
+        let xml = match req.body() {
+            Body::Text(s) => s.as_str(),
+            Body::Binary(b) => match std::str::from_utf8(b) {
+                Ok(xml) => xml,
+                Err(_) => return text_response(400, "export is not utf-8".to_string()),
+            },
+            _ => return text_response(400, "empty body".to_string()),
+        };
+        let imported = disqus::import(
+            xml,
+            stage.partition(&posts::posts_part()),
+            stage.partition(SLUGS_PARTITION),
+            stage.partition(COMMENTS_PARTITION),
+        )
+        .await;
+        return match imported {
+            Ok(Ok(report)) => json_response(200, json!(report)),
+            Ok(Err(message)) => text_response(400, message),
+            Err(e) => {
+                tracing::error!("dynamodb comment import error: {:?}", e);
+                dynamodb_error(e.as_ref())
+            }
+        };
+    }
+
+    if (path == "/admin/tags/rename" || path == "/admin/tags/merge") && method == "POST" {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum TagsFrom {
+            One(String),
+            Many(Vec<String>),
+        }
+        #[derive(Deserialize)]
+        struct RetagPayload {
+            from: TagsFrom,
+            #[serde(alias = "to")]
+            into: String,
+        }
+        let payload: RetagPayload = match parse_json_body(req.body())? {
+            Ok(payload) => payload,
+            Err(response) => return Ok(response),
+        };
+        let from = match payload.from {
+            TagsFrom::One(tag) => vec![tag],
+            TagsFrom::Many(tags) => tags,
+        };
+        let into = payload.into.trim().to_string();
+        if into.is_empty() || from.iter().any(|t| t.trim().is_empty()) {
+            return text_response(400, "tags must not be empty".to_string());
+        }
+
+        let kind = JobKind::Retag {
+            part: stage.partition(&posts::posts_part()),
+            from,
+            into,
+            merge: path.ends_with("merge"),
+        };
+        return submit_job(&ctx, kind).await;
+    }
+
+    if path == "/admin/tags/recount" && method == "POST" {
+        return match tags::recount(stage.partition(&posts::posts_part())).await {
+            Ok(count) => json_response(200, json!({ "tags": count })),
+            Err(e) => {
+                tracing::error!("dynamodb tag recount error: {:?}", e);
+                dynamodb_error(e.as_ref())
+            }
+        };
+    }
+
+    if path == "/admin/usage" && method == "GET" {
+        let day = query_param(&req, "day").unwrap_or_else(|| utc_date(now_millis()));
+
+        return match usage::report(day).await {
+            Ok(report) => json_response(200, json!(report)),
+            Err(e) => {
+                tracing::error!("usage report error: {:?}", e);
+                dynamodb_error(e.as_ref())
+            }
+        };
+    }
+
+    if path == "/admin/costs" && method == "GET" {
+        let day = query_param(&req, "day").unwrap_or_else(|| utc_date(now_millis()));
+
+        return match costs::report(day).await {
+            Ok(report) => json_response(200, json!(report)),
+            Err(e) => {
+                tracing::error!("cost report error: {:?}", e);
+                dynamodb_error(e.as_ref())
+            }
+        };
+    }
+
+    // 4) s3
+    if path == "/api/s3/list" && method == "GET" {
+        let part = query_param(&req, "part");
+        let idx = query_param(&req, "idx");
+
+        let prefix = if let (Some(part_val), Some(idx_val)) = (&part, &idx) {
+            if !part_val.is_empty() && !idx_val.is_empty() {
+                format!("{base_path}upload/{}/{}/", part_val, idx_val)
+            } else {
+                format!("{base_path}upload/")
+            }
+        } else {
+            format!("{base_path}upload/")
+        };
+
+        if let Err(response) = check_prefix_acl(&ctx, &prefix, true).await? {
+            return Ok(response);
+        }
+
+        // quota usage covers every upload, not just the listed folder
+        let uploads = format!("{base_path}upload/");
+        let client = ctx.s3().await;
+        let (listed, usage) = tokio::join!(
+            list_objects(client, bucket, prefix),
+            quota::storage_usage(bucket, &uploads)
+        );
+
+        return match (listed, usage) {
+            (Ok((folders, files)), Ok((objects, bytes))) => json_response(
+                200,
+                json!({
+                    "folders": folders,
+                    "files": files,
+                    "meta": quota::storage_meta(objects, bytes),
+                }),
+            ),
+            (Err(e), _) | (_, Err(e)) => {
+                tracing::error!("s3 list error: {:?}", e);
+                s3_error(e.as_ref())
+            }
+        };
+    }
+
+    if let Some(key) = path
+        .strip_prefix("/api/files/")
+        .and_then(|rest| rest.strip_suffix("/stats"))
+    {
+        if method == "GET" && !key.is_empty() {
+            if !ctx.is_admin() {
+                return text_response(403, "forbidden".to_string());
+            }
+            let key = percent_decode_str(key).decode_utf8_lossy();
+            let days = query_param(&req, "days")
+                .and_then(|d| d.parse::<usize>().ok())
+                .unwrap_or(downloads::DEFAULT_DAYS)
+                .clamp(1, downloads::MAX_DAYS);
+
+            let part = stage.partition(&downloads::partition(&key));
+            return match downloads::stats(part, days).await {
+                Ok(stats) => json_response(
+                    200,
+                    json!({ "key": key, "total": stats.total, "days": stats.days }),
+                ),
+                Err(e) => {
+                    tracing::error!("dynamodb download stats error: {:?}", e);
+                    dynamodb_error(e.as_ref())
+                }
+            };
+        }
+    }
+
+    // Object Lock: legal hold and retention of an uploaded file
+    if let Some((key, action)) = path
+        .strip_prefix("/api/files/")
+        .and_then(|rest| rest.rsplit_once('/'))
+        .filter(|(key, action)| {
+            !key.is_empty()
+                && matches!(
+                    (*action, method),
+                    ("lock", "GET") | ("legal-hold", "PUT") | ("retention", "PUT" | "DELETE")
+                )
+        })
+    {
+        if !ctx.is_admin() {
+            return text_response(403, "forbidden".to_string());
+        }
+        let key = percent_decode_str(key).decode_utf8_lossy().to_string();
+        let full_key = format!("{base_path}{key}");
+        let changed = match (action, method) {
+            ("legal-hold", _) => {
+                let hold: LegalHoldRequest = match parse_json_body(req.body())? {
+                    Ok(hold) => hold,
+                    Err(response) => return Ok(response),
+                };
+                Some(object_lock::set_legal_hold(bucket, &full_key, hold.enabled).await)
+            }
+            ("retention", "PUT") => {
+                let request: RetentionRequest = match parse_json_body(req.body())? {
+                    Ok(request) => request,
+                    Err(response) => return Ok(response),
+                };
+                let retention = match request.resolve(now_millis()) {
+                    Ok(retention) => retention,
+                    Err(message) => return text_response(400, message),
+                };
+                Some(object_lock::set_retention(bucket, &full_key, &retention).await)
+            }
+            ("retention", _) => Some(object_lock::clear_retention(bucket, &full_key).await),
+            _ => None,
+        };
+        match changed {
+            Some(Ok(Err(message))) => return text_response(400, message),
+            Some(Err(e)) => {
+                tracing::error!("s3 object lock error: {:?}", e);
+                return s3_error(e.as_ref());
+            }
+            Some(Ok(Ok(()))) | None => {}
+        }
+
+        return match object_lock::status(bucket, &full_key).await {
+            Ok(Ok(status)) => {
+                let mut body = json!(status);
+                body["key"] = json!(key);
+                json_response(200, body)
+            }
+            Ok(Err(message)) => text_response(400, message),
+            Err(e) => {
+                tracing::error!("s3 object lock error: {:?}", e);
+                s3_error(e.as_ref())
+            }
+        };
+    }
+
+    // display order, caption and alt text of an uploaded file
+    if let Some(key) = path.strip_prefix("/api/files/") {
+        if method == "PATCH" && !key.is_empty() {
+            if !ctx.is_admin() {
+                return text_response(403, "forbidden".to_string());
+            }
+            let key = percent_decode_str(key).decode_utf8_lossy().to_string();
+            let patch: AttachmentPatch = match parse_json_body(req.body())? {
+                Ok(patch) => patch,
+                Err(response) => return Ok(response),
+            };
+            if let Err(message) = patch.validate() {
+                return text_response(400, message);
+            }
+            match head_object(bucket, format!("{base_path}{key}")).await {
+                Ok(Some(_)) => {}
+                Ok(None) => return text_response(404, "file not found".to_string()),
+                Err(e) => {
+                    tracing::error!("s3 head error: {:?}", e);
+                    return s3_error(e.as_ref());
+                }
+            }
+
+            let part = stage.partition(ATTACHMENTS_PARTITION);
+            return match attachments::update(part, key.clone(), patch).await {
+                Ok(display) => {
+                    let mut body = json!(display);
+                    body["key"] = json!(key);
+                    json_response(200, body)
+                }
+                Err(e) => {
+                    tracing::error!("dynamodb attachment error: {:?}", e);
+                    dynamodb_error(e.as_ref())
+                }
+            };
+        }
+    }
+
+    if path == "/api/files/search" && method == "GET" {
+        let query = query_param(&req, "q").unwrap_or_default();
+        let file_type = match query_param(&req, "type").filter(|t| !t.is_empty()) {
+            Some(name) => match FileType::parse(&name) {
+                Some(file_type) => Some(file_type),
+                None => {
+                    return text_response(
+                        400,
+                        "type must be image, video, audio, document or other".to_string(),
+                    )
+                }
+            },
+            None => None,
+        };
+        let limit = query_param(&req, "limit")
+            .and_then(|l| l.parse::<usize>().ok())
+            .unwrap_or(files::DEFAULT_LIMIT)
+            .clamp(1, files::MAX_LIMIT);
+        // the cursor only continues the same search of the same stage
+        let search = format!("files\n{base_path}\n{query}\n{file_type:?}");
+        let after = match query_param(&req, CURSOR_PARAM).filter(|c| !c.is_empty()) {
+            Some(c) => match cursor::decode::<files::Position>(&search, &c) {
+                Some(position) => Some(position),
+                None => return text_response(400, "invalid cursor".to_string()),
+            },
+            None => None,
+        };
+
+        let uploads = format!("{base_path}upload/");
+        let (objects, rules) = tokio::join!(list_all_objects(bucket, &uploads), Rules::load());
+        let objects = match objects {
+            Ok(objects) => objects,
+            Err(e) => {
+                tracing::error!("s3 list error: {:?}", e);
+                return s3_error(e.as_ref());
+            }
+        };
+        let rules = match rules {
+            Ok(rules) => rules,
+            Err(e) => {
+                tracing::error!("dynamodb prefix acl error: {:?}", e);
+                return dynamodb_error(e.as_ref());
+            }
+        };
+
+        // only what the caller could also find by listing folders
+        let admin = ctx.is_admin();
+        let listable = objects
+            .into_iter()
+            .filter(|object| {
+                let key = object.key.strip_prefix(base_path).unwrap_or(&object.key);
+                rules.visibility(key).allows(admin, true)
+            })
+            .collect();
+        let page = files::search(listable, &query, file_type, after.as_ref(), limit);
+        let next = page.next.map(|position| cursor::encode(&search, position));
+        return json_response(
+            200,
+            json!({ "files": page.files, "total": page.total, "nextCursor": next }),
+        );
+    }
+
+    if path == "/api/s3/upload-url" && method == "GET" {
+        let part = query_param(&req, "part");
+        let idx = query_param(&req, "idx");
+        let filename = query_param(&req, "filename").unwrap_or_default();
+
+        if filename.is_empty() {
+            return text_response(400, "filename is required".to_string());
+        }
+
+        let content_type =
+            query_param(&req, "contentType").unwrap_or("application/octet-stream".to_string());
+
+        let storage_class = match upload_storage_class(
+            query_param(&req, "storageClass").filter(|c| !c.is_empty()).as_deref(),
+            bucket,
+        ) {
+            Ok(class) => class,
+            Err(message) => return text_response(400, message),
+        };
+
+        let checksum = query_param(&req, "checksumSha256").filter(|c| !c.is_empty());
+        if let Some(checksum) = &checksum {
+            if !is_base64_sha256(checksum) {
+                return text_response(400, "checksumSha256 must be a base64 SHA-256".to_string());
+            }
+        }
+
+        let key = if let (Some(part_val), Some(idx_val)) = (&part, &idx) {
+            if !part_val.is_empty() && !idx_val.is_empty() {
+                format!("{base_path}upload/{}/{}/{}", part_val, idx_val, filename)
+            } else {
+                format!("{base_path}upload/{}", filename)
+            }
+        } else {
+            format!("{base_path}upload/{}", filename)
+        };
+
+        if let Err(response) = check_prefix_acl(&ctx, &key, false).await? {
+            return Ok(response);
+        }
+
+        if query_param(&req, "dedupe").as_deref() == Some("true") {
+            match dedupe::find_duplicate(bucket, &key, checksum.as_deref()).await {
+                Ok(Some(existing)) => {
+                    return json_response(409, json!({ "error": "duplicate", "key": existing }));
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    tracing::error!("s3 duplicate check error: {:?}", e);
+                    return s3_error(e.as_ref());
+                }
+            }
+        }
+
+        if let Some(checksum) = &checksum {
+            if let Err(e) = dedupe::record_upload(checksum.clone(), key.clone()).await {
+                tracing::error!("dynamodb upload hash error: {:?}", e);
+            }
+        }
+
+        let client = ctx.s3().await;
+        let presigned =
+            presign_upload(client, bucket, key, content_type, storage_class, checksum, None);
+        return match presigned.await {
+            Ok(url) => text_response(200, url),
+            Err(e) => {
+                tracing::error!("s3 upload presign error: {:?}", e);
+                s3_error(e.as_ref())
+            }
+        };
+    }
+
+    // the server fetches on the caller's behalf, so only admins may ask it to
+    if path == "/api/s3/import-url" && method == "POST" {
+        if !ctx.is_admin() {
+            return text_response(403, "forbidden".to_string());
+        }
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct ImportUrlPayload {
+            url: String,
+            part: Option<String>,
+            idx: Option<String>,
+            filename: Option<String>,
+            storage_class: Option<String>,
+        }
+        let payload: ImportUrlPayload = match parse_json_body(req.body())? {
+            Ok(payload) => payload,
+            Err(response) => return Ok(response),
+        };
+
+        let storage_class = match upload_storage_class(
+            payload.storage_class.as_deref().filter(|c| !c.is_empty()),
+            bucket,
+        ) {
+            Ok(class) => class,
+            Err(message) => return text_response(400, message),
+        };
+        let prefix = match (payload.part.as_deref(), payload.idx.as_deref()) {
+            (Some(part), Some(idx)) if !part.is_empty() && !idx.is_empty() => {
+                format!("{base_path}upload/{part}/{idx}/")
+            }
+            _ => format!("{base_path}upload/"),
+        };
+        if let Err(response) = check_prefix_acl(&ctx, &prefix, false).await? {
+            return Ok(response);
+        }
+
+        let fetched = match media_import::fetch(&payload.url).await {
+            Ok(Ok(fetched)) => fetched,
+            Ok(Err(rejection)) => return text_response(rejection.status(), rejection.to_string()),
+            Err(e) => {
+                tracing::error!("import-url fetch error: {:?}", e);
+                return text_response(502, "fetch error".to_string());
+            }
+        };
+        let filename = payload
+            .filename
+            .map(|f| media_import::sanitize_filename(&f))
+            .filter(|f| !f.is_empty())
+            .unwrap_or(fetched.filename);
+        let key = format!("{prefix}{filename}");
+        if let Err(response) = check_prefix_acl(&ctx, &key, false).await? {
+            return Ok(response);
+        }
+
+        let size = fetched.bytes.len();
+        let client = ctx.s3().await;
+        let content_type = fetched.content_type;
+        let stored = put_upload(
+            client,
+            bucket,
+            key.clone(),
+            fetched.bytes,
+            &content_type,
+            storage_class,
+        );
+        return match stored.await {
+            Ok(()) => json_response(
+                201,
+                json!({ "key": key, "contentType": content_type, "size": size }),
+            ),
+            Err(e) => {
+                tracing::error!("s3 import-url put error: {:?}", e);
+                s3_error(e.as_ref())
+            }
+        };
+    }
+
+    if path == "/api/s3/upload-urls" && method == "POST" {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct UploadFile {
+            filename: String,
+            content_type: Option<String>,
+            size: i64,
+            checksum_sha256: Option<String>,
+        }
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct UploadUrlsPayload {
+            part: Option<String>,
+            idx: Option<String>,
+            storage_class: Option<String>,
+            files: Vec<UploadFile>,
+        }
+        let payload: UploadUrlsPayload = match parse_json_body(req.body())? {
+            Ok(payload) => payload,
+            Err(response) => return Ok(response),
+        };
+        if payload.files.is_empty() || payload.files.len() > MAX_BATCH_UPLOADS {
+            let message = format!("files must list 1 to {MAX_BATCH_UPLOADS} entries");
+            return text_response(400, message);
+        }
+
+        let storage_class = match upload_storage_class(
+            payload.storage_class.as_deref().filter(|c| !c.is_empty()),
+            bucket,
+        ) {
+            Ok(class) => class,
+            Err(message) => return text_response(400, message),
+        };
+        let prefix = match (payload.part.as_deref(), payload.idx.as_deref()) {
+            (Some(part), Some(idx)) if !part.is_empty() && !idx.is_empty() => {
+                format!("{base_path}upload/{part}/{idx}/")
+            }
+            _ => format!("{base_path}upload/"),
+        };
+
+        let rules = match Rules::load().await {
+            Ok(rules) => rules,
+            Err(e) => {
+                tracing::error!("dynamodb prefix acl error: {:?}", e);
+                return dynamodb_error(e.as_ref());
+            }
+        };
+        let admin = ctx.is_admin();
+        let client = ctx.s3().await;
+
+        // one bad entry is reported in place instead of failing the whole drop
+        let presigns = payload.files.into_iter().map(|file| {
+            let prefix = &prefix;
+            let rules = &rules;
+            let storage_class = storage_class.clone();
+            async move {
+                let checksum = file.checksum_sha256.filter(|c| !c.is_empty());
+                let error = if file.filename.is_empty() {
+                    Some("filename is required")
+                } else if file.size <= 0 {
+                    Some("size must be positive")
+                } else if checksum.as_deref().is_some_and(|c| !is_base64_sha256(c)) {
+                    Some("checksumSha256 must be a base64 SHA-256")
+                } else {
+                    None
+                };
+                if let Some(error) = error {
+                    return json!({ "filename": file.filename, "error": error });
+                }
+
+                let key = format!("{prefix}{}", file.filename);
+                let relative = key.strip_prefix(base_path.as_str()).unwrap_or(&key);
+                if !rules.visibility(relative).allows(admin, false) {
+                    return json!({ "filename": file.filename, "error": "forbidden" });
+                }
+                let content_type = file
+                    .content_type
+                    .unwrap_or_else(|| "application/octet-stream".to_string());
+                let presigned = presign_upload(
+                    client,
+                    bucket,
+                    key.clone(),
+                    content_type,
+                    storage_class,
+                    checksum,
+                    Some(file.size),
+                );
+                match presigned.await {
+                    Ok(url) => json!({ "filename": file.filename, "key": key, "url": url }),
+                    Err(e) => {
+                        tracing::error!("s3 upload presign error: {:?}", e);
+                        json!({ "filename": file.filename, "error": "s3 error" })
+                    }
+                }
+            }
+        });
+
+        let files = futures::future::join_all(presigns).await;
+        return json_response(200, json!({ "files": files }));
+    }
+
+    if path == "/api/s3/download-url" && method == "GET" {
+        let part = query_param(&req, "part");
+        let idx = query_param(&req, "idx");
+        let filename = query_param(&req, "filename").unwrap_or_default();
+
+        if filename.is_empty() {
+            return text_response(400, "filename is required".to_string());
+        }
+
+        let key = if let (Some(part_val), Some(idx_val)) = (&part, &idx) {
+            if !part_val.is_empty() && !idx_val.is_empty() {
+                format!("{base_path}{}/{}/{}", part_val, idx_val, filename)
+            } else {
+                format!("{base_path}{}", filename)
+            }
+        } else {
+            format!("{base_path}{}", filename)
+        };
+
+        let visibility = match check_prefix_acl(&ctx, &key, false).await? {
+            Ok(visibility) => visibility,
+            Err(response) => return Ok(response),
+        };
+        // a ranged request is one chunk of a download counted by its manifest
+        let range = query_param(&req, "range").filter(|r| !r.is_empty());
+
+        // public objects need no signature; the CDN also serves ranges itself
+        if let (Visibility::Public, Some(cdn)) = (visibility, &ctx.config.cdn_url) {
+            if range.is_none() {
+                record_download(&key, root_path).await;
+            }
+            return text_response(200, format!("{cdn}/{key}"));
+        }
+
+        if let Some(range) = &range {
+            if !is_byte_range(range) {
+                return text_response(400, "range must be bytes=start-end".to_string());
+            }
+        }
+
+        if let Err(response) = check_downloadable(bucket, &key).await? {
+            return Ok(response);
+        }
+
+        // revocable: a link through this API instead of a bare presigned URL
+        if let Some(links) = LinkConfig::from_env(ctx.config.api_url.as_ref()) {
+            let generations = match Generations::load().await {
+                Ok(generations) => generations,
+                Err(e) => {
+                    tracing::error!("dynamodb link generations error: {:?}", e);
+                    return dynamodb_error(e.as_ref());
+                }
+            };
+            let generation = generations.of(links::relative_key(&key, root_path));
+            return text_response(200, links.link(generation, &key, range.as_deref()));
+        }
+
+        if range.is_none() {
+            record_download(&key, root_path).await;
+        }
+        let expires_in = Duration::from_secs(900);
+        return match presign_download(ctx.s3().await, bucket, key, range, expires_in).await {
+            Ok(url) => text_response(200, url),
+            Err(e) => {
+                tracing::error!("s3 download presign error: {:?}", e);
+                s3_error(e.as_ref())
+            }
+        };
+    }
+
+    if let Some(key) = path.strip_prefix(IMAGE_ROUTE) {
+        if method == "GET" {
+            let key = percent_decode_str(key).decode_utf8_lossy().to_string();
+            if !hotlink::is_image_key(&key) {
+                return text_response(404, "image not found".to_string());
+            }
+            let object = format!("{base_path}{key}");
+
+            let site = ctx.urls.as_ref().map(|urls| urls.site());
+            let referer = req.headers().get("referer").and_then(|h| h.to_str().ok());
+            let signature = query_param(&req, "sig");
+            let signed = signature.as_deref().map(|signature| {
+                let expires = query_param(&req, "expires").and_then(|e| e.parse().ok());
+                (expires.unwrap_or_default(), signature)
+            });
+            match HotlinkConfig::from_env(site).check(&object, referer, signed) {
+                Ok(()) => {}
+                Err(Refusal::InvalidSignature) => {
+                    return text_response(403, "invalid or expired image link".to_string());
+                }
+                Err(Refusal::Referer) => {
+                    tracing::warn!("image {} refused for referer {:?}", object, referer);
+                    return text_response(403, "hotlinking is not allowed".to_string());
+                }
+            }
+            if let Err(response) = check_prefix_acl(&ctx, &object, false).await? {
+                return Ok(response);
+            }
+            if let Err(response) = check_downloadable(bucket, &object).await? {
+                return Ok(response);
+            }
+
+            let presigned =
+                presign_download(ctx.s3().await, bucket, object, None, links::REDIRECT_TTL).await;
+            return match presigned {
+                Ok(url) => {
+                    let mut response = Response::new(Body::Empty);
+                    *response.status_mut() = StatusCode::FOUND;
+                    add_cors_headers(&mut response);
+                    let headers = response.headers_mut();
+                    headers.insert("location", url.parse()?);
+                    // the answer depends on the Referer, and the URL expires
+                    headers.insert("cache-control", "no-store".parse()?);
+                    Ok(response)
+                }
+                Err(e) => {
+                    tracing::error!("s3 image presign error: {:?}", e);
+                    s3_error(e.as_ref())
+                }
+            };
+        }
+    }
+
+    if let Some(rest) = path.strip_prefix(LINK_ROUTE) {
+        if method == "GET" {
+            let Some(links) = LinkConfig::from_env(ctx.config.api_url.as_ref()) else {
+                return text_response(404, "download links are not configured".to_string());
+            };
+            let rest = percent_decode_str(rest).decode_utf8_lossy();
+            let Some((generation, key)) = rest
+                .split_once('/')
+                .and_then(|(g, key)| Some((g.parse::<u64>().ok()?, key.to_string())))
+            else {
+                return text_response(404, "link not found".to_string());
+            };
+            let expires = query_param(&req, "expires")
+                .and_then(|e| e.parse().ok())
+                .unwrap_or_default();
+            let range = query_param(&req, "range");
+            let signature = query_param(&req, "sig").unwrap_or_default();
+            if !links.verify(generation, &key, expires, range.as_deref(), &signature) {
+                return text_response(403, "invalid or expired link".to_string());
+            }
+
+            let current = match Generations::load().await {
+                Ok(generations) => generations.of(links::relative_key(&key, root_path)),
+                Err(e) => {
+                    tracing::error!("dynamodb link generations error: {:?}", e);
+                    return dynamodb_error(e.as_ref());
+                }
+            };
+            if generation != current {
+                return text_response(410, "link revoked".to_string());
+            }
+            if let Err(response) = check_downloadable(bucket, &key).await? {
+                return Ok(response);
+            }
+            if range.is_none() {
+                record_download(&key, root_path).await;
+            }
+
+            let presigned =
+                presign_download(ctx.s3().await, bucket, key, range, links::REDIRECT_TTL).await;
+            return match presigned {
+                Ok(url) => {
+                    let mut response = Response::new(Body::Empty);
+                    *response.status_mut() = StatusCode::FOUND;
+                    add_cors_headers(&mut response);
+                    let headers = response.headers_mut();
+                    headers.insert("location", url.parse()?);
+                    headers.insert("cache-control", "no-store".parse()?);
+                    Ok(response)
+                }
+                Err(e) => {
+                    tracing::error!("s3 download presign error: {:?}", e);
+                    s3_error(e.as_ref())
+                }
+            };
+        }
+    }
+
+    if let Some(token) = path.strip_prefix(PREVIEW_ROUTE) {
+        if method == "GET" {
+            let Some(previews) = PreviewConfig::from_env() else {
+                return text_response(404, "previews are not configured".to_string());
+            };
+            let Some(grant) = previews.verify(token) else {
+                return text_response(403, "invalid or expired preview".to_string());
+            };
+
+            let stage = match Stage::from_name(&grant.stage).await {
+                Ok(stage) => stage,
+                Err(e) => {
+                    tracing::error!("dynamodb preview stage error: {:?}", e);
+                    return dynamodb_error(e.as_ref());
+                }
+            };
+            let part = stage.partition(&posts::posts_part());
+            let value = match get_item_value(part, grant.idx.clone()).await {
+                Ok(Some(value)) => value,
+                Ok(None) => return text_response(404, "post not found".to_string()),
+                Err(e) => {
+                    tracing::error!("dynamodb preview post error: {:?}", e);
+                    return dynamodb_error(e.as_ref());
+                }
+            };
+            let title = serde_json::from_str::<serde_json::Value>(&value)
+                .ok()
+                .and_then(|post| post["title"].as_str().map(str::to_string))
+                .unwrap_or_else(|| grant.idx.clone());
+            let markdown = posts::post_body(&value);
+
+            // the grant's stage, not the request's, decides where images live
+            let image_base = stage.s3_base(root_path);
+            let ttl = Duration::from_secs(900);
+            let mut images = HashMap::new();
+            for key in preview::image_keys(&markdown) {
+                let object = format!("{image_base}{key}");
+                let url = match &ctx.config.cdn_url {
+                    Some(cdn) => Ok(format!("{cdn}/{object}")),
+                    None if bucket.is_empty() => continue,
+                    None => presign_download(ctx.s3().await, bucket, object, None, ttl).await,
+                };
+                match url {
+                    Ok(url) => {
+                        images.insert(key, url);
+                    }
+                    Err(e) => tracing::error!("s3 preview image presign error: {:?}", e),
+                }
+            }
+
+            let page = preview::render(&title, &markdown, &images);
+            let mut response = Response::new(Body::Text(page));
+            add_cors_headers(&mut response);
+            let headers = response.headers_mut();
+            headers.insert("content-type", "text/html; charset=utf-8".parse()?);
+            headers.insert("cache-control", "no-store".parse()?);
+            headers.insert("x-robots-tag", "noindex".parse()?);
+            // the token is in the URL, so it must not leak through links
+            headers.insert("referrer-policy", "no-referrer".parse()?);
+            // drafts may carry raw HTML; nothing in them gets to run
+            headers.insert(
+                "content-security-policy",
+                "default-src 'none'; img-src https: data:; media-src https:; \
+                 style-src 'unsafe-inline'"
+                    .parse()?,
+            );
+            return Ok(response);
+        }
+    }
+
+    if path == "/api/s3/download-manifest" && method == "GET" {
+        let part = query_param(&req, "part");
+        let idx = query_param(&req, "idx");
+        let filename = query_param(&req, "filename").unwrap_or_default();
+
+        if filename.is_empty() {
+            return text_response(400, "filename is required".to_string());
+        }
+
+        let chunk_size = query_param(&req, "chunkSize")
+            .and_then(|c| c.parse::<i64>().ok())
+            .unwrap_or(DEFAULT_CHUNK_SIZE)
+            .max(MIN_CHUNK_SIZE);
+
+        let key = if let (Some(part_val), Some(idx_val)) = (&part, &idx) {
+            if !part_val.is_empty() && !idx_val.is_empty() {
+                format!("{base_path}{}/{}/{}", part_val, idx_val, filename)
+            } else {
+                format!("{base_path}{}", filename)
+            }
+        } else {
+            format!("{base_path}{}", filename)
+        };
+
+        if let Err(response) = check_prefix_acl(&ctx, &key, false).await? {
+            return Ok(response);
+        }
+
+        return match head_object(bucket, key.clone()).await {
+            Ok(Some(info)) if info.archived => archived_response(&info),
+            Ok(Some(info)) => {
+                record_download(&key, root_path).await;
+                let ranges: Vec<String> = (0..info.size)
+                    .step_by(chunk_size as usize)
+                    .map(|start| {
+                        let end = (start + chunk_size).min(info.size) - 1;
+                        format!("bytes={start}-{end}")
+                    })
+                    .collect();
+                json_response(
+                    200,
+                    json!({
+                        "key": key,
+                        "size": info.size,
+                        "etag": info.etag,
+                        "contentType": info.content_type,
+                        "storageClass": info.storage_class,
+                        "chunkSize": chunk_size,
+                        "ranges": ranges,
+                    }),
+                )
+            }
+            Ok(None) => {
+                let folder = key.rsplit_once('/').map(|(f, _)| format!("{f}/"));
+                let folder = folder.unwrap_or_default();
+                let suggestions = match list_objects(ctx.s3().await, bucket, folder.clone()).await {
+                    Ok((_, files)) => suggest::closest(
+                        &filename,
+                        files.iter().map(|f| f.strip_prefix(&folder).unwrap_or(f)),
+                    ),
+                    Err(e) => {
+                        tracing::error!("s3 list error: {:?}", e);
+                        Vec::new()
+                    }
+                };
+                json_response(
+                    404,
+                    json!({ "error": "object not found", "suggestions": suggestions }),
+                )
+            }
+            Err(e) => {
+                tracing::error!("s3 head error: {:?}", e);
+                s3_error(e.as_ref())
+            }
+        };
+    }
+
+    if path == "/api/s3/delete-url" && method == "GET" {
+        let part = query_param(&req, "part");
+        let idx = query_param(&req, "idx");
+        let filename = query_param(&req, "filename").unwrap_or_default();
+
+        if filename.is_empty() {
+            return text_response(400, "filename is required".to_string());
+        }
+
+        let key = if let (Some(part_val), Some(idx_val)) = (&part, &idx) {
+            if !part_val.is_empty() && !idx_val.is_empty() {
+                format!("{base_path}{}/{}/{}", part_val, idx_val, filename)
+            } else {
+                format!("{base_path}{}", filename)
+            }
+        } else {
+            format!("{base_path}{}", filename)
+        };
+
+        if let Err(response) = check_prefix_acl(&ctx, &key, false).await? {
+            return Ok(response);
+        }
 
-        return match presign_delete(&bucket, key).await {
+        return match presign_delete(ctx.s3().await, bucket, key).await {
             Ok(url) => text_response(200, url),
             Err(e) => {
                 tracing::error!("s3 delete presign error: {:?}", e);
-                text_response(500, "s3 error".to_string())
+                s3_error(e.as_ref())
             }
         };
     }
@@ -0,0 +1,51 @@
+use serde_json::Value;
+
+/// Public base URL of the CDN in front of the bucket, from `cdn_url`.
+pub fn cdn_url() -> Option<String> {
+    std::env::var("cdn_url")
+        .ok()
+        .map(|u| u.trim_end_matches('/').to_string())
+        .filter(|u| !u.is_empty())
+}
+
+/// Rewrites image sources that are upload keys relative to the stage's S3
+/// base (`![x](upload/a/b/pic.jpg)`, `<img src="upload/...">`) into URLs
+/// under `base_url`. Links that are not images are left alone.
+pub fn rewrite_markdown(markdown: &str, base_url: &str) -> String {
+    let mut out = String::with_capacity(markdown.len());
+    let mut rest = markdown;
+
+    loop {
+        let markdown_image = rest.find("](upload/");
+        let html_image = rest.find("src=\"upload/");
+        let (at, marker) = match (markdown_image, html_image) {
+            (Some(m), Some(h)) if h < m => (h, "src=\""),
+            (Some(m), _) => (m, "]("),
+            (None, Some(h)) => (h, "src=\""),
+            (None, None) => break,
+        };
+
+        out.push_str(&rest[..at + marker.len()]);
+        let is_image = marker == "src=\""
+            || out[..out.len() - marker.len()]
+                .rfind('[')
+                .is_some_and(|open| out[..open].ends_with('!'));
+        if is_image {
+            out.push_str(base_url);
+        }
+        rest = &rest[at + marker.len()..];
+    }
+
+    out.push_str(rest);
+    out
+}
+
+/// Applies `rewrite_markdown` to the `body`/`content` fields of a post as
+/// returned by the posts routes.
+pub fn rewrite_post(post: &mut Value, base_url: &str) {
+    for field in ["body", "content"] {
+        if let Some(Value::String(markdown)) = post.get_mut(field) {
+            *markdown = rewrite_markdown(markdown, base_url);
+        }
+    }
+}
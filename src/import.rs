@@ -0,0 +1,228 @@
+use crate::dynamodb::{generate_idx, put_item};
+use crate::posts;
+use crate::slugs::SLUGS_PARTITION;
+use crate::stage::Stage;
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+
+const BATCH_SIZE: usize = 25;
+const MAX_REPORTED_REJECTIONS: usize = 100;
+
+#[derive(Debug, Clone, Copy)]
+pub enum ImportFormat {
+    Ndjson,
+    Csv,
+}
+
+impl ImportFormat {
+    /// `text/csv` selects CSV (header row `part,idx,value`); anything else is NDJSON.
+    pub fn from_content_type(content_type: &str) -> ImportFormat {
+        if content_type.starts_with("text/csv") {
+            ImportFormat::Csv
+        } else {
+            ImportFormat::Ndjson
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ImportRecord {
+    part: String,
+    #[serde(default)]
+    idx: Option<String>,
+    value: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Rejection {
+    pub line: usize,
+    pub error: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ImportReport {
+    pub imported: usize,
+    pub rejected_count: usize,
+    pub rejected: Vec<Rejection>,
+}
+
+impl ImportReport {
+    fn reject(&mut self, line: usize, error: String) {
+        self.rejected_count += 1;
+        if self.rejected.len() < MAX_REPORTED_REJECTIONS {
+            self.rejected.push(Rejection { line, error });
+        }
+    }
+}
+
+/// A record ready to be written: its line, whether it is a post, and the
+/// staged part, idx and value.
+struct Item {
+    line: usize,
+    is_post: bool,
+    part: String,
+    idx: String,
+    value: String,
+}
+
+/// Accumulates items and writes every `BATCH_SIZE` records at once, so at
+/// most one batch is held in memory besides the request body itself.
+///
+/// Items are written one by one rather than with BatchWriteItem: posts go
+/// through `posts::save_post` like any other save, and other items are
+/// merged into what is stored (see `put_item`), so attributes the import
+/// does not carry, like `pinned` or `created_at`, are kept and `version`
+/// is bumped.
+struct Writer {
+    stage: Stage,
+    batch: Vec<Item>,
+    imported: usize,
+    rejected: Vec<(usize, String)>,
+}
+
+impl Writer {
+    async fn push(&mut self, item: Item) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        // writes of a batch run concurrently, so one key is written once each
+        if self
+            .batch
+            .iter()
+            .any(|b| b.part == item.part && b.idx == item.idx)
+        {
+            self.flush().await?;
+        }
+        self.batch.push(item);
+        if self.batch.len() == BATCH_SIZE {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+        let batch = std::mem::take(&mut self.batch);
+        let slugs_part = self.stage.partition(SLUGS_PARTITION);
+        let writes = batch.into_iter().map(|item| {
+            let slugs_part = slugs_part.clone();
+            async move {
+                let written = if item.is_post {
+                    posts::save_post(item.part, slugs_part, item.idx, item.value, None)
+                        .await
+                        .map(|saved| saved.map_err(|_| "slug already in use".to_string()))
+                } else {
+                    put_item(item.part, item.idx, item.value).await.map(Ok)
+                };
+                (item.line, written)
+            }
+        });
+        for (line, written) in join_all(writes).await {
+            match written? {
+                Ok(()) => self.imported += 1,
+                Err(e) => self.rejected.push((line, e)),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The `part` and `idx` of every readable record of an NDJSON or CSV body,
+/// without writing anything; records without `idx` are left out.
+pub fn record_keys(body: &[u8], format: ImportFormat) -> Vec<(String, String)> {
+    let records: Vec<ImportRecord> = match format {
+        ImportFormat::Ndjson => body
+            .split(|b| *b == b'\n')
+            .filter_map(|line| serde_json::from_slice(line).ok())
+            .collect(),
+        ImportFormat::Csv => csv::Reader::from_reader(body)
+            .deserialize()
+            .filter_map(Result::ok)
+            .collect(),
+    };
+    records
+        .into_iter()
+        .filter_map(|record| Some((record.part, record.idx?)))
+        .collect()
+}
+
+/// Imports items from an NDJSON or CSV body, deserializing one record at a
+/// time and flushing to DynamoDB as batches fill up. Invalid records, and
+/// posts whose slug another post holds, are reported by line number and
+/// skipped; records without `idx` get a ULID.
+///
+/// The Lambda runtime hands over the body already buffered, so this bounds
+/// the parsed representation, not the raw payload.
+pub async fn import(
+    body: &[u8],
+    format: ImportFormat,
+    stage: Stage,
+    is_reserved_part: fn(&str) -> bool,
+) -> Result<ImportReport, Box<dyn std::error::Error + Send + Sync>> {
+    let mut report = ImportReport::default();
+    let mut writer = Writer {
+        stage,
+        batch: Vec::with_capacity(BATCH_SIZE),
+        imported: 0,
+        rejected: Vec::new(),
+    };
+    let posts_part = posts::posts_part();
+
+    let mut accept = |line: usize, record: Result<ImportRecord, String>| match record {
+        Ok(record) if record.part.is_empty() => {
+            report.reject(line, "part is required".to_string());
+            None
+        }
+        Ok(record) if is_reserved_part(&record.part) => {
+            report.reject(line, "part is reserved".to_string());
+            None
+        }
+        Ok(record) => {
+            let idx = record
+                .idx
+                .filter(|idx| !idx.is_empty())
+                .unwrap_or_else(generate_idx);
+            Some(Item {
+                line,
+                is_post: record.part == posts_part,
+                part: stage.partition(&record.part),
+                idx,
+                value: record.value,
+            })
+        }
+        Err(e) => {
+            report.reject(line, e);
+            None
+        }
+    };
+
+    match format {
+        ImportFormat::Ndjson => {
+            for (i, line) in body.split(|b| *b == b'\n').enumerate() {
+                if line.iter().all(|b| b.is_ascii_whitespace()) {
+                    continue;
+                }
+                let record = serde_json::from_slice(line).map_err(|e| e.to_string());
+                if let Some(item) = accept(i + 1, record) {
+                    writer.push(item).await?;
+                }
+            }
+        }
+        ImportFormat::Csv => {
+            let mut reader = csv::Reader::from_reader(body);
+            for (i, record) in reader.deserialize().enumerate() {
+                // line 1 is the header row
+                let record = record.map_err(|e: csv::Error| e.to_string());
+                if let Some(item) = accept(i + 2, record) {
+                    writer.push(item).await?;
+                }
+            }
+        }
+    }
+    writer.flush().await?;
+
+    for (line, error) in writer.rejected {
+        report.reject(line, error);
+    }
+    report.imported = writer.imported;
+    Ok(report)
+}
@@ -0,0 +1,27 @@
+use crate::{activitypub, dynamodb, failover, s3};
+use aws_config::{BehaviorVersion, SdkConfig};
+use tokio::sync::OnceCell;
+
+/// SDK configuration for the function's own region, used by the clients
+/// that do not follow failover (SNS, KMS, Firehose, Athena, Lambda).
+/// Loading resolves credentials, so it is done once per container.
+pub async fn sdk_config() -> &'static SdkConfig {
+    static CONFIG: OnceCell<SdkConfig> = OnceCell::const_new();
+    CONFIG
+        .get_or_init(|| aws_config::load_defaults(BehaviorVersion::latest()))
+        .await
+}
+
+/// Cold-start work, run from `main` before the first request. Lambda runs
+/// it during the init phase, so with provisioned concurrency a container is
+/// fully warm before it is handed a request.
+pub async fn init() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // loads the data region's configuration and opens a DynamoDB connection
+    dynamodb::init_schema().await?;
+    failover::sdk_config().await;
+    sdk_config().await;
+    // parses the signing key
+    activitypub::config();
+    s3::check_storage_classes()?;
+    Ok(())
+}
@@ -0,0 +1,453 @@
+use crate::clock::now_millis;
+use crate::correlation::CORRELATION_HEADER;
+use crate::dynamodb::{
+    dynamodb_client, generate_idx, get_record, put_record, record_to_json, schema,
+    update_record, TABLE_NAME,
+};
+use crate::lock::acquire_lock;
+use crate::stage::Stage;
+use crate::backup::{self, BackupTarget};
+use crate::{gc, init, linkcheck, outbox, static_site, tags, transition};
+use aws_sdk_dynamodb::operation::update_item::UpdateItemError;
+use aws_sdk_dynamodb::types::{AttributeValue, ReturnValue};
+use aws_sdk_lambda::primitives::Blob;
+use aws_sdk_lambda::types::InvocationType;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Status items of long-running operations, one per job id. A job moves
+/// `pending` → `running` → `succeeded` | `failed`.
+pub const JOBS_PARTITION: &str = "jobs";
+
+/// Header carrying the one-time token that lets a worker run a job.
+pub const JOB_TOKEN_HEADER: &str = "x-job-token";
+
+const TOKEN_HASH_ATTRIBUTE: &str = "token_hash";
+
+/// A job never outlives the 15 minute Lambda timeout.
+const JOB_LOCK_TTL: Duration = Duration::from_secs(15 * 60);
+
+/// Operations that run as jobs, with the parameters they were started with.
+pub enum JobKind {
+    Gc {
+        bucket: String,
+        base_path: String,
+        stage: Stage,
+        grace_days: u64,
+        dry_run: bool,
+    },
+    Retag {
+        part: String,
+        from: Vec<String>,
+        into: String,
+        merge: bool,
+    },
+    LinkCheck,
+    Backup {
+        bucket: String,
+    },
+    OutboxSweep,
+    StaticExport {
+        bucket: String,
+        base_path: String,
+        stage: Stage,
+        site_url: Option<String>,
+        cdn_url: Option<String>,
+    },
+    Transition {
+        bucket: String,
+        prefix: String,
+        storage_class: String,
+    },
+}
+
+impl JobKind {
+    fn name(&self) -> &'static str {
+        match self {
+            JobKind::Gc { .. } => "gc",
+            JobKind::Retag { merge: false, .. } => "tags.rename",
+            JobKind::Retag { merge: true, .. } => "tags.merge",
+            JobKind::LinkCheck => "links.check",
+            JobKind::Backup { .. } => "backup",
+            JobKind::OutboxSweep => "outbox.sweep",
+            JobKind::StaticExport { .. } => "static.export",
+            JobKind::Transition { .. } => "s3.transition",
+        }
+    }
+
+    fn params(&self) -> Value {
+        match self {
+            JobKind::Gc {
+                bucket,
+                base_path,
+                stage,
+                grace_days,
+                dry_run,
+            } => json!({
+                "bucket": bucket,
+                "basePath": base_path,
+                "stage": stage.as_str(),
+                "graceDays": grace_days,
+                "dryRun": dry_run,
+            }),
+            JobKind::Retag { part, from, into, .. } => {
+                json!({ "part": part, "from": from, "into": into })
+            }
+            JobKind::LinkCheck => json!({}),
+            JobKind::Backup { bucket } => json!({ "bucket": bucket }),
+            JobKind::OutboxSweep => json!({}),
+            JobKind::StaticExport {
+                bucket,
+                base_path,
+                stage,
+                site_url,
+                cdn_url,
+            } => json!({
+                "bucket": bucket,
+                "basePath": base_path,
+                "stage": stage.as_str(),
+                "siteUrl": site_url,
+                "cdnUrl": cdn_url,
+            }),
+            JobKind::Transition {
+                bucket,
+                prefix,
+                storage_class,
+            } => json!({
+                "bucket": bucket,
+                "prefix": prefix,
+                "storageClass": storage_class,
+            }),
+        }
+    }
+
+    /// Reads back a job recorded as `name` with `params`. A recorded live
+    /// stage becomes `live`, the live stage as it is now, so a job recorded
+    /// before a promotion runs against the promoted content.
+    fn parse(name: &str, params: &Value, live: Stage) -> Option<JobKind> {
+        let text = |key: &str| params[key].as_str().map(str::to_string);
+        let stage = || match params["stage"].as_str()? {
+            "draft" => Some(Stage::Draft),
+            _ => Some(live),
+        };
+        match name {
+            "gc" => Some(JobKind::Gc {
+                bucket: text("bucket")?,
+                base_path: text("basePath")?,
+                stage: stage()?,
+                grace_days: params["graceDays"].as_u64()?,
+                dry_run: params["dryRun"].as_bool()?,
+            }),
+            "tags.rename" | "tags.merge" => Some(JobKind::Retag {
+                part: text("part")?,
+                from: serde_json::from_value(params["from"].clone()).ok()?,
+                into: text("into")?,
+                merge: name == "tags.merge",
+            }),
+            "links.check" => Some(JobKind::LinkCheck),
+            "backup" => Some(JobKind::Backup {
+                bucket: text("bucket")?,
+            }),
+            "outbox.sweep" => Some(JobKind::OutboxSweep),
+            "static.export" => Some(JobKind::StaticExport {
+                bucket: text("bucket")?,
+                base_path: text("basePath")?,
+                stage: stage()?,
+                site_url: text("siteUrl"),
+                cdn_url: text("cdnUrl"),
+            }),
+            "s3.transition" => Some(JobKind::Transition {
+                bucket: text("bucket")?,
+                prefix: text("prefix")?,
+                storage_class: text("storageClass")?,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Runs the job while holding the lock of its kind, so overlapping
+    /// invocations (a schedule firing during a manual run, say) cannot run
+    /// the same task twice.
+    async fn run(self, id: &str) -> Result<Value, Box<dyn std::error::Error + Send + Sync>> {
+        let name = match &self {
+            JobKind::Retag { .. } => "tags",
+            kind => kind.name(),
+        };
+        let Some(lock) = acquire_lock(name, JOB_LOCK_TTL).await? else {
+            return Err(format!("another {name} job is running").into());
+        };
+        let result = self.execute(id).await;
+        if let Err(e) = lock.release().await {
+            tracing::error!("releasing lock {} failed: {:?}", name, e);
+        }
+        result
+    }
+
+    async fn execute(self, id: &str) -> Result<Value, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(match self {
+            JobKind::Gc {
+                bucket,
+                base_path,
+                stage,
+                grace_days,
+                dry_run,
+            } => json!(gc::collect(&bucket, &base_path, stage, grace_days, dry_run).await?),
+            JobKind::Retag {
+                part, from, into, ..
+            } => json!(tags::retag(part, from, into, id).await?),
+            JobKind::LinkCheck => json!(linkcheck::check_links().await?),
+            JobKind::Backup { bucket } => {
+                let target = BackupTarget::from_env().ok_or("backup is not configured")?;
+                json!(backup::run(&bucket, &target).await?)
+            }
+            JobKind::OutboxSweep => json!(outbox::sweep().await?),
+            JobKind::StaticExport {
+                bucket,
+                base_path,
+                stage,
+                site_url,
+                cdn_url,
+            } => {
+                let (site_url, cdn_url) = (site_url.as_deref(), cdn_url.as_deref());
+                let report =
+                    static_site::export(&bucket, &base_path, stage, site_url, cdn_url, id).await?;
+                json!(report)
+            }
+            JobKind::Transition {
+                bucket,
+                prefix,
+                storage_class,
+            } => json!(transition::transition(&bucket, &prefix, &storage_class, id).await?),
+        })
+    }
+}
+
+fn number(n: usize) -> AttributeValue {
+    AttributeValue::N(n.to_string())
+}
+
+fn token_hash(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Records a `pending` job; returns its id and the token that lets a worker
+/// claim it.
+async fn record(
+    kind: &JobKind,
+    correlation_id: &str,
+) -> Result<(String, String), Box<dyn std::error::Error + Send + Sync>> {
+    let id = generate_idx();
+    // ULIDs carry 80 random bits each; only the token's hash is stored
+    let token = format!("{}{}", generate_idx(), generate_idx());
+    let now = AttributeValue::N(now_millis().to_string());
+    let attributes = HashMap::from([
+        ("kind".to_string(), AttributeValue::S(kind.name().to_string())),
+        ("params".to_string(), AttributeValue::S(kind.params().to_string())),
+        ("status".to_string(), AttributeValue::S("pending".to_string())),
+        (TOKEN_HASH_ATTRIBUTE.to_string(), AttributeValue::S(token_hash(&token))),
+        ("processed".to_string(), number(0)),
+        ("total".to_string(), number(0)),
+        ("correlation_id".to_string(), AttributeValue::S(correlation_id.to_string())),
+        ("created_at".to_string(), now.clone()),
+        ("updated_at".to_string(), now),
+    ]);
+    put_record(JOBS_PARTITION.to_string(), id.clone(), attributes).await?;
+    Ok((id, token))
+}
+
+/// Records a job and runs it before returning, for callers that wait for
+/// the outcome anyway. The job is recorded like any other, so it takes its
+/// kind's lock and can be looked up afterwards. Returns the finished job,
+/// as `get` does.
+pub async fn run_now(
+    kind: JobKind,
+    correlation_id: &str,
+) -> Result<Value, Box<dyn std::error::Error + Send + Sync>> {
+    let (id, token) = record(&kind, correlation_id).await?;
+    run(&id, &token).await?;
+    get(&id).await?.ok_or_else(|| format!("job {id} vanished").into())
+}
+
+/// Records a `pending` job and hands it to a worker: an asynchronous
+/// invocation of this same function for `POST /jobs/{id}/run`, which carries
+/// the submitting request's `correlation_id` so both sides log under it.
+/// Outside Lambda (no `AWS_LAMBDA_FUNCTION_NAME`) the job runs before this
+/// returns.
+pub async fn submit(
+    kind: JobKind,
+    correlation_id: &str,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let (id, token) = record(&kind, correlation_id).await?;
+
+    let Some(function) = std::env::var("AWS_LAMBDA_FUNCTION_NAME")
+        .ok()
+        .filter(|f| !f.is_empty())
+    else {
+        run(&id, &token).await?;
+        return Ok(id);
+    };
+
+    // a minimal API Gateway v2 event, so the worker goes through `route` too
+    let path = format!("/jobs/{id}/run");
+    let event = json!({
+        "version": "2.0",
+        "routeKey": "$default",
+        "rawPath": path,
+        "rawQueryString": "",
+        "headers": { JOB_TOKEN_HEADER: token, CORRELATION_HEADER: correlation_id },
+        "requestContext": {
+            "accountId": "",
+            "apiId": "",
+            "domainName": "",
+            "domainPrefix": "",
+            "http": {
+                "method": "POST",
+                "path": path,
+                "protocol": "HTTP/1.1",
+                "sourceIp": "",
+                "userAgent": "job-worker",
+            },
+            "requestId": id,
+            "routeKey": "$default",
+            "stage": "$default",
+            "time": "",
+            "timeEpoch": now_millis(),
+        },
+        "isBase64Encoded": false,
+    });
+
+    let config = init::sdk_config().await;
+    let invoked = aws_sdk_lambda::Client::new(config)
+        .invoke()
+        .function_name(function)
+        .invocation_type(InvocationType::Event)
+        .payload(Blob::new(event.to_string()))
+        .send()
+        .await;
+    if let Err(e) = invoked {
+        finish(&id, Err("worker could not be started")).await?;
+        return Err(e.into());
+    }
+
+    Ok(id)
+}
+
+/// Worker side of `submit`: moves the job from `pending` to `running` in one
+/// conditional write (so a redelivered invocation cannot run it twice),
+/// executes it and records the outcome. `false` when the token doesn't match
+/// or the job was already picked up.
+pub async fn run(id: &str, token: &str) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let client = dynamodb_client().await;
+    let schema = schema();
+
+    let claimed = client
+        .update_item()
+        .table_name(TABLE_NAME)
+        .key(&schema.partition_key, AttributeValue::S(JOBS_PARTITION.to_string()))
+        .key(&schema.sort_key, AttributeValue::S(id.to_string()))
+        .update_expression("SET #status = :running, #updated = :now REMOVE #token")
+        .condition_expression("#status = :pending AND #token = :hash")
+        .expression_attribute_names("#status", "status")
+        .expression_attribute_names("#updated", "updated_at")
+        .expression_attribute_names("#token", TOKEN_HASH_ATTRIBUTE)
+        .expression_attribute_values(":running", AttributeValue::S("running".to_string()))
+        .expression_attribute_values(":pending", AttributeValue::S("pending".to_string()))
+        .expression_attribute_values(":hash", AttributeValue::S(token_hash(token)))
+        .expression_attribute_values(":now", AttributeValue::N(now_millis().to_string()))
+        .return_values(ReturnValue::AllNew)
+        .send()
+        .await;
+    let job = match claimed {
+        Ok(output) => output.attributes.unwrap_or_default(),
+        Err(e) => {
+            return match e.as_service_error() {
+                Some(UpdateItemError::ConditionalCheckFailedException(_)) => Ok(false),
+                _ => Err(e.into()),
+            }
+        }
+    };
+
+    let text = |name: &str| match job.get(name) {
+        Some(AttributeValue::S(s)) => s.clone(),
+        _ => String::new(),
+    };
+    let params: Value = serde_json::from_str(&text("params")).unwrap_or_default();
+    let live = match Stage::live().await {
+        Ok(live) => live,
+        Err(e) => {
+            finish(id, Err(&e.to_string())).await?;
+            return Ok(true);
+        }
+    };
+    let Some(kind) = JobKind::parse(&text("kind"), &params, live) else {
+        finish(id, Err("unknown job kind")).await?;
+        return Ok(true);
+    };
+
+    match kind.run(id).await {
+        Ok(result) => finish(id, Ok(&result)).await?,
+        Err(e) => {
+            tracing::error!("job {} failed: {:?}", id, e);
+            finish(id, Err(&e.to_string())).await?
+        }
+    }
+    Ok(true)
+}
+
+pub async fn progress(
+    id: &str,
+    processed: usize,
+    total: usize,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let attributes = HashMap::from([
+        ("processed".to_string(), number(processed)),
+        ("total".to_string(), number(total)),
+        ("updated_at".to_string(), AttributeValue::N(now_millis().to_string())),
+    ]);
+    update_record(JOBS_PARTITION.to_string(), id.to_string(), attributes).await
+}
+
+/// Marks the job `succeeded` with its JSON result, or `failed` with the error.
+pub async fn finish(
+    id: &str,
+    outcome: Result<&Value, &str>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let (status, field, detail) = match outcome {
+        Ok(result) => ("succeeded", "result", result.to_string()),
+        Err(error) => ("failed", "error", error.to_string()),
+    };
+    let attributes = HashMap::from([
+        ("status".to_string(), AttributeValue::S(status.to_string())),
+        (field.to_string(), AttributeValue::S(detail)),
+        ("updated_at".to_string(), AttributeValue::N(now_millis().to_string())),
+    ]);
+    update_record(JOBS_PARTITION.to_string(), id.to_string(), attributes).await
+}
+
+/// The job's status item as JSON, with `params` and `result` decoded.
+pub async fn get(id: &str) -> Result<Option<Value>, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(record) = get_record(JOBS_PARTITION.to_string(), id.to_string()).await? else {
+        return Ok(None);
+    };
+    let mut job = record_to_json(&record);
+    if let Some(fields) = job.as_object_mut() {
+        let schema = schema();
+        fields.remove(&schema.partition_key);
+        fields.remove(TOKEN_HASH_ATTRIBUTE);
+        if let Some(id) = fields.remove(&schema.sort_key) {
+            fields.insert("id".to_string(), id);
+        }
+        for name in ["params", "result"] {
+            if let Some(Value::String(encoded)) = fields.get(name) {
+                let decoded = serde_json::from_str(encoded).unwrap_or(Value::Null);
+                fields.insert(name.to_string(), decoded);
+            }
+        }
+    }
+    Ok(Some(job))
+}
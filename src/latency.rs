@@ -0,0 +1,120 @@
+use crate::clock::now_millis;
+use serde_json::json;
+use std::collections::VecDeque;
+use std::sync::{Mutex, OnceLock};
+
+/// Latency budgets in milliseconds of the routes readers and editors wait
+/// on. A `*` path segment matches any one segment.
+const BUDGETS: [(&str, &str, u64); 9] = [
+    ("POST", "/dynamodb/item", 1000),
+    ("GET", "/dynamodb/item", 300),
+    ("GET", "/dynamodb/items", 500),
+    ("GET", "/posts", 500),
+    ("GET", "/posts/by-slug/*", 300),
+    ("GET", "/feed.json", 1000),
+    ("GET", "/sitemap.xml", 1000),
+    ("GET", "/api/s3/upload-url", 300),
+    ("GET", "/api/s3/download-url", 300),
+];
+
+/// Latest durations kept per route; percentiles are taken over these.
+const WINDOW: usize = 200;
+
+const DEFAULT_NAMESPACE: &str = "blog_rust_lambda";
+
+/// Recent durations of each budgeted route, shared by every request this
+/// container serves.
+fn samples() -> &'static Vec<Mutex<VecDeque<u64>>> {
+    static SAMPLES: OnceLock<Vec<Mutex<VecDeque<u64>>>> = OnceLock::new();
+    SAMPLES.get_or_init(|| BUDGETS.iter().map(|_| Mutex::default()).collect())
+}
+
+fn matches(pattern: &str, path: &str) -> bool {
+    let (mut pattern, mut path) = (pattern.split('/'), path.split('/'));
+    loop {
+        match (pattern.next(), path.next()) {
+            (None, None) => return true,
+            (Some("*"), Some(segment)) if !segment.is_empty() => {}
+            (Some(expected), Some(segment)) if expected == segment => {}
+            _ => return false,
+        }
+    }
+}
+
+/// The declared route `method` and `path` fall under, as an index into
+/// `BUDGETS`.
+fn route(method: &str, path: &str) -> Option<usize> {
+    BUDGETS
+        .iter()
+        .position(|(m, pattern, _)| *m == method && matches(pattern, path))
+}
+
+/// The `p`th percentile (0–100) of `sorted`, nearest rank.
+fn percentile(sorted: &[u64], p: usize) -> u64 {
+    let rank = (sorted.len() * p).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+/// Records how long a request to a budgeted route took and emits its latency
+/// as CloudWatch embedded metrics (`Latency`, `LatencyP50`, `LatencyP99`,
+/// `BudgetExceeded`, by `Route`). A request over its route's budget is also
+/// logged as a warning. Other routes are not tracked.
+pub fn observe(method: &str, path: &str, duration_ms: u64) {
+    let Some(index) = route(method, path) else {
+        return;
+    };
+    let (method, pattern, budget) = BUDGETS[index];
+    let route = format!("{method} {pattern}");
+
+    let mut sorted: Vec<u64> = {
+        let mut window = samples()[index].lock().unwrap_or_else(|e| e.into_inner());
+        if window.len() == WINDOW {
+            window.pop_front();
+        }
+        window.push_back(duration_ms);
+        window.iter().copied().collect()
+    };
+    sorted.sort_unstable();
+    let p50 = percentile(&sorted, 50);
+    let p99 = percentile(&sorted, 99);
+    let exceeded = duration_ms > budget;
+
+    if exceeded {
+        tracing::warn!(
+            "{} took {}ms, over its {}ms budget (p50 {}ms, p99 {}ms)",
+            route,
+            duration_ms,
+            budget,
+            p50,
+            p99
+        );
+    }
+
+    let namespace = std::env::var("metrics_namespace")
+        .ok()
+        .filter(|n| !n.is_empty())
+        .unwrap_or_else(|| DEFAULT_NAMESPACE.to_string());
+    let milliseconds = |name: &str| json!({ "Name": name, "Unit": "Milliseconds" });
+    let metric = json!({
+        "_aws": {
+            "Timestamp": now_millis(),
+            "CloudWatchMetrics": [{
+                "Namespace": namespace,
+                "Dimensions": [["Route"]],
+                "Metrics": [
+                    milliseconds("Latency"),
+                    milliseconds("LatencyP50"),
+                    milliseconds("LatencyP99"),
+                    { "Name": "BudgetExceeded", "Unit": "Count" },
+                ],
+            }],
+        },
+        "Route": route,
+        "Latency": duration_ms,
+        "LatencyP50": p50,
+        "LatencyP99": p99,
+        "BudgetExceeded": u8::from(exceeded),
+    });
+    // written bare: CloudWatch only extracts metrics from lines that are the JSON
+    println!("{metric}");
+}
@@ -0,0 +1,160 @@
+use crate::clock::now_millis;
+use crate::dynamodb::{batch_put_items, delete_item, list_items};
+use crate::outbound;
+use crate::posts::{post_body, posts_part};
+use crate::stage::Stage;
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::OnceLock;
+use std::time::Duration;
+use url::Url;
+
+/// One item per outbound link (`idx` is the URL) with its last check result.
+pub const LINK_STATUS_PARTITION: &str = "link_status";
+
+/// Links checked at once; a crawl must not look like an attack to anyone.
+const CONCURRENCY: usize = 8;
+/// DynamoDB caps a string sort key at 1024 bytes.
+const MAX_URL_LEN: usize = 1024;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkStatus {
+    pub url: String,
+    /// Final HTTP status, or `None` when the request failed outright.
+    pub status: Option<u16>,
+    pub error: Option<String>,
+    pub posts: Vec<String>,
+    pub checked_at: u64,
+}
+
+impl LinkStatus {
+    pub fn is_broken(&self) -> bool {
+        !matches!(self.status, Some(200..=399))
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CheckReport {
+    pub checked: usize,
+    pub broken: usize,
+}
+
+fn client() -> Result<&'static reqwest::Client, Box<dyn std::error::Error + Send + Sync>> {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    outbound::cached(&CLIENT, Duration::from_secs(10), 5)
+}
+
+/// Absolute http(s) URLs in a markdown body, whether written as `[x](url)`,
+/// `<url>`, `href="url"` or bare.
+fn outbound_links(markdown: &str) -> Vec<String> {
+    let mut links = Vec::new();
+    let mut rest = markdown;
+    while let Some(start) = rest.find("http") {
+        let candidate = &rest[start..];
+        let end = candidate
+            .find(|c: char| c.is_whitespace() || matches!(c, ')' | '"' | '\'' | '<' | '>' | ']'))
+            .unwrap_or(candidate.len());
+        let link = candidate[..end].trim_end_matches(['.', ',', ';', ':', '!', '?']);
+        if let Ok(url) = Url::parse(link) {
+            if url.scheme() == "http" || url.scheme() == "https" {
+                links.push(link.to_string());
+            }
+        }
+        rest = &candidate[end..];
+    }
+    links
+}
+
+/// Whether `link` points back at the blog itself (`site_url`).
+fn is_internal(link: &str, site_host: Option<&str>) -> bool {
+    let host = Url::parse(link).ok().and_then(|u| u.host_str().map(str::to_string));
+    site_host.is_some() && host.as_deref() == site_host
+}
+
+/// Some servers refuse `HEAD`; those get a `GET` before being called broken.
+async fn check(client: &reqwest::Client, url: &str) -> (Option<u16>, Option<String>) {
+    match outbound::send(client.head(url)).await {
+        Ok(response) if response.status().is_success() || response.status().is_redirection() => {
+            (Some(response.status().as_u16()), None)
+        }
+        // an internal address stays unchecked and is reported as broken
+        Err(e) if outbound::is_blocked(e.as_ref()) => (None, Some(e.to_string())),
+        _ => match outbound::send(client.get(url)).await {
+            Ok(response) => (Some(response.status().as_u16()), None),
+            Err(e) => (None, Some(e.to_string())),
+        },
+    }
+}
+
+/// Crawls every outbound link of the live posts and replaces the stored
+/// results, so links removed from posts disappear from the report.
+pub async fn check_links() -> Result<CheckReport, Box<dyn std::error::Error + Send + Sync>> {
+    let site_host = std::env::var("site_url")
+        .ok()
+        .and_then(|s| Url::parse(&s).ok())
+        .and_then(|u| u.host_str().map(str::to_string));
+
+    let mut links: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for post in list_items(Stage::live().await?.partition(&posts_part())).await? {
+        let Some(value) = post.value else { continue };
+        for link in outbound_links(&post_body(&value)) {
+            if link.len() <= MAX_URL_LEN && !is_internal(&link, site_host.as_deref()) {
+                links.entry(link).or_default().insert(post.idx.clone());
+            }
+        }
+    }
+
+    let client = client()?;
+    let checked_at = now_millis();
+    let results: Vec<LinkStatus> = stream::iter(links)
+        .map(|(url, posts)| async move {
+            let (status, error) = check(client, &url).await;
+            LinkStatus {
+                url,
+                status,
+                error,
+                posts: posts.into_iter().collect(),
+                checked_at,
+            }
+        })
+        .buffer_unordered(CONCURRENCY)
+        .collect()
+        .await;
+
+    let current: BTreeSet<&str> = results.iter().map(|r| r.url.as_str()).collect();
+    for stale in list_items(LINK_STATUS_PARTITION.to_string()).await? {
+        if !current.contains(stale.idx.as_str()) {
+            delete_item(LINK_STATUS_PARTITION.to_string(), stale.idx).await?;
+        }
+    }
+
+    let items: Vec<(String, String, String)> = results
+        .iter()
+        .map(|r| {
+            let value = serde_json::to_string(r)?;
+            Ok((LINK_STATUS_PARTITION.to_string(), r.url.clone(), value))
+        })
+        .collect::<Result<_, serde_json::Error>>()?;
+    for chunk in items.chunks(25) {
+        batch_put_items(chunk.to_vec()).await?;
+    }
+
+    Ok(CheckReport {
+        checked: results.len(),
+        broken: results.iter().filter(|r| r.is_broken()).count(),
+    })
+}
+
+/// Stored results from the last crawl; only broken links unless `all`.
+pub async fn broken_links(
+    all: bool,
+) -> Result<Vec<LinkStatus>, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(list_items(LINK_STATUS_PARTITION.to_string())
+        .await?
+        .into_iter()
+        .filter_map(|item| serde_json::from_str::<LinkStatus>(item.value.as_deref()?).ok())
+        .filter(|link| all || link.is_broken())
+        .collect())
+}
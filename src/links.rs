@@ -0,0 +1,155 @@
+use crate::auth::constant_time_eq;
+use crate::clock::now_millis;
+use crate::dynamodb::{list_items, put_item};
+use crate::stage::Stage;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64URL;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::Serialize;
+use sha2::Sha256;
+use std::time::Duration;
+
+/// Link generations of S3 key prefixes, one item per prefix (relative to the
+/// stage's base path) with the generation as its value.
+pub const LINK_GENERATIONS_PARTITION: &str = "link_generations";
+
+/// Characters left as-is in the key segment of a link.
+pub const KEY_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'/')
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// Route prefix of download links: `/api/s3/d/{generation}/{key}`.
+pub const LINK_ROUTE: &str = "/api/s3/d/";
+
+/// How long a download link works, as long as a presigned URL would.
+const LINK_TTL_SECS: u64 = 900;
+
+/// Lifetime of the presigned URL a link redirects to. Kept short, since a
+/// presigned URL itself cannot be revoked.
+pub const REDIRECT_TTL: Duration = Duration::from_secs(60);
+
+/// Signing secret and public API base URL; download links are only issued
+/// when both `link_secret` and `api_url` are set.
+pub struct LinkConfig {
+    secret: String,
+    api_url: String,
+}
+
+impl LinkConfig {
+    pub fn from_env(api_url: Option<&String>) -> Option<LinkConfig> {
+        let secret = std::env::var("link_secret").ok().filter(|s| !s.is_empty())?;
+        Some(LinkConfig {
+            secret,
+            api_url: api_url?.clone(),
+        })
+    }
+
+    fn signature(&self, generation: u64, key: &str, expires: u64, range: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.secret.as_bytes())
+            .expect("hmac accepts any key length");
+        mac.update(format!("{generation}\n{key}\n{expires}\n{range}").as_bytes());
+        BASE64URL.encode(mac.finalize().into_bytes())
+    }
+
+    /// A download link for `key` at `generation`, valid for 15 minutes.
+    pub fn link(&self, generation: u64, key: &str, range: Option<&str>) -> String {
+        let expires = now_millis() / 1000 + LINK_TTL_SECS;
+        let range = range.unwrap_or_default();
+        let signature = self.signature(generation, key, expires, range);
+        let mut query = url::form_urlencoded::Serializer::new(String::new());
+        query.append_pair("expires", &expires.to_string());
+        if !range.is_empty() {
+            query.append_pair("range", range);
+        }
+        query.append_pair("sig", &signature);
+        let path = utf8_percent_encode(key, KEY_SEGMENT);
+        format!("{}{LINK_ROUTE}{generation}/{path}?{}", self.api_url, query.finish())
+    }
+
+    /// Whether `signature` was issued by `link` for these values and has not
+    /// expired. The generation is checked separately.
+    pub fn verify(
+        &self,
+        generation: u64,
+        key: &str,
+        expires: u64,
+        range: Option<&str>,
+        signature: &str,
+    ) -> bool {
+        if expires < now_millis() / 1000 {
+            return false;
+        }
+        let expected = self.signature(generation, key, expires, range.unwrap_or_default());
+        constant_time_eq(expected.as_bytes(), signature.as_bytes())
+    }
+}
+
+/// `key` relative to its stage's base path under `root_path`, which is what
+/// prefixes are matched against. Links are followed without the stage the
+/// issuing request had, so the stage is read off the key.
+pub fn relative_key<'a>(key: &'a str, root_path: &str) -> &'a str {
+    let key = key.strip_prefix(root_path).unwrap_or(key);
+    key.strip_prefix(Stage::Draft.s3_base("").as_str()).unwrap_or(key)
+}
+
+#[derive(Debug, Serialize)]
+pub struct PrefixGeneration {
+    pub prefix: String,
+    pub generation: u64,
+}
+
+/// Every bumped prefix, loaded once per request and matched in memory.
+pub struct Generations(Vec<PrefixGeneration>);
+
+impl Generations {
+    pub async fn load() -> Result<Generations, Box<dyn std::error::Error + Send + Sync>> {
+        let items = list_items(LINK_GENERATIONS_PARTITION.to_string()).await?;
+        Ok(Generations(
+            items
+                .into_iter()
+                .filter_map(|item| {
+                    let generation = item.value.as_deref()?.parse().ok()?;
+                    Some(PrefixGeneration {
+                        prefix: item.idx,
+                        generation,
+                    })
+                })
+                .collect(),
+        ))
+    }
+
+    /// Generation of `key` (relative to the base path): the latest of the
+    /// generations of every prefix it falls under, `0` if none was bumped.
+    /// Bumping a prefix therefore also revokes links under nested prefixes.
+    pub fn of(&self, key: &str) -> u64 {
+        self.0
+            .iter()
+            .filter(|g| key.starts_with(&g.prefix))
+            .map(|g| g.generation)
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn into_generations(self) -> Vec<PrefixGeneration> {
+        self.0
+    }
+}
+
+/// Starts a new generation for `prefix`: every link issued for a key under
+/// it stops working. Generations are bump times in milliseconds, so they
+/// only grow and a parent's bump outranks its children's older ones.
+pub async fn bump(prefix: String) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+    let current = Generations::load().await?.of(&prefix);
+    let generation = now_millis().max(current + 1);
+    put_item(
+        LINK_GENERATIONS_PARTITION.to_string(),
+        prefix,
+        generation.to_string(),
+    )
+    .await?;
+    Ok(generation)
+}
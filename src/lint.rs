@@ -0,0 +1,281 @@
+use serde::Serialize;
+
+/// Contrast WCAG AA asks of body text.
+const MIN_CONTRAST: f64 = 4.5;
+
+/// Longest excerpt of the offending tag quoted in a warning.
+const MAX_SNIPPET_CHARS: usize = 120;
+
+/// An sRGB color.
+type Rgb = (u8, u8, u8);
+
+/// Colors assumed where an inline style sets only one side: the blog's
+/// default black text on white.
+const DEFAULT_TEXT: Rgb = (0, 0, 0);
+const DEFAULT_BACKGROUND: Rgb = (255, 255, 255);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rule {
+    MissingAlt,
+    HeadingOrder,
+    LowContrast,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Warning {
+    pub rule: Rule,
+    pub message: String,
+    /// The tag the warning is about, shortened.
+    pub element: String,
+}
+
+/// A start tag in rendered HTML: its lowercased name, raw text and
+/// attributes (names lowercased, values as written).
+struct Tag<'a> {
+    name: String,
+    raw: &'a str,
+    attributes: Vec<(String, String)>,
+}
+
+impl Tag<'_> {
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn snippet(&self) -> String {
+        if self.raw.chars().count() <= MAX_SNIPPET_CHARS {
+            return self.raw.to_string();
+        }
+        let cut: String = self.raw.chars().take(MAX_SNIPPET_CHARS).collect();
+        format!("{cut}…")
+    }
+}
+
+fn parse_attributes(mut rest: &str) -> Vec<(String, String)> {
+    let mut attributes = Vec::new();
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+        let name_end = rest
+            .find(|c: char| c.is_whitespace() || c == '=' || c == '/')
+            .unwrap_or(rest.len());
+        if name_end == 0 {
+            return attributes;
+        }
+        let name = rest[..name_end].to_ascii_lowercase();
+        rest = rest[name_end..].trim_start();
+
+        let Some(after_eq) = rest.strip_prefix('=') else {
+            attributes.push((name, String::new()));
+            continue;
+        };
+        let after_eq = after_eq.trim_start();
+        let (value, remaining) = match after_eq.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let body = &after_eq[1..];
+                let end = body.find(quote).unwrap_or(body.len());
+                (&body[..end], body.get(end + 1..).unwrap_or_default())
+            }
+            _ => {
+                let end = after_eq.find(char::is_whitespace).unwrap_or(after_eq.len());
+                (&after_eq[..end], &after_eq[end..])
+            }
+        };
+        attributes.push((name, value.to_string()));
+        rest = remaining;
+    }
+}
+
+/// Start tags of `html` in document order. Comments, closing tags and
+/// declarations are skipped; text never holds a raw `<` once rendered.
+fn tags(html: &str) -> Vec<Tag<'_>> {
+    let mut tags = Vec::new();
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        let candidate = &rest[start..];
+        if candidate.starts_with("<!--") {
+            let end = candidate.find("-->").map_or(candidate.len(), |e| e + 3);
+            rest = &candidate[end..];
+            continue;
+        }
+        let end = candidate.find('>').map_or(candidate.len(), |e| e + 1);
+        let raw = &candidate[..end];
+        rest = &candidate[end..];
+
+        let inner = raw[1..].trim_end_matches('>');
+        if !inner.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            continue;
+        }
+        let name_end = inner
+            .find(|c: char| !c.is_ascii_alphanumeric())
+            .unwrap_or(inner.len());
+        tags.push(Tag {
+            name: inner[..name_end].to_ascii_lowercase(),
+            raw,
+            attributes: parse_attributes(&inner[name_end..]),
+        });
+    }
+    tags
+}
+
+fn named_color(name: &str) -> Option<Rgb> {
+    Some(match name {
+        "black" => (0, 0, 0),
+        "white" => (255, 255, 255),
+        "gray" | "grey" => (128, 128, 128),
+        "silver" => (192, 192, 192),
+        "lightgray" | "lightgrey" => (211, 211, 211),
+        "darkgray" | "darkgrey" => (169, 169, 169),
+        "red" => (255, 0, 0),
+        "green" => (0, 128, 0),
+        "lime" => (0, 255, 0),
+        "blue" => (0, 0, 255),
+        "navy" => (0, 0, 128),
+        "yellow" => (255, 255, 0),
+        "orange" => (255, 165, 0),
+        "purple" => (128, 0, 128),
+        "pink" => (255, 192, 203),
+        "cyan" | "aqua" => (0, 255, 255),
+        "magenta" | "fuchsia" => (255, 0, 255),
+        _ => return None,
+    })
+}
+
+/// An opaque CSS color: `#rgb`, `#rrggbb`, `rgb(r, g, b)` or a common name.
+/// Anything else, including transparency, is not judged.
+fn parse_color(value: &str) -> Option<Rgb> {
+    let value = value.trim().to_ascii_lowercase();
+    if let Some(hex) = value.strip_prefix('#') {
+        let digits: Vec<u8> = hex
+            .chars()
+            .map(|c| c.to_digit(16).map(|d| d as u8))
+            .collect::<Option<_>>()?;
+        return match digits[..] {
+            [r, g, b] => Some((r * 17, g * 17, b * 17)),
+            [r1, r2, g1, g2, b1, b2] => Some((r1 * 16 + r2, g1 * 16 + g2, b1 * 16 + b2)),
+            _ => None,
+        };
+    }
+    if let Some(args) = value
+        .strip_prefix("rgb(")
+        .and_then(|rest| rest.strip_suffix(')'))
+    {
+        let channels: Vec<u8> = args
+            .split(',')
+            .map(|c| c.trim().parse().ok())
+            .collect::<Option<_>>()?;
+        return match channels[..] {
+            [r, g, b] => Some((r, g, b)),
+            _ => None,
+        };
+    }
+    named_color(&value)
+}
+
+/// WCAG relative luminance of an sRGB color.
+fn luminance((r, g, b): Rgb) -> f64 {
+    let linear = |c: u8| {
+        let c = f64::from(c) / 255.0;
+        if c <= 0.03928 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    0.2126 * linear(r) + 0.7152 * linear(g) + 0.0722 * linear(b)
+}
+
+fn contrast(a: Rgb, b: Rgb) -> f64 {
+    let (la, lb) = (luminance(a), luminance(b));
+    (la.max(lb) + 0.05) / (la.min(lb) + 0.05)
+}
+
+/// Text and background colors an inline `style` sets, if any.
+fn style_colors(style: &str) -> (Option<Rgb>, Option<Rgb>) {
+    let (mut text, mut background) = (None, None);
+    for declaration in style.split(';') {
+        let Some((property, value)) = declaration.split_once(':') else {
+            continue;
+        };
+        match property.trim().to_ascii_lowercase().as_str() {
+            "color" => text = parse_color(value),
+            "background" | "background-color" => background = parse_color(value),
+            _ => {}
+        }
+    }
+    (text, background)
+}
+
+/// Accessibility warnings for the rendered body of a post: images without
+/// alt text, headings that skip a level (the post title is the page's
+/// `h1`), and inline styles whose text contrast is below WCAG AA.
+pub fn audit(html: &str) -> Vec<Warning> {
+    let mut warnings = Vec::new();
+    let mut previous_level = 1;
+
+    for tag in tags(html) {
+        if tag.name == "img" {
+            let message = match tag.attribute("alt").map(str::trim) {
+                None => Some("image has no alt attribute"),
+                Some("") => Some("image has empty alt text; fine only for decorative images"),
+                Some(_) => None,
+            };
+            if let Some(message) = message {
+                warnings.push(Warning {
+                    rule: Rule::MissingAlt,
+                    message: message.to_string(),
+                    element: tag.snippet(),
+                });
+            }
+        }
+
+        let level = match tag.name.as_bytes() {
+            [b'h', level @ b'1'..=b'6'] => Some(usize::from(level - b'0')),
+            _ => None,
+        };
+        if let Some(level) = level {
+            let message = if level == 1 {
+                Some("h1 in the body; the post title is already the page's h1".to_string())
+            } else if level > previous_level + 1 {
+                Some(format!(
+                    "h{level} follows h{previous_level}, skipping a level"
+                ))
+            } else {
+                None
+            };
+            if let Some(message) = message {
+                warnings.push(Warning {
+                    rule: Rule::HeadingOrder,
+                    message,
+                    element: tag.snippet(),
+                });
+            }
+            previous_level = level;
+        }
+
+        if let Some(style) = tag.attribute("style") {
+            let colors = match style_colors(style) {
+                (None, None) => None,
+                (text, background) => Some((
+                    text.unwrap_or(DEFAULT_TEXT),
+                    background.unwrap_or(DEFAULT_BACKGROUND),
+                )),
+            };
+            if let Some((text, background)) = colors {
+                let ratio = contrast(text, background);
+                if ratio < MIN_CONTRAST {
+                    warnings.push(Warning {
+                        rule: Rule::LowContrast,
+                        message: format!("text contrast is {ratio:.2}:1, below {MIN_CONTRAST}:1"),
+                        element: tag.snippet(),
+                    });
+                }
+            }
+        }
+    }
+
+    warnings
+}
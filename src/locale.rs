@@ -0,0 +1,89 @@
+use serde_json::Value;
+
+/// Language ranges of an `Accept-Language` header, most preferred first.
+/// Ranges with `q=0` are refused and dropped.
+fn preferences(header: &str) -> Vec<String> {
+    let mut ranges: Vec<(String, f32)> = header
+        .split(',')
+        .filter_map(|part| {
+            let mut params = part.split(';');
+            let range = params.next()?.trim().to_lowercase();
+            if range.is_empty() {
+                return None;
+            }
+            let q = params
+                .filter_map(|p| p.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            Some((range, q))
+        })
+        .filter(|(_, q)| *q > 0.0)
+        .collect();
+    // stable, so equal weights keep the header's order
+    ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+    ranges.into_iter().map(|(range, _)| range).collect()
+}
+
+/// Best of `available` for the header, matched as RFC 4647 lookup does: a
+/// range matches a tag equal to it or to one of its prefixes (`de-AT` takes
+/// `de`), and a bare range also takes regional tags (`de` takes `de-AT`).
+/// `*` and unmatched headers give `None`.
+pub fn negotiate<'a>(header: &str, available: &[&'a str]) -> Option<&'a str> {
+    for range in preferences(header) {
+        if range == "*" {
+            return None;
+        }
+        let mut candidate = range.as_str();
+        loop {
+            if let Some(tag) = available.iter().find(|t| t.eq_ignore_ascii_case(candidate)) {
+                return Some(tag);
+            }
+            match candidate.rsplit_once('-') {
+                Some((prefix, _)) => candidate = prefix,
+                None => break,
+            }
+        }
+        let regional = available.iter().find(|t| {
+            t.to_lowercase()
+                .strip_prefix(range.as_str())
+                .is_some_and(|rest| rest.starts_with('-'))
+        });
+        if let Some(tag) = regional {
+            return Some(tag);
+        }
+    }
+    None
+}
+
+/// Picks the variant of a post for `accept_language`. Translations are kept
+/// in the post as `"translations": {"de": {"title": ..., "body": ...}}` and
+/// the original's language in `lang`. The chosen translation's fields replace
+/// the original's; the map itself is replaced by the list of `languages`.
+/// Returns the language of the resulting post, when known.
+pub fn select_variant(post: &mut Value, accept_language: Option<&str>) -> Option<String> {
+    let fields = post.as_object_mut()?;
+    let original = fields.get("lang").and_then(|l| l.as_str()).map(str::to_string);
+    let Some(Value::Object(translations)) = fields.remove("translations") else {
+        return original;
+    };
+
+    let mut languages: Vec<&str> = original.iter().map(String::as_str).collect();
+    languages.extend(translations.keys().map(String::as_str));
+    let chosen = accept_language
+        .and_then(|header| negotiate(header, &languages))
+        .map(str::to_string);
+    let languages: Vec<Value> = languages.iter().map(|l| Value::from(*l)).collect();
+
+    let selected = match chosen {
+        Some(lang) if Some(&lang) != original.as_ref() => {
+            if let Some(Value::Object(variant)) = translations.get(&lang) {
+                fields.extend(variant.clone());
+            }
+            fields.insert("lang".to_string(), Value::String(lang.clone()));
+            Some(lang)
+        }
+        _ => original,
+    };
+    fields.insert("languages".to_string(), Value::Array(languages));
+    selected
+}
@@ -0,0 +1,85 @@
+use crate::clock::now_millis;
+use crate::dynamodb::{dynamodb_client, generate_idx, schema, TABLE_NAME};
+use aws_sdk_dynamodb::operation::delete_item::DeleteItemError;
+use aws_sdk_dynamodb::operation::put_item::PutItemError;
+use aws_sdk_dynamodb::types::AttributeValue;
+use std::time::Duration;
+
+/// One item per held lock, keyed by lock name.
+pub const LOCKS_PARTITION: &str = "locks";
+
+/// A held lock; give it back with `release` once the work is done.
+#[derive(Debug)]
+pub struct Lock {
+    name: String,
+    token: String,
+}
+
+/// Takes the lock `name` unless another holder has it. A lock whose holder
+/// died is free again once `ttl` has passed, so `ttl` must cover the longest
+/// run of the guarded task. `None` when the lock is held.
+pub async fn acquire_lock(
+    name: &str,
+    ttl: Duration,
+) -> Result<Option<Lock>, Box<dyn std::error::Error + Send + Sync>> {
+    let client = dynamodb_client().await;
+    let schema = schema();
+
+    let token = generate_idx();
+    let now = now_millis();
+    let expires_at = now + ttl.as_millis() as u64;
+
+    let result = client
+        .put_item()
+        .table_name(TABLE_NAME)
+        .item(&schema.partition_key, AttributeValue::S(LOCKS_PARTITION.to_string()))
+        .item(&schema.sort_key, AttributeValue::S(name.to_string()))
+        .item("token", AttributeValue::S(token.clone()))
+        .item("expires_at", AttributeValue::N(expires_at.to_string()))
+        // lets DynamoDB TTL clean up locks that were never released
+        .item("ttl", AttributeValue::N((expires_at / 1000 + 1).to_string()))
+        .condition_expression("attribute_not_exists(#pk) OR #expires < :now")
+        .expression_attribute_names("#pk", &schema.partition_key)
+        .expression_attribute_names("#expires", "expires_at")
+        .expression_attribute_values(":now", AttributeValue::N(now.to_string()))
+        .send()
+        .await;
+
+    match result {
+        Ok(_) => Ok(Some(Lock {
+            name: name.to_string(),
+            token,
+        })),
+        Err(e) => match e.as_service_error() {
+            Some(PutItemError::ConditionalCheckFailedException(_)) => Ok(None),
+            _ => Err(e.into()),
+        },
+    }
+}
+
+impl Lock {
+    /// Frees the lock, unless it expired and was taken over meanwhile.
+    pub async fn release(self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let client = dynamodb_client().await;
+        let schema = schema();
+
+        let result = client
+            .delete_item()
+            .table_name(TABLE_NAME)
+            .key(&schema.partition_key, AttributeValue::S(LOCKS_PARTITION.to_string()))
+            .key(&schema.sort_key, AttributeValue::S(self.name))
+            .condition_expression("#token = :token")
+            .expression_attribute_names("#token", "token")
+            .expression_attribute_values(":token", AttributeValue::S(self.token))
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) => match e.as_service_error() {
+                Some(DeleteItemError::ConditionalCheckFailedException(_)) => Ok(()),
+                _ => Err(e.into()),
+            },
+        }
+    }
+}
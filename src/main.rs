@@ -1,13 +1,98 @@
-use lambda_http::{run, service_fn, tracing, Error};
-mod http_handler;
+use lambda_http::{service_fn, tracing, Error};
+mod acl;
+mod activitypub;
+mod athena;
+mod attachments;
+mod audit;
+mod auth;
+mod autosave;
+mod avatar;
+mod backup;
+mod clock;
+mod commands;
+mod comments;
+mod compression;
+mod concurrency;
+mod correlation;
+mod costs;
+mod counters;
+mod ctx;
+mod cursor;
+mod dedupe;
+mod digest;
+mod disqus;
+mod downloads;
 mod dynamodb;
+mod edit_locks;
+mod excerpt;
+mod failover;
+mod feed;
+mod fields;
+mod files;
+mod firehose;
+mod gc;
+mod geo;
+mod health;
+mod honeytoken;
+mod hotlink;
+mod http_handler;
+mod images;
+mod init;
+mod import;
+mod jobs;
+mod latency;
+mod linkcheck;
+mod links;
+mod lint;
+mod locale;
+mod lock;
+mod masking;
+mod media_import;
+mod object_lock;
+mod outbound;
+mod outbox;
+mod overflow;
+mod posts;
+mod preview;
+mod promote;
+mod publish;
+mod quota;
+mod reactions;
+mod replay;
 mod s3;
+mod secrets;
+mod security_headers;
+mod ses;
+mod series;
+mod settings;
+mod shadow;
+mod shortlinks;
+mod slugs;
+mod snapshots;
+mod stage;
+mod static_site;
+mod subscribers;
+mod suggest;
+mod sync;
+mod syndicate;
+mod summary;
+mod tags;
+mod themes;
+mod transition;
+mod urls;
+mod usage;
+mod user_data;
+mod view;
+mod webmention;
 
-use http_handler::function_handler;
+use commands::invoke;
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
     tracing::init_default_subscriber();
 
-    run(service_fn(function_handler)).await
+    init::init().await?;
+
+    // HTTP events and direct command invocations share the function
+    lambda_http::lambda_runtime::run(service_fn(invoke)).await
 }
@@ -0,0 +1,203 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// Longest comment `POST /comments/mask` takes.
+pub const MAX_TEXT_BYTES: usize = 64 * 1024;
+
+const MAX_WORDS: usize = 500;
+const MAX_WORD_CHARS: usize = 50;
+
+const EMAIL_MASK: &str = "[email]";
+const PHONE_MASK: &str = "[phone]";
+
+/// What the masking pass hides from comments shown publicly. Everything is
+/// off until turned on in the site settings.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct CommentMasking {
+    #[serde(default)]
+    pub emails: bool,
+    #[serde(default)]
+    pub phones: bool,
+    /// Whole words, matched ignoring case, replaced by asterisks.
+    #[serde(default)]
+    pub profanity: Vec<String>,
+}
+
+impl CommentMasking {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.profanity.len() > MAX_WORDS {
+            return Err(format!("at most {MAX_WORDS} profanity words are allowed"));
+        }
+        for word in &self.profanity {
+            let valid = !word.is_empty()
+                && word.chars().count() <= MAX_WORD_CHARS
+                && word.chars().all(char::is_alphanumeric);
+            if !valid {
+                return Err(format!(
+                    "profanity words must be 1 to {MAX_WORD_CHARS} letters or digits"
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A comment as it may be shown, and how many spans were hidden.
+#[derive(Debug, Serialize)]
+pub struct Masked {
+    pub text: String,
+    pub masked: usize,
+}
+
+/// Replaces what `options` asks to hide in `text`: email addresses,
+/// phone numbers and listed words.
+pub fn mask(text: &str, options: &CommentMasking) -> Masked {
+    let mut masked = 0;
+    let mut text = text.to_string();
+    if options.emails {
+        text = replace_spans(
+            &text,
+            email_spans(&text),
+            |_| EMAIL_MASK.to_string(),
+            &mut masked,
+        );
+    }
+    if options.phones {
+        text = replace_spans(
+            &text,
+            phone_spans(&text),
+            |_| PHONE_MASK.to_string(),
+            &mut masked,
+        );
+    }
+    if !options.profanity.is_empty() {
+        let words: HashSet<String> = options.profanity.iter().map(|w| w.to_lowercase()).collect();
+        let spans = word_spans(&text)
+            .into_iter()
+            .filter(|&(start, end)| words.contains(&text[start..end].to_lowercase()))
+            .collect();
+        let stars = |word: &str| "*".repeat(word.chars().count());
+        text = replace_spans(&text, spans, stars, &mut masked);
+    }
+    Masked { text, masked }
+}
+
+/// `text` with each of `spans` (byte ranges, in order, not overlapping)
+/// replaced by `with` of what it covers.
+fn replace_spans(
+    text: &str,
+    spans: Vec<(usize, usize)>,
+    with: impl Fn(&str) -> String,
+    count: &mut usize,
+) -> String {
+    *count += spans.len();
+    let mut out = String::with_capacity(text.len());
+    let mut last = 0;
+    for (start, end) in spans {
+        out.push_str(&text[last..start]);
+        out.push_str(&with(&text[start..end]));
+        last = end;
+    }
+    out.push_str(&text[last..]);
+    out
+}
+
+/// Byte ranges of the runs of letters and digits in `text`.
+fn word_spans(text: &str) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    let mut start = None;
+    for (i, c) in text.char_indices() {
+        match (c.is_alphanumeric(), start) {
+            (true, None) => start = Some(i),
+            (false, Some(s)) => {
+                spans.push((s, i));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        spans.push((s, text.len()));
+    }
+    spans
+}
+
+/// `local@domain.tld`, with an ASCII local part and a dotted domain ending in
+/// at least two letters.
+fn email_spans(text: &str) -> Vec<(usize, usize)> {
+    let bytes = text.as_bytes();
+    let local = |b: u8| b.is_ascii_alphanumeric() || b"._%+-".contains(&b);
+    let domain = |b: u8| b.is_ascii_alphanumeric() || b == b'.' || b == b'-';
+
+    let mut spans: Vec<(usize, usize)> = Vec::new();
+    for (at, _) in text.match_indices('@') {
+        if spans.last().is_some_and(|&(_, end)| at < end) {
+            continue;
+        }
+        let mut start = at;
+        while start > 0 && local(bytes[start - 1]) {
+            start -= 1;
+        }
+        let mut end = at + 1;
+        while end < bytes.len() && domain(bytes[end]) {
+            end += 1;
+        }
+        // a sentence may end right after the address
+        while end > at + 1 && matches!(bytes[end - 1], b'.' | b'-') {
+            end -= 1;
+        }
+        let host = &text[at + 1..end];
+        let tld = host.rsplit('.').next().unwrap_or_default();
+        if start < at
+            && host.contains('.')
+            && tld.len() >= 2
+            && tld.bytes().all(|b| b.is_ascii_alphabetic())
+        {
+            spans.push((start, end));
+        }
+    }
+    spans
+}
+
+/// Digits with the usual separators (spaces, `-`, `.`, parentheses) and an
+/// optional leading `+`: 9 to 15 digits, or 7 with the `+`. Shorter runs
+/// are left alone so dates and year ranges are not taken for numbers.
+fn phone_spans(text: &str) -> Vec<(usize, usize)> {
+    let bytes = text.as_bytes();
+    let mut spans = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let starts = bytes[i].is_ascii_digit() || matches!(bytes[i], b'+' | b'(');
+        let after_word = i > 0 && bytes[i - 1].is_ascii_alphanumeric();
+        if !starts || after_word {
+            i += 1;
+            continue;
+        }
+
+        let plus = bytes[i] == b'+';
+        let mut end = i + 1;
+        let mut digits = usize::from(bytes[i].is_ascii_digit());
+        while end < bytes.len() {
+            match bytes[end] {
+                b'0'..=b'9' => digits += 1,
+                b' ' | b'-' | b'.' | b'(' | b')' => {}
+                _ => break,
+            }
+            end += 1;
+        }
+        while end > i && !bytes[end - 1].is_ascii_digit() {
+            end -= 1;
+        }
+        let before_word = end < bytes.len() && bytes[end].is_ascii_alphanumeric();
+        let min = if plus { 7 } else { 9 };
+        if (min..=15).contains(&digits) && !before_word {
+            spans.push((i, end));
+            i = end;
+        } else {
+            i = end.max(i + 1);
+        }
+    }
+    spans
+}
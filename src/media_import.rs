@@ -0,0 +1,156 @@
+use crate::outbound;
+use percent_encoding::percent_decode_str;
+use std::sync::OnceLock;
+use std::time::Duration;
+use url::Url;
+
+/// Largest file `POST /api/s3/import-url` stores.
+const MAX_IMPORT_BYTES: usize = 25 * 1024 * 1024;
+
+/// Content types the media library takes; `image/svg+xml` is left out since
+/// an SVG can carry scripts.
+fn is_media_type(content_type: &str) -> bool {
+    (content_type.starts_with("image/") && content_type != "image/svg+xml")
+        || content_type.starts_with("video/")
+        || content_type.starts_with("audio/")
+        || content_type == "application/pdf"
+}
+
+/// Why a URL was not imported; rendered as a 4xx response.
+#[derive(Debug)]
+pub enum Rejection {
+    InvalidUrl,
+    /// The host is, or resolves to, an address that is not public.
+    Blocked,
+    TooLarge,
+    UnsupportedType(String),
+    /// The remote server answered with this status.
+    Upstream(u16),
+}
+
+impl Rejection {
+    pub fn status(&self) -> u16 {
+        match self {
+            Rejection::InvalidUrl | Rejection::Blocked => 400,
+            Rejection::TooLarge => 413,
+            Rejection::UnsupportedType(_) => 415,
+            Rejection::Upstream(_) => 502,
+        }
+    }
+}
+
+impl std::fmt::Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rejection::InvalidUrl => write!(f, "url must be an http(s) URL"),
+            Rejection::Blocked => write!(f, "url must point at a public address"),
+            Rejection::TooLarge => write!(f, "file is larger than {MAX_IMPORT_BYTES} bytes"),
+            Rejection::UnsupportedType(t) => write!(f, "content type {t} is not a media type"),
+            Rejection::Upstream(status) => write!(f, "remote server responded {status}"),
+        }
+    }
+}
+
+fn client() -> Result<&'static reqwest::Client, Box<dyn std::error::Error + Send + Sync>> {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    outbound::cached(&CLIENT, Duration::from_secs(10), 3)
+}
+
+/// A file fetched for the media library.
+pub struct Fetched {
+    pub bytes: Vec<u8>,
+    pub content_type: String,
+    /// File name taken from the final URL, after redirects.
+    pub filename: String,
+}
+
+/// `name` reduced to characters safe in an S3 key and a download header.
+pub fn sanitize_filename(name: &str) -> String {
+    let cleaned: String = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' | '_' => c,
+            _ => '-',
+        })
+        .collect();
+    cleaned.trim_matches(|c| c == '.' || c == '-').to_string()
+}
+
+/// File name for a download from `url`: its last path segment, or `import`,
+/// with an extension from `content_type` when it has none.
+fn filename_for(url: &Url, content_type: &str) -> String {
+    let segment = url
+        .path_segments()
+        .and_then(|mut segments| segments.rfind(|s| !s.is_empty()))
+        .map(|s| percent_decode_str(s).decode_utf8_lossy().into_owned())
+        .unwrap_or_default();
+    let name = match sanitize_filename(&segment) {
+        name if name.is_empty() => "import".to_string(),
+        name => name,
+    };
+    if name.contains('.') {
+        return name;
+    }
+    let subtype = content_type.split('/').nth(1).unwrap_or_default();
+    match subtype {
+        "jpeg" => format!("{name}.jpg"),
+        "" => name,
+        subtype => format!("{name}.{subtype}"),
+    }
+}
+
+/// Fetches `url` server-side for the media library through the outbound
+/// client, accepting only media files of at most `MAX_IMPORT_BYTES`.
+pub async fn fetch(
+    url: &str,
+) -> Result<Result<Fetched, Rejection>, Box<dyn std::error::Error + Send + Sync>> {
+    let url = match Url::parse(url) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => url,
+        _ => return Ok(Err(Rejection::InvalidUrl)),
+    };
+    let response = match outbound::send(client()?.get(url)).await {
+        Ok(response) => response,
+        Err(e) if outbound::is_blocked(e.as_ref()) => {
+            tracing::warn!("import-url refused: {}", e);
+            return Ok(Err(Rejection::Blocked));
+        }
+        Err(e) => return Err(e),
+    };
+    if !response.status().is_success() {
+        return Ok(Err(Rejection::Upstream(response.status().as_u16())));
+    }
+
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|c| c.to_str().ok())
+        .map(|c| {
+            c.split(';')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_ascii_lowercase()
+        })
+        .unwrap_or_default();
+    if !is_media_type(&content_type) {
+        return Ok(Err(Rejection::UnsupportedType(content_type)));
+    }
+    if response
+        .content_length()
+        .is_some_and(|l| l > MAX_IMPORT_BYTES as u64)
+    {
+        return Ok(Err(Rejection::TooLarge));
+    }
+
+    let filename = filename_for(response.url(), &content_type);
+    let (bytes, truncated) = outbound::read_limited(response, MAX_IMPORT_BYTES).await?;
+    if truncated {
+        return Ok(Err(Rejection::TooLarge));
+    }
+
+    Ok(Ok(Fetched {
+        bytes,
+        content_type,
+        filename,
+    }))
+}
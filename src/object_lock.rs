@@ -0,0 +1,224 @@
+use crate::s3::s3_client;
+use aws_sdk_s3::error::ProvideErrorMetadata;
+use aws_sdk_s3::primitives::DateTime;
+use aws_sdk_s3::types::{
+    ObjectLockLegalHold, ObjectLockLegalHoldStatus, ObjectLockRetention, ObjectLockRetentionMode,
+};
+use serde::{Deserialize, Serialize};
+
+const DAY_MILLIS: u64 = 24 * 60 * 60 * 1000;
+
+/// Longest retention that can be set, in days.
+pub const MAX_RETENTION_DAYS: u64 = 100 * 365;
+
+/// S3 error codes that mean the request cannot be honoured for this bucket
+/// or object rather than that S3 failed, such as a bucket without Object
+/// Lock or a retention date S3 does not take.
+const REJECTED_CODES: [&str; 2] = ["InvalidRequest", "InvalidArgument"];
+
+/// Retention of an object version: it cannot be deleted or overwritten
+/// until `retain_until`. A `Governance` retention can be lifted by callers
+/// allowed `s3:BypassGovernanceRetention`; a `Compliance` one by no one,
+/// and it can only be extended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RetentionMode {
+    Governance,
+    Compliance,
+}
+
+impl RetentionMode {
+    fn to_s3(self) -> ObjectLockRetentionMode {
+        match self {
+            RetentionMode::Governance => ObjectLockRetentionMode::Governance,
+            RetentionMode::Compliance => ObjectLockRetentionMode::Compliance,
+        }
+    }
+
+    fn from_s3(mode: &ObjectLockRetentionMode) -> Option<RetentionMode> {
+        match mode {
+            ObjectLockRetentionMode::Governance => Some(RetentionMode::Governance),
+            ObjectLockRetentionMode::Compliance => Some(RetentionMode::Compliance),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Retention {
+    pub mode: RetentionMode,
+    /// Epoch milliseconds.
+    pub retain_until: u64,
+}
+
+/// Body of `PUT /api/files/{key}/retention`: the mode and either an
+/// absolute `retain_until` or a number of `days` from now.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct RetentionRequest {
+    pub mode: RetentionMode,
+    #[serde(default)]
+    pub retain_until: Option<u64>,
+    #[serde(default)]
+    pub days: Option<u64>,
+}
+
+impl RetentionRequest {
+    /// The retention asked for, as of `now`.
+    pub fn resolve(&self, now: u64) -> Result<Retention, String> {
+        let max = now + MAX_RETENTION_DAYS * DAY_MILLIS;
+        let retain_until = match (self.retain_until, self.days) {
+            (Some(until), None) => until,
+            (None, Some(days)) if (1..=MAX_RETENTION_DAYS).contains(&days) => {
+                now + days * DAY_MILLIS
+            }
+            (None, Some(_)) => {
+                return Err(format!("days must be 1-{MAX_RETENTION_DAYS}"));
+            }
+            _ => return Err("exactly one of retainUntil and days is required".to_string()),
+        };
+        if retain_until <= now || retain_until > max {
+            return Err(format!(
+                "retainUntil must be in the future and within {MAX_RETENTION_DAYS} days"
+            ));
+        }
+        Ok(Retention {
+            mode: self.mode,
+            retain_until,
+        })
+    }
+}
+
+/// Body of `PUT /api/files/{key}/legal-hold`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LegalHoldRequest {
+    pub enabled: bool,
+}
+
+/// Object Lock state of the current version of an object.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LockStatus {
+    /// A legal hold keeps the version until it is released, with no date.
+    pub legal_hold: bool,
+    pub retention: Option<Retention>,
+}
+
+type LockResult<T> = Result<Result<T, String>, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Splits an S3 error into a rejection the caller can act on, with S3's
+/// message, and any other failure.
+fn rejected<T, E>(e: aws_sdk_s3::error::SdkError<E>) -> LockResult<T>
+where
+    E: ProvideErrorMetadata + std::error::Error + Send + Sync + 'static,
+{
+    match e.as_service_error() {
+        Some(service) if REJECTED_CODES.contains(&service.code().unwrap_or_default()) => {
+            Ok(Err(service.message().unwrap_or("rejected by s3").to_string()))
+        }
+        _ => Err(e.into()),
+    }
+}
+
+/// Whether an error says the object has no lock of that kind.
+fn is_unset<E: ProvideErrorMetadata>(e: &aws_sdk_s3::error::SdkError<E>) -> bool {
+    e.as_service_error()
+        .is_some_and(|e| e.code() == Some("NoSuchObjectLockConfiguration"))
+}
+
+/// Legal hold and retention of `key`. Objects that were never locked have
+/// neither; a bucket without Object Lock is rejected.
+pub async fn status(bucket: &str, key: &str) -> LockResult<LockStatus> {
+    let client = s3_client().await;
+    let (hold, retention) = tokio::join!(
+        client.get_object_legal_hold().bucket(bucket).key(key).send(),
+        client.get_object_retention().bucket(bucket).key(key).send(),
+    );
+
+    let legal_hold = match hold {
+        Ok(output) => output
+            .legal_hold
+            .and_then(|hold| hold.status)
+            .is_some_and(|status| status == ObjectLockLegalHoldStatus::On),
+        Err(e) if is_unset(&e) => false,
+        Err(e) => return rejected(e),
+    };
+    let retention = match retention {
+        Ok(output) => output.retention.and_then(|retention| {
+            Some(Retention {
+                mode: RetentionMode::from_s3(retention.mode.as_ref()?)?,
+                retain_until: retention.retain_until_date?.to_millis().ok()?.max(0) as u64,
+            })
+        }),
+        Err(e) if is_unset(&e) => None,
+        Err(e) => return rejected(e),
+    };
+    Ok(Ok(LockStatus {
+        legal_hold,
+        retention,
+    }))
+}
+
+/// Places or releases a legal hold on `key`.
+pub async fn set_legal_hold(bucket: &str, key: &str, enabled: bool) -> LockResult<()> {
+    let status = match enabled {
+        true => ObjectLockLegalHoldStatus::On,
+        false => ObjectLockLegalHoldStatus::Off,
+    };
+    let sent = s3_client()
+        .await
+        .put_object_legal_hold()
+        .bucket(bucket)
+        .key(key)
+        .legal_hold(ObjectLockLegalHold::builder().status(status).build())
+        .send()
+        .await;
+    match sent {
+        Ok(_) => Ok(Ok(())),
+        Err(e) => rejected(e),
+    }
+}
+
+/// Sets the retention of `key`. S3 only lets a compliance retention be
+/// extended, and a governance one be shortened or turned into compliance.
+pub async fn set_retention(bucket: &str, key: &str, retention: &Retention) -> LockResult<()> {
+    let until = DateTime::from_millis(retention.retain_until as i64);
+    let sent = s3_client()
+        .await
+        .put_object_retention()
+        .bucket(bucket)
+        .key(key)
+        .retention(
+            ObjectLockRetention::builder()
+                .mode(retention.mode.to_s3())
+                .retain_until_date(until)
+                .build(),
+        )
+        .bypass_governance_retention(retention.mode == RetentionMode::Governance)
+        .send()
+        .await;
+    match sent {
+        Ok(_) => Ok(Ok(())),
+        Err(e) => rejected(e),
+    }
+}
+
+/// Lifts a governance retention from `key`, bypassing it. Compliance
+/// retentions cannot be lifted and are rejected by S3.
+pub async fn clear_retention(bucket: &str, key: &str) -> LockResult<()> {
+    let sent = s3_client()
+        .await
+        .put_object_retention()
+        .bucket(bucket)
+        .key(key)
+        .retention(ObjectLockRetention::builder().build())
+        .bypass_governance_retention(true)
+        .send()
+        .await;
+    match sent {
+        Ok(_) => Ok(Ok(())),
+        Err(e) => rejected(e),
+    }
+}
@@ -0,0 +1,244 @@
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::{RequestBuilder, Response};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use url::{Host, Url};
+
+/// Most redirects any outbound request follows.
+const MAX_REDIRECTS: usize = 5;
+
+/// Why an outbound request was not made: its URL, a redirect, or the
+/// address its host resolved to is not public.
+#[derive(Debug)]
+pub struct Blocked(String);
+
+impl std::fmt::Display for Blocked {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "blocked outbound request: {}", self.0)
+    }
+}
+
+impl std::error::Error for Blocked {}
+
+/// Whether `e`, or any error it wraps, is a `Blocked`.
+pub fn is_blocked(e: &(dyn std::error::Error + 'static)) -> bool {
+    let mut current = Some(e);
+    while let Some(e) = current {
+        if e.is::<Blocked>() {
+            return true;
+        }
+        current = e.source();
+    }
+    false
+}
+
+/// Whether `ip` is reachable on the public internet. Loopback, private,
+/// link-local (which holds the instance metadata endpoint), shared,
+/// reserved and multicast ranges are not.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => {
+            if let Some(v4) = ip.to_ipv4_mapped() {
+                return is_public_v4(v4);
+            }
+            let first = ip.segments()[0];
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // unique local fc00::/7 and link-local fe80::/10
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80
+                // NAT64 64:ff9b::/96 reaches IPv4 addresses
+                || ip.segments()[..6] == [0x64, 0xff9b, 0, 0, 0, 0]
+                // documentation 2001:db8::/32
+                || ip.segments()[..2] == [0x2001, 0xdb8])
+        }
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        || a == 0
+        // shared address space 100.64.0.0/10
+        || (a == 100 && (b & 0xc0) == 64)
+        // IETF protocol assignments 192.0.0.0/24
+        || (a == 192 && b == 0 && c == 0)
+        // benchmarking 198.18.0.0/15
+        || (a == 198 && (b & 0xfe) == 18)
+        // reserved 240.0.0.0/4
+        || a >= 240)
+}
+
+/// Resolves host names like the system resolver, but only to public
+/// addresses, so no name (or redirect to one) reaches an internal service.
+/// Checking here rather than before the request leaves no window for the
+/// name to resolve differently between the check and the connection.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .filter(|addr| is_public(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(Box::new(Blocked(format!("{host} has no public address"))) as _);
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Whether `url` may be requested: http(s), with a host that is a name or a
+/// public IP literal. Names are checked when they are resolved.
+pub fn is_allowed(url: &Url) -> bool {
+    if !matches!(url.scheme(), "http" | "https") {
+        return false;
+    }
+    match url.host() {
+        Some(Host::Domain(_)) => true,
+        Some(Host::Ipv4(ip)) => is_public(IpAddr::V4(ip)),
+        Some(Host::Ipv6(ip)) => is_public(IpAddr::V6(ip)),
+        None => false,
+    }
+}
+
+/// A client for requests to URLs that come from posts, payloads or remote
+/// documents. It only connects to public addresses, follows at most
+/// `max_redirects` (capped at 5) redirects, each checked like the first
+/// URL, and gives up after `timeout`. Callers keep one through `cached`
+/// and send through `send`. A client that cannot be built this way is an
+/// error: a stock one would have none of these guards.
+pub fn client(
+    timeout: Duration,
+    max_redirects: usize,
+) -> Result<reqwest::Client, Box<dyn std::error::Error + Send + Sync>> {
+    let max_redirects = max_redirects.min(MAX_REDIRECTS);
+    reqwest::Client::builder()
+        .timeout(timeout)
+        .dns_resolver(Arc::new(PublicResolver))
+        // a proxy would resolve names itself
+        .no_proxy()
+        .redirect(reqwest::redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() >= max_redirects {
+                attempt.error("too many redirects")
+            } else if !is_allowed(attempt.url()) {
+                let url = attempt.url().to_string();
+                attempt.error(Blocked(format!("redirect to {url}")))
+            } else {
+                attempt.follow()
+            }
+        }))
+        .build()
+        .map_err(|e| format!("outbound client: {e}").into())
+}
+
+/// The client in `cell`, built by `client` on first use. A failed build is
+/// returned rather than kept, so the next call tries again.
+pub fn cached(
+    cell: &'static OnceLock<reqwest::Client>,
+    timeout: Duration,
+    max_redirects: usize,
+) -> Result<&'static reqwest::Client, Box<dyn std::error::Error + Send + Sync>> {
+    if let Some(client) = cell.get() {
+        return Ok(client);
+    }
+    let client = client(timeout, max_redirects)?;
+    Ok(cell.get_or_init(|| client))
+}
+
+/// Sends `request`, built on a `client`, unless its URL is not allowed.
+/// Whether a failure was a refusal tells `is_blocked`.
+pub async fn send(
+    request: RequestBuilder,
+) -> Result<Response, Box<dyn std::error::Error + Send + Sync>> {
+    let (client, request) = request.build_split();
+    let request = request?;
+    if !is_allowed(request.url()) {
+        return Err(Box::new(Blocked(request.url().to_string())));
+    }
+    Ok(client.execute(request).await?)
+}
+
+/// The body of `response`, read up to `max_bytes`. The flag says whether
+/// there was more; the rest is not downloaded.
+pub async fn read_limited(
+    mut response: Response,
+    max_bytes: usize,
+) -> Result<(Vec<u8>, bool), Box<dyn std::error::Error + Send + Sync>> {
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        body.extend_from_slice(&chunk);
+        if body.len() > max_bytes {
+            body.truncate(max_bytes);
+            return Ok((body, true));
+        }
+    }
+    Ok((body, false))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn allowed(url: &str) -> bool {
+        is_allowed(&Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn internal_addresses_are_not_public() {
+        for addr in [
+            "127.0.0.1",
+            "169.254.169.254",
+            "10.0.0.1",
+            "172.16.0.1",
+            "192.168.1.1",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "::ffff:10.0.0.1",
+            "::ffff:127.0.0.1",
+            "64:ff9b::a00:1",
+            "fc00::1",
+            "fd12:3456::1",
+            "fe80::1",
+        ] {
+            assert!(!is_public(ip(addr)), "{addr}");
+        }
+    }
+
+    #[test]
+    fn public_addresses_are_public() {
+        for addr in ["93.184.216.34", "1.1.1.1", "::ffff:1.1.1.1", "2606:4700::1111"] {
+            assert!(is_public(ip(addr)), "{addr}");
+        }
+    }
+
+    #[test]
+    fn only_http_urls_to_names_or_public_ips_are_allowed() {
+        assert!(allowed("https://example.com/post"));
+        assert!(allowed("http://93.184.216.34/"));
+        assert!(!allowed("http://127.0.0.1/"));
+        assert!(!allowed("http://169.254.169.254/latest/meta-data/"));
+        assert!(!allowed("http://[::ffff:10.0.0.1]/"));
+        assert!(!allowed("http://[64:ff9b::a00:1]/"));
+        assert!(!allowed("http://[fd00::1]/"));
+        assert!(!allowed("file:///etc/passwd"));
+        assert!(!allowed("ftp://example.com/"));
+    }
+}
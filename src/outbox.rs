@@ -0,0 +1,163 @@
+use crate::clock::now_millis;
+use crate::dynamodb::{
+    dynamodb_client, generate_idx, query_records, schema, update_record, TABLE_NAME,
+};
+use crate::stage::Stage;
+use crate::{init, snapshots};
+use aws_sdk_dynamodb::types::{AttributeValue, Put, TransactWriteItem};
+use aws_sdk_sns::types::MessageAttributeValue;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+
+/// Domain events waiting to be published, one item per event. `idx` is a
+/// ULID, so the partition reads back in the order the changes were made.
+pub const OUTBOX_PARTITION: &str = "outbox";
+
+/// Published events are kept this long for inspection, then expire.
+const SENT_RETENTION_SECS: u64 = 7 * 24 * 3600;
+
+/// Events published per sweep; the rest wait for the next one.
+const SWEEP_BATCH: usize = 500;
+
+/// SNS topic events are published to, from `outbox_topic_arn`.
+pub fn topic_arn() -> Option<String> {
+    std::env::var("outbox_topic_arn").ok().filter(|t| !t.is_empty())
+}
+
+/// The outbox write for an event of `kind`, to be committed in the same
+/// transaction as the change it describes.
+pub fn event(
+    kind: &str,
+    payload: Value,
+) -> Result<TransactWriteItem, Box<dyn std::error::Error + Send + Sync>> {
+    let schema = schema();
+    let put = Put::builder()
+        .table_name(TABLE_NAME)
+        .item(&schema.partition_key, AttributeValue::S(OUTBOX_PARTITION.to_string()))
+        .item(&schema.sort_key, AttributeValue::S(generate_idx()))
+        .item("type", AttributeValue::S(kind.to_string()))
+        .item("payload", AttributeValue::S(payload.to_string()))
+        .item("status", AttributeValue::S("pending".to_string()))
+        .item("created_at", AttributeValue::N(now_millis().to_string()))
+        .build()?;
+    Ok(TransactWriteItem::builder().put(put).build())
+}
+
+/// Records an event of `kind` on its own, for events that do not go with
+/// a change to another item.
+pub async fn record(
+    kind: &str,
+    payload: Value,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    dynamodb_client()
+        .await
+        .transact_write_items()
+        .transact_items(event(kind, payload)?)
+        .send()
+        .await?;
+    Ok(())
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct SweepReport {
+    pub published: usize,
+    /// Events left for the next sweep: beyond the batch, or behind a failure.
+    pub pending: usize,
+    /// Stages whose feed and sitemap snapshots were rebuilt because posts
+    /// in them changed.
+    pub snapshots: Vec<&'static str>,
+}
+
+/// Publishes pending events to `outbox_topic_arn` in the order they were
+/// written and marks each one sent. A failed publish ends the sweep, so
+/// consumers never see an event before an earlier one. An event published
+/// but not marked sent is published again next time: delivery is at least
+/// once, and consumers dedupe on the event `id`. Once published, post
+/// events have the snapshots of their stage rebuilt.
+pub async fn sweep() -> Result<SweepReport, Box<dyn std::error::Error + Send + Sync>> {
+    let topic = topic_arn().ok_or("outbox_topic_arn is not set")?;
+    let pending = query_records(
+        OUTBOX_PARTITION.to_string(),
+        None,
+        vec![("status".to_string(), AttributeValue::S("pending".to_string()))],
+        usize::MAX,
+        false,
+    )
+    .await?;
+
+    let config = init::sdk_config().await;
+    let sns = aws_sdk_sns::Client::new(config);
+    let schema = schema();
+    let mut report = SweepReport {
+        pending: pending.len(),
+        ..Default::default()
+    };
+    let mut changed = HashSet::new();
+    let live = Stage::live().await?;
+
+    for record in pending.iter().take(SWEEP_BATCH) {
+        let text = |name: &str| match record.get(name) {
+            Some(AttributeValue::S(s)) => s.clone(),
+            _ => String::new(),
+        };
+        let id = text(&schema.sort_key);
+        let kind = text("type");
+        let payload = serde_json::from_str::<Value>(&text("payload")).unwrap_or_default();
+        let message = json!({
+            "id": id,
+            "type": kind,
+            "payload": payload,
+            "createdAt": match record.get("created_at") {
+                Some(AttributeValue::N(n)) => n.parse::<u64>().unwrap_or_default(),
+                _ => 0,
+            },
+        });
+
+        // `type` as an attribute lets SQS subscriptions filter by event
+        let mut publish = sns
+            .publish()
+            .topic_arn(&topic)
+            .message(message.to_string())
+            .message_attributes(
+                "type",
+                MessageAttributeValue::builder()
+                    .data_type("String")
+                    .string_value(&kind)
+                    .build()?,
+            );
+        if topic.ends_with(".fifo") {
+            publish = publish.message_group_id("outbox").message_deduplication_id(&id);
+        }
+        if let Err(e) = publish.send().await {
+            tracing::error!("outbox publish of {} failed: {:?}", id, e);
+            break;
+        }
+
+        let sent_at = now_millis();
+        let attributes = HashMap::from([
+            ("status".to_string(), AttributeValue::S("sent".to_string())),
+            ("sent_at".to_string(), AttributeValue::N(sent_at.to_string())),
+            (
+                "ttl".to_string(),
+                AttributeValue::N((sent_at / 1000 + SENT_RETENTION_SECS).to_string()),
+            ),
+        ]);
+        update_record(OUTBOX_PARTITION.to_string(), id, attributes).await?;
+        report.published += 1;
+        report.pending -= 1;
+        if kind.starts_with("post.") {
+            let part = payload["part"].as_str().unwrap_or_default();
+            changed.extend(snapshots::stage_of(part, live));
+        }
+    }
+
+    for stage in changed {
+        match snapshots::refresh(stage).await {
+            Ok(true) => report.snapshots.push(stage.as_str()),
+            Ok(false) => {}
+            Err(e) => tracing::error!("snapshots of {} failed: {:?}", stage.as_str(), e),
+        }
+    }
+    Ok(report)
+}
@@ -0,0 +1,110 @@
+use crate::dynamodb::{generate_idx, schema};
+use crate::failover;
+use crate::s3::{delete_objects, get_text_object, put_text_object};
+use aws_sdk_dynamodb::types::AttributeValue;
+use std::collections::HashMap;
+
+/// S3 key of a value that was too large to keep on its item. The item's
+/// value attribute is left empty while this is set.
+pub const VALUE_REF_ATTRIBUTE: &str = "value_ref";
+
+/// Leaves headroom under DynamoDB's 400 KB item limit for the other
+/// attributes (excerpt, timestamps, counters).
+const DEFAULT_THRESHOLD_BYTES: usize = 300 * 1024;
+
+pub fn threshold() -> usize {
+    std::env::var("overflow_threshold_bytes")
+        .ok()
+        .and_then(|t| t.parse().ok())
+        .unwrap_or(DEFAULT_THRESHOLD_BYTES)
+}
+
+fn bucket() -> Option<String> {
+    failover::s3_bucket()
+}
+
+/// What to store for `value` of item (`part`, `idx`): the value itself, or,
+/// past `overflow_threshold_bytes`, an empty value plus the key it was
+/// spilled to. Every spill gets a key of its own,
+/// `overflow/{part}/{idx}/{ulid}`, so the body a write replaces can be
+/// deleted once the write is known to have gone through (see `settle`).
+/// Without `s3_bucket` the value is kept inline and the write fails as
+/// before.
+pub async fn spill(
+    part: &str,
+    idx: &str,
+    value: String,
+) -> Result<(String, Option<String>), Box<dyn std::error::Error + Send + Sync>> {
+    let Some(bucket) = bucket().filter(|_| value.len() > threshold()) else {
+        return Ok((value, None));
+    };
+
+    let key = format!("overflow/{part}/{idx}/{}", generate_idx());
+    put_text_object(&bucket, key.clone(), value).await?;
+    Ok((String::new(), Some(key)))
+}
+
+/// Deletes the body that lost a write which spilled to `new` over an item
+/// that pointed at `old`: `old` once the write went through, `new` when it
+/// did not. Failed deletes are only logged, leaving the body behind.
+pub async fn settle(written: bool, new: Option<&str>, old: Option<&str>) {
+    let loser = match written {
+        true => old.filter(|old| Some(*old) != new),
+        false => new,
+    };
+    if let Some(key) = loser {
+        discard(vec![key.to_string()]).await;
+    }
+}
+
+/// Deletes spilled bodies no item points at any more.
+pub async fn discard(keys: Vec<String>) {
+    let Some(bucket) = bucket().filter(|_| !keys.is_empty()) else {
+        return;
+    };
+    if let Err(e) = delete_objects(&bucket, keys).await {
+        tracing::warn!("overflow delete error: {:?}", e);
+    }
+}
+
+/// A copy of the body at `key` for item (`part`, `idx`), for items copied
+/// to another partition, which must not share a body the original may
+/// delete.
+pub async fn copy(
+    key: &str,
+    part: &str,
+    idx: &str,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(bucket) = bucket() else {
+        return Err("item value is stored in S3 but s3_bucket is not set".into());
+    };
+    let value = get_text_object(&bucket, key.to_string()).await?;
+    let copy = format!("overflow/{part}/{idx}/{}", generate_idx());
+    put_text_object(&bucket, copy.clone(), value).await?;
+    Ok(copy)
+}
+
+/// The key a record's value was spilled to, if it was.
+pub fn value_ref(record: &HashMap<String, AttributeValue>) -> Option<&str> {
+    match record.get(VALUE_REF_ATTRIBUTE) {
+        Some(AttributeValue::S(key)) => Some(key),
+        _ => None,
+    }
+}
+
+/// Puts a spilled value back on a record read from the table, so callers
+/// see the item as if it had been stored inline.
+pub async fn restore(
+    record: &mut HashMap<String, AttributeValue>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let Some(key) = value_ref(record).map(str::to_string) else {
+        return Ok(());
+    };
+    let Some(bucket) = bucket() else {
+        return Err("item value is stored in S3 but s3_bucket is not set".into());
+    };
+
+    let value = get_text_object(&bucket, key).await?;
+    record.insert(schema().value_attribute.clone(), AttributeValue::S(value));
+    Ok(())
+}
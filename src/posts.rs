@@ -0,0 +1,604 @@
+use crate::clock::{now_millis, utc_date};
+use crate::compression::{self, COMPRESSED_ATTRIBUTE};
+use crate::counters;
+use crate::dynamodb::{
+    dynamodb_client, get_record, increment_counter, query_index, query_records, record_to_json,
+    schema, BumpsVersion, Condition, Precondition, CREATED_AT_ATTRIBUTE, PINNED_ATTRIBUTE,
+    SORT_WEIGHT_ATTRIBUTE, TABLE_NAME, UPDATED_AT_ATTRIBUTE, VERSION_ATTRIBUTE, VERSION_BUMP,
+};
+use crate::excerpt::excerpt;
+use crate::outbox;
+use crate::overflow::{self, VALUE_REF_ATTRIBUTE};
+use crate::slugs::{slug_of, slug_put};
+use crate::sync;
+use crate::tags;
+use aws_sdk_dynamodb::operation::transact_write_items::TransactWriteItemsError;
+use aws_sdk_dynamodb::operation::update_item::UpdateItemError;
+use aws_sdk_dynamodb::types::{
+    AttributeValue, Delete, ReturnValue, TransactWriteItem, Update,
+};
+use serde_json::{json, Map, Value};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/// GSI (partition key + `created_at`) backing `sort=created_at`.
+pub const CREATED_AT_INDEX: &str = "part-created_at-index";
+/// GSI (partition key + `updated_at`) backing `/sync`. Tombstones of deleted
+/// posts are in it too, under their own partition.
+pub const UPDATED_AT_INDEX: &str = "part-updated_at-index";
+/// GSI (partition key + `views`) backing `sort=views`. Sparse: posts that
+/// were never viewed are not in it.
+pub const VIEWS_INDEX: &str = "part-views-index";
+
+pub const VIEWS_ATTRIBUTE: &str = "views";
+
+/// GSIs the posts routes query, as `(index name, numeric sort key)`.
+pub const REQUIRED_INDEXES: [(&str, &str); 3] = [
+    (CREATED_AT_INDEX, CREATED_AT_ATTRIBUTE),
+    (VIEWS_INDEX, VIEWS_ATTRIBUTE),
+    (UPDATED_AT_INDEX, UPDATED_AT_ATTRIBUTE),
+];
+
+const EXCERPT_ATTRIBUTE: &str = "excerpt";
+const DEFAULT_EXCERPT_SENTENCES: usize = 2;
+
+/// Site-wide views per UTC day; `idx` is the date.
+pub const DAILY_VIEWS_PARTITION: &str = "daily_views";
+const COUNT_ATTRIBUTE: &str = "count";
+
+/// Partition holding the blog's posts, from `posts_part` (default `post`).
+pub fn posts_part() -> String {
+    std::env::var("posts_part")
+        .ok()
+        .filter(|p| !p.is_empty())
+        .unwrap_or_else(|| "post".to_string())
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum PostSort {
+    CreatedAt,
+    Views,
+}
+
+impl PostSort {
+    pub fn parse(value: &str) -> Option<PostSort> {
+        match value {
+            "created_at" => Some(PostSort::CreatedAt),
+            "views" => Some(PostSort::Views),
+            _ => None,
+        }
+    }
+
+    fn index(&self) -> &'static str {
+        match self {
+            PostSort::CreatedAt => CREATED_AT_INDEX,
+            PostSort::Views => VIEWS_INDEX,
+        }
+    }
+
+    fn attribute(&self) -> &'static str {
+        match self {
+            PostSort::CreatedAt => CREATED_AT_ATTRIBUTE,
+            PostSort::Views => VIEWS_ATTRIBUTE,
+        }
+    }
+}
+
+/// The slug is held by a different post.
+#[derive(Debug)]
+pub struct SlugConflict;
+
+/// Excerpt for a post value: its own `excerpt` field if set, else the first
+/// `excerpt_sentences` (default 2) sentences of `body` (or `content`) with
+/// markdown stripped. Non-JSON values are treated as the body itself.
+fn post_excerpt(value: &str) -> String {
+    let sentences = std::env::var("excerpt_sentences")
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(DEFAULT_EXCERPT_SENTENCES);
+
+    if let Ok(Value::Object(fields)) = serde_json::from_str::<Value>(value) {
+        if let Some(own) = fields.get(EXCERPT_ATTRIBUTE).and_then(|e| e.as_str()) {
+            return own.to_string();
+        }
+    }
+    excerpt(&post_body(value), sentences)
+}
+
+/// Markdown of a post value: `body` (or `content`) of a JSON object, else
+/// the value itself.
+pub fn post_body(value: &str) -> String {
+    match serde_json::from_str::<Value>(value) {
+        Ok(Value::Object(fields)) => fields
+            .get("body")
+            .or_else(|| fields.get("content"))
+            .and_then(|b| b.as_str())
+            .unwrap_or_default()
+            .to_string(),
+        _ => value.to_string(),
+    }
+}
+
+/// Flattens a post item into one JSON object: bookkeeping attributes plus,
+/// when the stored value is a JSON object, its fields; otherwise `value`.
+pub fn post_to_json(record: &std::collections::HashMap<String, AttributeValue>) -> Value {
+    let schema = schema();
+    let raw = record_to_json(record);
+
+    let mut post = Map::new();
+    post.insert("idx".to_string(), raw[&schema.sort_key].clone());
+    for name in [
+        CREATED_AT_ATTRIBUTE,
+        UPDATED_AT_ATTRIBUTE,
+        VERSION_ATTRIBUTE,
+        VIEWS_ATTRIBUTE,
+        EXCERPT_ATTRIBUTE,
+        "pinned",
+    ] {
+        if let Some(value) = raw.get(name) {
+            post.insert(name.to_string(), value.clone());
+        }
+    }
+
+    let value = raw[&schema.value_attribute].as_str().unwrap_or_default();
+    match serde_json::from_str::<Value>(value) {
+        Ok(Value::Object(fields)) => post.extend(fields),
+        _ => {
+            post.insert("value".to_string(), Value::String(value.to_string()));
+        }
+    }
+    Value::Object(post)
+}
+
+/// The condition that the post is still as `record` read it (`None` for no
+/// post), for writes that update the tag index from the tags they replace.
+/// With `precondition`, both must hold.
+fn unchanged_since(
+    record: Option<&std::collections::HashMap<String, AttributeValue>>,
+    precondition: Option<&Precondition>,
+) -> Condition {
+    let (mut expression, mut values) = match record.and_then(|r| r.get(VERSION_ATTRIBUTE)) {
+        Some(AttributeValue::N(version)) => (
+            "#version = :read_version".to_string(),
+            vec![(":read_version".to_string(), AttributeValue::N(version.clone()))],
+        ),
+        _ => ("attribute_not_exists(#version)".to_string(), Vec::new()),
+    };
+    let mut names = vec![("#version".to_string(), VERSION_ATTRIBUTE.to_string())];
+    if let Some(precondition) = precondition {
+        let condition = precondition.condition();
+        expression = format!("({}) AND ({expression})", condition.expression);
+        names.extend(condition.names);
+        values.extend(condition.values);
+    }
+    Condition {
+        expression,
+        names,
+        values,
+    }
+}
+
+/// Saves a post and keeps derived data in step, all in one transaction: the
+/// `excerpt` attribute is regenerated from the body unless the post sets its
+/// own, the new slug is claimed for the post, a changed slug is left behind
+/// as a redirect, the tag index is updated and a `post.saved` event goes to
+/// the outbox. Fails with `SlugConflict` when another post owns the slug,
+/// and with an `ErrorKind::Conflict` error when the post does not meet
+/// `precondition` or changed since it was read here.
+pub async fn save_post(
+    posts_part: String,
+    slugs_part: String,
+    idx: String,
+    value: String,
+    precondition: Option<&Precondition>,
+) -> Result<Result<(), SlugConflict>, Box<dyn std::error::Error + Send + Sync>> {
+    let client = dynamodb_client().await;
+    let schema = schema();
+
+    let new_slug = slug_of(&value);
+    let old = get_record(posts_part.clone(), idx.clone()).await?;
+    let old_value = match old.as_ref().and_then(|r| r.get(&schema.value_attribute)) {
+        Some(AttributeValue::S(old)) => Some(old.as_str()),
+        _ => None,
+    };
+    let old_slug = old_value.and_then(slug_of);
+    let deltas = tags::count_deltas(old_value, Some(&value));
+    let tag_counts = tags::count_updates(&posts_part, &deltas)?;
+
+    let excerpt = post_excerpt(&value);
+    let (value, value_ref) = compression::store(&posts_part, &idx, value).await?;
+    let old_ref = old.as_ref().and_then(overflow::value_ref);
+    let compressed = matches!(value, AttributeValue::B(_));
+    let event = outbox::event(
+        "post.saved",
+        json!({ "part": posts_part, "idx": idx, "slug": new_slug }),
+    )?;
+    let mut assignments = vec![
+        "#value = :value",
+        "#excerpt = :excerpt",
+        "#created = if_not_exists(#created, :now)",
+        "#updated = :now",
+        VERSION_BUMP,
+    ];
+    let mut removals = Vec::new();
+    let mut post_update = Update::builder()
+        .table_name(TABLE_NAME)
+        .key(&schema.partition_key, AttributeValue::S(posts_part))
+        .key(&schema.sort_key, AttributeValue::S(idx.clone()))
+        .expression_attribute_names("#value", &schema.value_attribute)
+        .expression_attribute_names("#excerpt", EXCERPT_ATTRIBUTE)
+        .expression_attribute_names("#created", CREATED_AT_ATTRIBUTE)
+        .expression_attribute_names("#updated", UPDATED_AT_ATTRIBUTE)
+        .expression_attribute_names("#ref", VALUE_REF_ATTRIBUTE)
+        .expression_attribute_names("#compressed", COMPRESSED_ATTRIBUTE)
+        .expression_attribute_values(":value", value)
+        .expression_attribute_values(":excerpt", AttributeValue::S(excerpt))
+        .expression_attribute_values(":now", AttributeValue::N(now_millis().to_string()))
+        .bump_version();
+    match &value_ref {
+        Some(key) => {
+            assignments.push("#ref = :ref");
+            let key = AttributeValue::S(key.clone());
+            post_update = post_update.expression_attribute_values(":ref", key);
+        }
+        None => removals.push("#ref"),
+    }
+    if compressed {
+        assignments.push("#compressed = :compressed");
+        post_update =
+            post_update.expression_attribute_values(":compressed", AttributeValue::Bool(true));
+    } else {
+        removals.push("#compressed");
+    }
+    let condition = unchanged_since(old.as_ref(), precondition);
+    post_update = post_update.condition_expression(condition.expression);
+    for (name, attribute) in condition.names {
+        post_update = post_update.expression_attribute_names(name, attribute);
+    }
+    for (name, value) in condition.values {
+        post_update = post_update.expression_attribute_values(name, value);
+    }
+    let post_update = post_update
+        .update_expression(format!(
+            "SET {} REMOVE {}",
+            assignments.join(", "),
+            removals.join(", ")
+        ))
+        .build()?;
+
+    let mut writes = vec![TransactWriteItem::builder().update(post_update).build(), event];
+    if let Some(slug) = &new_slug {
+        writes.push(slug_put(&slugs_part, slug.clone(), &idx, false)?);
+    }
+    if let Some(old) = old_slug.filter(|old| Some(old) != new_slug.as_ref()) {
+        writes.push(slug_put(&slugs_part, old, &idx, true)?);
+    }
+    writes.extend(tag_counts);
+
+    let result = client
+        .transact_write_items()
+        .set_transact_items(Some(writes))
+        .send()
+        .await;
+    overflow::settle(result.is_ok(), value_ref.as_deref(), old_ref).await;
+
+    match result {
+        Ok(_) => Ok(Ok(())),
+        Err(e) => {
+            // the post's own update only carries the precondition; the
+            // slug claims follow the update and the event
+            let conflict = match e.as_service_error() {
+                Some(TransactWriteItemsError::TransactionCanceledException(cancelled)) => cancelled
+                    .cancellation_reasons()
+                    .iter()
+                    .skip(2)
+                    .any(|r| r.code() == Some("ConditionalCheckFailed")),
+                _ => false,
+            };
+            if conflict {
+                Ok(Err(SlugConflict))
+            } else {
+                Err(e.into())
+            }
+        }
+    }
+}
+
+/// Deletes a post and records a `post.deleted` event and a sync tombstone in
+/// the same transaction, which also takes the post out of the tag index.
+/// Its slugs stay behind, like the redirects of renamed posts. A post that
+/// does not meet `precondition`, or changed since it was read here, is
+/// kept, with an `ErrorKind::Conflict` error.
+pub async fn delete_post(
+    part: String,
+    idx: String,
+    precondition: Option<&Precondition>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let client = dynamodb_client().await;
+    let schema = schema();
+
+    let old = get_record(part.clone(), idx.clone()).await?;
+    let old_value = match old.as_ref().and_then(|r| r.get(&schema.value_attribute)) {
+        Some(AttributeValue::S(old)) => Some(old.as_str()),
+        _ => None,
+    };
+    let tag_counts = tags::count_updates(&part, &tags::count_deltas(old_value, None))?;
+
+    let mut delete = Delete::builder()
+        .table_name(TABLE_NAME)
+        .key(&schema.partition_key, AttributeValue::S(part.clone()))
+        .key(&schema.sort_key, AttributeValue::S(idx.clone()));
+    let condition = unchanged_since(old.as_ref(), precondition);
+    delete = delete.condition_expression(condition.expression);
+    for (name, attribute) in condition.names {
+        delete = delete.expression_attribute_names(name, attribute);
+    }
+    for (name, value) in condition.values {
+        delete = delete.expression_attribute_values(name, value);
+    }
+    let delete = delete.build()?;
+    let tombstone = sync::tombstone(&part, &idx)?;
+    let event = outbox::event("post.deleted", json!({ "part": part, "idx": idx }))?;
+
+    let mut writes = vec![TransactWriteItem::builder().delete(delete).build(), tombstone, event];
+    writes.extend(tag_counts);
+    client
+        .transact_write_items()
+        .set_transact_items(Some(writes))
+        .send()
+        .await?;
+    if let Some(key) = old.as_ref().and_then(overflow::value_ref) {
+        overflow::discard(vec![key.to_string()]).await;
+    }
+
+    Ok(())
+}
+
+/// Keeps only the requested fields; `idx` is always kept.
+pub fn select_fields(post: Value, fields: &[String]) -> Value {
+    match post {
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .filter(|(k, _)| k == "idx" || fields.iter().any(|f| f == k))
+                .collect(),
+        ),
+        other => other,
+    }
+}
+
+/// Lists posts ordered by the sort's index, optionally trimmed to `fields`.
+pub async fn list_posts(
+    part: String,
+    sort: PostSort,
+    descending: bool,
+    limit: usize,
+    fields: Option<Vec<String>>,
+) -> Result<Vec<Value>, Box<dyn std::error::Error + Send + Sync>> {
+    let records = query_index(sort.index(), part, !descending, limit).await?;
+
+    Ok(records
+        .iter()
+        .map(post_to_json)
+        .map(|post| match &fields {
+            Some(fields) => select_fields(post, fields),
+            None => post,
+        })
+        .collect())
+}
+
+/// `list_posts` with the pinned posts ahead of the others, in the order
+/// `POST /dynamodb/items/order` gave them (posts without a `sort_weight`
+/// after those with one), then sorted like the rest. Pinned posts count
+/// towards `limit`.
+pub async fn list_posts_pinned_first(
+    part: String,
+    sort: PostSort,
+    descending: bool,
+    limit: usize,
+    fields: Option<Vec<String>>,
+) -> Result<Vec<Value>, Box<dyn std::error::Error + Send + Sync>> {
+    let pinned_filter = vec![(PINNED_ATTRIBUTE.to_string(), AttributeValue::Bool(true))];
+    let mut pinned = query_records(part.clone(), None, pinned_filter, usize::MAX, false).await?;
+    let number = |record: &HashMap<String, AttributeValue>, name: &str| match record.get(name) {
+        Some(AttributeValue::N(n)) => n.parse::<i64>().ok(),
+        _ => None,
+    };
+    pinned.sort_by(|a, b| {
+        let weights = (number(a, SORT_WEIGHT_ATTRIBUTE), number(b, SORT_WEIGHT_ATTRIBUTE));
+        let weighted = match weights {
+            (Some(x), Some(y)) => x.cmp(&y),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        };
+        let sorted = number(a, sort.attribute()).cmp(&number(b, sort.attribute()));
+        weighted.then(if descending { sorted.reverse() } else { sorted })
+    });
+
+    let mut posts: Vec<Value> = pinned.iter().take(limit).map(post_to_json).collect();
+    let pinned_idxs: Vec<Value> = posts.iter().map(|post| post["idx"].clone()).collect();
+    let rest = list_posts(part, sort, descending, limit + pinned_idxs.len(), None).await?;
+    posts.extend(rest.into_iter().filter(|post| !pinned_idxs.contains(&post["idx"])));
+    posts.truncate(limit);
+
+    Ok(match &fields {
+        Some(fields) => posts.into_iter().map(|p| select_fields(p, fields)).collect(),
+        None => posts,
+    })
+}
+
+/// Name of the sharded counter holding the views of post `idx` in `part`
+/// that were not yet moved onto the post.
+fn views_counter(part: &str, idx: &str) -> String {
+    format!("views#{part}#{idx}")
+}
+
+fn daily_views_counter(day: &str) -> String {
+    format!("{DAILY_VIEWS_PARTITION}#{day}")
+}
+
+/// The `views` stored on post `idx`, `None` when there is no such post.
+pub async fn stored_views(
+    part: String,
+    idx: String,
+) -> Result<Option<i64>, Box<dyn std::error::Error + Send + Sync>> {
+    let client = dynamodb_client().await;
+    let schema = schema();
+
+    let output = client
+        .get_item()
+        .table_name(TABLE_NAME)
+        .key(&schema.partition_key, AttributeValue::S(part))
+        .key(&schema.sort_key, AttributeValue::S(idx))
+        .projection_expression("#pk, #views")
+        .expression_attribute_names("#pk", &schema.partition_key)
+        .expression_attribute_names("#views", VIEWS_ATTRIBUTE)
+        .send()
+        .await?;
+
+    let Some(item) = output.item else {
+        return Ok(None);
+    };
+    Ok(Some(match item.get(VIEWS_ATTRIBUTE) {
+        Some(AttributeValue::N(n)) => n.parse()?,
+        _ => 0,
+    }))
+}
+
+/// Counts a view of post `idx` and returns its new view count, `None` when
+/// there is no such post.
+///
+/// With a single counter shard (see `counters::shard_count`) views are added
+/// to the post itself. With more, they land on a random shard, which is
+/// moved onto the post once it holds as many views as there are shards, so
+/// a popular post is written `shards` times less often. The count returned
+/// is then the post's views plus its shards', and `sort=views` lags by up to
+/// that many views per shard.
+pub async fn record_view(
+    part: String,
+    idx: String,
+) -> Result<Option<i64>, Box<dyn std::error::Error + Send + Sync>> {
+    let shards = counters::shard_count();
+    if shards == 1 {
+        return record_view_unsharded(part, idx).await;
+    }
+
+    let Some(stored) = stored_views(part.clone(), idx.clone()).await? else {
+        return Ok(None);
+    };
+    let counter = views_counter(&part, &idx);
+    let day = daily_views_counter(&utc_date(now_millis()));
+    let (added, daily) = tokio::join!(counters::add(counter.clone()), counters::add(day));
+    let (shard, count) = added?;
+    daily?;
+
+    // views moved onto the post after `stored` was read
+    let mut moved = 0;
+    if count >= shards as i64 {
+        let client = dynamodb_client().await;
+        let schema = schema();
+        let post = Update::builder()
+            .table_name(TABLE_NAME)
+            .key(&schema.partition_key, AttributeValue::S(part))
+            .key(&schema.sort_key, AttributeValue::S(idx))
+            .update_expression("ADD #views :count")
+            .condition_expression("attribute_exists(#pk)")
+            .expression_attribute_names("#views", VIEWS_ATTRIBUTE)
+            .expression_attribute_names("#pk", &schema.partition_key)
+            .expression_attribute_values(":count", AttributeValue::N(count.to_string()))
+            .build()?;
+        let result = client
+            .transact_write_items()
+            .transact_items(counters::drain(&counter, shard, count)?)
+            .transact_items(TransactWriteItem::builder().update(post).build())
+            .send()
+            .await;
+        match result {
+            Ok(_) => moved = count,
+            Err(e) => match e.as_service_error() {
+                // moved by a concurrent view, or the post is gone: the shard keeps it
+                Some(TransactWriteItemsError::TransactionCanceledException(_)) => {}
+                _ => return Err(e.into()),
+            },
+        }
+    }
+
+    let totals = counters::totals(std::slice::from_ref(&counter)).await?;
+    let sharded = totals.get(&counter).copied().unwrap_or(0);
+    Ok(Some(stored + moved + sharded))
+}
+
+async fn record_view_unsharded(
+    part: String,
+    idx: String,
+) -> Result<Option<i64>, Box<dyn std::error::Error + Send + Sync>> {
+    let client = dynamodb_client().await;
+    let schema = schema();
+
+    let result = client
+        .update_item()
+        .table_name(TABLE_NAME)
+        .key(&schema.partition_key, AttributeValue::S(part))
+        .key(&schema.sort_key, AttributeValue::S(idx))
+        .update_expression("ADD #views :one")
+        .condition_expression("attribute_exists(#pk)")
+        .expression_attribute_names("#views", VIEWS_ATTRIBUTE)
+        .expression_attribute_names("#pk", &schema.partition_key)
+        .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
+        .return_values(ReturnValue::UpdatedNew)
+        .send()
+        .await;
+    let output = match result {
+        Ok(output) => output,
+        Err(e) => {
+            return match e.as_service_error() {
+                Some(UpdateItemError::ConditionalCheckFailedException(_)) => Ok(None),
+                _ => Err(e.into()),
+            }
+        }
+    };
+
+    let views = match output.attributes.as_ref().and_then(|a| a.get(VIEWS_ATTRIBUTE)) {
+        Some(AttributeValue::N(n)) => n.parse()?,
+        _ => 0,
+    };
+
+    let day = utc_date(now_millis());
+    increment_counter(DAILY_VIEWS_PARTITION.to_string(), day, COUNT_ATTRIBUTE, 1).await?;
+
+    Ok(Some(views))
+}
+
+/// Site-wide view counts for the given days, in the same order.
+pub async fn daily_views(
+    days: &[String],
+) -> Result<Vec<i64>, Box<dyn std::error::Error + Send + Sync>> {
+    let (Some(first), Some(last)) = (days.iter().min(), days.iter().max()) else {
+        return Ok(Vec::new());
+    };
+    let range = Some((first.clone(), last.clone()));
+    let records =
+        query_records(DAILY_VIEWS_PARTITION.to_string(), range, Vec::new(), days.len(), false)
+            .await?;
+
+    // views counted while counters were sharded never leave their shards
+    let counters: Vec<String> = days.iter().map(|day| daily_views_counter(day)).collect();
+    let sharded = counters::totals(&counters).await?;
+
+    let sort_key = &schema().sort_key;
+    Ok(days
+        .iter()
+        .zip(&counters)
+        .map(|(day, counter)| {
+            let stored = records
+                .iter()
+                .find(|r| matches!(r.get(sort_key), Some(AttributeValue::S(d)) if d == day))
+                .and_then(|r| match r.get(COUNT_ATTRIBUTE) {
+                    Some(AttributeValue::N(n)) => n.parse().ok(),
+                    _ => None,
+                })
+                .unwrap_or(0);
+            stored + sharded.get(counter).copied().unwrap_or(0)
+        })
+        .collect())
+}
@@ -0,0 +1,176 @@
+use crate::auth::constant_time_eq;
+use crate::clock::now_millis;
+use crate::stage::Stage;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64URL;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag};
+use sha2::Sha256;
+use std::collections::HashMap;
+
+/// Route prefix of previews: `/preview/{token}`.
+pub const PREVIEW_ROUTE: &str = "/preview/";
+
+/// How long a preview token works.
+const TOKEN_TTL_SECS: u64 = 3600;
+
+/// Prefix of the image sources resolved to the stage's uploads.
+const UPLOAD_PREFIX: &str = "upload/";
+
+/// Signing secret of preview tokens, from `preview_secret`; previews are
+/// unavailable without it.
+pub struct PreviewConfig {
+    secret: String,
+}
+
+/// What a token grants: a look at one post of one stage.
+pub struct Grant {
+    /// The stage's name, resolved with `Stage::from_name` when the post is
+    /// read, so a token outlives a promotion.
+    pub stage: String,
+    pub idx: String,
+}
+
+impl PreviewConfig {
+    pub fn from_env() -> Option<PreviewConfig> {
+        let secret = std::env::var("preview_secret").ok().filter(|s| !s.is_empty())?;
+        Some(PreviewConfig { secret })
+    }
+
+    fn signature(&self, payload: &str) -> Vec<u8> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.secret.as_bytes())
+            .expect("hmac accepts any key length");
+        mac.update(payload.as_bytes());
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    /// A token for post `idx` of `stage`, valid for an hour, and when it
+    /// expires (epoch seconds).
+    pub fn mint(&self, stage: Stage, idx: &str) -> (String, u64) {
+        let expires = now_millis() / 1000 + TOKEN_TTL_SECS;
+        let payload = format!("{}\n{expires}\n{idx}", stage.as_str());
+        let signature = self.signature(&payload);
+        let token = format!("{}.{}", BASE64URL.encode(&payload), BASE64URL.encode(signature));
+        (token, expires)
+    }
+
+    /// The grant of `token`, if it was issued by `mint` and has not expired.
+    pub fn verify(&self, token: &str) -> Option<Grant> {
+        let (payload, signature) = token.split_once('.')?;
+        let payload = String::from_utf8(BASE64URL.decode(payload).ok()?).ok()?;
+        let signature = BASE64URL.decode(signature).ok()?;
+        let expected = self.signature(&payload);
+        if !constant_time_eq(&expected, &signature) {
+            return None;
+        }
+
+        let mut fields = payload.splitn(3, '\n');
+        let stage = fields.next()?.to_string();
+        let expires: u64 = fields.next()?.parse().ok()?;
+        let idx = fields.next()?.to_string();
+        if expires < now_millis() / 1000 {
+            return None;
+        }
+        Some(Grant { stage, idx })
+    }
+}
+
+/// Markdown extensions the preview renders, those the blog frontend supports.
+fn options() -> Options {
+    Options::ENABLE_TABLES
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+}
+
+/// Upload keys in the `src="..."` attributes of raw HTML.
+fn html_image_keys(html: &str) -> impl Iterator<Item = &str> {
+    html.split("src=\"")
+        .skip(1)
+        .filter(|rest| rest.starts_with(UPLOAD_PREFIX))
+        .filter_map(|rest| rest.split_once('"').map(|(key, _)| key))
+}
+
+/// Upload keys used as image sources in `markdown`, relative to the stage's
+/// S3 base, without duplicates.
+pub fn image_keys(markdown: &str) -> Vec<String> {
+    let mut keys: Vec<String> = Vec::new();
+    let mut add = |key: &str| {
+        if key.starts_with(UPLOAD_PREFIX) && !keys.iter().any(|k| k == key) {
+            keys.push(key.to_string());
+        }
+    };
+    for event in Parser::new_ext(markdown, options()) {
+        match event {
+            Event::Start(Tag::Image { dest_url, .. }) => add(&dest_url),
+            Event::Html(html) | Event::InlineHtml(html) => {
+                html_image_keys(&html).for_each(&mut add)
+            }
+            _ => {}
+        }
+    }
+    keys
+}
+
+/// `text` escaped for HTML text and double-quoted attributes.
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn resolve_html<'a>(html: CowStr<'a>, images: &HashMap<String, String>) -> CowStr<'a> {
+    if !html_image_keys(&html).any(|key| images.contains_key(key)) {
+        return html;
+    }
+    let mut resolved = html.to_string();
+    for (key, url) in images {
+        let src = format!("src=\"{}\"", escape(url));
+        resolved = resolved.replace(&format!("src=\"{key}\""), &src);
+    }
+    CowStr::from(resolved)
+}
+
+/// `markdown` rendered to HTML as it would publish, with image upload keys
+/// replaced by their entry in `images`. Raw HTML in the markdown is kept as
+/// written, apart from its image sources.
+pub fn render_body(markdown: &str, images: &HashMap<String, String>) -> String {
+    let parser = Parser::new_ext(markdown, options()).map(|event| match event {
+        Event::Start(Tag::Image {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => {
+            let dest_url = match images.get(&*dest_url) {
+                Some(url) => CowStr::from(url.clone()),
+                None => dest_url,
+            };
+            Event::Start(Tag::Image {
+                link_type,
+                dest_url,
+                title,
+                id,
+            })
+        }
+        Event::Html(html) => Event::Html(resolve_html(html, images)),
+        Event::InlineHtml(html) => Event::InlineHtml(resolve_html(html, images)),
+        event => event,
+    });
+    let mut body = String::with_capacity(markdown.len() * 3 / 2);
+    html::push_html(&mut body, parser);
+    body
+}
+
+/// A standalone page showing the post as it would publish, its body
+/// rendered by `render_body`.
+pub fn render(title: &str, markdown: &str, images: &HashMap<String, String>) -> String {
+    let body = render_body(markdown, images);
+    let title = escape(title);
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"robots\" content=\"noindex\">\n<title>Preview: {title}</title>\n\
+         </head>\n<body>\n<article>\n<h1>{title}</h1>\n{body}</article>\n</body>\n</html>\n"
+    )
+}
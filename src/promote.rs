@@ -0,0 +1,250 @@
+use crate::clock::now_millis;
+use crate::dynamodb::{
+    clear_partition, copy_partition, dynamodb_client, get_item_value, put_item, schema,
+    stored_records, versions, PINNED_ATTRIBUTE, SORT_WEIGHT_ATTRIBUTE, TABLE_NAME,
+};
+use crate::lock::acquire_lock;
+use crate::posts::{self, VIEWS_ATTRIBUTE};
+use crate::s3::copy_prefix;
+use crate::snapshots;
+use crate::stage::{content_partitions, Stage};
+use crate::tags;
+use aws_sdk_dynamodb::operation::put_item::PutItemError;
+use aws_sdk_dynamodb::types::AttributeValue;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// The latest promotion, under `CURRENT_IDX`, and the live generation, under
+/// `GENERATION_IDX`.
+pub const PROMOTIONS_PARTITION: &str = "promotions";
+const CURRENT_IDX: &str = "current";
+const GENERATION_IDX: &str = "generation";
+const GENERATION_ATTRIBUTE: &str = "generation";
+
+const LOCK_NAME: &str = "promote";
+
+/// Matches the Lambda timeout, so a lock left by a crashed run frees up.
+const LOCK_TTL: Duration = Duration::from_secs(15 * 60);
+
+/// Attributes that record what happened on the live site rather than what
+/// the content says, so a promotion keeps the live ones.
+const LIVE_ATTRIBUTES: [&str; 3] = [VIEWS_ATTRIBUTE, PINNED_ATTRIBUTE, SORT_WEIGHT_ATTRIBUTE];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Running,
+    Failed,
+    Succeeded,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Promotion {
+    pub status: Status,
+    /// The live generation the promotion builds and switches to.
+    pub generation: u64,
+    pub started_at: u64,
+    pub finished_at: Option<u64>,
+    pub items: usize,
+    pub objects: usize,
+    pub error: Option<String>,
+}
+
+impl Promotion {
+    fn new(generation: u64) -> Promotion {
+        Promotion {
+            status: Status::Running,
+            generation,
+            started_at: now_millis(),
+            finished_at: None,
+            items: 0,
+            objects: 0,
+            error: None,
+        }
+    }
+}
+
+/// The latest promotion, `None` before the first.
+pub async fn current() -> Result<Option<Promotion>, Box<dyn std::error::Error + Send + Sync>> {
+    match get_item_value(PROMOTIONS_PARTITION.to_string(), CURRENT_IDX.to_string()).await? {
+        Some(value) => Ok(Some(serde_json::from_str(&value)?)),
+        None => Ok(None),
+    }
+}
+
+async fn save(promotion: &Promotion) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    put_item(
+        PROMOTIONS_PARTITION.to_string(),
+        CURRENT_IDX.to_string(),
+        serde_json::to_string(promotion)?,
+    )
+    .await
+}
+
+/// The generation live reads go to, 0 before the first promotion. Read
+/// strongly consistent, so no request lands on a generation already
+/// switched away from.
+pub async fn generation() -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+    let schema = schema();
+    let output = dynamodb_client()
+        .await
+        .get_item()
+        .table_name(TABLE_NAME)
+        .key(&schema.partition_key, AttributeValue::S(PROMOTIONS_PARTITION.to_string()))
+        .key(&schema.sort_key, AttributeValue::S(GENERATION_IDX.to_string()))
+        .consistent_read(true)
+        .send()
+        .await?;
+
+    match output.item.as_ref().and_then(|item| item.get(GENERATION_ATTRIBUTE)) {
+        Some(AttributeValue::N(n)) => Ok(n.parse()?),
+        _ => Ok(0),
+    }
+}
+
+/// Points live reads at generation `to`, provided they still go to `from`.
+/// This single write is what makes a promotion take effect.
+async fn switch(from: u64, to: u64) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let schema = schema();
+    let request = dynamodb_client()
+        .await
+        .put_item()
+        .table_name(TABLE_NAME)
+        .item(&schema.partition_key, AttributeValue::S(PROMOTIONS_PARTITION.to_string()))
+        .item(&schema.sort_key, AttributeValue::S(GENERATION_IDX.to_string()))
+        .item(GENERATION_ATTRIBUTE, AttributeValue::N(to.to_string()))
+        .item("switched_at", AttributeValue::N(now_millis().to_string()));
+    let request = match from {
+        0 => request
+            .condition_expression("attribute_not_exists(#pk)")
+            .expression_attribute_names("#pk", &schema.partition_key),
+        _ => request
+            .condition_expression("#generation = :from")
+            .expression_attribute_names("#generation", GENERATION_ATTRIBUTE)
+            .expression_attribute_values(":from", AttributeValue::N(from.to_string())),
+    };
+
+    match request.send().await {
+        Ok(_) => Ok(()),
+        Err(e) => match e.as_service_error() {
+            Some(PutItemError::ConditionalCheckFailedException(_)) => {
+                Err("the live generation changed during the promotion".into())
+            }
+            _ => Err(e.into()),
+        },
+    }
+}
+
+/// Builds generation `to` of the live content from the draft site, then
+/// switches live reads to it. Until the switch, generation `from` is what
+/// the live site shows, untouched. The switch only happens when no live
+/// content item changed `version` while `to` was built, so an edit made on
+/// the live site meanwhile fails the promotion rather than being lost.
+/// Returns how many items `to` holds.
+async fn build_and_switch(
+    from: u64,
+    to: u64,
+) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    let (live, next) = (Stage::Live(from), Stage::Live(to));
+
+    let mut read = Vec::new();
+    let mut items = 0;
+    for part in content_partitions() {
+        // left over from a promotion that failed before switching
+        clear_partition(&next.partition(&part)).await?;
+        let records = stored_records(&live.partition(&part)).await?;
+        items += copy_partition(
+            &Stage::Draft.partition(&part),
+            &next.partition(&part),
+            &records,
+            &LIVE_ATTRIBUTES,
+        )
+        .await?;
+        read.push((live.partition(&part), versions(&records)));
+    }
+    // the draft posts' tag index is not the new generation's
+    tags::recount(next.partition(&posts::posts_part())).await?;
+
+    for (part, versions_read) in &read {
+        if &versions(&stored_records(part).await?) != versions_read {
+            return Err(format!("live {part} changed during the promotion").into());
+        }
+    }
+    switch(from, to).await?;
+    Ok(items)
+}
+
+/// Deletes generation `generation` of the live content once live reads
+/// have moved off it. Failures leave items behind and are only logged.
+async fn discard_generation(generation: u64) {
+    let stage = Stage::Live(generation);
+    let posts_part = stage.partition(&posts::posts_part());
+    let mut parts = content_partitions()
+        .iter()
+        .map(|part| stage.partition(part))
+        .collect::<Vec<_>>();
+    parts.push(tags::tag_counts_part(&posts_part));
+    for part in parts {
+        if let Err(e) = clear_partition(&part).await {
+            tracing::error!("promote discard {} error: {:?}", part, e);
+        }
+    }
+}
+
+/// Promotes the draft site to live. The draft objects are copied over the
+/// live ones first, so the files new content links to are in place when it
+/// goes live; a draft file under the key of a live one replaces it right
+/// away. The content partitions (see `content_partitions`) are then built
+/// as the next live generation, which one conditional write switches live
+/// reads to, so the live site shows either all of the promoted content or
+/// none of it. Content items keep their live `LIVE_ATTRIBUTES`. A failed
+/// promotion leaves the live content as it was, and the next call starts
+/// over. `None` when another promotion is running.
+pub async fn run(
+    bucket: &str,
+    root_path: &str,
+) -> Result<Option<Promotion>, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(lock) = acquire_lock(LOCK_NAME, LOCK_TTL).await? else {
+        return Ok(None);
+    };
+
+    let from = match generation().await {
+        Ok(from) => from,
+        Err(e) => {
+            lock.release().await?;
+            return Err(e);
+        }
+    };
+    let mut promotion = Promotion::new(from + 1);
+
+    let outcome = async {
+        save(&promotion).await?;
+        let draft_path = Stage::Draft.s3_base(root_path);
+        promotion.objects = copy_prefix(bucket, &draft_path, root_path).await?;
+        promotion.items = build_and_switch(from, promotion.generation).await?;
+        Ok::<_, Box<dyn std::error::Error + Send + Sync>>(())
+    }
+    .await;
+
+    promotion.finished_at = Some(now_millis());
+    promotion.status = match &outcome {
+        Ok(()) => Status::Succeeded,
+        Err(_) => Status::Failed,
+    };
+    if let Err(e) = &outcome {
+        tracing::error!("promote error: {:?}", e);
+        promotion.error = Some(e.to_string());
+    }
+    let saved = save(&promotion).await;
+    if outcome.is_ok() {
+        discard_generation(from).await;
+        // the objects copied include the draft's snapshots
+        if let Err(e) = snapshots::refresh(Stage::Live(promotion.generation)).await {
+            tracing::error!("promote snapshots error: {:?}", e);
+        }
+    }
+    lock.release().await?;
+    saved?;
+    Ok(Some(promotion))
+}
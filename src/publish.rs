@@ -0,0 +1,127 @@
+use crate::ctx::Config;
+use crate::dynamodb::get_record;
+use crate::outbox;
+use crate::posts;
+use crate::preview;
+use crate::s3::head_object;
+use crate::snapshots;
+use crate::stage::Stage;
+use crate::static_site::{self, SITE_PREFIX};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde_json::{json, Value};
+
+/// Steps of the publish workflow in `stepfunctions/publish.asl.json`, in
+/// order. Each is run by the command `publish.{name}` and can be retried on
+/// its own: every step leaves the same result when run twice, except
+/// `Notify`, whose event consumers already dedupe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    Render,
+    Thumbnails,
+    Index,
+    Invalidate,
+    Notify,
+}
+
+impl Step {
+    /// The step run by command `cmd`, such as `publish.render`.
+    pub fn from_command(cmd: &str) -> Option<Step> {
+        match cmd.strip_prefix("publish.")? {
+            "render" => Some(Step::Render),
+            "thumbnails" => Some(Step::Thumbnails),
+            "index" => Some(Step::Index),
+            "invalidate" => Some(Step::Invalidate),
+            "notify" => Some(Step::Notify),
+            _ => None,
+        }
+    }
+}
+
+fn bucket(config: &Config) -> Result<&str, &'static str> {
+    match config.bucket.is_empty() {
+        true => Err("s3_bucket is not set"),
+        false => Ok(&config.bucket),
+    }
+}
+
+/// Runs `step` for post `idx` of `stage` and returns what it did, which
+/// the workflow keeps in its state.
+pub async fn run(
+    step: Step,
+    stage: Stage,
+    idx: &str,
+) -> Result<Value, Box<dyn std::error::Error + Send + Sync>> {
+    let config = Config::from_env();
+    let base_path = stage.s3_base(&config.root_path);
+    let cdn_url = config.cdn_url.as_deref();
+    let part = stage.partition(&posts::posts_part());
+
+    match step {
+        // the post's page and JSON in the static site
+        Step::Render => {
+            let bucket = bucket(&config)?;
+            let written = static_site::render_post(bucket, &base_path, stage, cdn_url, idx)
+                .await?
+                .ok_or_else(|| format!("post {idx} does not exist or is a draft"))?;
+            Ok(json!({ "files": written }))
+        }
+        // images are resized by the CDN or a separate function, if at all;
+        // this step makes sure every one the post shows has arrived
+        Step::Thumbnails => {
+            let bucket = bucket(&config)?;
+            let record = get_record(part, idx.to_string())
+                .await?
+                .ok_or_else(|| format!("post {idx} does not exist"))?;
+            let post = posts::post_to_json(&record);
+            let markdown = ["body", "content", "value"]
+                .iter()
+                .find_map(|field| post.get(*field).and_then(|v| v.as_str()))
+                .unwrap_or_default();
+            let images = preview::image_keys(markdown);
+            let mut missing = Vec::new();
+            for key in &images {
+                if head_object(bucket, format!("{base_path}{key}"))
+                    .await?
+                    .is_none()
+                {
+                    missing.push(key.clone());
+                }
+            }
+            if !missing.is_empty() {
+                return Err(format!("images not uploaded yet: {}", missing.join(", ")).into());
+            }
+            Ok(json!({ "images": images }))
+        }
+        // the front page, archive and feed of the static site, and the
+        // feed and sitemap snapshots the API serves
+        Step::Index => {
+            let bucket = bucket(&config)?;
+            let site_url = config.site_url.as_deref();
+            let written =
+                static_site::render_indexes(bucket, &base_path, stage, site_url, cdn_url).await?;
+            let snapshots = snapshots::refresh(stage).await?;
+            Ok(json!({ "files": written, "snapshots": snapshots }))
+        }
+        // CloudFront is called by the workflow itself; this names the paths
+        Step::Invalidate => {
+            let site = format!("/{base_path}{SITE_PREFIX}");
+            let post = utf8_percent_encode(idx, NON_ALPHANUMERIC);
+            let paths = [
+                format!("{site}posts/{post}/*"),
+                format!("{site}posts/{post}.json"),
+                site.clone(),
+                format!("{site}index.html"),
+                format!("{site}posts.json"),
+                format!("{site}archive/*"),
+                format!("{site}archive.json"),
+                format!("{site}feed.json"),
+            ];
+            Ok(json!({ "paths": paths, "quantity": paths.len() }))
+        }
+        Step::Notify => {
+            let payload = json!({ "part": part, "idx": idx, "stage": stage.as_str() });
+            outbox::record("post.published", payload).await?;
+            Ok(json!({ "event": "post.published" }))
+        }
+    }
+}
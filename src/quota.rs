@@ -0,0 +1,95 @@
+use crate::clock::now_millis;
+use crate::s3::prefix_usage;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+const DEFAULT_WARN_PERCENT: i64 = 80;
+
+const DEFAULT_CACHE_SECS: u64 = 300;
+
+/// Storage usage per bucket and prefix, with when it was counted.
+type UsageCache = HashMap<(String, String), (u64, (i64, i64))>;
+
+/// How long a container reuses a storage count, from `quota_cache_secs`.
+fn cache_millis() -> u64 {
+    std::env::var("quota_cache_secs")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(DEFAULT_CACHE_SECS)
+        * 1000
+}
+
+/// Usage of one resource against its (optional) limit.
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+pub struct Usage {
+    #[cfg_attr(feature = "ts", ts(type = "number"))]
+    pub used: i64,
+    #[cfg_attr(feature = "ts", ts(type = "number | null"))]
+    pub allowed: Option<i64>,
+    /// Set once `used` reaches `quota_warn_percent` of `allowed`.
+    pub warning: bool,
+}
+
+fn limit(name: &str) -> Option<i64> {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|l| *l > 0)
+}
+
+fn usage(used: i64, allowed: Option<i64>) -> Usage {
+    let percent = limit("quota_warn_percent").unwrap_or(DEFAULT_WARN_PERCENT);
+    Usage {
+        used,
+        allowed,
+        warning: allowed.is_some_and(|allowed| used * 100 >= allowed * percent),
+    }
+}
+
+/// `meta` block of `/api/s3/list`: stored bytes and objects against
+/// `storage_quota_bytes` / `storage_quota_objects`.
+pub fn storage_meta(objects: i64, bytes: i64) -> Value {
+    json!({
+        "bytes": usage(bytes, limit("storage_quota_bytes")),
+        "objects": usage(objects, limit("storage_quota_objects")),
+    })
+}
+
+/// `meta` block of `/dynamodb/items`: the partition's item count against
+/// `item_quota`.
+pub fn items_meta(count: usize) -> Value {
+    json!({ "items": usage(count as i64, limit("item_quota")) })
+}
+
+/// Objects and bytes stored under `prefix`, counted with a full listing at
+/// most once per `quota_cache_secs` per container, since listings are paged
+/// and billed per thousand keys.
+pub async fn storage_usage(
+    bucket: &str,
+    prefix: &str,
+) -> Result<(i64, i64), Box<dyn std::error::Error + Send + Sync>> {
+    static CACHE: Mutex<Option<UsageCache>> = Mutex::new(None);
+    let key = (bucket.to_string(), prefix.to_string());
+
+    if let Some((at, usage)) = CACHE
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .as_ref()
+        .and_then(|cache| cache.get(&key))
+    {
+        if now_millis().saturating_sub(*at) < cache_millis() {
+            return Ok(*usage);
+        }
+    }
+
+    let usage = prefix_usage(bucket, prefix).await?;
+    CACHE
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get_or_insert_with(HashMap::new)
+        .insert(key, (now_millis(), usage));
+    Ok(usage)
+}
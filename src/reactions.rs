@@ -0,0 +1,48 @@
+use crate::counters;
+use serde::Serialize;
+
+/// Emoji a post can be reacted with, in the order they are listed.
+pub const REACTIONS: [&str; 6] = ["👍", "❤️", "🎉", "😂", "😮", "🙏"];
+
+#[derive(Debug, Serialize)]
+pub struct ReactionCount {
+    pub emoji: &'static str,
+    pub count: i64,
+}
+
+/// The reaction `emoji` stands for, if it is one of `REACTIONS`.
+pub fn parse(emoji: &str) -> Option<&'static str> {
+    REACTIONS.iter().copied().find(|r| *r == emoji)
+}
+
+/// Name of the sharded counter of `emoji` reactions to post `idx` in `part`.
+fn counter(part: &str, idx: &str, emoji: &str) -> String {
+    format!("reactions#{part}#{idx}#{emoji}")
+}
+
+/// Counts one `emoji` reaction to post `idx`. The post is not checked here.
+pub async fn react(
+    part: &str,
+    idx: &str,
+    emoji: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    counters::add(counter(part, idx, emoji)).await?;
+    Ok(())
+}
+
+/// Reactions to post `idx`, one entry per emoji in `REACTIONS` order.
+pub async fn counts(
+    part: &str,
+    idx: &str,
+) -> Result<Vec<ReactionCount>, Box<dyn std::error::Error + Send + Sync>> {
+    let names: Vec<String> = REACTIONS.iter().map(|e| counter(part, idx, e)).collect();
+    let totals = counters::totals(&names).await?;
+    Ok(REACTIONS
+        .iter()
+        .zip(&names)
+        .map(|(emoji, name)| ReactionCount {
+            emoji,
+            count: totals.get(name).copied().unwrap_or(0),
+        })
+        .collect())
+}
@@ -0,0 +1,71 @@
+use crate::clock::now_millis;
+use crate::dynamodb::{delete_item, dynamodb_client, schema, TABLE_NAME};
+use aws_sdk_dynamodb::operation::put_item::PutItemError;
+use aws_sdk_dynamodb::types::AttributeValue;
+use sha2::{Digest, Sha256};
+
+/// Delivery ids already accepted by the public event endpoints, one item per
+/// `{receiver}#{sha256(id)}`, expired through the `ttl` attribute.
+pub const DELIVERIES_PARTITION: &str = "deliveries";
+
+const DEFAULT_TTL_SECS: u64 = 24 * 60 * 60;
+
+fn delivery_idx(receiver: &str, id: &str) -> String {
+    let digest: String = Sha256::digest(id.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    format!("{receiver}#{digest}")
+}
+
+/// Claims a delivery id for `receiver`. `false` means the same delivery was
+/// already accepted within `replay_ttl_secs` (default one day) and must be
+/// rejected as a replay.
+pub async fn claim(
+    receiver: &str,
+    id: &str,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let client = dynamodb_client().await;
+    let schema = schema();
+
+    let ttl_secs = std::env::var("replay_ttl_secs")
+        .ok()
+        .and_then(|t| t.parse().ok())
+        .unwrap_or(DEFAULT_TTL_SECS);
+    let now = now_millis();
+
+    let result = client
+        .put_item()
+        .table_name(TABLE_NAME)
+        .item(
+            &schema.partition_key,
+            AttributeValue::S(DELIVERIES_PARTITION.to_string()),
+        )
+        .item(&schema.sort_key, AttributeValue::S(delivery_idx(receiver, id)))
+        .item("received_at", AttributeValue::N(now.to_string()))
+        .item("ttl", AttributeValue::N((now / 1000 + ttl_secs).to_string()))
+        // expired items linger until TTL deletes them; they no longer count
+        .condition_expression("attribute_not_exists(#pk) OR #ttl < :now")
+        .expression_attribute_names("#pk", &schema.partition_key)
+        .expression_attribute_names("#ttl", "ttl")
+        .expression_attribute_values(":now", AttributeValue::N((now / 1000).to_string()))
+        .send()
+        .await;
+
+    match result {
+        Ok(_) => Ok(true),
+        Err(e) => match e.as_service_error() {
+            Some(PutItemError::ConditionalCheckFailedException(_)) => Ok(false),
+            _ => Err(e.into()),
+        },
+    }
+}
+
+/// Gives a claimed delivery back when it could not be processed, so the
+/// sender's retry is not mistaken for a replay.
+pub async fn release(
+    receiver: &str,
+    id: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    delete_item(DELIVERIES_PARTITION.to_string(), delivery_idx(receiver, id)).await
+}
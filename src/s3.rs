@@ -1,19 +1,258 @@
 use aws_config::BehaviorVersion;
 use aws_sdk_s3::{presigning::PresigningConfig, Client};
-use aws_sdk_s3::types::StorageClass;
+use aws_sdk_s3::types::{Delete, ObjectIdentifier, StorageClass};
+use crate::clock::now_millis;
+use crate::costs::S3RequestMeter;
+use crate::correlation::Propagate;
+use crate::failover::{self, FailureDetector};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
+use tokio::sync::OnceCell;
 
-async fn s3_client() -> Client {
-    let config = aws_config::load_defaults(BehaviorVersion::latest()).await;
+// Characters left as-is in an `x-amz-copy-source` value.
+pub const COPY_SOURCE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'/')
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+const DEFAULT_STORAGE_CLASS: &str = "GLACIER_IR";
+const DEFAULT_ALLOWED_STORAGE_CLASSES: &str = "STANDARD,STANDARD_IA,INTELLIGENT_TIERING,GLACIER_IR";
+
+/// Default storage class of uploads to `bucket`: its entry in
+/// `s3_storage_class_by_bucket` (comma-separated `bucket=CLASS` pairs, the
+/// bucket named as in `s3_bucket` or `failover_s3_bucket`, access point
+/// aliases included), else `s3_storage_class`, else `GLACIER_IR`.
+fn default_storage_class(bucket: &str) -> String {
+    let by_bucket = std::env::var("s3_storage_class_by_bucket").unwrap_or_default();
+    by_bucket
+        .split(',')
+        .filter_map(|entry| entry.trim().split_once('='))
+        .find(|(alias, _)| alias.trim() == bucket)
+        .map(|(_, class)| class.trim().to_uppercase())
+        .or_else(|| std::env::var("s3_storage_class").ok().map(|c| c.trim().to_uppercase()))
+        .filter(|class| !class.is_empty())
+        .unwrap_or_else(|| DEFAULT_STORAGE_CLASS.to_string())
+}
+
+fn is_storage_class(class: &str) -> bool {
+    StorageClass::values().contains(&class)
+}
+
+/// Checks, at startup, that every configured default storage class is one
+/// S3 knows, so a typo fails the deployment rather than each upload.
+pub fn check_storage_classes() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let by_bucket = std::env::var("s3_storage_class_by_bucket").unwrap_or_default();
+    let configured = by_bucket
+        .split(',')
+        .filter(|entry| !entry.trim().is_empty())
+        .map(|entry| match entry.split_once('=') {
+            Some((_, class)) => Ok(class.trim().to_uppercase()),
+            None => Err(format!("s3_storage_class_by_bucket entry {entry:?} is not bucket=CLASS")),
+        })
+        .chain(std::env::var("s3_storage_class").ok().map(|c| Ok(c.trim().to_uppercase())));
+    for class in configured {
+        let class = class?;
+        if !class.is_empty() && !is_storage_class(&class) {
+            return Err(format!("{class} is not an S3 storage class").into());
+        }
+    }
+    Ok(())
+}
+
+/// Resolves the storage class for an upload to `bucket`: the bucket's
+/// default (see `default_storage_class`) unless the request names one,
+/// which must then be listed in `s3_storage_classes`.
+pub fn upload_storage_class(requested: Option<&str>, bucket: &str) -> Result<StorageClass, String> {
+    let allowed = std::env::var("s3_storage_classes")
+        .unwrap_or_else(|_| DEFAULT_ALLOWED_STORAGE_CLASSES.to_string());
+    let allowed: Vec<&str> = allowed.split(',').map(|c| c.trim()).collect();
+
+    let class = match requested {
+        Some(requested) => requested.to_uppercase(),
+        None => {
+            let class = default_storage_class(bucket);
+            // `check_storage_classes` refuses this at startup already
+            if !is_storage_class(&class) {
+                return Err(format!("configured storage class {class} is not valid"));
+            }
+            return Ok(StorageClass::from(class.as_str()));
+        }
+    };
+    if !allowed.contains(&class.as_str()) || !is_storage_class(&class) {
+        return Err(format!("storageClass must be one of {}", allowed.join(", ")));
+    }
+    Ok(StorageClass::from(class.as_str()))
+}
+
+pub async fn s3_client() -> Client {
+    let config = failover::sdk_config().await;
+    let config = aws_sdk_s3::config::Builder::from(&config)
+        .interceptor(FailureDetector)
+        .interceptor(S3RequestMeter)
+        .interceptor(Propagate)
+        .build();
+    Client::from_conf(config)
+}
+
+/// What a failed S3 call means for the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    NotFound,
+    AccessDenied,
+    /// The object is archived and has to be restored before it can be read.
+    Archived,
+    /// S3 asked for the request rate to be reduced.
+    SlowDown,
+    Other,
+}
+
+impl ErrorKind {
+    pub fn status(self) -> u16 {
+        match self {
+            ErrorKind::NotFound => 404,
+            ErrorKind::AccessDenied => 403,
+            ErrorKind::Archived => 409,
+            ErrorKind::SlowDown => 429,
+            ErrorKind::Other => 500,
+        }
+    }
+
+    /// Machine-readable code for the `error` field of error responses.
+    pub fn code(self) -> &'static str {
+        match self {
+            ErrorKind::NotFound => "no_such_key",
+            ErrorKind::AccessDenied => "access_denied",
+            ErrorKind::Archived => "object_archived",
+            ErrorKind::SlowDown => "slow_down",
+            ErrorKind::Other => "s3_error",
+        }
+    }
+}
+
+/// Classifies an error returned by the helpers in this module. `HeadObject`
+/// errors have no body to carry a code, so their HTTP status is used.
+pub fn error_kind(e: &(dyn std::error::Error + 'static)) -> ErrorKind {
+    use aws_sdk_s3::error::{ProvideErrorMetadata, SdkError};
+    use aws_sdk_s3::operation::{
+        copy_object::CopyObjectError, delete_object::DeleteObjectError,
+        delete_objects::DeleteObjectsError, get_object::GetObjectError,
+        get_object_legal_hold::GetObjectLegalHoldError,
+        get_object_retention::GetObjectRetentionError, head_object::HeadObjectError,
+        list_objects_v2::ListObjectsV2Error, put_object::PutObjectError,
+        put_object_legal_hold::PutObjectLegalHoldError,
+        put_object_retention::PutObjectRetentionError,
+    };
+
+    fn classify(code: Option<&str>, status: Option<u16>) -> ErrorKind {
+        match (code, status) {
+            (Some("NoSuchKey" | "NotFound"), _) => ErrorKind::NotFound,
+            (Some("AccessDenied" | "Forbidden"), _) => ErrorKind::AccessDenied,
+            (Some("InvalidObjectState"), _) => ErrorKind::Archived,
+            (Some("SlowDown"), _) => ErrorKind::SlowDown,
+            (Some(_), _) => ErrorKind::Other,
+            (None, Some(404)) => ErrorKind::NotFound,
+            (None, Some(403)) => ErrorKind::AccessDenied,
+            (None, Some(503)) => ErrorKind::SlowDown,
+            (None, _) => ErrorKind::Other,
+        }
+    }
+
+    macro_rules! by_code {
+        ($e:expr, $($op:ty),*) => {
+            $(
+                if let Some(e) = $e.downcast_ref::<SdkError<$op>>() {
+                    let status = e.raw_response().map(|r| r.status().as_u16());
+                    return classify(e.code(), status);
+                }
+            )*
+        };
+    }
+
+    let mut current = Some(e);
+    while let Some(e) = current {
+        by_code!(
+            e,
+            HeadObjectError,
+            GetObjectError,
+            PutObjectError,
+            CopyObjectError,
+            DeleteObjectError,
+            DeleteObjectsError,
+            ListObjectsV2Error,
+            GetObjectLegalHoldError,
+            GetObjectRetentionError,
+            PutObjectLegalHoldError,
+            PutObjectRetentionError
+        );
+        current = e.source();
+    }
+    ErrorKind::Other
+}
+
+/// Client for a bucket in another region than the function's.
+async fn regional_client(region: &str) -> Client {
+    let config = aws_config::defaults(BehaviorVersion::latest())
+        .region(aws_config::Region::new(region.to_string()))
+        .load()
+        .await;
     Client::new(&config)
 }
 
+/// Copies `key` of `bucket` to the same key under `target_prefix` in
+/// `target_bucket`, a bucket in `region`.
+pub async fn copy_object_to(
+    bucket: &str,
+    key: &str,
+    target_bucket: &str,
+    region: &str,
+    target_prefix: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let client = regional_client(region).await;
+
+    let source = format!("{bucket}/{key}");
+    client
+        .copy_object()
+        .bucket(target_bucket)
+        .copy_source(utf8_percent_encode(&source, COPY_SOURCE).to_string())
+        .key(format!("{target_prefix}{key}"))
+        .send()
+        .await?;
+
+    Ok(())
+}
+
+/// `put_object` for a bucket in `region`.
+pub async fn put_object_to(
+    target_bucket: &str,
+    region: &str,
+    key: String,
+    bytes: Vec<u8>,
+    content_type: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let client = regional_client(region).await;
+
+    client
+        .put_object()
+        .bucket(target_bucket)
+        .key(key)
+        .content_type(content_type)
+        .body(bytes.into())
+        .send()
+        .await?;
+
+    Ok(())
+}
+
 pub async fn list_objects(
+    client: &Client,
     bucket: &str,
     prefix: String,
 ) -> Result<(Vec<String>, Vec<String>), Box<dyn std::error::Error + Send + Sync>> {
-    let client = s3_client().await;
-
     let resp = client
         .list_objects_v2()
         .bucket(bucket)
@@ -46,47 +285,263 @@ pub async fn list_objects(
     Ok((folders, files))
 }
 
+/// Presigns a `PutObject`. With `checksum_sha256` (base64 SHA-256 of the
+/// content) the checksum becomes a signed header: the client must send the
+/// same `x-amz-checksum-sha256` value and S3 rejects bodies that don't match.
 pub async fn presign_upload(
+    client: &Client,
     bucket: &str,
     key: String,
     content_type: String,
+    storage_class: StorageClass,
+    checksum_sha256: Option<String>,
+    content_length: Option<i64>,
 ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
-    let client = s3_client().await;
-
     let presigned = client
         .put_object()
         .bucket(bucket)
         .key(key)
         .content_type(content_type)
-        .storage_class(StorageClass::GlacierIr)
+        .storage_class(storage_class)
+        .set_checksum_sha256(checksum_sha256)
+        .set_content_length(content_length)
         .presigned(PresigningConfig::expires_in(Duration::from_secs(900))?)
         .await?;
 
     Ok(presigned.uri().to_string())
 }
 
+/// A presigned `GetObject` URL is reused for this fraction of its validity,
+/// so a reused URL always has at least two thirds of it left.
+const PRESIGN_REUSE_DIVISOR: u64 = 3;
+
+/// Most presigned URLs kept per container.
+const MAX_PRESIGNED: usize = 10_000;
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct PresignKey {
+    bucket: String,
+    key: String,
+    range: Option<String>,
+    expires_in: Duration,
+}
+
+struct Presigned {
+    url: String,
+    /// Epoch milliseconds until which the URL is handed out again.
+    reuse_until: u64,
+}
+
+type PresignCell = Arc<OnceCell<Presigned>>;
+
+/// The cell holding the URL for `key`: the cached one while it may still be
+/// reused, one being signed right now, or a fresh one to sign into.
+fn presign_cell(key: &PresignKey) -> PresignCell {
+    static CELLS: Mutex<Option<HashMap<PresignKey, PresignCell>>> = Mutex::new(None);
+
+    let mut cells = CELLS.lock().unwrap_or_else(|e| e.into_inner());
+    let cells = cells.get_or_insert_with(HashMap::new);
+    let now = now_millis();
+    if let Some(cell) = cells.get(key) {
+        if cell.get().is_none_or(|presigned| presigned.reuse_until > now) {
+            return cell.clone();
+        }
+    }
+    if cells.len() >= MAX_PRESIGNED {
+        cells.retain(|_, cell| cell.get().is_none_or(|presigned| presigned.reuse_until > now));
+    }
+    let cell = PresignCell::default();
+    if cells.len() < MAX_PRESIGNED {
+        cells.insert(key.clone(), cell.clone());
+    }
+    cell
+}
+
+/// Presigns a `GetObject` valid for `expires_in`. A `range`
+/// (`bytes=start-end`) is signed into the URL, so the client must send
+/// exactly that `Range` header. The URL is kept in memory and handed out
+/// again for the first third of its validity, and concurrent requests for
+/// the same object wait for one signature instead of each signing their own.
 pub async fn presign_download(
+    client: &Client,
     bucket: &str,
     key: String,
+    range: Option<String>,
+    expires_in: Duration,
 ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let cache_key = PresignKey {
+        bucket: bucket.to_string(),
+        key,
+        range,
+        expires_in,
+    };
+    let cell = presign_cell(&cache_key);
+    let presigned = cell
+        .get_or_try_init(|| async {
+            let signed_at = now_millis();
+            let presigned = client
+                .get_object()
+                .bucket(bucket)
+                .key(&cache_key.key)
+                .set_range(cache_key.range.clone())
+                .presigned(PresigningConfig::expires_in(expires_in)?)
+                .await?;
+            let reuse_millis = expires_in.as_millis() as u64 / PRESIGN_REUSE_DIVISOR;
+            Ok::<_, Box<dyn std::error::Error + Send + Sync>>(Presigned {
+                url: presigned.uri().to_string(),
+                reuse_until: signed_at + reuse_millis,
+            })
+        })
+        .await?;
+
+    Ok(presigned.url.clone())
+}
+
+#[derive(Debug, Serialize)]
+pub struct ObjectInfo {
+    pub size: i64,
+    pub etag: Option<String>,
+    pub content_type: Option<String>,
+    pub storage_class: Option<String>,
+    /// In Glacier Flexible Retrieval, Deep Archive or an Intelligent-Tiering
+    /// archive tier, with no restored copy: it cannot be read until restored.
+    pub archived: bool,
+    /// A restore was requested and has not finished yet.
+    pub restoring: bool,
+}
+
+/// Object metadata via `HeadObject`; `None` when the key does not exist.
+pub async fn head_object(
+    bucket: &str,
+    key: String,
+) -> Result<Option<ObjectInfo>, Box<dyn std::error::Error + Send + Sync>> {
     let client = s3_client().await;
 
-    let presigned = client
-        .get_object()
+    let output = match client.head_object().bucket(bucket).key(key).send().await {
+        Ok(output) => output,
+        Err(e) if e.as_service_error().is_some_and(|e| e.is_not_found()) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    // `x-amz-restore` is `ongoing-request="false", expiry-date=...` once a
+    // temporary copy is readable
+    let restore = output.restore.unwrap_or_default();
+    let cold = matches!(
+        output.storage_class,
+        Some(StorageClass::Glacier | StorageClass::DeepArchive)
+    ) || output.archive_status.is_some();
+    Ok(Some(ObjectInfo {
+        size: output.content_length.unwrap_or_default(),
+        etag: output.e_tag,
+        content_type: output.content_type,
+        storage_class: output.storage_class.map(|c| c.as_str().to_string()),
+        archived: cold && !restore.contains("ongoing-request=\"false\""),
+        restoring: restore.contains("ongoing-request=\"true\""),
+    }))
+}
+
+/// An object's content as read by `get_object`.
+pub struct ObjectBody {
+    pub bytes: Vec<u8>,
+    pub content_type: Option<String>,
+    /// Epoch milliseconds; 0 when S3 did not say.
+    pub last_modified: i64,
+}
+
+/// Reads a whole object; `None` when the key does not exist.
+pub async fn get_object(
+    bucket: &str,
+    key: String,
+) -> Result<Option<ObjectBody>, Box<dyn std::error::Error + Send + Sync>> {
+    let client = s3_client().await;
+
+    let output = match client.get_object().bucket(bucket).key(key).send().await {
+        Ok(output) => output,
+        Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    let content_type = output.content_type;
+    let last_modified = output
+        .last_modified
+        .and_then(|t| t.to_millis().ok())
+        .unwrap_or_default();
+    let bytes = output.body.collect().await?.into_bytes().to_vec();
+    Ok(Some(ObjectBody {
+        bytes,
+        content_type,
+        last_modified,
+    }))
+}
+
+pub async fn put_object(
+    bucket: &str,
+    key: String,
+    bytes: Vec<u8>,
+    content_type: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let client = s3_client().await;
+
+    client
+        .put_object()
         .bucket(bucket)
         .key(key)
-        .presigned(PresigningConfig::expires_in(Duration::from_secs(900))?)
+        .content_type(content_type)
+        .body(bytes.into())
+        .send()
         .await?;
 
-    Ok(presigned.uri().to_string())
+    Ok(())
 }
 
-pub async fn presign_delete(
+/// Writes an upload the server fetched itself, in `storage_class` like the
+/// uploads presigned by `presign_upload`.
+pub async fn put_upload(
+    client: &Client,
+    bucket: &str,
+    key: String,
+    bytes: Vec<u8>,
+    content_type: &str,
+    storage_class: StorageClass,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    client
+        .put_object()
+        .bucket(bucket)
+        .key(key)
+        .content_type(content_type)
+        .storage_class(storage_class)
+        .body(bytes.into())
+        .send()
+        .await?;
+
+    Ok(())
+}
+
+/// Writes a text object.
+pub async fn put_text_object(
+    bucket: &str,
+    key: String,
+    body: String,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    put_object(bucket, key, body.into_bytes(), "text/plain; charset=utf-8").await
+}
+
+/// Reads a text object written by `put_text_object`.
+pub async fn get_text_object(
     bucket: &str,
     key: String,
 ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
-    let client = s3_client().await;
+    match get_object(bucket, key.clone()).await? {
+        Some(object) => Ok(String::from_utf8(object.bytes)?),
+        None => Err(format!("s3 object {key} not found").into()),
+    }
+}
 
+pub async fn presign_delete(
+    client: &Client,
+    bucket: &str,
+    key: String,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
     let presigned = client
         .delete_object()
         .bucket(bucket)
@@ -96,3 +551,132 @@ pub async fn presign_delete(
 
     Ok(presigned.uri().to_string())
 }
+
+/// Copies every object under `from` to the same relative key under `to`.
+pub async fn copy_prefix(
+    bucket: &str,
+    from: &str,
+    to: &str,
+) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    let client = s3_client().await;
+
+    let mut copied = 0;
+    let mut token = None;
+    loop {
+        let resp = client
+            .list_objects_v2()
+            .bucket(bucket)
+            .prefix(from)
+            .set_continuation_token(token)
+            .send()
+            .await?;
+
+        for obj in resp.contents.unwrap_or_default() {
+            let Some(key) = obj.key else { continue };
+            let target = format!("{to}{}", &key[from.len()..]);
+            let source = format!("{bucket}/{key}");
+            client
+                .copy_object()
+                .bucket(bucket)
+                .copy_source(utf8_percent_encode(&source, COPY_SOURCE).to_string())
+                .key(target)
+                .send()
+                .await?;
+            copied += 1;
+        }
+
+        token = resp.next_continuation_token;
+        if token.is_none() {
+            break;
+        }
+    }
+
+    Ok(copied)
+}
+
+/// Object count and total bytes stored under a prefix.
+pub async fn prefix_usage(
+    bucket: &str,
+    prefix: &str,
+) -> Result<(i64, i64), Box<dyn std::error::Error + Send + Sync>> {
+    let objects = list_all_objects(bucket, prefix).await?;
+    let bytes = objects.iter().map(|o| o.size).sum();
+    Ok((objects.len() as i64, bytes))
+}
+
+/// An object found by `list_all_objects`.
+#[derive(Debug, Serialize)]
+pub struct StoredObject {
+    pub key: String,
+    pub size: i64,
+    /// Epoch milliseconds.
+    pub last_modified: i64,
+}
+
+/// Every object under a prefix, recursively.
+pub async fn list_all_objects(
+    bucket: &str,
+    prefix: &str,
+) -> Result<Vec<StoredObject>, Box<dyn std::error::Error + Send + Sync>> {
+    let client = s3_client().await;
+
+    let mut objects = Vec::new();
+    let mut token = None;
+    loop {
+        let resp = client
+            .list_objects_v2()
+            .bucket(bucket)
+            .prefix(prefix)
+            .set_continuation_token(token)
+            .send()
+            .await?;
+
+        for obj in resp.contents.unwrap_or_default() {
+            let Some(key) = obj.key else { continue };
+            objects.push(StoredObject {
+                key,
+                size: obj.size.unwrap_or_default(),
+                last_modified: obj
+                    .last_modified
+                    .and_then(|t| t.to_millis().ok())
+                    .unwrap_or_default(),
+            });
+        }
+
+        token = resp.next_continuation_token;
+        if token.is_none() {
+            break;
+        }
+    }
+
+    Ok(objects)
+}
+
+/// Deletes `keys` with `DeleteObjects`, 1000 at a time. Returns how many
+/// S3 confirmed.
+pub async fn delete_objects(
+    bucket: &str,
+    keys: Vec<String>,
+) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    let client = s3_client().await;
+
+    let mut deleted = 0;
+    for chunk in keys.chunks(1000) {
+        let objects = chunk
+            .iter()
+            .map(|key| ObjectIdentifier::builder().key(key).build())
+            .collect::<Result<Vec<_>, _>>()?;
+        let output = client
+            .delete_objects()
+            .bucket(bucket)
+            .delete(Delete::builder().set_objects(Some(objects)).quiet(false).build()?)
+            .send()
+            .await?;
+        for error in output.errors.unwrap_or_default() {
+            tracing::warn!("s3 delete failed for {:?}: {:?}", error.key, error.message);
+        }
+        deleted += output.deleted.map(|d| d.len()).unwrap_or_default();
+    }
+
+    Ok(deleted)
+}
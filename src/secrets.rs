@@ -0,0 +1,83 @@
+use aes_gcm::aead::{Aead, Generate, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use aws_sdk_kms::primitives::Blob;
+use aws_sdk_kms::types::DataKeySpec;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use crate::init;
+use serde::{Deserialize, Serialize};
+
+/// Largest value accepted for encryption. Secrets are small by nature, and
+/// staying well under the item limit keeps envelopes out of S3 overflow.
+pub const MAX_SECRET_BYTES: usize = 64 * 1024;
+
+/// What an encrypted item stores as its value: the AES-256-GCM ciphertext
+/// and the data key it was sealed with, itself encrypted under `kms_key_id`.
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    /// `CiphertextBlob` of the data key, base64.
+    key: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// The KMS key that seals data keys; encryption is unavailable without it.
+pub fn key_id() -> Option<String> {
+    std::env::var("kms_key_id").ok().filter(|k| !k.is_empty())
+}
+
+async fn kms_client() -> aws_sdk_kms::Client {
+    let config = init::sdk_config().await;
+    aws_sdk_kms::Client::new(config)
+}
+
+/// Seals `plaintext` with a fresh data key from `key_id`. Only the encrypted
+/// data key is kept, so reading the value back needs `kms:Decrypt`.
+pub async fn encrypt(
+    key_id: &str,
+    plaintext: &str,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let data_key = kms_client()
+        .await
+        .generate_data_key()
+        .key_id(key_id)
+        .key_spec(DataKeySpec::Aes256)
+        .send()
+        .await?;
+    let (Some(key), Some(sealed_key)) = (data_key.plaintext(), data_key.ciphertext_blob()) else {
+        return Err("kms returned no data key".into());
+    };
+
+    let cipher = Aes256Gcm::new_from_slice(key.as_ref())?;
+    let nonce = Nonce::generate();
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext.as_bytes())
+        .map_err(|_| "encryption failed")?;
+
+    let envelope = Envelope {
+        key: BASE64.encode(sealed_key.as_ref()),
+        nonce: BASE64.encode(nonce),
+        ciphertext: BASE64.encode(ciphertext),
+    };
+    Ok(serde_json::to_string(&envelope)?)
+}
+
+/// Opens a value sealed by `encrypt`.
+pub async fn decrypt(envelope: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let envelope: Envelope = serde_json::from_str(envelope)?;
+    let data_key = kms_client()
+        .await
+        .decrypt()
+        .ciphertext_blob(Blob::new(BASE64.decode(&envelope.key)?))
+        .send()
+        .await?;
+    let key = data_key.plaintext().ok_or("kms returned no data key")?;
+
+    let cipher = Aes256Gcm::new_from_slice(key.as_ref())?;
+    let nonce = BASE64.decode(&envelope.nonce)?;
+    let nonce = Nonce::try_from(nonce.as_slice()).map_err(|_| "invalid nonce")?;
+    let plaintext = cipher
+        .decrypt(&nonce, BASE64.decode(&envelope.ciphertext)?.as_ref())
+        .map_err(|_| "decryption failed")?;
+    Ok(String::from_utf8(plaintext)?)
+}
@@ -0,0 +1,48 @@
+use lambda_http::http::header::{HeaderName, HeaderValue};
+use lambda_http::{Body, Response};
+
+const DEFAULT_CSP: &str = "default-src 'none'; frame-ancestors 'none'; base-uri 'none'";
+const DEFAULT_REFERRER_POLICY: &str = "strict-origin-when-cross-origin";
+const DEFAULT_HSTS_MAX_AGE: u64 = 31_536_000;
+
+/// `name` from the environment, `default` when unset. Set to an empty string
+/// to drop the header entirely.
+fn configured(name: &str, default: &str) -> Option<String> {
+    let value = std::env::var(name).unwrap_or_else(|_| default.to_string());
+    Some(value).filter(|v| !v.is_empty())
+}
+
+fn headers() -> Vec<(&'static str, String)> {
+    let mut headers = vec![("x-content-type-options", "nosniff".to_string())];
+    if let Some(csp) = configured("content_security_policy", DEFAULT_CSP) {
+        headers.push(("content-security-policy", csp));
+    }
+    if let Some(policy) = configured("referrer_policy", DEFAULT_REFERRER_POLICY) {
+        headers.push(("referrer-policy", policy));
+    }
+    let max_age = std::env::var("hsts_max_age")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_HSTS_MAX_AGE);
+    if max_age > 0 {
+        headers.push((
+            "strict-transport-security",
+            format!("max-age={max_age}; includeSubDomains"),
+        ));
+    }
+    headers
+}
+
+/// Adds the security headers to `response`, leaving any a route set itself.
+pub fn apply(response: &mut Response<Body>) {
+    for (name, value) in headers() {
+        let Ok(value) = HeaderValue::from_str(&value) else {
+            tracing::warn!("invalid {} header value: {:?}", name, value);
+            continue;
+        };
+        response
+            .headers_mut()
+            .entry(HeaderName::from_static(name))
+            .or_insert(value);
+    }
+}
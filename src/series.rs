@@ -0,0 +1,271 @@
+use crate::dynamodb::{
+    dynamodb_client, query_records, schema, BumpsVersion, ItemSummary, SERIES_ATTRIBUTE,
+    TABLE_NAME, VERSION_BUMP,
+};
+use aws_sdk_dynamodb::types::{AttributeValue, TransactWriteItem, Update};
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Partition holding one item per series; `idx` is the series id.
+pub const SERIES_PARTITION: &str = "series";
+
+const TITLE_ATTRIBUTE: &str = "title";
+const MEMBERS_ATTRIBUTE: &str = "members";
+
+/// A post in a series, addressed by its unstaged `part` and `idx`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+pub struct SeriesMember {
+    pub part: String,
+    pub idx: String,
+}
+
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+pub struct Series {
+    pub id: String,
+    pub title: String,
+    pub posts: Vec<SeriesMember>,
+}
+
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+pub struct SeriesSummary {
+    pub id: String,
+    pub title: String,
+    #[cfg_attr(feature = "ts", ts(type = "number"))]
+    pub count: usize,
+}
+
+/// Position of a post within its series, embedded in item listings.
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+pub struct SeriesLinks {
+    pub id: String,
+    pub previous: Option<SeriesMember>,
+    pub next: Option<SeriesMember>,
+}
+
+fn parse_series(record: &HashMap<String, AttributeValue>) -> Series {
+    let string = |value: Option<&AttributeValue>| match value {
+        Some(AttributeValue::S(s)) => s.clone(),
+        _ => String::new(),
+    };
+
+    let posts = match record.get(MEMBERS_ATTRIBUTE) {
+        Some(AttributeValue::L(members)) => members
+            .iter()
+            .filter_map(|m| match m {
+                AttributeValue::M(m) => Some(SeriesMember {
+                    part: string(m.get("part")),
+                    idx: string(m.get("idx")),
+                }),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    Series {
+        id: string(record.get(&schema().sort_key)),
+        title: string(record.get(TITLE_ATTRIBUTE)),
+        posts,
+    }
+}
+
+fn member_value(member: &SeriesMember) -> AttributeValue {
+    let mut map = HashMap::new();
+    map.insert("part".to_string(), AttributeValue::S(member.part.clone()));
+    map.insert("idx".to_string(), AttributeValue::S(member.idx.clone()));
+    AttributeValue::M(map)
+}
+
+pub async fn list_series(
+    series_part: String,
+) -> Result<Vec<SeriesSummary>, Box<dyn std::error::Error + Send + Sync>> {
+    let records = query_records(series_part, None, Vec::new(), usize::MAX, false).await?;
+
+    Ok(records
+        .iter()
+        .map(parse_series)
+        .map(|s| SeriesSummary {
+            id: s.id,
+            title: s.title,
+            count: s.posts.len(),
+        })
+        .collect())
+}
+
+pub async fn get_series(
+    series_part: String,
+    id: String,
+) -> Result<Option<Series>, Box<dyn std::error::Error + Send + Sync>> {
+    let range = Some((id.clone(), id));
+    let records = query_records(series_part, range, Vec::new(), 1, false).await?;
+    Ok(records.first().map(parse_series))
+}
+
+/// Creates the series or renames it, keeping its posts.
+pub async fn put_series(
+    series_part: String,
+    id: String,
+    title: String,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let client = dynamodb_client().await;
+    let schema = schema();
+
+    client
+        .update_item()
+        .table_name(TABLE_NAME)
+        .key(&schema.partition_key, AttributeValue::S(series_part))
+        .key(&schema.sort_key, AttributeValue::S(id))
+        .update_expression(format!(
+            "SET #title = :title, #members = if_not_exists(#members, :empty), {VERSION_BUMP}"
+        ))
+        .expression_attribute_names("#title", TITLE_ATTRIBUTE)
+        .expression_attribute_names("#members", MEMBERS_ATTRIBUTE)
+        .expression_attribute_values(":title", AttributeValue::S(title))
+        .expression_attribute_values(":empty", AttributeValue::L(Vec::new()))
+        .bump_version()
+        .send()
+        .await?;
+
+    Ok(())
+}
+
+/// Appends a post to the series and marks the post as a member in one
+/// transaction. Fails if the series or post is missing, or the post already
+/// belongs to a series.
+pub async fn add_post(
+    series_part: String,
+    id: String,
+    post_part: String,
+    member: SeriesMember,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let client = dynamodb_client().await;
+    let schema = schema();
+
+    let series_update = Update::builder()
+        .table_name(TABLE_NAME)
+        .key(&schema.partition_key, AttributeValue::S(series_part))
+        .key(&schema.sort_key, AttributeValue::S(id.clone()))
+        .update_expression(format!(
+            "SET #members = list_append(#members, :member), {VERSION_BUMP}"
+        ))
+        .condition_expression("attribute_exists(#members)")
+        .expression_attribute_names("#members", MEMBERS_ATTRIBUTE)
+        .expression_attribute_values(":member", AttributeValue::L(vec![member_value(&member)]))
+        .bump_version()
+        .build()?;
+
+    let post_update = Update::builder()
+        .table_name(TABLE_NAME)
+        .key(&schema.partition_key, AttributeValue::S(post_part))
+        .key(&schema.sort_key, AttributeValue::S(member.idx))
+        .update_expression(format!("SET #series = :id, {VERSION_BUMP}"))
+        .condition_expression("attribute_exists(#pk) AND attribute_not_exists(#series)")
+        .expression_attribute_names("#pk", &schema.partition_key)
+        .expression_attribute_names("#series", SERIES_ATTRIBUTE)
+        .expression_attribute_values(":id", AttributeValue::S(id))
+        .bump_version()
+        .build()?;
+
+    client
+        .transact_write_items()
+        .transact_items(TransactWriteItem::builder().update(series_update).build())
+        .transact_items(TransactWriteItem::builder().update(post_update).build())
+        .send()
+        .await?;
+
+    Ok(())
+}
+
+/// Removes a post from the series and clears its membership in one
+/// transaction. Returns `false` when the post is not in the series.
+pub async fn remove_post(
+    series_part: String,
+    id: String,
+    post_part: String,
+    member: SeriesMember,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let series = match get_series(series_part.clone(), id.clone()).await? {
+        Some(series) => series,
+        None => return Ok(false),
+    };
+    let position = match series.posts.iter().position(|p| *p == member) {
+        Some(position) => position,
+        None => return Ok(false),
+    };
+
+    let client = dynamodb_client().await;
+    let schema = schema();
+
+    // the guard on the element keeps a concurrent reorder from removing the wrong post
+    let series_update = Update::builder()
+        .table_name(TABLE_NAME)
+        .key(&schema.partition_key, AttributeValue::S(series_part))
+        .key(&schema.sort_key, AttributeValue::S(id.clone()))
+        .update_expression(format!("SET {VERSION_BUMP} REMOVE #members[{position}]"))
+        .condition_expression(format!(
+            "#members[{position}].#part = :part AND #members[{position}].#idx = :idx"
+        ))
+        .expression_attribute_names("#members", MEMBERS_ATTRIBUTE)
+        .expression_attribute_names("#part", "part")
+        .expression_attribute_names("#idx", "idx")
+        .expression_attribute_values(":part", AttributeValue::S(member.part))
+        .expression_attribute_values(":idx", AttributeValue::S(member.idx.clone()))
+        .bump_version()
+        .build()?;
+
+    let post_update = Update::builder()
+        .table_name(TABLE_NAME)
+        .key(&schema.partition_key, AttributeValue::S(post_part))
+        .key(&schema.sort_key, AttributeValue::S(member.idx))
+        .update_expression(format!("SET {VERSION_BUMP} REMOVE #series"))
+        .condition_expression("#series = :id")
+        .expression_attribute_names("#series", SERIES_ATTRIBUTE)
+        .expression_attribute_values(":id", AttributeValue::S(id))
+        .bump_version()
+        .build()?;
+
+    client
+        .transact_write_items()
+        .transact_items(TransactWriteItem::builder().update(series_update).build())
+        .transact_items(TransactWriteItem::builder().update(post_update).build())
+        .send()
+        .await?;
+
+    Ok(true)
+}
+
+/// Fills in previous/next links for every listed item that belongs to a series.
+pub async fn attach_links(
+    series_part: String,
+    part: &str,
+    items: &mut [ItemSummary],
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut loaded: HashMap<String, Option<Series>> = HashMap::new();
+
+    for item in items.iter_mut() {
+        let Some(id) = item.series_id.clone() else { continue };
+        if !loaded.contains_key(&id) {
+            let series = get_series(series_part.clone(), id.clone()).await?;
+            loaded.insert(id.clone(), series);
+        }
+        let Some(series) = loaded.get(&id).and_then(|s| s.as_ref()) else { continue };
+
+        let position = series
+            .posts
+            .iter()
+            .position(|p| p.part == part && p.idx == item.idx);
+        if let Some(position) = position {
+            item.series = Some(SeriesLinks {
+                id,
+                previous: position.checked_sub(1).map(|i| series.posts[i].clone()),
+                next: series.posts.get(position + 1).cloned(),
+            });
+        }
+    }
+
+    Ok(())
+}
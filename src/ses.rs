@@ -0,0 +1,90 @@
+use crate::init;
+use aws_credential_types::provider::ProvideCredentials;
+use aws_sigv4::http_request::{sign, SignableBody, SignableRequest, SigningSettings};
+use aws_sigv4::sign::v4;
+use serde_json::{json, Value};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime};
+
+/// Address mail is sent from, from `ses_from`: an identity verified in SES
+/// in `ses_region` (default: the function's own region).
+pub fn sender() -> Option<String> {
+    std::env::var("ses_from")
+        .ok()
+        .filter(|from| !from.is_empty())
+}
+
+fn client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .unwrap_or_default()
+    })
+}
+
+/// Sends a plain-text email from `from` to `to` through the SES v2
+/// `SendEmail` API, signed with the function's credentials, and returns
+/// the SES message id. The role needs `ses:SendEmail` on the identity.
+pub async fn send(
+    from: &str,
+    to: &[String],
+    subject: &str,
+    text: &str,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let config = init::sdk_config().await;
+    let region = match std::env::var("ses_region").ok().filter(|r| !r.is_empty()) {
+        Some(region) => region,
+        None => config.region().ok_or("no region for ses")?.to_string(),
+    };
+    let credentials = config
+        .credentials_provider()
+        .ok_or("no credentials for ses")?
+        .provide_credentials()
+        .await?;
+
+    let url = format!("https://email.{region}.amazonaws.com/v2/email/outbound-emails");
+    let body = json!({
+        "FromEmailAddress": from,
+        "Destination": { "ToAddresses": to },
+        "Content": {
+            "Simple": {
+                "Subject": { "Data": subject, "Charset": "UTF-8" },
+                "Body": { "Text": { "Data": text, "Charset": "UTF-8" } },
+            },
+        },
+    })
+    .to_string();
+
+    let identity = credentials.into();
+    let params = v4::SigningParams::builder()
+        .identity(&identity)
+        .region(&region)
+        .name("ses")
+        .time(SystemTime::now())
+        .settings(SigningSettings::default())
+        .build()?
+        .into();
+    let headers = [("content-type", "application/json")];
+    let signable = SignableRequest::new(
+        "POST",
+        url.as_str(),
+        headers.into_iter(),
+        SignableBody::Bytes(body.as_bytes()),
+    )?;
+    let (instructions, _) = sign(signable, &params)?.into_parts();
+
+    let mut request = client().post(&url).body(body.clone());
+    for (name, value) in headers.into_iter().chain(instructions.headers()) {
+        request = request.header(name, value);
+    }
+    let response = request.send().await?;
+    let status = response.status();
+    let answer: Value = serde_json::from_slice(&response.bytes().await?).unwrap_or_default();
+    if !status.is_success() {
+        let message = answer["message"].as_str().unwrap_or("no message");
+        return Err(format!("ses answered {status}: {message}").into());
+    }
+    Ok(answer["MessageId"].as_str().unwrap_or_default().to_string())
+}
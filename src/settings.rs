@@ -0,0 +1,148 @@
+use crate::comments::MAX_CLOSE_AFTER_DAYS;
+use crate::dynamodb::{get_item_value, put_item};
+use crate::masking::CommentMasking;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// Site-wide settings, kept as one JSON item.
+pub const SETTINGS_PARTITION: &str = "settings";
+const SETTINGS_IDX: &str = "site";
+
+const MAX_TITLE_CHARS: usize = 100;
+const MAX_DESCRIPTION_CHARS: usize = 500;
+const MAX_SOCIAL_LINKS: usize = 20;
+/// SES takes at most 50 recipients per message.
+const MAX_DIGEST_RECIPIENTS: usize = 50;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct SocialLink {
+    /// Network or label shown for the link, e.g. `github`.
+    pub name: String,
+    pub url: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct SiteSettings {
+    pub title: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub social_links: Vec<SocialLink>,
+    #[serde(default = "enabled")]
+    pub comments_enabled: bool,
+    /// Days after which posts stop taking comments, unless a post sets its own.
+    #[serde(default)]
+    pub comments_close_after_days: Option<u32>,
+    /// What is hidden from comments shown publicly.
+    #[serde(default)]
+    pub comment_masking: CommentMasking,
+    #[serde(default)]
+    pub digest: DigestSettings,
+}
+
+/// The daily digest email (see `digest::run`).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct DigestSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    /// UTC hour (0–23) from which the previous day's digest is sent.
+    #[serde(default)]
+    pub hour: u8,
+    #[serde(default)]
+    pub recipients: Vec<String>,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+impl Default for SiteSettings {
+    /// What `GET /settings` serves before any settings are saved.
+    fn default() -> Self {
+        SiteSettings {
+            title: std::env::var("site_title")
+                .ok()
+                .filter(|t| !t.is_empty())
+                .unwrap_or_else(|| "Blog".to_string()),
+            description: String::new(),
+            social_links: Vec::new(),
+            comments_enabled: true,
+            comments_close_after_days: None,
+            comment_masking: CommentMasking::default(),
+            digest: DigestSettings::default(),
+        }
+    }
+}
+
+impl SiteSettings {
+    /// Checks the settings before they are saved; the message names the
+    /// offending field.
+    pub fn validate(&self) -> Result<(), String> {
+        let title = self.title.trim();
+        if title.is_empty() || title.chars().count() > MAX_TITLE_CHARS {
+            return Err(format!("title must be 1 to {MAX_TITLE_CHARS} characters"));
+        }
+        if self.description.chars().count() > MAX_DESCRIPTION_CHARS {
+            return Err(format!("description must be at most {MAX_DESCRIPTION_CHARS} characters"));
+        }
+        if let Some(days) = self.comments_close_after_days {
+            if days == 0 || days > MAX_CLOSE_AFTER_DAYS {
+                return Err(format!(
+                    "commentsCloseAfterDays must be 1 to {MAX_CLOSE_AFTER_DAYS}"
+                ));
+            }
+        }
+        self.comment_masking.validate()?;
+        if self.digest.hour > 23 {
+            return Err("digest hour must be 0 to 23".to_string());
+        }
+        if self.digest.enabled && self.digest.recipients.is_empty() {
+            return Err("digest needs recipients when enabled".to_string());
+        }
+        if self.digest.recipients.len() > MAX_DIGEST_RECIPIENTS {
+            return Err(format!("at most {MAX_DIGEST_RECIPIENTS} digest recipients are allowed"));
+        }
+        for recipient in &self.digest.recipients {
+            let valid = recipient
+                .split_once('@')
+                .is_some_and(|(local, domain)| !local.is_empty() && domain.contains('.'));
+            if !valid || recipient.chars().any(char::is_whitespace) {
+                return Err(format!("digest recipient {recipient} is not an email address"));
+            }
+        }
+        if self.social_links.len() > MAX_SOCIAL_LINKS {
+            return Err(format!("at most {MAX_SOCIAL_LINKS} socialLinks are allowed"));
+        }
+        for link in &self.social_links {
+            if link.name.trim().is_empty() {
+                return Err("socialLinks need a name".to_string());
+            }
+            match Url::parse(&link.url) {
+                Ok(url) if url.scheme() == "https" || url.scheme() == "http" => {}
+                _ => return Err(format!("socialLinks url of {} must be http(s)", link.name)),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The saved settings of `part`, or the defaults when none are saved yet.
+pub async fn load(part: String) -> Result<SiteSettings, Box<dyn std::error::Error + Send + Sync>> {
+    match get_item_value(part, SETTINGS_IDX.to_string()).await? {
+        Some(value) => Ok(serde_json::from_str(&value)?),
+        None => Ok(SiteSettings::default()),
+    }
+}
+
+pub async fn save(
+    part: String,
+    settings: &SiteSettings,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    put_item(part, SETTINGS_IDX.to_string(), serde_json::to_string(settings)?).await
+}
@@ -0,0 +1,168 @@
+use lambda_http::http::HeaderMap;
+use lambda_http::{Body, Request, Response};
+use std::future::Future;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+const DEFAULT_TIMEOUT_MS: u64 = 2000;
+const DEFAULT_GRACE_MS: u64 = 50;
+
+// headers that describe the original hop rather than the request itself,
+// and credentials, which the alternate backend must not receive
+const SKIPPED_HEADERS: [&str; 6] = [
+    "host",
+    "content-length",
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "x-api-key",
+];
+
+struct ShadowConfig {
+    base_url: String,
+    routes: Vec<String>,
+    client: reqwest::Client,
+    /// How long the primary response may wait for the shadow one.
+    grace: Duration,
+}
+
+fn config() -> Option<&'static ShadowConfig> {
+    static CONFIG: OnceLock<Option<ShadowConfig>> = OnceLock::new();
+    CONFIG
+        .get_or_init(|| {
+            let base_url = std::env::var("shadow_url").ok().filter(|u| !u.is_empty())?;
+            let routes = std::env::var("shadow_routes")
+                .unwrap_or_default()
+                .split(',')
+                .map(|r| r.trim().to_string())
+                .filter(|r| !r.is_empty())
+                .collect();
+            let timeout = std::env::var("shadow_timeout_ms")
+                .ok()
+                .and_then(|t| t.parse().ok())
+                .unwrap_or(DEFAULT_TIMEOUT_MS);
+            let grace = std::env::var("shadow_grace_ms")
+                .ok()
+                .and_then(|t| t.parse().ok())
+                .unwrap_or(DEFAULT_GRACE_MS);
+            let client = reqwest::Client::builder()
+                .timeout(Duration::from_millis(timeout))
+                .build()
+                .ok()?;
+
+            Some(ShadowConfig {
+                base_url: base_url.trim_end_matches('/').to_string(),
+                routes,
+                client,
+                grace: Duration::from_millis(grace),
+            })
+        })
+        .as_ref()
+}
+
+/// URL to mirror the request to, if shadowing is configured for its route.
+///
+/// Only `GET` requests are mirrored so the alternate backend can never
+/// duplicate a write.
+pub fn target(req: &Request) -> Option<String> {
+    let config = config()?;
+    if req.method() != "GET" || !config.routes.iter().any(|r| r == req.uri().path()) {
+        return None;
+    }
+
+    let path_and_query = req
+        .uri()
+        .path_and_query()
+        .map(|p| p.as_str())
+        .unwrap_or("/");
+    Some(format!("{}{}", config.base_url, path_and_query))
+}
+
+/// Runs `primary` while the request is mirrored to `url`. The primary
+/// response is never held back by the mirror: once it is ready, the shadow
+/// response gets `shadow_grace_ms` more to arrive and is abandoned after
+/// that. A frozen Lambda would never finish a detached task, so an
+/// abandoned mirror is not compared.
+pub async fn alongside<T>(
+    primary: impl Future<Output = T>,
+    url: String,
+    headers: HeaderMap,
+) -> (T, Result<(u16, String), String>) {
+    let grace = config().map(|c| c.grace).unwrap_or_default();
+    let forwarded = forward(url, headers);
+    tokio::pin!(primary, forwarded);
+
+    let mut shadowed = None;
+    let result = loop {
+        tokio::select! {
+            result = &mut primary => break result,
+            answer = &mut forwarded, if shadowed.is_none() => shadowed = Some(answer),
+        }
+    };
+    let shadowed = match shadowed {
+        Some(answer) => answer,
+        None => tokio::time::timeout(grace, forwarded)
+            .await
+            .unwrap_or_else(|_| Err("no answer within the grace period".to_string())),
+    };
+    (result, shadowed)
+}
+
+/// Sends the mirrored request. Errors and timeouts are returned, not raised,
+/// so the primary response is never affected.
+async fn forward(url: String, headers: HeaderMap) -> Result<(u16, String), String> {
+    let config = config().ok_or("shadow not configured")?;
+
+    let mut request = config.client.get(&url);
+    for (name, value) in headers.iter() {
+        if SKIPPED_HEADERS.contains(&name.as_str()) {
+            continue;
+        }
+        if let Ok(value) = value.to_str() {
+            request = request.header(name.as_str(), value);
+        }
+    }
+
+    let response = request.send().await.map_err(|e| e.to_string())?;
+    let status = response.status().as_u16();
+    let body = response.text().await.map_err(|e| e.to_string())?;
+    Ok((status, body))
+}
+
+/// Logs how the shadow response differs from the one served to the client.
+pub fn compare(path: &str, primary: &Response<Body>, shadow: Result<(u16, String), String>) {
+    let (shadow_status, shadow_body) = match shadow {
+        Ok(result) => result,
+        Err(e) => {
+            tracing::warn!(path, "shadow request failed: {}", e);
+            return;
+        }
+    };
+
+    let primary_status = primary.status().as_u16();
+    let primary_body: &[u8] = match primary.body() {
+        Body::Text(s) => s.as_bytes(),
+        Body::Binary(b) => b,
+        _ => &[],
+    };
+
+    if primary_status != shadow_status {
+        tracing::warn!(path, primary_status, shadow_status, "shadow status mismatch");
+    }
+    if primary_body != shadow_body.as_bytes() {
+        let first_difference = primary_body
+            .iter()
+            .zip(shadow_body.as_bytes())
+            .position(|(a, b)| a != b)
+            .unwrap_or(primary_body.len().min(shadow_body.len()));
+        tracing::warn!(
+            path,
+            primary_len = primary_body.len(),
+            shadow_len = shadow_body.len(),
+            first_difference,
+            "shadow body mismatch"
+        );
+    } else if primary_status == shadow_status {
+        tracing::info!(path, "shadow response matches");
+    }
+}
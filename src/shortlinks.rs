@@ -0,0 +1,184 @@
+use crate::clock::now_millis;
+use crate::dynamodb::{dynamodb_client, query_records, schema, TABLE_NAME};
+use aws_sdk_dynamodb::operation::put_item::PutItemError;
+use aws_sdk_dynamodb::operation::update_item::UpdateItemError;
+use aws_sdk_dynamodb::types::{AttributeValue, ReturnValue};
+use serde::Serialize;
+use std::collections::HashMap;
+use ulid::Ulid;
+
+/// Short links, one item per code with the target URL as its value and the
+/// number of times it was followed in `clicks`.
+pub const SHORTLINKS_PARTITION: &str = "shortlinks";
+
+/// Route prefix of short links: `/s/{code}`.
+pub const SHORTLINK_ROUTE: &str = "/s/";
+
+const CLICKS_ATTRIBUTE: &str = "clicks";
+
+/// Length of generated codes: 35 random bits, drawn again on a collision.
+const CODE_LEN: usize = 7;
+const MAX_ATTEMPTS: usize = 5;
+
+/// Longest code an editor may choose.
+const MAX_CODE_LEN: usize = 32;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShortLink {
+    pub code: String,
+    pub target: String,
+    pub clicks: i64,
+    /// Epoch milliseconds.
+    pub created_at: u64,
+}
+
+impl ShortLink {
+    fn from_record(record: &HashMap<String, AttributeValue>) -> Option<ShortLink> {
+        let schema = schema();
+        let string = |name: &str| match record.get(name) {
+            Some(AttributeValue::S(s)) => Some(s.clone()),
+            _ => None,
+        };
+        let number = |name: &str| match record.get(name) {
+            Some(AttributeValue::N(n)) => n.as_str(),
+            _ => "0",
+        };
+        Some(ShortLink {
+            code: string(&schema.sort_key)?,
+            target: string(&schema.value_attribute)?,
+            clicks: number(CLICKS_ATTRIBUTE).parse().unwrap_or(0),
+            created_at: number("created_at").parse().unwrap_or(0),
+        })
+    }
+}
+
+/// Canonical form of a code: trimmed and lowercased, so links typed by hand
+/// still resolve.
+pub fn normalize_code(code: &str) -> String {
+    code.trim().to_lowercase()
+}
+
+/// Whether an editor-chosen (normalized) code is usable in a link: letters,
+/// digits, `-` and `_`, at most 32 characters.
+pub fn is_valid_code(code: &str) -> bool {
+    !code.is_empty()
+        && code.len() <= MAX_CODE_LEN
+        && code
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-' || b == b'_')
+}
+
+/// Whether `target` can be linked to: an absolute http(s) URL.
+pub fn is_valid_target(target: &str) -> bool {
+    url::Url::parse(target).is_ok_and(|url| matches!(url.scheme(), "http" | "https"))
+}
+
+/// A fresh random code; the tail of a ULID is its random part.
+fn random_code() -> String {
+    let ulid = Ulid::generate().to_string().to_lowercase();
+    ulid[ulid.len() - CODE_LEN..].to_string()
+}
+
+/// Stores `code` for `target` unless the code is taken. `false` means it is.
+async fn claim(
+    part: &str,
+    code: &str,
+    target: &str,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let client = dynamodb_client().await;
+    let schema = schema();
+
+    let result = client
+        .put_item()
+        .table_name(TABLE_NAME)
+        .item(&schema.partition_key, AttributeValue::S(part.to_string()))
+        .item(&schema.sort_key, AttributeValue::S(code.to_string()))
+        .item(
+            &schema.value_attribute,
+            AttributeValue::S(target.to_string()),
+        )
+        .item(CLICKS_ATTRIBUTE, AttributeValue::N("0".to_string()))
+        .item("created_at", AttributeValue::N(now_millis().to_string()))
+        .condition_expression("attribute_not_exists(#pk)")
+        .expression_attribute_names("#pk", &schema.partition_key)
+        .send()
+        .await;
+
+    match result {
+        Ok(_) => Ok(true),
+        Err(e) => match e.as_service_error() {
+            Some(PutItemError::ConditionalCheckFailedException(_)) => Ok(false),
+            _ => Err(e.into()),
+        },
+    }
+}
+
+/// Mints a short link to `target` in `part`, under `code` if given (and
+/// normalized) or a random one. `None` when the chosen code is taken.
+pub async fn create(
+    part: String,
+    target: String,
+    code: Option<String>,
+) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+    if let Some(code) = code {
+        return Ok(claim(&part, &code, &target).await?.then_some(code));
+    }
+    for _ in 0..MAX_ATTEMPTS {
+        let code = random_code();
+        if claim(&part, &code, &target).await? {
+            return Ok(Some(code));
+        }
+    }
+    Err("no free short link code".into())
+}
+
+/// Follows the short link `code`: counts the click and returns the target.
+/// `None` when no such link exists; nothing is counted then.
+pub async fn follow(
+    part: String,
+    code: String,
+) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+    let client = dynamodb_client().await;
+    let schema = schema();
+
+    // one round trip: the count only goes up for links that exist
+    let result = client
+        .update_item()
+        .table_name(TABLE_NAME)
+        .key(&schema.partition_key, AttributeValue::S(part))
+        .key(&schema.sort_key, AttributeValue::S(code))
+        .update_expression("ADD #clicks :one")
+        .condition_expression("attribute_exists(#pk)")
+        .expression_attribute_names("#pk", &schema.partition_key)
+        .expression_attribute_names("#clicks", CLICKS_ATTRIBUTE)
+        .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
+        .return_values(ReturnValue::AllNew)
+        .send()
+        .await;
+
+    let output = match result {
+        Ok(output) => output,
+        Err(e) => {
+            return match e.as_service_error() {
+                Some(UpdateItemError::ConditionalCheckFailedException(_)) => Ok(None),
+                _ => Err(e.into()),
+            }
+        }
+    };
+    let attributes = output.attributes.unwrap_or_default();
+    Ok(match attributes.get(&schema.value_attribute) {
+        Some(AttributeValue::S(target)) => Some(target.clone()),
+        _ => None,
+    })
+}
+
+/// Every short link in `part` with its click count, oldest first.
+pub async fn list(
+    part: String,
+) -> Result<Vec<ShortLink>, Box<dyn std::error::Error + Send + Sync>> {
+    let records = query_records(part, None, Vec::new(), usize::MAX, false).await?;
+    let mut links: Vec<ShortLink> = records.iter().filter_map(ShortLink::from_record).collect();
+    links.sort_by_key(|link| link.created_at);
+    Ok(links)
+}
@@ -0,0 +1,166 @@
+use crate::clock::now_millis;
+use crate::dynamodb::{get_record, schema, TABLE_NAME};
+use crate::posts::{list_posts, post_to_json, PostSort};
+use crate::subscribers::SUBSCRIBERS_VISIBILITY;
+use aws_sdk_dynamodb::types::{AttributeValue, Put, TransactWriteItem};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Slug index: `idx` is the slug, `post` the owning post's idx. Slugs a post
+/// no longer uses stay behind with `redirect = true`, so old links resolve.
+pub const SLUGS_PARTITION: &str = "slugs";
+
+const POST_ATTRIBUTE: &str = "post";
+const REDIRECT_ATTRIBUTE: &str = "redirect";
+
+/// Newest posts whose slugs are offered as 404 suggestions.
+const MAX_CANDIDATES: usize = 1000;
+
+const DEFAULT_CANDIDATES_CACHE_SECS: u64 = 300;
+
+/// Suggestion candidates per posts part, with when they were listed.
+type CandidateCache = HashMap<String, (u64, Arc<Vec<String>>)>;
+
+/// How long a container reuses its suggestion candidates, from
+/// `slug_suggestions_cache_secs`.
+fn candidates_cache_millis() -> u64 {
+    std::env::var("slug_suggestions_cache_secs")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(DEFAULT_CANDIDATES_CACHE_SECS)
+        * 1000
+}
+
+/// Canonical form of a slug: trimmed, lowercased, whitespace runs as `-`.
+/// Emoji and other non-ASCII characters are kept as-is.
+pub fn normalize_slug(slug: &str) -> String {
+    slug.split_whitespace()
+        .collect::<Vec<_>>()
+        .join("-")
+        .to_lowercase()
+}
+
+/// The normalized `slug` field of a JSON post value, if any.
+pub fn slug_of(value: &str) -> Option<String> {
+    let parsed: Value = serde_json::from_str(value).ok()?;
+    let slug = normalize_slug(parsed["slug"].as_str()?);
+    (!slug.is_empty()).then_some(slug)
+}
+
+/// Transaction entry claiming `slug` for the post `idx`, or leaving it
+/// behind as a redirect to it.
+pub fn slug_put(
+    slugs_part: &str,
+    slug: String,
+    idx: &str,
+    redirect: bool,
+) -> Result<TransactWriteItem, Box<dyn std::error::Error + Send + Sync>> {
+    let schema = schema();
+
+    let mut item = HashMap::new();
+    item.insert(schema.partition_key.clone(), AttributeValue::S(slugs_part.to_string()));
+    item.insert(schema.sort_key.clone(), AttributeValue::S(slug));
+    item.insert(POST_ATTRIBUTE.to_string(), AttributeValue::S(idx.to_string()));
+    item.insert(REDIRECT_ATTRIBUTE.to_string(), AttributeValue::Bool(redirect));
+
+    // a slug may be (re)claimed if it is free, already ours, or only a redirect
+    let condition = if redirect {
+        "attribute_not_exists(#pk) OR #post = :idx"
+    } else {
+        "attribute_not_exists(#pk) OR #post = :idx OR #redirect = :true"
+    };
+    let mut put = Put::builder()
+        .table_name(TABLE_NAME)
+        .set_item(Some(item))
+        .condition_expression(condition)
+        .expression_attribute_names("#pk", &schema.partition_key)
+        .expression_attribute_names("#post", POST_ATTRIBUTE)
+        .expression_attribute_values(":idx", AttributeValue::S(idx.to_string()));
+    if !redirect {
+        put = put
+            .expression_attribute_names("#redirect", REDIRECT_ATTRIBUTE)
+            .expression_attribute_values(":true", AttributeValue::Bool(true));
+    }
+
+    Ok(TransactWriteItem::builder().put(put.build()?).build())
+}
+
+/// Resolves a slug: the post itself, or for a retired slug a 301-style
+/// pointer to the post's current slug. `None` when nothing matches.
+pub async fn resolve(
+    posts_part: String,
+    slugs_part: String,
+    slug: &str,
+) -> Result<Option<Value>, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(entry) = get_record(slugs_part, normalize_slug(slug)).await? else {
+        return Ok(None);
+    };
+    let Some(AttributeValue::S(idx)) = entry.get(POST_ATTRIBUTE) else {
+        return Ok(None);
+    };
+    let Some(post) = get_record(posts_part, idx.clone()).await? else {
+        return Ok(None);
+    };
+
+    if !matches!(entry.get(REDIRECT_ATTRIBUTE), Some(AttributeValue::Bool(true))) {
+        return Ok(Some(post_to_json(&post)));
+    }
+
+    let current = match post.get(&schema().value_attribute) {
+        Some(AttributeValue::S(value)) => slug_of(value),
+        _ => None,
+    };
+    Ok(Some(json!({
+        "moved": true,
+        "status": 301,
+        "idx": idx,
+        "slug": current,
+        "location": current.as_ref().map(|slug| format!("/posts/by-slug/{slug}")),
+    })))
+}
+
+/// Slugs an unknown slug may be corrected to: those of the newest
+/// `MAX_CANDIDATES` published, public posts in `posts_part`, so drafts and
+/// subscriber posts are never named. Retired slugs are left out too. Each
+/// container reuses the list for `slug_suggestions_cache_secs`.
+pub async fn suggestion_candidates(
+    posts_part: String,
+) -> Result<Arc<Vec<String>>, Box<dyn std::error::Error + Send + Sync>> {
+    static CACHE: Mutex<Option<CandidateCache>> = Mutex::new(None);
+
+    if let Some((at, slugs)) = CACHE
+        .lock()
+        .unwrap()
+        .as_ref()
+        .and_then(|cache| cache.get(&posts_part))
+    {
+        if now_millis().saturating_sub(*at) < candidates_cache_millis() {
+            return Ok(slugs.clone());
+        }
+    }
+
+    let fields = ["slug", "status", "visibility"].map(String::from).to_vec();
+    let posts = list_posts(
+        posts_part.clone(),
+        PostSort::CreatedAt,
+        true,
+        MAX_CANDIDATES,
+        Some(fields),
+    )
+    .await?;
+    let slugs: Vec<String> = posts
+        .iter()
+        .filter(|post| post["status"] != "draft" && post["visibility"] != SUBSCRIBERS_VISIBILITY)
+        .filter_map(|post| post["slug"].as_str().map(normalize_slug))
+        .filter(|slug| !slug.is_empty())
+        .collect();
+    let slugs = Arc::new(slugs);
+
+    CACHE
+        .lock()
+        .unwrap()
+        .get_or_insert_with(HashMap::new)
+        .insert(posts_part, (now_millis(), slugs.clone()));
+    Ok(slugs)
+}
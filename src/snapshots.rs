@@ -0,0 +1,269 @@
+use crate::clock::utc_date;
+use crate::ctx::Config;
+use crate::outbox;
+use crate::posts::{self, list_posts, PostSort};
+use crate::preview::escape;
+use crate::s3::s3_client;
+use crate::settings::{self, SETTINGS_PARTITION};
+use crate::stage::{is_live_partition, Stage};
+use crate::urls::PublicUrls;
+use crate::{feed, images};
+use sha2::{Digest, Sha256};
+
+/// Prefix of the feed and sitemap snapshots under the stage's S3 base.
+pub const SNAPSHOTS_PREFIX: &str = "snapshots/";
+
+/// Object metadata holding the snapshot's ETag, a hash of its content, so
+/// a rebuild that changes nothing keeps the ETag clients already have.
+const ETAG_METADATA: &str = "etag";
+
+/// Most URLs one sitemap file may list.
+const MAX_SITEMAP_URLS: usize = 50_000;
+
+/// Aggregate documents kept as snapshots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Document {
+    Feed,
+    Sitemap,
+}
+
+impl Document {
+    const ALL: [Document; 2] = [Document::Feed, Document::Sitemap];
+
+    /// The document served at `path`, if any.
+    pub fn at(path: &str) -> Option<Document> {
+        match path {
+            "/feed.json" => Some(Document::Feed),
+            "/sitemap.xml" => Some(Document::Sitemap),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Document::Feed => "feed.json",
+            Document::Sitemap => "sitemap.xml",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Document::Feed => "application/feed+json; charset=utf-8",
+            Document::Sitemap => "application/xml; charset=utf-8",
+        }
+    }
+
+    fn key(self, base_path: &str) -> String {
+        format!("{base_path}{SNAPSHOTS_PREFIX}{}", self.name())
+    }
+}
+
+/// A rendered document and its strong ETag.
+pub struct Snapshot {
+    pub bytes: Vec<u8>,
+    pub etag: String,
+}
+
+impl Snapshot {
+    fn new(bytes: Vec<u8>) -> Snapshot {
+        let digest: String = Sha256::digest(&bytes)
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        Snapshot {
+            bytes,
+            etag: format!("\"{digest}\""),
+        }
+    }
+}
+
+/// Where snapshots are kept and the URLs they link to. Snapshots are only
+/// kept when the outbox is published, since that is what refreshes them,
+/// and when `site_url` is set, since they cannot follow the request origin.
+fn snapshot_urls(config: &Config) -> Option<PublicUrls> {
+    outbox::topic_arn()?;
+    if config.bucket.is_empty() {
+        return None;
+    }
+    let site = config.site_url.clone()?;
+    Some(PublicUrls::new(site, config.api_url.clone()))
+}
+
+/// Renders `document` for `stage` from the posts as they are now.
+async fn render(
+    document: Document,
+    stage: Stage,
+    base_path: &str,
+    urls: &PublicUrls,
+    cdn_url: Option<&str>,
+) -> Result<Snapshot, Box<dyn std::error::Error + Send + Sync>> {
+    let part = stage.partition(&posts::posts_part());
+    let bytes = match document {
+        Document::Feed => {
+            let title = settings::load(stage.partition(SETTINGS_PARTITION))
+                .await?
+                .title;
+            let mut feed = feed::build(part, urls, title).await?;
+            if let Some(cdn) = cdn_url {
+                let base_url = format!("{cdn}/{base_path}");
+                for item in &mut feed.items {
+                    item.content = images::rewrite_markdown(&item.content, &base_url);
+                }
+            }
+            feed::to_json_feed(&feed, &urls.feed()).to_string().into_bytes()
+        }
+        Document::Sitemap => sitemap(part, urls).await?.into_bytes(),
+    };
+    Ok(Snapshot::new(bytes))
+}
+
+/// Sitemap (https://www.sitemaps.org/protocol.html) of the site root and
+/// every published post, newest first.
+async fn sitemap(
+    part: String,
+    urls: &PublicUrls,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let fields = ["status", "created_at", "updated_at"].map(String::from).to_vec();
+    let listed = list_posts(part, PostSort::CreatedAt, true, usize::MAX, Some(fields)).await?;
+
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    );
+    xml.push_str(&format!("<url><loc>{}/</loc></url>\n", escape(urls.site())));
+    let published = listed
+        .iter()
+        .filter(|post| post.get("status").and_then(|s| s.as_str()) != Some("draft"))
+        .take(MAX_SITEMAP_URLS - 1);
+    for post in published {
+        let Some(idx) = post.get("idx").and_then(|i| i.as_str()) else {
+            continue;
+        };
+        let modified = ["updated_at", "created_at"]
+            .iter()
+            .find_map(|field| post.get(*field).and_then(|t| t.as_u64()));
+        xml.push_str(&format!("<url><loc>{}</loc>", escape(&urls.post(idx))));
+        if let Some(modified) = modified {
+            xml.push_str(&format!("<lastmod>{}</lastmod>", utc_date(modified)));
+        }
+        xml.push_str("</url>\n");
+    }
+    xml.push_str("</urlset>\n");
+    Ok(xml)
+}
+
+async fn read(
+    bucket: &str,
+    key: String,
+) -> Result<Option<Snapshot>, Box<dyn std::error::Error + Send + Sync>> {
+    let client = s3_client().await;
+    let output = match client.get_object().bucket(bucket).key(key).send().await {
+        Ok(output) => output,
+        Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let etag = output
+        .metadata
+        .as_ref()
+        .and_then(|m| m.get(ETAG_METADATA))
+        .cloned();
+    let bytes = output.body.collect().await?.into_bytes().to_vec();
+    Ok(Some(match etag {
+        Some(etag) => Snapshot { bytes, etag },
+        None => Snapshot::new(bytes),
+    }))
+}
+
+async fn write(
+    bucket: &str,
+    key: String,
+    document: Document,
+    snapshot: &Snapshot,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    s3_client()
+        .await
+        .put_object()
+        .bucket(bucket)
+        .key(key)
+        .content_type(document.content_type())
+        .metadata(ETAG_METADATA, &snapshot.etag)
+        .body(snapshot.bytes.clone().into())
+        .send()
+        .await?;
+    Ok(())
+}
+
+/// `document` as served at its route: the stored snapshot when snapshots
+/// are kept (written on first use if there is none yet), and otherwise
+/// rendered for the request with `urls`.
+pub async fn load(
+    document: Document,
+    config: &Config,
+    stage: Stage,
+    base_path: &str,
+    urls: &PublicUrls,
+) -> Result<Snapshot, Box<dyn std::error::Error + Send + Sync>> {
+    let cdn_url = config.cdn_url.as_deref();
+    let Some(snapshot_urls) = snapshot_urls(config) else {
+        return render(document, stage, base_path, urls, cdn_url).await;
+    };
+
+    let key = document.key(base_path);
+    if let Some(snapshot) = read(&config.bucket, key.clone()).await? {
+        return Ok(snapshot);
+    }
+    let snapshot = render(document, stage, base_path, &snapshot_urls, cdn_url).await?;
+    write(&config.bucket, key, document, &snapshot).await?;
+    Ok(snapshot)
+}
+
+/// Rebuilds the snapshots of `stage` after its content changed. A snapshot
+/// that cannot be rebuilt is deleted, so its route renders it afresh rather
+/// than serve it stale. `false` when snapshots are not kept.
+pub async fn refresh(stage: Stage) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let config = Config::from_env();
+    let Some(urls) = snapshot_urls(&config) else {
+        return Ok(false);
+    };
+    let base_path = stage.s3_base(&config.root_path);
+    let cdn_url = config.cdn_url.as_deref();
+
+    for document in Document::ALL {
+        let key = document.key(&base_path);
+        let written = match render(document, stage, &base_path, &urls, cdn_url).await {
+            Ok(snapshot) => write(&config.bucket, key.clone(), document, &snapshot).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = written {
+            tracing::error!("refreshing snapshot {} failed: {:?}", key, e);
+            s3_client()
+                .await
+                .delete_object()
+                .bucket(&config.bucket)
+                .key(key)
+                .send()
+                .await?;
+        }
+    }
+    Ok(true)
+}
+
+/// The stage whose posts live in `part`, for the `part` of a post event.
+/// `live` is the live stage as it is now; an event from an earlier live
+/// generation maps to it too, since that generation is gone.
+pub fn stage_of(part: &str, live: Stage) -> Option<Stage> {
+    let posts_part = posts::posts_part();
+    if part == Stage::Draft.partition(&posts_part) {
+        return Some(Stage::Draft);
+    }
+    is_live_partition(part, &posts_part).then_some(live)
+}
+
+/// Whether `if_none_match` (an `If-None-Match` header) lists `etag`, by
+/// the weak comparison the header calls for.
+pub fn matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match
+        .split(',')
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
@@ -0,0 +1,131 @@
+use crate::posts;
+use crate::promote;
+use crate::series::SERIES_PARTITION;
+use crate::settings::SETTINGS_PARTITION;
+use crate::slugs::SLUGS_PARTITION;
+use crate::themes::THEMES_PARTITION;
+use lambda_http::Request;
+
+const STAGE_HEADER: &str = "x-stage";
+const DRAFT_PARTITION_PREFIX: &str = "draft#";
+const LIVE_PARTITION_PREFIX: &str = "live#";
+const DRAFT_S3_PREFIX: &str = "draft/";
+
+/// Content environment a request operates on.
+///
+/// `Live` keeps the original, unprefixed DynamoDB partitions and S3 keys so
+/// existing content is untouched; `Draft` lives next to it under a prefix.
+/// The live content partitions (see `content_partitions`) belong to a
+/// generation: generation 0 is the unprefixed one, and each promotion
+/// builds the next under `live#{generation}#` before switching to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Stage {
+    Live(u64),
+    Draft,
+}
+
+impl Stage {
+    /// Picks the stage from the `X-Stage` header, falling back to a `draft.`
+    /// subdomain on the `Host` header, and finally to `Live` at the current
+    /// generation.
+    pub async fn from_request(
+        req: &Request,
+    ) -> Result<Stage, Box<dyn std::error::Error + Send + Sync>> {
+        Stage::from_name(Stage::name_of(req)).await
+    }
+
+    /// The name of the stage `req` asks for, without looking up the live
+    /// generation.
+    pub fn name_of(req: &Request) -> &'static str {
+        if let Some(value) = req.headers().get(STAGE_HEADER) {
+            return match value.to_str().unwrap_or_default() {
+                "draft" => "draft",
+                _ => "live",
+            };
+        }
+
+        let host = req
+            .headers()
+            .get("host")
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or_default();
+        if host.starts_with("draft.") {
+            "draft"
+        } else {
+            "live"
+        }
+    }
+
+    /// The live stage at its current generation.
+    pub async fn live() -> Result<Stage, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(Stage::Live(promote::generation().await?))
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Stage::Live(_) => "live",
+            Stage::Draft => "draft",
+        }
+    }
+
+    /// Inverse of `as_str`; anything but `draft` is `Live`.
+    pub async fn from_name(name: &str) -> Result<Stage, Box<dyn std::error::Error + Send + Sync>> {
+        match name {
+            "draft" => Ok(Stage::Draft),
+            _ => Stage::live().await,
+        }
+    }
+
+    /// DynamoDB partition key value for `part` in this stage.
+    pub fn partition(&self, part: &str) -> String {
+        match self {
+            Stage::Live(generation) if *generation > 0 && is_content(part) => {
+                format!("{LIVE_PARTITION_PREFIX}{generation}#{part}")
+            }
+            Stage::Live(_) => part.to_string(),
+            Stage::Draft => format!("{DRAFT_PARTITION_PREFIX}{part}"),
+        }
+    }
+
+    /// S3 key prefix for this stage, rooted at the configured `s3_path`.
+    pub fn s3_base(&self, base_path: &str) -> String {
+        match self {
+            Stage::Live(_) => base_path.to_string(),
+            Stage::Draft => format!("{base_path}{DRAFT_S3_PREFIX}"),
+        }
+    }
+}
+
+/// The partitions that make up the site's content, which a promotion copies
+/// from the draft site. Everything else, such as comments, short links,
+/// counters and logs, belongs to the live site alone. The tag index follows
+/// the posts, since its partition is named after theirs.
+pub fn content_partitions() -> Vec<String> {
+    vec![
+        posts::posts_part(),
+        SLUGS_PARTITION.to_string(),
+        SERIES_PARTITION.to_string(),
+        SETTINGS_PARTITION.to_string(),
+        THEMES_PARTITION.to_string(),
+    ]
+}
+
+fn is_content(part: &str) -> bool {
+    content_partitions().iter().any(|content| content == part)
+}
+
+/// Whether `physical` is partition `part` of any live generation, as
+/// `Stage::Live(..).partition(part)` names it.
+pub fn is_live_partition(physical: &str, part: &str) -> bool {
+    let generation = physical
+        .strip_suffix(part)
+        .and_then(|prefix| prefix.strip_prefix(LIVE_PARTITION_PREFIX))
+        .and_then(|prefix| prefix.strip_suffix('#'));
+    physical == part || generation.is_some_and(|generation| generation.parse::<u64>().is_ok())
+}
+
+/// Whether `part` already carries a stage's prefix, as `draft#audit` and
+/// `live#3#post` do.
+pub fn is_staged(part: &str) -> bool {
+    part.starts_with(DRAFT_PARTITION_PREFIX) || part.starts_with(LIVE_PARTITION_PREFIX)
+}
@@ -0,0 +1,403 @@
+use crate::clock::utc_date;
+use crate::dynamodb::get_record;
+use crate::images::{rewrite_markdown, rewrite_post};
+use crate::posts::{list_posts, PostSort};
+use crate::preview::{escape, render_body};
+use crate::s3::{delete_objects, list_all_objects, put_object};
+use crate::settings::{self, SETTINGS_PARTITION};
+use crate::stage::Stage;
+use crate::urls::PublicUrls;
+use crate::view::View;
+use crate::{feed, jobs, posts};
+use futures::future::join_all;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Prefix of the exported site under the stage's S3 base.
+pub const SITE_PREFIX: &str = "site/";
+
+/// Posts listed on the front page and in `posts.json`, as `/posts` does.
+const INDEX_SIZE: usize = 20;
+
+/// Posts whose files are written at once; progress is reported per page.
+const PAGE_SIZE: usize = 25;
+
+const HTML: &str = "text/html; charset=utf-8";
+const JSON: &str = "application/json";
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportReport {
+    /// Key prefix the site was written under.
+    pub prefix: String,
+    pub posts: usize,
+    pub files: usize,
+    /// Files of an earlier export that are gone from this one, such as
+    /// pages of posts since deleted or turned back into drafts.
+    pub removed: usize,
+    /// Whether `feed.json` was written; it needs `site_url`.
+    pub feed: bool,
+}
+
+/// A published post, shaped by the public view.
+struct Post {
+    idx: String,
+    title: String,
+    created_at: Option<u64>,
+    json: Value,
+}
+
+impl Post {
+    fn text(&self, field: &str) -> Option<&str> {
+        self.json.get(field).and_then(|v| v.as_str())
+    }
+
+    fn date(&self) -> String {
+        self.created_at.map(utc_date).unwrap_or_default()
+    }
+
+    /// Link to the post's page from the site root.
+    fn href(&self) -> String {
+        format!(
+            "posts/{}/",
+            utf8_percent_encode(&self.idx, NON_ALPHANUMERIC)
+        )
+    }
+}
+
+/// A page of the site, with `root` the relative path back to its top.
+fn page(site_title: &str, title: &str, root: &str, canonical: Option<&str>, main: &str) -> String {
+    let site_title = escape(site_title);
+    let head_title = match title {
+        "" => site_title.clone(),
+        title => format!("{} · {site_title}", escape(title)),
+    };
+    let canonical = canonical
+        .map(|url| format!("<link rel=\"canonical\" href=\"{}\">\n", escape(url)))
+        .unwrap_or_default();
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <title>{head_title}</title>\n{canonical}</head>\n<body>\n<header>\
+         <a href=\"{root}\">{site_title}</a> <a href=\"{root}archive/\">Archive</a>\
+         </header>\n<main>\n{main}</main>\n</body>\n</html>\n"
+    )
+}
+
+fn post_page(site_title: &str, post: &Post, image_base: &str) -> String {
+    let content = match post.json.get("locked") {
+        Some(Value::Bool(true)) => {
+            let teaser = match post.text("excerpt") {
+                Some(excerpt) => format!("<p>{}</p>\n", escape(excerpt)),
+                None => String::new(),
+            };
+            format!("{teaser}<p><em>This post is for subscribers.</em></p>\n")
+        }
+        _ => {
+            let markdown = post
+                .text("body")
+                .or_else(|| post.text("content"))
+                .or_else(|| post.text("value"))
+                .unwrap_or_default();
+            render_body(&rewrite_markdown(markdown, image_base), &HashMap::new())
+        }
+    };
+    let main = format!(
+        "<article>\n<h1>{}</h1>\n<p><time>{}</time></p>\n{content}</article>\n",
+        escape(&post.title),
+        post.date()
+    );
+    page(
+        site_title,
+        &post.title,
+        "../../",
+        post.text("canonicalUrl"),
+        &main,
+    )
+}
+
+fn index_page(site_title: &str, posts: &[Post]) -> String {
+    let mut main = String::from("<ul>\n");
+    for post in posts.iter().take(INDEX_SIZE) {
+        main.push_str(&format!(
+            "<li><a href=\"{}\">{}</a> <time>{}</time><p>{}</p></li>\n",
+            post.href(),
+            escape(&post.title),
+            post.date(),
+            escape(post.text("excerpt").unwrap_or_default())
+        ));
+    }
+    main.push_str("</ul>\n");
+    page(site_title, "", "", None, &main)
+}
+
+/// Every post, newest first, under a heading per month.
+fn archive_page(site_title: &str, posts: &[Post]) -> String {
+    let mut months: BTreeMap<String, Vec<&Post>> = BTreeMap::new();
+    for post in posts {
+        let date = post.date();
+        let month = date.get(..7).unwrap_or("undated").to_string();
+        months.entry(month).or_default().push(post);
+    }
+    let mut main = String::from("<h1>Archive</h1>\n");
+    for (month, posts) in months.iter().rev() {
+        main.push_str(&format!("<h2>{month}</h2>\n<ul>\n"));
+        for post in posts {
+            main.push_str(&format!(
+                "<li><a href=\"../{}\">{}</a> <time>{}</time></li>\n",
+                post.href(),
+                escape(&post.title),
+                post.date()
+            ));
+        }
+        main.push_str("</ul>\n");
+    }
+    page(site_title, "Archive", "../", None, &main)
+}
+
+/// `json` as the export shows it; `None` for drafts and for posts whose
+/// `idx` cannot be a path segment.
+fn published(mut json: Value, cdn_base: Option<&str>) -> Option<Post> {
+    if json.get("status").and_then(|s| s.as_str()) == Some("draft") {
+        return None;
+    }
+    let idx = json.get("idx")?.as_str()?.to_string();
+    if idx.is_empty() || idx.contains('/') {
+        return None;
+    }
+    View::Public.post(&mut json);
+    if let Some(base) = cdn_base {
+        rewrite_post(&mut json, base);
+    }
+    Some(Post {
+        title: json
+            .get("title")
+            .and_then(|t| t.as_str())
+            .unwrap_or(&idx)
+            .to_string(),
+        created_at: json.get("created_at").and_then(|t| t.as_u64()),
+        idx,
+        json,
+    })
+}
+
+/// The page and JSON file of `post`.
+fn post_files(
+    prefix: &str,
+    site_title: &str,
+    post: &Post,
+    image_base: &str,
+) -> [(String, String, &'static str); 2] {
+    [
+        (
+            format!("{prefix}posts/{}/index.html", post.idx),
+            post_page(site_title, post, image_base),
+            HTML,
+        ),
+        (
+            format!("{prefix}posts/{}.json", post.idx),
+            post.json.to_string(),
+            JSON,
+        ),
+    ]
+}
+
+/// The front page, archive and, with `site_url`, the feed, listing
+/// `published` (newest first).
+async fn index_files(
+    prefix: &str,
+    part: String,
+    site_title: &str,
+    published: &[Post],
+    site_url: Option<&str>,
+    cdn_base: Option<&str>,
+) -> Result<Vec<(String, String, &'static str)>, Box<dyn std::error::Error + Send + Sync>> {
+    let newest: Vec<&Value> = published.iter().take(INDEX_SIZE).map(|p| &p.json).collect();
+    let archive: Vec<Value> = published
+        .iter()
+        .map(|p| json!({ "idx": p.idx, "title": p.title, "created_at": p.created_at }))
+        .collect();
+    let mut files = vec![
+        (
+            format!("{prefix}index.html"),
+            index_page(site_title, published),
+            HTML,
+        ),
+        (
+            format!("{prefix}posts.json"),
+            json!({ "posts": newest }).to_string(),
+            JSON,
+        ),
+        (
+            format!("{prefix}archive/index.html"),
+            archive_page(site_title, published),
+            HTML,
+        ),
+        (
+            format!("{prefix}archive.json"),
+            json!({ "posts": archive }).to_string(),
+            JSON,
+        ),
+    ];
+    if let Some(site_url) = site_url {
+        // the feed is served beside the site rather than by the API
+        let urls = PublicUrls::new(site_url.to_string(), None);
+        let mut feed = feed::build(part, &urls, site_title.to_string()).await?;
+        if let Some(base) = cdn_base {
+            for item in &mut feed.items {
+                item.content = rewrite_markdown(&item.content, base);
+            }
+        }
+        let feed = feed::to_json_feed(&feed, &urls.feed());
+        let content_type = "application/feed+json; charset=utf-8";
+        files.push((format!("{prefix}feed.json"), feed.to_string(), content_type));
+    }
+    Ok(files)
+}
+
+/// Renders the published posts of `stage` into a static copy of the blog
+/// under `{base_path}site/`: a page and a JSON file per post
+/// (`posts/{idx}/index.html`, `posts/{idx}.json`), the front page with
+/// `posts.json`, the archive with `archive.json`, and `feed.json` when
+/// `site_url` is set. Drafts are left out and subscribers-only posts keep
+/// only their `teaser`, as in the public view. Images point at `cdn_url`
+/// when there is one and at the stage's uploads next to the site
+/// otherwise. Files left from an earlier export are deleted, so the prefix
+/// always mirrors the blog as of this run. Progress counts posts.
+pub async fn export(
+    bucket: &str,
+    base_path: &str,
+    stage: Stage,
+    site_url: Option<&str>,
+    cdn_url: Option<&str>,
+    job_id: &str,
+) -> Result<ExportReport, Box<dyn std::error::Error + Send + Sync>> {
+    let prefix = format!("{base_path}{SITE_PREFIX}");
+    let part = stage.partition(&posts::posts_part());
+    let site_title = settings::load(stage.partition(SETTINGS_PARTITION))
+        .await?
+        .title;
+    let cdn_base = cdn_url.map(|cdn| format!("{cdn}/{base_path}"));
+    // post pages sit three levels below the stage's base, beside `upload/`
+    let image_base = cdn_base.clone().unwrap_or_else(|| "../../../".to_string());
+
+    let listed = list_posts(part.clone(), PostSort::CreatedAt, true, usize::MAX, None).await?;
+    let published: Vec<Post> = listed
+        .into_iter()
+        .filter_map(|json| published(json, cdn_base.as_deref()))
+        .collect();
+
+    let mut written = HashSet::new();
+    jobs::progress(job_id, 0, published.len()).await?;
+    for (page, chunk) in published.chunks(PAGE_SIZE).enumerate() {
+        let files = chunk
+            .iter()
+            .flat_map(|post| post_files(&prefix, &site_title, post, &image_base))
+            .collect();
+        write_all(bucket, files, &mut written).await?;
+
+        let processed = (page * PAGE_SIZE + chunk.len()).min(published.len());
+        jobs::progress(job_id, processed, published.len()).await?;
+    }
+
+    let cdn_base = cdn_base.as_deref();
+    let files = index_files(&prefix, part, &site_title, &published, site_url, cdn_base).await?;
+    write_all(bucket, files, &mut written).await?;
+
+    let stale: Vec<String> = list_all_objects(bucket, &prefix)
+        .await?
+        .into_iter()
+        .map(|object| object.key)
+        .filter(|key| !written.contains(key))
+        .collect();
+    let removed = if stale.is_empty() {
+        0
+    } else {
+        delete_objects(bucket, stale).await?
+    };
+
+    Ok(ExportReport {
+        prefix,
+        posts: published.len(),
+        files: written.len(),
+        removed,
+        feed: site_url.is_some(),
+    })
+}
+
+/// Renders post `idx` of `stage` into the exported site, as `export`
+/// would, without touching the rest of it. Returns the keys written, or
+/// `None` when the post does not exist or is a draft.
+pub async fn render_post(
+    bucket: &str,
+    base_path: &str,
+    stage: Stage,
+    cdn_url: Option<&str>,
+    idx: &str,
+) -> Result<Option<Vec<String>>, Box<dyn std::error::Error + Send + Sync>> {
+    let part = stage.partition(&posts::posts_part());
+    let Some(record) = get_record(part, idx.to_string()).await? else {
+        return Ok(None);
+    };
+    let cdn_base = cdn_url.map(|cdn| format!("{cdn}/{base_path}"));
+    let Some(post) = published(posts::post_to_json(&record), cdn_base.as_deref()) else {
+        return Ok(None);
+    };
+    let site_title = settings::load(stage.partition(SETTINGS_PARTITION))
+        .await?
+        .title;
+    let image_base = cdn_base.unwrap_or_else(|| "../../../".to_string());
+
+    let prefix = format!("{base_path}{SITE_PREFIX}");
+    let files = post_files(&prefix, &site_title, &post, &image_base).to_vec();
+    let mut written = HashSet::new();
+    write_all(bucket, files, &mut written).await?;
+    Ok(Some(written.into_iter().collect()))
+}
+
+/// Rewrites the front page, archive and feed of the exported site of
+/// `stage` from the posts as they are now. Returns the keys written.
+pub async fn render_indexes(
+    bucket: &str,
+    base_path: &str,
+    stage: Stage,
+    site_url: Option<&str>,
+    cdn_url: Option<&str>,
+) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+    let part = stage.partition(&posts::posts_part());
+    let site_title = settings::load(stage.partition(SETTINGS_PARTITION))
+        .await?
+        .title;
+    let cdn_base = cdn_url.map(|cdn| format!("{cdn}/{base_path}"));
+    let listed = list_posts(part.clone(), PostSort::CreatedAt, true, usize::MAX, None).await?;
+    let published: Vec<Post> = listed
+        .into_iter()
+        .filter_map(|json| published(json, cdn_base.as_deref()))
+        .collect();
+
+    let prefix = format!("{base_path}{SITE_PREFIX}");
+    let cdn_base = cdn_base.as_deref();
+    let files = index_files(&prefix, part, &site_title, &published, site_url, cdn_base).await?;
+    let mut written = HashSet::new();
+    write_all(bucket, files, &mut written).await?;
+    Ok(written.into_iter().collect())
+}
+
+/// Writes `files` (key, body, content type) concurrently and records their
+/// keys in `written`.
+async fn write_all(
+    bucket: &str,
+    files: Vec<(String, String, &str)>,
+    written: &mut HashSet<String>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let puts = files.iter().map(|(key, body, content_type)| {
+        put_object(bucket, key.clone(), body.clone().into_bytes(), content_type)
+    });
+    for result in join_all(puts).await {
+        result?;
+    }
+    written.extend(files.into_iter().map(|(key, ..)| key));
+    Ok(())
+}
@@ -0,0 +1,198 @@
+use crate::clock::now_millis;
+use crate::dynamodb::{dynamodb_client, schema, BumpsVersion, TABLE_NAME, VERSION_BUMP};
+use aws_sdk_dynamodb::operation::update_item::UpdateItemError;
+use aws_sdk_dynamodb::types::AttributeValue;
+use hmac::{Hmac, Mac};
+use serde_json::{Map, Value};
+use sha2::Sha256;
+use std::collections::HashMap;
+
+/// Paying readers, one item per Stripe customer id.
+pub const SUBSCRIBERS_PARTITION: &str = "subscribers";
+
+/// Posts whose `visibility` is this are only readable by subscribers.
+pub const SUBSCRIBERS_VISIBILITY: &str = "subscribers";
+
+/// Stripe `created` time of the newest event applied to a subscriber.
+const EVENT_AT_ATTRIBUTE: &str = "event_at";
+
+/// Oldest `Stripe-Signature` timestamp accepted, as Stripe's own libraries do.
+const SIGNATURE_TOLERANCE_SECS: u64 = 300;
+
+/// Checks a `Stripe-Signature` header (`t=<ts>,v1=<hex hmac>,...`) against
+/// the raw body and `stripe_webhook_secret`.
+pub fn verify_signature(header: &str, body: &[u8]) -> bool {
+    let Some(secret) = std::env::var("stripe_webhook_secret")
+        .ok()
+        .filter(|s| !s.is_empty())
+    else {
+        return false;
+    };
+
+    let mut timestamp = None;
+    let mut signatures = Vec::new();
+    for part in header.split(',') {
+        match part.trim().split_once('=') {
+            Some(("t", t)) => timestamp = t.parse::<u64>().ok(),
+            Some(("v1", sig)) => signatures.push(sig),
+            _ => {}
+        }
+    }
+    let Some(timestamp) = timestamp else {
+        return false;
+    };
+    if (now_millis() / 1000).abs_diff(timestamp) > SIGNATURE_TOLERANCE_SECS {
+        return false;
+    }
+
+    signatures.iter().any(|sig| {
+        let Ok(expected) = decode_hex(sig) else {
+            return false;
+        };
+        let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(secret.as_bytes()) else {
+            return false;
+        };
+        mac.update(timestamp.to_string().as_bytes());
+        mac.update(b".");
+        mac.update(body);
+        mac.verify_slice(&expected).is_ok()
+    })
+}
+
+fn decode_hex(value: &str) -> Result<Vec<u8>, std::num::ParseIntError> {
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(value.get(i..i + 2).unwrap_or("zz"), 16))
+        .collect()
+}
+
+/// Sets `attributes` on the subscriber item of `customer`, creating it if
+/// needed. With `event_at` (Stripe's `created`, in seconds), nothing is set
+/// when a newer event was applied already: Stripe does not deliver events
+/// in order, so a late `checkout.session.completed` must not undo a
+/// cancellation. `false` when the event was older.
+async fn apply(
+    customer: &str,
+    attributes: HashMap<&str, AttributeValue>,
+    event_at: Option<u64>,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let client = dynamodb_client().await;
+    let schema = schema();
+
+    let mut request = client
+        .update_item()
+        .table_name(TABLE_NAME)
+        .key(&schema.partition_key, AttributeValue::S(SUBSCRIBERS_PARTITION.to_string()))
+        .key(&schema.sort_key, AttributeValue::S(customer.to_string()))
+        .bump_version();
+    let mut assignments = vec![VERSION_BUMP.to_string()];
+    if let Some(event_at) = event_at {
+        assignments.push("#event_at = :event_at".to_string());
+        request = request
+            .condition_expression("attribute_not_exists(#event_at) OR #event_at <= :event_at")
+            .expression_attribute_names("#event_at", EVENT_AT_ATTRIBUTE)
+            .expression_attribute_values(":event_at", AttributeValue::N(event_at.to_string()));
+    }
+    for (i, (name, value)) in attributes.into_iter().enumerate() {
+        assignments.push(format!("#a{i} = :a{i}"));
+        request = request
+            .expression_attribute_names(format!("#a{i}"), name)
+            .expression_attribute_values(format!(":a{i}"), value);
+    }
+
+    let sent = request
+        .update_expression(format!("SET {}", assignments.join(", ")))
+        .send()
+        .await;
+    match sent {
+        Ok(_) => Ok(true),
+        Err(e) => match e.as_service_error() {
+            Some(UpdateItemError::ConditionalCheckFailedException(_)) => Ok(false),
+            _ => Err(e.into()),
+        },
+    }
+}
+
+/// Applies a verified Stripe event to the subscribers partition. Event types
+/// that don't affect subscriptions are ignored. Subscription events may
+/// arrive before the checkout that started them and create the item; the
+/// checkout then adds the email without changing a newer status.
+pub async fn handle_event(event: &Value) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let object = &event["data"]["object"];
+    let Some(customer) = object["customer"].as_str() else {
+        return Ok(());
+    };
+    let event_at = event["created"].as_u64().unwrap_or_default();
+    let now = AttributeValue::N(now_millis().to_string());
+
+    let status = match event["type"].as_str().unwrap_or_default() {
+        "checkout.session.completed" => {
+            let email = object["customer_details"]["email"]
+                .as_str()
+                .or_else(|| object["customer_email"].as_str())
+                .unwrap_or_default();
+            let mut details = HashMap::from([
+                ("email", AttributeValue::S(email.to_string())),
+                ("updated_at", now),
+            ]);
+            if let Some(subscription) = object["subscription"].as_str() {
+                details.insert("subscription", AttributeValue::S(subscription.to_string()));
+            }
+            let mut attributes = details.clone();
+            attributes.insert("status", AttributeValue::S("active".to_string()));
+            if !apply(customer, attributes, Some(event_at)).await? {
+                // a later subscription event already set the status
+                apply(customer, details, None).await?;
+            }
+            return Ok(());
+        }
+        "customer.subscription.created" | "customer.subscription.updated" => {
+            object["status"].as_str().unwrap_or("incomplete").to_string()
+        }
+        "customer.subscription.deleted" => "canceled".to_string(),
+        _ => return Ok(()),
+    };
+
+    let attributes = HashMap::from([
+        ("status", AttributeValue::S(status)),
+        ("updated_at", now),
+    ]);
+    if !apply(customer, attributes, Some(event_at)).await? {
+        tracing::info!("stale subscription event for customer {}", customer);
+    }
+    Ok(())
+}
+
+/// Whether a stored post value is marked subscribers-only.
+pub fn is_subscribers_only(value: &str) -> bool {
+    serde_json::from_str::<Value>(value)
+        .ok()
+        .and_then(|v| v.get("visibility")?.as_str().map(str::to_string))
+        .as_deref()
+        == Some(SUBSCRIBERS_VISIBILITY)
+}
+
+/// Removes the body of a post from its fields. The `excerpt` goes too, as it
+/// is cut from the body when the post sets none; a `teaser` the post sets
+/// takes its place.
+pub fn withhold_body(fields: &mut Map<String, Value>) {
+    for field in ["body", "content", "value", "translations", "excerpt"] {
+        fields.remove(field);
+    }
+    if let Some(teaser) = fields.get("teaser").filter(|t| t.is_string()).cloned() {
+        fields.insert("excerpt".to_string(), teaser);
+    }
+}
+
+/// Strips the readable content from a subscribers-only post. Readers are
+/// not identified yet, so only admin requests see these posts in full.
+pub fn lock_post(post: &mut Value) {
+    let Some(fields) = post.as_object_mut() else {
+        return;
+    };
+    if fields.get("visibility").and_then(|v| v.as_str()) != Some(SUBSCRIBERS_VISIBILITY) {
+        return;
+    }
+    withhold_body(fields);
+    fields.insert("locked".to_string(), Value::Bool(true));
+}
@@ -0,0 +1,40 @@
+/// Suggestions offered in a 404 body.
+const MAX_SUGGESTIONS: usize = 3;
+
+/// Case-insensitive Levenshtein distance.
+fn distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.to_lowercase().chars().collect();
+    let b: Vec<char> = b.to_lowercase().chars().collect();
+
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// "Did you mean" candidates for `target`, closest first. A candidate must be
+/// within a third of the target's length (at least 2 edits) to count.
+pub fn closest<'a>(target: &str, candidates: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    let threshold = (target.chars().count() / 3).max(2);
+
+    let mut scored: Vec<(usize, &str)> = candidates
+        .into_iter()
+        .filter(|c| *c != target)
+        .map(|c| (distance(target, c), c))
+        .filter(|(d, _)| *d <= threshold)
+        .collect();
+    scored.sort();
+    scored.dedup_by(|a, b| a.1 == b.1);
+
+    scored
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, c)| c.to_string())
+        .collect()
+}
@@ -0,0 +1,86 @@
+use crate::clock::{now_millis, utc_date};
+use crate::comments::COMMENTS_PARTITION;
+use crate::dynamodb::count_items;
+use crate::geo::country_views;
+use crate::posts::{daily_views, posts_part};
+use crate::s3::prefix_usage;
+use crate::stage::Stage;
+use crate::usage;
+use futures::future::join_all;
+use serde_json::{json, Value};
+
+const DAY_MILLIS: u64 = 24 * 60 * 60 * 1000;
+const SUMMARY_DAYS: u64 = 7;
+
+/// Everything the admin home page shows, gathered concurrently. A failing
+/// section is reported as `null` with the error logged, rather than failing
+/// the whole summary.
+///
+/// Comments are counted but not split by moderation state: the only
+/// comments kept are imported ones, which arrive already moderated (spam
+/// and deleted comments are dropped on import), so nothing is ever pending.
+pub async fn summary(bucket: Option<String>, root_path: String) -> Value {
+    let now = now_millis();
+    let days: Vec<String> = (0..SUMMARY_DAYS)
+        .rev()
+        .map(|ago| utc_date(now - ago * DAY_MILLIS))
+        .collect();
+
+    let part = posts_part();
+    let storage = async {
+        match &bucket {
+            Some(bucket) => prefix_usage(bucket, &root_path).await.map(Some),
+            None => Ok(None),
+        }
+    };
+    let requests = async {
+        let reports = join_all(days.iter().map(|day| usage::report(day.clone()))).await;
+        reports
+            .into_iter()
+            .map(|report| report.map(|r| r.total))
+            .collect::<Result<Vec<_>, _>>()
+    };
+
+    let live = async { count_items(Stage::live().await?.partition(&part)).await };
+    // comments are not content, so every live generation shares them
+    let comments = count_items(COMMENTS_PARTITION.to_string());
+
+    let (live, draft, comments, storage, views, countries, requests) = tokio::join!(
+        live,
+        count_items(Stage::Draft.partition(&part)),
+        comments,
+        storage,
+        daily_views(&days),
+        country_views(SUMMARY_DAYS),
+        requests,
+    );
+
+    let section = |name: &str, result: Result<Value, Box<dyn std::error::Error + Send + Sync>>| {
+        result.unwrap_or_else(|e| {
+            tracing::error!("summary {} error: {:?}", name, e);
+            Value::Null
+        })
+    };
+
+    let per_day = |counts: Vec<i64>| -> Value {
+        days.iter()
+            .zip(counts)
+            .map(|(day, count)| json!({ "day": day, "count": count }))
+            .collect()
+    };
+
+    json!({
+        "posts": {
+            "live": section("live posts", live.map(Value::from)),
+            "draft": section("draft posts", draft.map(Value::from)),
+        },
+        "comments": section("comments", comments.map(Value::from)),
+        "storage": section("storage", storage.map(|usage| match usage {
+            Some((objects, bytes)) => json!({ "objects": objects, "bytes": bytes }),
+            None => Value::Null,
+        })),
+        "views": section("views", views.map(per_day)),
+        "countries": section("countries", countries.map(|countries| json!(countries))),
+        "requests": section("requests", requests.map(per_day)),
+    })
+}
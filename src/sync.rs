@@ -0,0 +1,220 @@
+use crate::clock::now_millis;
+use crate::dynamodb::{dynamodb_client, schema, TABLE_NAME, UPDATED_AT_ATTRIBUTE};
+use crate::posts::{post_to_json, UPDATED_AT_INDEX};
+use crate::view::View;
+use crate::{compression, overflow};
+use aws_sdk_dynamodb::types::{AttributeValue, Put, TransactWriteItem};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Deleted posts leave a tombstone in `tombstones#{posts part}`, so clients
+/// that synced before the delete learn about it.
+pub const TOMBSTONES_PARTITION_PREFIX: &str = "tombstones#";
+
+/// How long tombstones are kept. A token older than this may have missed
+/// deletes whose tombstones are gone, so it is refused.
+const TOMBSTONE_RETENTION_MILLIS: u64 = 30 * 24 * 3600 * 1000;
+
+/// Changes this recent are left for the next sync: `updated_at` is stamped
+/// before the write lands, so a write still in flight could otherwise end
+/// up behind a token that already passed it.
+const SETTLE_MILLIS: u64 = 2000;
+
+pub const DEFAULT_SYNC_LIMIT: usize = 100;
+pub const MAX_SYNC_LIMIT: usize = 500;
+
+fn tombstones_part(posts_part: &str) -> String {
+    format!("{TOMBSTONES_PARTITION_PREFIX}{posts_part}")
+}
+
+/// The tombstone write for post `idx`, to be committed in the same
+/// transaction as its delete.
+pub fn tombstone(
+    posts_part: &str,
+    idx: &str,
+) -> Result<TransactWriteItem, Box<dyn std::error::Error + Send + Sync>> {
+    let schema = schema();
+    let now = now_millis();
+    let put = Put::builder()
+        .table_name(TABLE_NAME)
+        .item(
+            &schema.partition_key,
+            AttributeValue::S(tombstones_part(posts_part)),
+        )
+        .item(&schema.sort_key, AttributeValue::S(idx.to_string()))
+        .item(UPDATED_AT_ATTRIBUTE, AttributeValue::N(now.to_string()))
+        .item(
+            "ttl",
+            AttributeValue::N(((now + TOMBSTONE_RETENTION_MILLIS) / 1000 + 1).to_string()),
+        )
+        .build()?;
+    Ok(TransactWriteItem::builder().put(put).build())
+}
+
+/// Where a sync left off: the `updated_at` and id of the last change sent,
+/// or no id when every change up to `updated_at` was sent.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Position(u64, Option<String>);
+
+impl Position {
+    fn is_before(&self, updated_at: u64, idx: &str) -> bool {
+        updated_at > self.0 || (updated_at == self.0 && self.1.as_deref().is_some_and(|i| idx > i))
+    }
+}
+
+/// A post removed from the client's view: deleted, or turned back into a
+/// draft for public clients.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Removal {
+    pub idx: String,
+    pub removed_at: u64,
+}
+
+#[derive(Debug)]
+pub struct SyncPage {
+    /// Posts created or changed since the position, shaped for the viewer.
+    pub upserts: Vec<Value>,
+    pub removals: Vec<Removal>,
+    pub next: Position,
+    /// Whether changes are left beyond `limit`; sync again right away.
+    pub has_more: bool,
+}
+
+/// `updated_at`, id and record of a changed item.
+type Change = (u64, String, HashMap<String, AttributeValue>);
+
+/// Records of `part` with `updated_at` in `from.0..=until` that come after
+/// `from`, oldest first, at most `limit`.
+async fn changed_since(
+    part: String,
+    from: &Position,
+    until: u64,
+    limit: usize,
+) -> Result<Vec<Change>, Box<dyn std::error::Error + Send + Sync>> {
+    let client = dynamodb_client().await;
+    let schema = schema();
+
+    let mut changes = Vec::new();
+    let mut start_key = None;
+    loop {
+        let output = client
+            .query()
+            .table_name(TABLE_NAME)
+            .index_name(UPDATED_AT_INDEX)
+            .key_condition_expression("#part = :part AND #updated BETWEEN :from AND :until")
+            .expression_attribute_names("#part", &schema.partition_key)
+            .expression_attribute_names("#updated", UPDATED_AT_ATTRIBUTE)
+            .expression_attribute_values(":part", AttributeValue::S(part.clone()))
+            .expression_attribute_values(":from", AttributeValue::N(from.0.to_string()))
+            .expression_attribute_values(":until", AttributeValue::N(until.to_string()))
+            .limit((limit + 1).min(1000) as i32)
+            .set_exclusive_start_key(start_key)
+            .send()
+            .await?;
+
+        for record in output.items.unwrap_or_default() {
+            let updated_at = match record.get(UPDATED_AT_ATTRIBUTE) {
+                Some(AttributeValue::N(n)) => n.parse().unwrap_or_default(),
+                _ => continue,
+            };
+            let idx = match record.get(&schema.sort_key) {
+                Some(AttributeValue::S(idx)) => idx.clone(),
+                _ => continue,
+            };
+            if from.is_before(updated_at, &idx) {
+                changes.push((updated_at, idx, record));
+            }
+        }
+        start_key = output.last_evaluated_key;
+        if changes.len() >= limit || start_key.is_none() {
+            break;
+        }
+    }
+    changes.sort_by(|a, b| (a.0, &a.1).cmp(&(b.0, &b.1)));
+    changes.truncate(limit);
+    Ok(changes)
+}
+
+/// Changes to the posts of `posts_part` after `from`, oldest first, at most
+/// `limit` of them. `None` for a position older than the tombstones, which
+/// calls for a full sync. Without a position every post is sent and
+/// tombstones are skipped, the client having nothing to delete yet.
+pub async fn changes(
+    posts_part: String,
+    from: Option<Position>,
+    limit: usize,
+    view: View,
+) -> Result<Option<SyncPage>, Box<dyn std::error::Error + Send + Sync>> {
+    let now = now_millis();
+    let until = now.saturating_sub(SETTLE_MILLIS);
+    let full = from.is_none();
+    let from = from.unwrap_or_default();
+    if !full && from.0 + TOMBSTONE_RETENTION_MILLIS < now {
+        return Ok(None);
+    }
+    if from.0 > until {
+        return Ok(Some(SyncPage {
+            upserts: Vec::new(),
+            removals: Vec::new(),
+            next: from,
+            has_more: false,
+        }));
+    }
+
+    let tombstones_part = tombstones_part(&posts_part);
+    let tombstones = async {
+        match full {
+            true => Ok(Vec::new()),
+            false => changed_since(tombstones_part, &from, until, limit + 1).await,
+        }
+    };
+    let (posts, tombstones) = tokio::join!(
+        changed_since(posts_part, &from, until, limit + 1),
+        tombstones
+    );
+
+    // `None` marks a tombstone
+    let mut merged: Vec<_> = posts?
+        .into_iter()
+        .map(|(at, idx, record)| (at, idx, Some(record)))
+        .chain(tombstones?.into_iter().map(|(at, idx, _)| (at, idx, None)))
+        .collect();
+    merged.sort_by(|a, b| (a.0, &a.1).cmp(&(b.0, &b.1)));
+    let has_more = merged.len() > limit;
+    merged.truncate(limit);
+
+    let next = match merged.last() {
+        Some((at, idx, _)) if has_more => Position(*at, Some(idx.clone())),
+        _ => Position(until.max(from.0), None),
+    };
+    let mut page = SyncPage {
+        upserts: Vec::new(),
+        removals: Vec::new(),
+        next,
+        has_more,
+    };
+    for (updated_at, idx, record) in merged {
+        let Some(mut record) = record else {
+            page.removals.push(Removal {
+                idx,
+                removed_at: updated_at,
+            });
+            continue;
+        };
+        overflow::restore(&mut record).await?;
+        compression::decompress(&mut record)?;
+        let mut post = post_to_json(&record);
+        if view == View::Public && post["status"] == "draft" {
+            page.removals.push(Removal {
+                idx,
+                removed_at: updated_at,
+            });
+            continue;
+        }
+        view.post(&mut post);
+        page.upserts.push(post);
+    }
+    Ok(Some(page))
+}
@@ -0,0 +1,240 @@
+use crate::clock::now_millis;
+use crate::dynamodb::{error_kind, get_stored_value, ErrorKind, Precondition};
+use crate::posts::{post_body, save_post};
+use crate::subscribers::SUBSCRIBERS_VISIBILITY;
+use crate::urls::PublicUrls;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// dev.to accepts at most four tags per article.
+const DEVTO_MAX_TAGS: usize = 4;
+/// Medium accepts at most five tags per post.
+const MEDIUM_MAX_TAGS: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    DevTo,
+    Medium,
+}
+
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+pub struct SyndicationResult {
+    pub target: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "ts", ts(optional))]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "ts", ts(optional))]
+    pub error: Option<String>,
+}
+
+fn var(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|v| !v.is_empty())
+}
+
+fn client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .unwrap_or_default()
+    })
+}
+
+impl Target {
+    pub fn name(self) -> &'static str {
+        match self {
+            Target::DevTo => "devto",
+            Target::Medium => "medium",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Target> {
+        match name {
+            "devto" => Some(Target::DevTo),
+            "medium" => Some(Target::Medium),
+            _ => None,
+        }
+    }
+
+    /// Targets whose credentials are set: `devto_api_key`, and
+    /// `medium_token` with `medium_user_id`.
+    pub fn configured() -> Vec<Target> {
+        let mut targets = Vec::new();
+        if var("devto_api_key").is_some() {
+            targets.push(Target::DevTo);
+        }
+        if var("medium_token").is_some() && var("medium_user_id").is_some() {
+            targets.push(Target::Medium);
+        }
+        targets
+    }
+
+    /// Publishes the post on the target, pointing back at `canonical_url`,
+    /// and returns the URL of the copy.
+    async fn push(
+        self,
+        post: &Value,
+        body: String,
+        canonical_url: &str,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let title = post["title"].as_str().unwrap_or_default();
+        let tags: Vec<&str> = post["tags"]
+            .as_array()
+            .map(|tags| tags.iter().filter_map(|t| t.as_str()).collect())
+            .unwrap_or_default();
+
+        let (request, url_pointer) = match self {
+            Target::DevTo => {
+                let article = json!({
+                    "article": {
+                        "title": title,
+                        "body_markdown": body,
+                        "published": true,
+                        "canonical_url": canonical_url,
+                        "tags": tags.iter().take(DEVTO_MAX_TAGS).collect::<Vec<_>>(),
+                    }
+                });
+                let request = client()
+                    .post("https://dev.to/api/articles")
+                    .header("api-key", var("devto_api_key").unwrap_or_default())
+                    .header("content-type", "application/json")
+                    .body(article.to_string());
+                (request, "/url")
+            }
+            Target::Medium => {
+                let user = var("medium_user_id").unwrap_or_default();
+                let story = json!({
+                    "title": title,
+                    "contentFormat": "markdown",
+                    "content": body,
+                    "canonicalUrl": canonical_url,
+                    "tags": tags.iter().take(MEDIUM_MAX_TAGS).collect::<Vec<_>>(),
+                    "publishStatus": "public",
+                });
+                let request = client()
+                    .post(format!("https://api.medium.com/v1/users/{user}/posts"))
+                    .bearer_auth(var("medium_token").unwrap_or_default())
+                    .header("content-type", "application/json")
+                    .body(story.to_string());
+                (request, "/data/url")
+            }
+        };
+
+        let response = request.send().await?.error_for_status()?;
+        let created: Value = serde_json::from_str(&response.text().await?)?;
+        match created.pointer(url_pointer).and_then(|u| u.as_str()) {
+            Some(url) => Ok(url.to_string()),
+            None => Err(format!("{} response has no url", self.name()).into()),
+        }
+    }
+}
+
+/// Why a post was not syndicated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Refusal {
+    NotFound,
+    /// Drafts and subscriber-only posts are not public, and copies of them
+    /// elsewhere would be.
+    NotPublic,
+}
+
+/// What a syndication did. `recorded` is false when the post was saved by
+/// someone else while it was pushed, so the copies made are not in its
+/// `syndication` list.
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+pub struct Syndication {
+    pub results: Vec<SyndicationResult>,
+    pub recorded: bool,
+}
+
+/// Pushes post `idx` to `targets` and records each copy in the post's
+/// `syndication` list (`{"target", "url", "syndicatedAt"}`). Targets the post
+/// is already syndicated to are skipped, so a retry never posts twice. The
+/// list is saved only if the post is still at the version read, so an edit
+/// made meanwhile is never overwritten.
+pub async fn syndicate(
+    posts_part: String,
+    slugs_part: String,
+    idx: String,
+    urls: &PublicUrls,
+    targets: Vec<Target>,
+) -> Result<Result<Syndication, Refusal>, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(stored) = get_stored_value(posts_part.clone(), idx.clone()).await? else {
+        return Ok(Err(Refusal::NotFound));
+    };
+    let value = stored.value;
+    let mut post: Value = match serde_json::from_str(&value) {
+        Ok(post @ Value::Object(_)) => post,
+        _ => json!({ "body": value }),
+    };
+    if post["status"] == "draft" || post["visibility"] == SUBSCRIBERS_VISIBILITY {
+        return Ok(Err(Refusal::NotPublic));
+    }
+    let canonical_url = post["canonicalUrl"]
+        .as_str()
+        .map(str::to_string)
+        .unwrap_or_else(|| urls.post(&idx));
+    let body = post_body(&value);
+
+    let mut recorded: Vec<Value> = post["syndication"].as_array().cloned().unwrap_or_default();
+    let mut results = Vec::new();
+    for target in targets {
+        if recorded.iter().any(|r| r["target"] == target.name()) {
+            continue;
+        }
+        match target.push(&post, body.clone(), &canonical_url).await {
+            Ok(url) => {
+                recorded.push(json!({
+                    "target": target.name(),
+                    "url": url,
+                    "syndicatedAt": now_millis(),
+                }));
+                results.push(SyndicationResult {
+                    target: target.name(),
+                    url: Some(url),
+                    error: None,
+                });
+            }
+            Err(e) => {
+                tracing::warn!("syndication of {} to {} failed: {:?}", idx, target.name(), e);
+                results.push(SyndicationResult {
+                    target: target.name(),
+                    url: None,
+                    error: Some(e.to_string()),
+                });
+            }
+        }
+    }
+
+    if !results.iter().any(|r| r.url.is_some()) {
+        return Ok(Ok(Syndication {
+            results,
+            recorded: true,
+        }));
+    }
+    post["syndication"] = Value::Array(recorded);
+    let read = Precondition::Versions(vec![stored.version]);
+    // the slug is unchanged, so this cannot conflict
+    match save_post(posts_part, slugs_part, idx.clone(), post.to_string(), Some(&read)).await {
+        Ok(Ok(())) => {}
+        Ok(Err(conflict)) => return Err(format!("{conflict:?}").into()),
+        Err(e) if error_kind(e.as_ref()) == ErrorKind::Conflict => {
+            tracing::warn!("post {} changed during syndication: {:?}", idx, results);
+            return Ok(Ok(Syndication {
+                results,
+                recorded: false,
+            }));
+        }
+        Err(e) => return Err(e),
+    }
+    Ok(Ok(Syndication {
+        results,
+        recorded: true,
+    }))
+}
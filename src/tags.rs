@@ -0,0 +1,410 @@
+use crate::clock::now_millis;
+use crate::compression::{self, COMPRESSED_ATTRIBUTE};
+use crate::dynamodb::{
+    dynamodb_client, list_items, query_records, schema, BumpsVersion, ItemSummary, Precondition,
+    TABLE_NAME, UPDATED_AT_ATTRIBUTE, VERSION_BUMP,
+};
+use crate::jobs;
+use crate::outbox;
+use crate::overflow::{self, VALUE_REF_ATTRIBUTE};
+use aws_sdk_dynamodb::operation::transact_write_items::TransactWriteItemsError;
+use aws_sdk_dynamodb::types::{AttributeValue, TransactWriteItem, Update};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Posts rewritten per transaction, each with its outbox event.
+const PAGE_SIZE: usize = 25;
+
+/// Most items one `TransactWriteItems` takes.
+const MAX_TRANSACT_ITEMS: usize = 100;
+
+/// The tag index: published posts per tag of posts partition `p`, as item
+/// `idx = tag` of `tag_counts#{p}` with the number in `count`. Post writes
+/// keep it in step in their own transaction (see `count_updates`).
+pub const TAG_COUNTS_PARTITION_PREFIX: &str = "tag_counts#";
+
+const COUNT_ATTRIBUTE: &str = "count";
+
+#[derive(Debug, Default, Serialize)]
+pub struct RetagReport {
+    pub scanned: usize,
+    pub updated: usize,
+    /// Posts edited by someone else mid-run; they keep their old tags.
+    pub conflicts: usize,
+}
+
+/// The post value with every tag in `from` replaced by `into` (kept once,
+/// where the first replaced tag stood). `None` when the post is untouched.
+fn retag_value(value: &str, from: &[String], into: &str) -> Option<String> {
+    let mut post: Value = serde_json::from_str(value).ok()?;
+    let tags = post.get_mut("tags")?.as_array_mut()?;
+    if !tags.iter().any(|t| t.as_str().is_some_and(|t| from.iter().any(|f| f == t))) {
+        return None;
+    }
+
+    let mut retagged: Vec<Value> = Vec::with_capacity(tags.len());
+    for tag in tags.drain(..) {
+        let tag = match tag.as_str() {
+            Some(t) if from.iter().any(|f| f == t) => Value::String(into.to_string()),
+            _ => tag,
+        };
+        if !retagged.contains(&tag) {
+            retagged.push(tag);
+        }
+    }
+    *tags = retagged;
+    Some(post.to_string())
+}
+
+/// One post's rewrite: its tags `old` and `new` value, and `new` as it is
+/// stored, spilled to S3 like any saved post when too large.
+struct Change {
+    idx: String,
+    version: u64,
+    old: String,
+    old_ref: Option<String>,
+    new: String,
+    stored: AttributeValue,
+    new_ref: Option<String>,
+}
+
+impl Change {
+    async fn prepare(
+        part: &str,
+        post: &ItemSummary,
+        old: &str,
+        new: String,
+    ) -> Result<Change, Box<dyn std::error::Error + Send + Sync>> {
+        let (stored, new_ref) = compression::store(part, &post.idx, new.clone()).await?;
+        Ok(Change {
+            idx: post.idx.clone(),
+            version: post.version,
+            old: old.to_string(),
+            old_ref: post.value_ref.clone(),
+            new,
+            stored,
+            new_ref,
+        })
+    }
+
+    /// Deletes the body this rewrite made or replaced, whichever lost.
+    async fn settle(&self, written: bool) {
+        overflow::settle(written, self.new_ref.as_deref(), self.old_ref.as_deref()).await;
+    }
+}
+
+/// Conditional rewrite of one post's value, which fails if it changed since
+/// read, plus its `post.saved` outbox event.
+fn value_update(
+    part: &str,
+    change: &Change,
+) -> Result<[TransactWriteItem; 2], Box<dyn std::error::Error + Send + Sync>> {
+    let schema = schema();
+    let idx = &change.idx;
+    let mut update = Update::builder()
+        .table_name(TABLE_NAME)
+        .key(&schema.partition_key, AttributeValue::S(part.to_string()))
+        .key(&schema.sort_key, AttributeValue::S(idx.to_string()))
+        .expression_attribute_names("#value", &schema.value_attribute)
+        .expression_attribute_names("#updated", UPDATED_AT_ATTRIBUTE)
+        .expression_attribute_names("#compressed", COMPRESSED_ATTRIBUTE)
+        .expression_attribute_names("#ref", VALUE_REF_ATTRIBUTE)
+        .expression_attribute_values(":new", change.stored.clone())
+        .expression_attribute_values(":now", AttributeValue::N(now_millis().to_string()))
+        .bump_version();
+
+    // every write of the post bumps its version
+    let condition = Precondition::Versions(vec![change.version]).condition();
+    update = update.condition_expression(condition.expression);
+    for (name, attribute) in condition.names {
+        update = update.expression_attribute_names(name, attribute);
+    }
+    for (name, value) in condition.values {
+        update = update.expression_attribute_values(name, value);
+    }
+
+    let mut assignments = vec!["#value = :new", "#updated = :now", VERSION_BUMP];
+    let mut removals = Vec::new();
+    if matches!(change.stored, AttributeValue::B(_)) {
+        assignments.push("#compressed = :compressed");
+        update = update.expression_attribute_values(":compressed", AttributeValue::Bool(true));
+    } else {
+        removals.push("#compressed");
+    }
+    match &change.new_ref {
+        Some(key) => {
+            assignments.push("#ref = :ref");
+            update = update.expression_attribute_values(":ref", AttributeValue::S(key.clone()));
+        }
+        None => removals.push("#ref"),
+    }
+    let mut expression = format!("SET {}", assignments.join(", "));
+    if !removals.is_empty() {
+        expression.push_str(&format!(" REMOVE {}", removals.join(", ")));
+    }
+    let update = update.update_expression(expression).build()?;
+    let event = outbox::event("post.saved", json!({ "part": part, "idx": idx }))?;
+    Ok([TransactWriteItem::builder().update(update).build(), event])
+}
+
+fn is_conflict(e: &aws_sdk_dynamodb::error::SdkError<TransactWriteItemsError>) -> bool {
+    match e.as_service_error() {
+        Some(TransactWriteItemsError::TransactionCanceledException(cancelled)) => cancelled
+            .cancellation_reasons()
+            .iter()
+            .any(|r| r.code() == Some("ConditionalCheckFailed")),
+        _ => false,
+    }
+}
+
+/// Replaces the tags `from` with `into` across every post of `part`: a rename
+/// when `from` has one tag, a merge otherwise. Pages of posts are rewritten
+/// in one transaction each, along with their changes to the tag index; a
+/// page that hits a concurrent edit, or has too many tags to fit, is
+/// retried post by post so only the edited posts are skipped. Progress is
+/// kept on the job item `job_id`.
+pub async fn retag(
+    part: String,
+    from: Vec<String>,
+    into: String,
+    job_id: &str,
+) -> Result<RetagReport, Box<dyn std::error::Error + Send + Sync>> {
+    let client = dynamodb_client().await;
+
+    let posts = list_items(part.clone()).await?;
+    let edits: Vec<(&ItemSummary, &str, String)> = posts
+        .iter()
+        .filter_map(|post| {
+            let old = post.value.as_deref()?;
+            let new = retag_value(old, &from, &into)?;
+            Some((post, old, new))
+        })
+        .collect();
+
+    let mut report = RetagReport {
+        scanned: posts.len(),
+        ..Default::default()
+    };
+    jobs::progress(job_id, 0, edits.len()).await?;
+
+    for (page, chunk) in edits.chunks(PAGE_SIZE).enumerate() {
+        let mut changes = Vec::with_capacity(chunk.len());
+        for (post, old, new) in chunk {
+            changes.push(Change::prepare(&part, post, old, new.clone()).await?);
+        }
+
+        let mut writes = Vec::with_capacity(changes.len() * 2);
+        let mut deltas = BTreeMap::new();
+        for change in &changes {
+            writes.extend(value_update(&part, change)?);
+            for (tag, delta) in count_deltas(Some(&change.old), Some(&change.new)) {
+                *deltas.entry(tag).or_insert(0) += delta;
+            }
+        }
+        deltas.retain(|_, delta| *delta != 0);
+        writes.extend(count_updates(&part, &deltas)?);
+        // a merge of many tags may not fit with a whole page of posts
+        let result = match writes.len() <= MAX_TRANSACT_ITEMS {
+            true => Some(
+                client
+                    .transact_write_items()
+                    .set_transact_items(Some(writes))
+                    .send()
+                    .await,
+            ),
+            false => None,
+        };
+
+        match result {
+            Some(Ok(_)) => {
+                for change in &changes {
+                    change.settle(true).await;
+                }
+                report.updated += changes.len();
+            }
+            Some(Err(e)) if !is_conflict(&e) => {
+                for change in &changes {
+                    change.settle(false).await;
+                }
+                return Err(e.into());
+            }
+            _ => {
+                for (i, change) in changes.iter().enumerate() {
+                    let mut writes = value_update(&part, change)?.to_vec();
+                    let deltas = count_deltas(Some(&change.old), Some(&change.new));
+                    writes.extend(count_updates(&part, &deltas)?);
+                    let single = client
+                        .transact_write_items()
+                        .set_transact_items(Some(writes))
+                        .send()
+                        .await;
+                    change.settle(single.is_ok()).await;
+                    match single {
+                        Ok(_) => report.updated += 1,
+                        Err(e) if is_conflict(&e) => report.conflicts += 1,
+                        Err(e) => {
+                            for change in &changes[i + 1..] {
+                                change.settle(false).await;
+                            }
+                            return Err(e.into());
+                        }
+                    }
+                }
+            }
+        }
+
+        let processed = (page * PAGE_SIZE + chunk.len()).min(edits.len());
+        jobs::progress(job_id, processed, edits.len()).await?;
+    }
+
+    Ok(report)
+}
+
+pub fn tag_counts_part(posts_part: &str) -> String {
+    format!("{TAG_COUNTS_PARTITION_PREFIX}{posts_part}")
+}
+
+/// The tags post `value` is counted under: all of them, unless it is a draft.
+fn counted_tags(value: &str) -> BTreeSet<String> {
+    let Ok(post) = serde_json::from_str::<Value>(value) else {
+        return BTreeSet::new();
+    };
+    if post["status"] == "draft" {
+        return BTreeSet::new();
+    }
+    post["tags"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|tag| tag.as_str())
+        .filter(|tag| !tag.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// How the count of each tag changes when a post goes from `old` to `new`,
+/// `None` standing for no post. Tags whose count stays are left out.
+pub fn count_deltas(old: Option<&str>, new: Option<&str>) -> BTreeMap<String, i64> {
+    let old = old.map(counted_tags).unwrap_or_default();
+    let new = new.map(counted_tags).unwrap_or_default();
+    let removed = old.difference(&new).map(|tag| (tag.clone(), -1));
+    let added = new.difference(&old).map(|tag| (tag.clone(), 1));
+    removed.chain(added).collect()
+}
+
+/// The writes applying `deltas` to the tag index of `posts_part`, for the
+/// transaction that changes the posts. They carry no condition, so they
+/// only count when the post writes are conditioned on what was read.
+pub fn count_updates(
+    posts_part: &str,
+    deltas: &BTreeMap<String, i64>,
+) -> Result<Vec<TransactWriteItem>, Box<dyn std::error::Error + Send + Sync>> {
+    let schema = schema();
+    let part = tag_counts_part(posts_part);
+    let mut writes = Vec::with_capacity(deltas.len());
+    for (tag, delta) in deltas {
+        let update = Update::builder()
+            .table_name(TABLE_NAME)
+            .key(&schema.partition_key, AttributeValue::S(part.clone()))
+            .key(&schema.sort_key, AttributeValue::S(tag.clone()))
+            .update_expression("ADD #count :delta")
+            .expression_attribute_names("#count", COUNT_ATTRIBUTE)
+            .expression_attribute_values(":delta", AttributeValue::N(delta.to_string()))
+            .build()?;
+        writes.push(TransactWriteItem::builder().update(update).build());
+    }
+    Ok(writes)
+}
+
+/// A tag of the cloud. `weight` is its count relative to the most used
+/// tag's, from just above 0 up to 1.
+#[derive(Debug, Serialize)]
+pub struct TagCount {
+    pub tag: String,
+    pub count: u64,
+    pub weight: f64,
+}
+
+/// Every tag of a published post in `posts_part`, by name, read from the
+/// tag index.
+pub async fn cloud(
+    posts_part: &str,
+) -> Result<Vec<TagCount>, Box<dyn std::error::Error + Send + Sync>> {
+    let schema = schema();
+    let records =
+        query_records(tag_counts_part(posts_part), None, Vec::new(), usize::MAX, false).await?;
+
+    // tags whose last post went keep an item at 0
+    let counts: Vec<(String, u64)> = records
+        .iter()
+        .filter_map(|record| {
+            let tag = match record.get(&schema.sort_key) {
+                Some(AttributeValue::S(tag)) => tag.clone(),
+                _ => return None,
+            };
+            let count = match record.get(COUNT_ATTRIBUTE) {
+                Some(AttributeValue::N(n)) => n.parse::<i64>().ok()?,
+                _ => return None,
+            };
+            Some((tag, u64::try_from(count).ok().filter(|c| *c > 0)?))
+        })
+        .collect();
+
+    let max = counts.iter().map(|(_, count)| *count).max().unwrap_or(1);
+    Ok(counts
+        .into_iter()
+        .map(|(tag, count)| TagCount {
+            tag,
+            count,
+            weight: (count as f64 / max as f64 * 100.0).round() / 100.0,
+        })
+        .collect())
+}
+
+/// Rebuilds the tag index of `posts_part` from its posts, for posts written
+/// around it: imports, promotions and posts saved before the index existed.
+/// A post saved while it runs may be miscounted until the next rebuild.
+/// Returns the number of tags.
+pub async fn recount(
+    posts_part: String,
+) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    let client = dynamodb_client().await;
+    let schema = schema();
+    let part = tag_counts_part(&posts_part);
+
+    let mut counts: BTreeMap<String, i64> = BTreeMap::new();
+    for post in list_items(posts_part).await? {
+        for tag in counted_tags(post.value.as_deref().unwrap_or_default()) {
+            *counts.entry(tag).or_insert(0) += 1;
+        }
+    }
+
+    let indexed = query_records(part.clone(), None, Vec::new(), usize::MAX, false).await?;
+    for record in &indexed {
+        let Some(AttributeValue::S(tag)) = record.get(&schema.sort_key) else {
+            continue;
+        };
+        if counts.contains_key(tag) {
+            continue;
+        }
+        client
+            .delete_item()
+            .table_name(TABLE_NAME)
+            .key(&schema.partition_key, AttributeValue::S(part.clone()))
+            .key(&schema.sort_key, AttributeValue::S(tag.clone()))
+            .send()
+            .await?;
+    }
+    for (tag, count) in &counts {
+        client
+            .put_item()
+            .table_name(TABLE_NAME)
+            .item(&schema.partition_key, AttributeValue::S(part.clone()))
+            .item(&schema.sort_key, AttributeValue::S(tag.clone()))
+            .item(COUNT_ATTRIBUTE, AttributeValue::N(count.to_string()))
+            .send()
+            .await?;
+    }
+    Ok(counts.len())
+}
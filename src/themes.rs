@@ -0,0 +1,211 @@
+use crate::clock::now_millis;
+use crate::dynamodb::{get_item_value, list_items, put_item};
+use crate::s3::s3_client;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Theme assets, under the stage's S3 base: `themes/{theme}/{asset}.{hash}.{ext}`.
+pub const THEMES_PREFIX: &str = "themes/";
+
+/// One item per theme with the latest upload of each of its assets.
+pub const THEMES_PARTITION: &str = "themes";
+
+/// Item of the settings partition holding the active theme.
+const ACTIVE_THEME_IDX: &str = "theme";
+
+/// Largest asset that can be uploaded.
+pub const MAX_ASSET_BYTES: usize = 1024 * 1024;
+
+const MAX_THEME_NAME_CHARS: usize = 40;
+
+/// Hex digits of the content hash in an asset's key.
+const HASH_CHARS: usize = 16;
+
+/// Assets never change under their key, since a new upload gets a new one.
+const ASSET_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Asset {
+    Css,
+    Js,
+    Logo,
+}
+
+impl Asset {
+    pub fn from_name(name: &str) -> Option<Asset> {
+        match name {
+            "css" => Some(Asset::Css),
+            "js" => Some(Asset::Js),
+            "logo" => Some(Asset::Logo),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Asset::Css => "css",
+            Asset::Js => "js",
+            Asset::Logo => "logo",
+        }
+    }
+
+    /// Extension of the asset's key for `content_type`, if the asset may
+    /// have that type.
+    pub fn extension(self, content_type: &str) -> Option<&'static str> {
+        let essence = content_type.split(';').next().unwrap_or_default().trim();
+        match (self, essence.to_ascii_lowercase().as_str()) {
+            (Asset::Css, "text/css") => Some("css"),
+            (Asset::Js, "text/javascript" | "application/javascript") => Some("js"),
+            (Asset::Logo, "image/png") => Some("png"),
+            (Asset::Logo, "image/jpeg") => Some("jpg"),
+            (Asset::Logo, "image/webp") => Some("webp"),
+            (Asset::Logo, "image/svg+xml") => Some("svg"),
+            _ => None,
+        }
+    }
+}
+
+/// Lowercase letters, digits and dashes.
+pub fn is_theme_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_THEME_NAME_CHARS
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+}
+
+/// A theme and the keys (relative to the stage's base path) of its assets.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(export))]
+#[serde(rename_all = "camelCase")]
+pub struct Theme {
+    pub name: String,
+    pub css: Option<String>,
+    pub js: Option<String>,
+    pub logo: Option<String>,
+    /// Epoch milliseconds of the last upload.
+    pub updated_at: u64,
+}
+
+impl Theme {
+    fn asset_mut(&mut self, asset: Asset) -> &mut Option<String> {
+        match asset {
+            Asset::Css => &mut self.css,
+            Asset::Js => &mut self.js,
+            Asset::Logo => &mut self.logo,
+        }
+    }
+
+    /// Each asset the theme has, with its key.
+    pub fn assets(&self) -> Vec<(Asset, &str)> {
+        [
+            (Asset::Css, &self.css),
+            (Asset::Js, &self.js),
+            (Asset::Logo, &self.logo),
+        ]
+        .into_iter()
+        .filter_map(|(asset, key)| Some((asset, key.as_deref()?)))
+        .collect()
+    }
+}
+
+async fn load(
+    part: String,
+    name: &str,
+) -> Result<Option<Theme>, Box<dyn std::error::Error + Send + Sync>> {
+    match get_item_value(part, name.to_string()).await? {
+        Some(value) => Ok(Some(serde_json::from_str(&value)?)),
+        None => Ok(None),
+    }
+}
+
+/// Stores `bytes` as `asset` of theme `name` and makes it the theme's
+/// current one. The key carries a hash of the content, so every version
+/// gets its own URL and earlier ones stay in place for pages still using
+/// them. `themes_part` is the stage's `THEMES_PARTITION`.
+pub async fn upload(
+    bucket: &str,
+    base_path: &str,
+    themes_part: String,
+    name: &str,
+    asset: Asset,
+    bytes: Vec<u8>,
+    content_type: &str,
+) -> Result<Theme, Box<dyn std::error::Error + Send + Sync>> {
+    let extension = asset
+        .extension(content_type)
+        .ok_or("unsupported content type")?;
+    let hash: String = Sha256::digest(&bytes)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    let key = format!(
+        "{THEMES_PREFIX}{name}/{}.{}.{extension}",
+        asset.name(),
+        &hash[..HASH_CHARS]
+    );
+    s3_client()
+        .await
+        .put_object()
+        .bucket(bucket)
+        .key(format!("{base_path}{key}"))
+        .content_type(content_type)
+        .cache_control(ASSET_CACHE_CONTROL)
+        .body(bytes.into())
+        .send()
+        .await?;
+
+    let mut theme = load(themes_part.clone(), name)
+        .await?
+        .unwrap_or_else(|| Theme {
+            name: name.to_string(),
+            ..Theme::default()
+        });
+    *theme.asset_mut(asset) = Some(key);
+    theme.updated_at = now_millis();
+    put_item(
+        themes_part,
+        name.to_string(),
+        serde_json::to_string(&theme)?,
+    )
+    .await?;
+    Ok(theme)
+}
+
+/// Every theme of the stage whose `THEMES_PARTITION` is `themes_part`.
+pub async fn list(
+    themes_part: String,
+) -> Result<Vec<Theme>, Box<dyn std::error::Error + Send + Sync>> {
+    let items = list_items(themes_part).await?;
+    Ok(items
+        .into_iter()
+        .filter_map(|item| serde_json::from_str(item.value.as_deref()?).ok())
+        .collect())
+}
+
+/// Makes theme `name` as it is now the active one. Later uploads to it
+/// only show once it is activated again. `None` when there is no such
+/// theme.
+pub async fn activate(
+    themes_part: String,
+    settings_part: String,
+    name: &str,
+) -> Result<Option<Theme>, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(theme) = load(themes_part, name).await? else {
+        return Ok(None);
+    };
+    put_item(
+        settings_part,
+        ACTIVE_THEME_IDX.to_string(),
+        serde_json::to_string(&theme)?,
+    )
+    .await?;
+    Ok(Some(theme))
+}
+
+/// The active theme, kept in `settings_part`; `None` until one is activated.
+pub async fn active(
+    settings_part: String,
+) -> Result<Option<Theme>, Box<dyn std::error::Error + Send + Sync>> {
+    load(settings_part, ACTIVE_THEME_IDX).await
+}
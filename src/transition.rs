@@ -0,0 +1,179 @@
+use crate::jobs;
+use crate::s3::{self, s3_client, COPY_SOURCE};
+use aws_sdk_s3::types::{MetadataDirective, StorageClass};
+use futures::future::join_all;
+use percent_encoding::utf8_percent_encode;
+use serde::Serialize;
+
+/// Storage classes objects may be moved to.
+pub const TARGET_CLASSES: [&str; 7] = [
+    "STANDARD",
+    "STANDARD_IA",
+    "ONEZONE_IA",
+    "INTELLIGENT_TIERING",
+    "GLACIER_IR",
+    "GLACIER",
+    "DEEP_ARCHIVE",
+];
+
+/// Largest object `CopyObject` takes; bigger ones need a multipart copy.
+const MAX_COPY_BYTES: i64 = 5 * 1024 * 1024 * 1024;
+
+/// Objects copied at once; progress is reported per batch.
+const BATCH_SIZE: usize = 25;
+
+/// Failures listed in the report; the rest are only counted.
+const MAX_REPORTED_FAILURES: usize = 100;
+
+/// The storage class `TARGET_CLASSES` lists under `name`, in any case.
+pub fn target_class(name: &str) -> Result<StorageClass, String> {
+    let name = name.trim().to_uppercase();
+    if !TARGET_CLASSES.contains(&name.as_str()) {
+        return Err(format!("storageClass must be one of {}", TARGET_CLASSES.join(", ")));
+    }
+    Ok(StorageClass::from(name.as_str()))
+}
+
+#[derive(Debug, Serialize)]
+pub struct Failure {
+    pub key: String,
+    pub error: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransitionReport {
+    pub prefix: String,
+    pub storage_class: String,
+    pub scanned: usize,
+    pub transitioned: usize,
+    pub transitioned_bytes: i64,
+    /// Objects already in the storage class.
+    pub skipped: usize,
+    pub failed: usize,
+    /// The first failures, with the error code of each.
+    pub failures: Vec<Failure>,
+}
+
+struct Listed {
+    key: String,
+    size: i64,
+    storage_class: String,
+}
+
+/// Every object under `prefix` with its storage class; S3 lists objects
+/// without one as `STANDARD`.
+async fn list(
+    client: &aws_sdk_s3::Client,
+    bucket: &str,
+    prefix: &str,
+) -> Result<Vec<Listed>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut objects = Vec::new();
+    let mut token = None;
+    loop {
+        let resp = client
+            .list_objects_v2()
+            .bucket(bucket)
+            .prefix(prefix)
+            .set_continuation_token(token)
+            .send()
+            .await?;
+
+        for obj in resp.contents.unwrap_or_default() {
+            let Some(key) = obj.key else { continue };
+            objects.push(Listed {
+                key,
+                size: obj.size.unwrap_or_default(),
+                storage_class: obj
+                    .storage_class
+                    .map(|c| c.as_str().to_string())
+                    .unwrap_or_else(|| "STANDARD".to_string()),
+            });
+        }
+
+        token = resp.next_continuation_token;
+        if token.is_none() {
+            break;
+        }
+    }
+    Ok(objects)
+}
+
+/// Moves every object under `prefix` to `storage_class` by copying it onto
+/// itself, which keeps its metadata and tags. Objects already in the class
+/// are skipped, so a run cut short can simply be started again. Archived
+/// objects that were not restored, and objects over 5 GiB, cannot be
+/// copied and are reported as failures. Progress counts objects.
+pub async fn transition(
+    bucket: &str,
+    prefix: &str,
+    storage_class: &str,
+    job_id: &str,
+) -> Result<TransitionReport, Box<dyn std::error::Error + Send + Sync>> {
+    let client = s3_client().await;
+    let target = target_class(storage_class)?;
+    let objects = list(&client, bucket, prefix).await?;
+
+    let mut report = TransitionReport {
+        prefix: prefix.to_string(),
+        storage_class: target.as_str().to_string(),
+        scanned: objects.len(),
+        transitioned: 0,
+        transitioned_bytes: 0,
+        skipped: 0,
+        failed: 0,
+        failures: Vec::new(),
+    };
+    let (current, pending): (Vec<Listed>, Vec<Listed>) = objects
+        .into_iter()
+        .partition(|object| object.storage_class == target.as_str());
+    report.skipped = current.len();
+
+    jobs::progress(job_id, report.skipped, report.scanned).await?;
+    for (batch, chunk) in pending.chunks(BATCH_SIZE).enumerate() {
+        let copies = chunk.iter().map(|object| {
+            let (client, target) = (&client, target.clone());
+            async move {
+                if object.size > MAX_COPY_BYTES {
+                    return Err("object_too_large".to_string());
+                }
+                let source = format!("{bucket}/{}", object.key);
+                client
+                    .copy_object()
+                    .bucket(bucket)
+                    .copy_source(utf8_percent_encode(&source, COPY_SOURCE).to_string())
+                    .key(&object.key)
+                    .storage_class(target)
+                    .metadata_directive(MetadataDirective::Copy)
+                    .send()
+                    .await
+                    .map_err(|e| {
+                        tracing::warn!("s3 transition of {} failed: {:?}", object.key, e);
+                        s3::error_kind(&e).code().to_string()
+                    })
+            }
+        });
+        for (object, result) in chunk.iter().zip(join_all(copies).await) {
+            match result {
+                Ok(_) => {
+                    report.transitioned += 1;
+                    report.transitioned_bytes += object.size;
+                }
+                Err(error) => {
+                    report.failed += 1;
+                    if report.failures.len() < MAX_REPORTED_FAILURES {
+                        report.failures.push(Failure {
+                            key: object.key.clone(),
+                            error,
+                        });
+                    }
+                }
+            }
+        }
+
+        let processed = report.skipped + (batch * BATCH_SIZE + chunk.len()).min(pending.len());
+        jobs::progress(job_id, processed, report.scanned).await?;
+    }
+
+    Ok(report)
+}
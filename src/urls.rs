@@ -0,0 +1,168 @@
+use lambda_http::Request;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::Serialize;
+use serde_json::Value;
+use url::Url;
+
+/// Absolute URLs of the blog as readers reach it. Every link the API hands
+/// out for use elsewhere (feed items, Open Graph, share links, canonical
+/// links of syndicated copies) is built here, so they all agree on one base.
+#[derive(Debug, Clone)]
+pub struct PublicUrls {
+    site: String,
+    api: String,
+}
+
+impl PublicUrls {
+    /// `site` is where posts are read, `api` where this API is served;
+    /// without one the API is taken to live on the site.
+    pub fn new(site: String, api: Option<String>) -> PublicUrls {
+        let site = site.trim_end_matches('/').to_string();
+        let api = api
+            .map(|api| api.trim_end_matches('/').to_string())
+            .unwrap_or_else(|| site.clone());
+        PublicUrls { site, api }
+    }
+
+    /// `site_url` and `api_url` when set, and otherwise the origin the
+    /// request came in on, so a blog behind a custom domain links to that
+    /// domain without extra configuration. `None` when neither is known.
+    pub fn from_request(
+        req: &Request,
+        site_url: Option<&String>,
+        api_url: Option<&String>,
+    ) -> Option<PublicUrls> {
+        let origin = request_origin(req);
+        let site = site_url.cloned().or_else(|| origin.clone())?;
+        Some(PublicUrls::new(site, api_url.cloned().or(origin)))
+    }
+
+    pub fn site(&self) -> &str {
+        &self.site
+    }
+
+    /// Reader-facing page of post `idx`, `/posts/{idx}` as ActivityPub
+    /// links it.
+    pub fn post(&self, idx: &str) -> String {
+        format!("{}/posts/{idx}", self.site)
+    }
+
+    /// Where this API is served, for links to its own routes.
+    pub fn api(&self) -> &str {
+        &self.api
+    }
+
+    pub fn feed(&self) -> String {
+        format!("{}/feed.json", self.api)
+    }
+}
+
+/// `X-Forwarded-Proto` (only `http` or `https`, `https` by default) and the
+/// `Host` header, if the host is a bare host name with an optional port.
+fn request_origin(req: &Request) -> Option<String> {
+    let header = |name: &str| {
+        req.headers()
+            .get(name)
+            .and_then(|h| h.to_str().ok())
+            .map(|v| v.split(',').next().unwrap_or_default().trim())
+            .filter(|v| !v.is_empty())
+    };
+    let scheme = match header("x-forwarded-proto") {
+        Some("http") => "http",
+        _ => "https",
+    };
+    let host = header("host")?;
+    let origin = format!("{scheme}://{host}");
+    let url = Url::parse(&origin).ok()?;
+    let bare = url.host_str().is_some()
+        && url.username().is_empty()
+        && url.password().is_none()
+        && url.path() == "/"
+        && url.query().is_none()
+        && url.fragment().is_none()
+        && !host.contains(['/', '?', '#', '@']);
+    bare.then_some(origin.to_ascii_lowercase())
+}
+
+/// Open Graph properties of a post (`og:url`, `og:title`, ...), for pages
+/// rendered outside the API to put in their `<head>`.
+#[derive(Debug, Serialize)]
+pub struct OpenGraph {
+    pub url: String,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// First image of the body, if it has an absolute URL.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+}
+
+/// Links that open each network's composer on the post.
+#[derive(Debug, Serialize)]
+pub struct ShareLinks {
+    pub x: String,
+    pub bluesky: String,
+    pub linkedin: String,
+    pub facebook: String,
+    pub email: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Share {
+    pub url: String,
+    pub og: OpenGraph,
+    pub links: ShareLinks,
+}
+
+/// Share details of post `idx`, shaped for the viewer beforehand. Links
+/// point at the post's `canonicalUrl` when it has one.
+pub fn share(urls: &PublicUrls, idx: &str, post: &Value) -> Share {
+    let text = |field: &str| {
+        post.get(field)
+            .and_then(|v| v.as_str())
+            .filter(|v| !v.is_empty())
+            .map(str::to_string)
+    };
+    let url = text("canonicalUrl").unwrap_or_else(|| urls.post(idx));
+    let title = text("title").unwrap_or_else(|| idx.to_string());
+    let body = text("body").or_else(|| text("content"));
+
+    let encode = |s: &str| utf8_percent_encode(s, NON_ALPHANUMERIC).to_string();
+    let (u, t) = (encode(&url), encode(&title));
+    let links = ShareLinks {
+        x: format!("https://x.com/intent/post?url={u}&text={t}"),
+        bluesky: format!(
+            "https://bsky.app/intent/compose?text={}",
+            encode(&format!("{title} {url}"))
+        ),
+        linkedin: format!("https://www.linkedin.com/sharing/share-offsite/?url={u}"),
+        facebook: format!("https://www.facebook.com/sharer/sharer.php?u={u}"),
+        email: format!("mailto:?subject={t}&body={u}"),
+    };
+    Share {
+        og: OpenGraph {
+            url: url.clone(),
+            kind: "article",
+            title,
+            description: text("excerpt"),
+            image: body.as_deref().and_then(first_image),
+        },
+        url,
+        links,
+    }
+}
+
+/// Source of the first markdown image (`![alt](src "title")`) when it is an
+/// absolute http(s) URL; upload keys only become URLs behind `cdn_url`.
+fn first_image(markdown: &str) -> Option<String> {
+    let start = markdown.find("![")?;
+    let rest = &markdown[start..];
+    let open = rest.find("](")? + 2;
+    let src = rest[open..].split([')', ' ']).next()?;
+    Url::parse(src)
+        .ok()
+        .filter(|u| u.scheme() == "http" || u.scheme() == "https")
+        .map(|u| u.to_string())
+}
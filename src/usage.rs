@@ -0,0 +1,313 @@
+use crate::audit::client_ip;
+use crate::clock::{now_millis, utc_date};
+use crate::dynamodb::{
+    dynamodb_client, get_record, increment_counter, query_records, schema, TABLE_NAME,
+};
+use crate::hotlink::IMAGE_ROUTE;
+use crate::links::LINK_ROUTE;
+use crate::preview::PREVIEW_ROUTE;
+use crate::shortlinks::SHORTLINK_ROUTE;
+use aws_sdk_dynamodb::types::AttributeValue;
+use lambda_http::Request;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+/// Usage counters live in one partition per UTC day, `usage#YYYY-MM-DD`,
+/// with one item per client and route, expired through the `ttl` attribute.
+pub const USAGE_PARTITION_PREFIX: &str = "usage#";
+
+/// Requests answered with a server error (5xx), per UTC day; `idx` is the
+/// date.
+pub const DAILY_ERRORS_PARTITION: &str = "daily_errors";
+
+const COUNT_ATTRIBUTE: &str = "count";
+const TOP_ROUTES: usize = 10;
+
+const DEFAULT_RETENTION_DAYS: u64 = 30;
+
+/// Routes usage is counted under. A `*` segment matches any one segment and
+/// a trailing `**` the rest of the path.
+const ROUTES: [&str; 88] = [
+    "/.well-known/webfinger",
+    "/activitypub/actor",
+    "/activitypub/inbox",
+    "/activitypub/outbox",
+    "/admin/acl",
+    "/admin/activitypub/publish",
+    "/admin/analytics/countries",
+    "/admin/analytics/queries",
+    "/admin/analytics/queries/*",
+    "/admin/analytics/queries/*/results",
+    "/admin/audit",
+    "/admin/backup",
+    "/admin/broken-links",
+    "/admin/broken-links/check",
+    "/admin/comments/export",
+    "/admin/comments/import",
+    "/admin/costs",
+    "/admin/digest",
+    "/admin/digest/send",
+    "/admin/export-static",
+    "/admin/gc",
+    "/admin/honeytokens/seed",
+    "/admin/img/sign",
+    "/admin/indexes",
+    "/admin/links",
+    "/admin/links/revoke",
+    "/admin/outbox/sweep",
+    "/admin/preview",
+    "/admin/s3/transition",
+    "/admin/settings",
+    "/admin/shortlinks",
+    "/admin/summary",
+    "/admin/tags/merge",
+    "/admin/tags/recount",
+    "/admin/tags/rename",
+    "/admin/themes",
+    "/admin/themes/*",
+    "/admin/themes/*/*",
+    "/admin/usage",
+    "/admin/users/*",
+    "/admin/users/*/export",
+    "/api/files/search",
+    "/api/files/**",
+    "/api/s3/delete-url",
+    "/api/s3/download-manifest",
+    "/api/s3/download-url",
+    "/api/s3/import-url",
+    "/api/s3/list",
+    "/api/s3/upload-url",
+    "/api/s3/upload-urls",
+    "/avatar",
+    "/comments/mask",
+    "/dynamodb/import",
+    "/dynamodb/item",
+    "/dynamodb/item/pin",
+    "/dynamodb/items",
+    "/dynamodb/items/order",
+    "/feed.json",
+    "/health/live",
+    "/health/ready",
+    "/helloWorld",
+    "/jobs/*",
+    "/jobs/*/run",
+    "/posts",
+    "/posts/by-slug/*",
+    "/posts/*/attachments",
+    "/posts/*/autosave",
+    "/posts/*/comments/settings",
+    "/posts/*/lint",
+    "/posts/*/lock",
+    "/posts/*/lock/heartbeat",
+    "/posts/*/mentions",
+    "/posts/*/reactions",
+    "/posts/*/share",
+    "/posts/*/syndicate",
+    "/posts/*/view",
+    "/series",
+    "/series/*",
+    "/series/*/posts",
+    "/settings",
+    "/sitemap.xml",
+    "/stage/promote",
+    "/sync",
+    "/tags/cloud",
+    "/theme",
+    "/warmup",
+    "/webhooks/stripe",
+    "/webmention",
+];
+
+/// Route prefixes whose remainder is a key, code or token.
+const PREFIX_ROUTES: [&str; 4] = [IMAGE_ROUTE, LINK_ROUTE, PREVIEW_ROUTE, SHORTLINK_ROUTE];
+
+/// Route of requests to paths no route serves.
+const OTHER_ROUTE: &str = "other";
+
+/// How long usage counters are kept, from `usage_retention_days`.
+fn retention_secs() -> u64 {
+    let days = std::env::var("usage_retention_days")
+        .ok()
+        .and_then(|d| d.parse().ok())
+        .unwrap_or(DEFAULT_RETENTION_DAYS);
+    days * 24 * 60 * 60
+}
+
+fn matches(pattern: &str, path: &str) -> bool {
+    let (mut pattern, mut path) = (pattern.split('/'), path.split('/'));
+    loop {
+        match (pattern.next(), path.next()) {
+            (None, None) => return true,
+            (Some("**"), Some(segment)) => return !segment.is_empty(),
+            (Some("*"), Some(segment)) if !segment.is_empty() => {}
+            (Some(expected), Some(segment)) if expected == segment => {}
+            _ => return false,
+        }
+    }
+}
+
+/// The route template `path` falls under, such as `/posts/*/view`, so that
+/// ids, keys and unknown paths do not each get their own counter.
+fn route_template(path: &str) -> String {
+    if let Some(route) = ROUTES.iter().find(|pattern| matches(pattern, path)) {
+        return route.to_string();
+    }
+    match PREFIX_ROUTES.iter().find(|prefix| path.starts_with(*prefix)) {
+        Some(prefix) => format!("{prefix}**"),
+        None => OTHER_ROUTE.to_string(),
+    }
+}
+
+/// Who made a request and to which route, captured before routing.
+pub struct UsageKey {
+    client: String,
+    route: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RouteCount {
+    pub route: String,
+    pub count: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClientUsage {
+    pub client: String,
+    pub total: i64,
+    pub routes: Vec<RouteCount>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UsageReport {
+    pub day: String,
+    pub total: i64,
+    pub clients: Vec<ClientUsage>,
+    pub top_routes: Vec<RouteCount>,
+}
+
+/// Identifies the caller by API key when one is sent, otherwise by IP. Keys
+/// are stored as a short hash so the table never holds the secret itself.
+pub fn usage_key(req: &Request) -> UsageKey {
+    let api_key = req
+        .headers()
+        .get("x-api-key")
+        .and_then(|h| h.to_str().ok())
+        .filter(|k| !k.is_empty());
+
+    let client = match api_key {
+        Some(key) => {
+            let digest = Sha256::digest(key.as_bytes());
+            let short: String = digest[..8].iter().map(|b| format!("{b:02x}")).collect();
+            format!("key:{short}")
+        }
+        None => format!("ip:{}", client_ip(req)),
+    };
+
+    UsageKey {
+        client,
+        route: route_template(req.uri().path()),
+    }
+}
+
+impl UsageKey {
+    pub fn client(&self) -> &str {
+        &self.client
+    }
+}
+
+/// Counts a server error against today.
+pub async fn record_error() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let day = utc_date(now_millis());
+    increment_counter(DAILY_ERRORS_PARTITION.to_string(), day, COUNT_ATTRIBUTE, 1).await?;
+    Ok(())
+}
+
+/// Server errors answered on `day` (`YYYY-MM-DD`).
+pub async fn errors(day: String) -> Result<i64, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(record) = get_record(DAILY_ERRORS_PARTITION.to_string(), day).await? else {
+        return Ok(0);
+    };
+    match record.get(COUNT_ATTRIBUTE) {
+        Some(AttributeValue::N(n)) => Ok(n.parse()?),
+        _ => Ok(0),
+    }
+}
+
+/// Counts a request against today, keeping the day's counters for
+/// `usage_retention_days` (default 30).
+pub async fn record(key: UsageKey) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let now = now_millis();
+    let schema = schema();
+    let part = format!("{USAGE_PARTITION_PREFIX}{}", utc_date(now));
+    let idx = format!("{}#{}", key.client, key.route);
+    let ttl = now / 1000 + retention_secs();
+
+    dynamodb_client()
+        .await
+        .update_item()
+        .table_name(TABLE_NAME)
+        .key(&schema.partition_key, AttributeValue::S(part))
+        .key(&schema.sort_key, AttributeValue::S(idx))
+        .update_expression("ADD #count :one SET #ttl = if_not_exists(#ttl, :ttl)")
+        .expression_attribute_names("#count", COUNT_ATTRIBUTE)
+        .expression_attribute_names("#ttl", "ttl")
+        .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
+        .expression_attribute_values(":ttl", AttributeValue::N(ttl.to_string()))
+        .send()
+        .await?;
+    Ok(())
+}
+
+fn sorted_counts(counts: HashMap<String, i64>) -> Vec<RouteCount> {
+    let mut routes: Vec<RouteCount> = counts
+        .into_iter()
+        .map(|(route, count)| RouteCount { route, count })
+        .collect();
+    routes.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.route.cmp(&b.route)));
+    routes
+}
+
+/// Totals per client (busiest first) and the busiest routes for one day.
+pub async fn report(day: String) -> Result<UsageReport, Box<dyn std::error::Error + Send + Sync>> {
+    let part = format!("{USAGE_PARTITION_PREFIX}{day}");
+    let records = query_records(part, None, Vec::new(), usize::MAX, false).await?;
+    let sort_key = &schema().sort_key;
+
+    let mut per_client: HashMap<String, HashMap<String, i64>> = HashMap::new();
+    let mut per_route: HashMap<String, i64> = HashMap::new();
+    for record in &records {
+        let Some(AttributeValue::S(idx)) = record.get(sort_key) else { continue };
+        let Some((client, route)) = idx.split_once('#') else { continue };
+        let count = match record.get(COUNT_ATTRIBUTE) {
+            Some(AttributeValue::N(n)) => n.parse().unwrap_or(0),
+            _ => 0,
+        };
+
+        per_client
+            .entry(client.to_string())
+            .or_default()
+            .insert(route.to_string(), count);
+        *per_route.entry(route.to_string()).or_default() += count;
+    }
+
+    let mut clients: Vec<ClientUsage> = per_client
+        .into_iter()
+        .map(|(client, routes)| ClientUsage {
+            client,
+            total: routes.values().sum(),
+            routes: sorted_counts(routes),
+        })
+        .collect();
+    clients.sort_by(|a, b| b.total.cmp(&a.total).then_with(|| a.client.cmp(&b.client)));
+
+    let mut top_routes = sorted_counts(per_route);
+    top_routes.truncate(TOP_ROUTES);
+
+    Ok(UsageReport {
+        day,
+        total: clients.iter().map(|c| c.total).sum(),
+        clients,
+        top_routes,
+    })
+}
@@ -0,0 +1,188 @@
+use crate::clock::now_millis;
+use crate::comments::{self, Comment, COMMENTS_PARTITION};
+use crate::dynamodb::{delete_item, generate_idx, get_record, record_to_json};
+use crate::s3::{delete_objects, get_object, list_all_objects, put_object, StoredObject};
+use crate::stage::Stage;
+use crate::subscribers::SUBSCRIBERS_PARTITION;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::Serialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+/// Archives written by `export`, under the root S3 path, one folder per
+/// user.
+pub const EXPORTS_PREFIX: &str = "exports/users/";
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// What is kept about a user, who is a subscriber known by Stripe customer
+/// id: the subscriber item, avatars stored under the MD5 or SHA-256 hash of
+/// their email, and imported comments left with that email, per comments
+/// partition of either stage.
+struct Holdings {
+    record: Value,
+    objects: Vec<StoredObject>,
+    comments: Vec<(String, Vec<Comment>)>,
+}
+
+impl Holdings {
+    fn items(&self) -> usize {
+        1 + self.comments.iter().map(|(_, c)| c.len()).sum::<usize>()
+    }
+}
+
+async fn holdings(
+    bucket: &str,
+    root_path: &str,
+    id: &str,
+) -> Result<Option<Holdings>, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(record) = get_record(SUBSCRIBERS_PARTITION.to_string(), id.to_string()).await? else {
+        return Ok(None);
+    };
+    let record = record_to_json(&record);
+
+    let mut objects = Vec::new();
+    let mut comments = Vec::new();
+    let email = record["email"]
+        .as_str()
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase();
+    if !email.is_empty() {
+        let md5 = <md5::Md5 as md5::Digest>::digest(email.as_bytes());
+        for hash in [hex(&md5), hex(&Sha256::digest(email.as_bytes()))] {
+            let uploaded = format!("{root_path}avatars/{hash}");
+            let cached = format!("{root_path}avatars/gravatar/{hash}-");
+            for prefix in [uploaded.clone(), cached] {
+                let listed = list_all_objects(bucket, &prefix).await?;
+                objects.extend(
+                    listed
+                        .into_iter()
+                        .filter(|o| prefix != uploaded || o.key == uploaded),
+                );
+            }
+        }
+        for stage in [Stage::live().await?, Stage::Draft] {
+            let part = stage.partition(COMMENTS_PARTITION);
+            let left = comments::by_author_email(part.clone(), &email).await?;
+            if !left.is_empty() {
+                comments.push((part, left));
+            }
+        }
+    }
+    Ok(Some(Holdings {
+        record,
+        objects,
+        comments,
+    }))
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserExport {
+    /// Key of the archive, under `EXPORTS_PREFIX`.
+    pub key: String,
+    pub items: usize,
+    pub objects: usize,
+}
+
+/// Writes everything kept about user `id` into one JSON archive in S3:
+/// `{"user", "exportedAt", "items", "objects"}`, each object with its
+/// content in base64. `None` when there is no such user.
+pub async fn export(
+    bucket: &str,
+    root_path: &str,
+    id: &str,
+) -> Result<Option<UserExport>, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(held) = holdings(bucket, root_path, id).await? else {
+        return Ok(None);
+    };
+
+    let mut objects = Vec::with_capacity(held.objects.len());
+    for object in &held.objects {
+        let Some(body) = get_object(bucket, object.key.clone()).await? else {
+            continue;
+        };
+        objects.push(json!({
+            "key": object.key.strip_prefix(root_path).unwrap_or(&object.key),
+            "contentType": body.content_type,
+            "size": object.size,
+            "lastModified": object.last_modified,
+            "data": BASE64.encode(&body.bytes),
+        }));
+    }
+    let mut items = vec![json!({ "partition": SUBSCRIBERS_PARTITION, "item": held.record })];
+    for (part, left) in &held.comments {
+        items.extend(
+            left.iter()
+                .map(|comment| json!({ "partition": part, "item": comment })),
+        );
+    }
+    let archive = json!({
+        "user": id,
+        "exportedAt": now_millis(),
+        "items": items,
+        "objects": objects,
+    });
+
+    let key = format!("{root_path}{EXPORTS_PREFIX}{id}/{}.json", generate_idx());
+    put_object(
+        bucket,
+        key.clone(),
+        archive.to_string().into_bytes(),
+        "application/json",
+    )
+    .await?;
+    Ok(Some(UserExport {
+        key,
+        items: held.items(),
+        objects: objects.len(),
+    }))
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserErasure {
+    pub items: usize,
+    pub objects: usize,
+    /// Earlier exports of the user, deleted with the rest.
+    pub exports: usize,
+}
+
+/// Deletes everything kept about user `id`, along with the archives
+/// `export` wrote for them. `None` when there is no such user.
+pub async fn erase(
+    bucket: &str,
+    root_path: &str,
+    id: &str,
+) -> Result<Option<UserErasure>, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(held) = holdings(bucket, root_path, id).await? else {
+        return Ok(None);
+    };
+
+    // objects first: with the item gone, they could no longer be found
+    let exports = list_all_objects(bucket, &format!("{root_path}{EXPORTS_PREFIX}{id}/")).await?;
+    let keys: Vec<String> = held.objects.iter().map(|o| o.key.clone()).collect();
+    let objects = match keys.is_empty() {
+        true => 0,
+        false => delete_objects(bucket, keys).await?,
+    };
+    let keys: Vec<String> = exports.into_iter().map(|o| o.key).collect();
+    let exports = match keys.is_empty() {
+        true => 0,
+        false => delete_objects(bucket, keys).await?,
+    };
+    for (part, left) in &held.comments {
+        comments::remove(part, left).await?;
+    }
+    delete_item(SUBSCRIBERS_PARTITION.to_string(), id.to_string()).await?;
+
+    Ok(Some(UserErasure {
+        items: held.items(),
+        objects,
+        exports,
+    }))
+}
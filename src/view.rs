@@ -0,0 +1,58 @@
+use crate::subscribers::{lock_post, withhold_body};
+use serde_json::Value;
+
+/// Post fields only the admin view shows.
+const INTERNAL_FIELDS: [&str; 5] = [
+    "notes",
+    "internal_notes",
+    "internalNotes",
+    "author_email",
+    "authorEmail",
+];
+
+/// Who a response is shaped for. Every route that returns post content runs
+/// it through `View::post` (or `post_value` for stored values), so what the
+/// public may see is decided here rather than per route.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum View {
+    Public,
+    Admin,
+}
+
+impl View {
+    /// Shapes a post object in place. The public view drops internal notes
+    /// and the author's email, and the body and excerpt of posts whose
+    /// `status` is `draft` or that are locked to subscribers (see
+    /// `withhold_body`). The admin view is the stored post as is.
+    pub fn post(self, post: &mut Value) {
+        if self == View::Admin {
+            return;
+        }
+        lock_post(post);
+        let Some(fields) = post.as_object_mut() else {
+            return;
+        };
+
+        for field in INTERNAL_FIELDS {
+            fields.remove(field);
+        }
+        if let Some(author) = fields.get_mut("author").and_then(|a| a.as_object_mut()) {
+            author.remove("email");
+        }
+        if fields.get("status").and_then(|s| s.as_str()) == Some("draft") {
+            withhold_body(fields);
+        }
+    }
+
+    /// `post` for a stored post value. Values that aren't JSON objects have
+    /// nothing to redact and are returned unchanged.
+    pub fn post_value(self, value: &str) -> String {
+        match serde_json::from_str::<Value>(value) {
+            Ok(mut post @ Value::Object(_)) if self == View::Public => {
+                self.post(&mut post);
+                post.to_string()
+            }
+            _ => value.to_string(),
+        }
+    }
+}
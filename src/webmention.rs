@@ -0,0 +1,192 @@
+use crate::clock::now_millis;
+use crate::dynamodb::{delete_item, get_record, put_record, query_records, record_to_json};
+use crate::outbound;
+use crate::posts::post_to_json;
+use crate::replay;
+use aws_sdk_dynamodb::types::AttributeValue;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use std::time::Duration;
+use url::Url;
+
+/// Mentions of a post are stored in `mentions#<post id>`, keyed by source URL.
+pub const MENTIONS_PARTITION_PREFIX: &str = "mentions#";
+
+const MAX_SOURCE_BYTES: usize = 1024 * 1024;
+
+/// Receiver name of webmention deliveries in `replay`.
+const RECEIVER: &str = "webmention";
+
+/// Why a webmention was not accepted; rendered as a 400 response.
+#[derive(Debug)]
+pub enum Rejection {
+    InvalidUrl(&'static str),
+    SameUrl,
+    UnknownTarget,
+    /// The source could not be fetched.
+    SourceUnavailable,
+    NoLink,
+    /// The same source content was already received for the target.
+    Replayed,
+}
+
+impl std::fmt::Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rejection::InvalidUrl(which) => write!(f, "{which} must be an http(s) URL"),
+            Rejection::SameUrl => write!(f, "source and target must differ"),
+            Rejection::UnknownTarget => {
+                write!(f, "target is not a published post on this site")
+            }
+            Rejection::SourceUnavailable => write!(f, "source could not be fetched"),
+            Rejection::NoLink => write!(f, "source does not link to target"),
+            Rejection::Replayed => write!(f, "replayed delivery"),
+        }
+    }
+}
+
+fn client() -> Result<&'static reqwest::Client, Box<dyn std::error::Error + Send + Sync>> {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    outbound::cached(&CLIENT, Duration::from_secs(5), 3)
+}
+
+fn parse_http_url(value: &str, which: &'static str) -> Result<Url, Rejection> {
+    match Url::parse(value) {
+        Ok(url) if url.scheme() == "http" || url.scheme() == "https" => Ok(url),
+        _ => Err(Rejection::InvalidUrl(which)),
+    }
+}
+
+/// Post id for a target URL: the last path segment of a URL under `site_url`.
+fn target_post_id(target: &Url) -> Option<String> {
+    let site = Url::parse(&std::env::var("site_url").ok()?).ok()?;
+    if target.origin() != site.origin() || !target.path().starts_with(site.path()) {
+        return None;
+    }
+    target
+        .path_segments()?
+        .rfind(|s| !s.is_empty())
+        .map(|s| s.to_string())
+}
+
+/// Fetches the source document, capped at `MAX_SOURCE_BYTES`. `None` means
+/// the source is gone (404/410) and any stored mention should be dropped.
+async fn fetch_source(
+    source: &Url,
+) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+    let response = outbound::send(client()?.get(source.as_str())).await?;
+    if matches!(response.status().as_u16(), 404 | 410) {
+        return Ok(None);
+    }
+    if !response.status().is_success() {
+        return Err(format!("source responded {}", response.status()).into());
+    }
+
+    let (body, _) = outbound::read_limited(response, MAX_SOURCE_BYTES).await?;
+    Ok(Some(String::from_utf8_lossy(&body).into_owned()))
+}
+
+/// The delivery a webmention is claimed as (see `replay::claim`): its source
+/// and target, and what the source holds now, so that a resend after the
+/// source changed or disappeared is processed again, as senders are meant
+/// to do.
+fn delivery(source: &str, target: &str, document: Option<&str>) -> String {
+    let content: String = match document {
+        Some(document) => Sha256::digest(document.as_bytes())
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect(),
+        None => "gone".to_string(),
+    };
+    format!("{source} {target} {content}")
+}
+
+/// Validates and verifies a webmention, then stores (or, if the source is
+/// gone, removes) it. The target must be a published post in `posts_part`.
+/// Verification happens inline: Lambda freezes once the response is sent,
+/// so there is no later point to do it. A resend of the same source content
+/// is rejected as a replay.
+pub async fn receive(
+    posts_part: String,
+    source: &str,
+    target: &str,
+) -> Result<Result<(), Rejection>, Box<dyn std::error::Error + Send + Sync>> {
+    let source_url = match parse_http_url(source, "source") {
+        Ok(url) => url,
+        Err(rejection) => return Ok(Err(rejection)),
+    };
+    let target_url = match parse_http_url(target, "target") {
+        Ok(url) => url,
+        Err(rejection) => return Ok(Err(rejection)),
+    };
+    if source_url == target_url {
+        return Ok(Err(Rejection::SameUrl));
+    }
+    let post_id = match target_post_id(&target_url) {
+        Some(id) => id,
+        None => return Ok(Err(Rejection::UnknownTarget)),
+    };
+    match get_record(posts_part, post_id.clone()).await? {
+        Some(post) if post_to_json(&post)["status"] != "draft" => {}
+        _ => return Ok(Err(Rejection::UnknownTarget)),
+    }
+
+    let part = format!("{MENTIONS_PARTITION_PREFIX}{post_id}");
+    let document = match fetch_source(&source_url).await {
+        Ok(document) => document,
+        Err(e) => {
+            tracing::warn!("webmention source {} fetch failed: {:?}", source_url, e);
+            return Ok(Err(Rejection::SourceUnavailable));
+        }
+    };
+    if let Some(document) = &document {
+        if !document.contains(target_url.as_str()) && !document.contains(target) {
+            return Ok(Err(Rejection::NoLink));
+        }
+    }
+
+    let delivery = delivery(source, target, document.as_deref());
+    if !replay::claim(RECEIVER, &delivery).await? {
+        return Ok(Err(Rejection::Replayed));
+    }
+    let stored = match document {
+        None => delete_item(part, source_url.to_string()).await,
+        Some(_) => {
+            let mut attributes = HashMap::new();
+            attributes.insert("source".to_string(), AttributeValue::S(source_url.to_string()));
+            attributes.insert("target".to_string(), AttributeValue::S(target_url.to_string()));
+            let now = AttributeValue::N(now_millis().to_string());
+            attributes.insert("verified_at".to_string(), now);
+            put_record(part, source_url.to_string(), attributes).await
+        }
+    };
+    if let Err(e) = stored {
+        // the sender's retry must not be mistaken for a replay
+        if let Err(e) = replay::release(RECEIVER, &delivery).await {
+            tracing::error!("dynamodb delivery release error: {:?}", e);
+        }
+        return Err(e);
+    }
+
+    Ok(Ok(()))
+}
+
+pub async fn list_mentions(
+    post_id: String,
+) -> Result<Vec<serde_json::Value>, Box<dyn std::error::Error + Send + Sync>> {
+    let part = format!("{MENTIONS_PARTITION_PREFIX}{post_id}");
+    let records = query_records(part, None, Vec::new(), usize::MAX, false).await?;
+
+    Ok(records
+        .iter()
+        .map(|record| {
+            let json = record_to_json(record);
+            serde_json::json!({
+                "source": json["source"],
+                "target": json["target"],
+                "verified_at": json["verified_at"],
+            })
+        })
+        .collect())
+}